@@ -0,0 +1,455 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use async_std::fs;
+use async_std::os::unix::fs::OpenOptionsExt;
+use async_std::path::PathBuf;
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::sign::Signer;
+use openssl::stack::Stack;
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509Req, X509};
+use reqwest::{header, Url};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+use crate::reaper;
+use crate::ship::HARBOR;
+
+lazy_static! {
+    /// The ACME directory to get certificates for the vhost proxy's hostnames from, such as Let's Encrypt's,
+    /// `https://acme-v02.api.letsencrypt.org/directory`. Unset serves no HTTPS.
+    pub static ref ACME_DIRECTORY: Option<Url> = env::var_os("NUCLEUS_ACME_DIRECTORY")
+        .map(|s| s.to_str().unwrap().parse::<Url>().unwrap());
+
+    /// An email address for the CA to warn of expiring certificates and problems with the account.
+    pub static ref ACME_CONTACT: Option<String> = env::var_os("NUCLEUS_ACME_CONTACT")
+        .map(|s| s.to_str().unwrap().to_owned());
+
+    /// How the CA is shown that the orchestrator controls the hostnames: `http-01`, answered by the vhost listener,
+    /// which must then be reachable on port 80, or `dns-01`, through `NUCLEUS_ACME_DNS_HOOK`.
+    pub static ref ACME_CHALLENGE: ChallengeType = env::var_os("NUCLEUS_ACME_CHALLENGE")
+        .map(|s| s.to_str().unwrap().parse().unwrap())
+        .unwrap_or(ChallengeType::Http01);
+
+    /// For `dns-01`, a program run as `hook set <record> <value>` to publish a TXT record, returning once it is
+    /// visible to the CA, and as `hook clear <record> <value>` to remove it again.
+    pub static ref ACME_DNS_HOOK: Option<PathBuf> = env::var_os("NUCLEUS_ACME_DNS_HOOK").map(PathBuf::from);
+
+    /// Key authorizations for pending `http-01` challenges, by token.
+    static ref HTTP_CHALLENGES: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+
+    /// The TLS configuration with the current certificate, replaced whenever the certificate is.
+    static ref ACCEPTOR: RwLock<Option<tokio_native_tls::TlsAcceptor>> = RwLock::new(None);
+}
+
+/// Certificates are renewed once they have this long left, as Let's Encrypt recommends for its 90-day certificates.
+const RENEW_BEFORE_DAYS: u32 = 30;
+
+/// The most hostnames Let's Encrypt puts on one certificate.
+const MAX_NAMES: usize = 100;
+
+const ACME_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long the CA gets to validate a challenge or issue a certificate.
+const VALIDATION_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChallengeType {
+    Http01,
+    Dns01,
+}
+
+impl ChallengeType {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChallengeType::Http01 => "http-01",
+            ChallengeType::Dns01 => "dns-01",
+        }
+    }
+}
+
+impl std::str::FromStr for ChallengeType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "http-01" => Ok(ChallengeType::Http01),
+            "dns-01" if ACME_DNS_HOOK.is_none() => bail!("the dns-01 challenge needs NUCLEUS_ACME_DNS_HOOK"),
+            "dns-01" => Ok(ChallengeType::Dns01),
+            _ => bail!("unknown ACME challenge type {:?}; expected http-01 or dns-01", s),
+        }
+    }
+}
+
+/// The key authorization to answer an `http-01` challenge for `token` with, if one is pending.
+pub fn http_challenge(token: &str) -> Option<String> {
+    HTTP_CHALLENGES.lock().unwrap().get(token).cloned()
+}
+
+/// The TLS configuration to accept connections with, once there is a certificate.
+pub fn acceptor() -> Option<tokio_native_tls::TlsAcceptor> {
+    ACCEPTOR.read().unwrap().clone()
+}
+
+/// Where the ACME account key and the current certificate and its key are kept. Created on demand.
+async fn acme_path() -> Result<PathBuf> {
+    let path = HARBOR.as_path().join("acme");
+    fs::create_dir_all(&path).await?;
+    Ok(path)
+}
+
+fn b64(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+fn new_key() -> Result<PKey<Private>> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    Ok(PKey::from_ec_key(EcKey::generate(&group)?)?)
+}
+
+/// Writes a private key readable only by the orchestrator, replacing any already there.
+async fn write_private(path: &PathBuf, contents: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&tmp_path).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Order {
+    status: String,
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: String,
+}
+
+/// An ACME account, which signs every request to the CA with its key as a JWS.
+struct Account {
+    client: reqwest::Client,
+    directory: Directory,
+    key: PKey<Private>,
+    /// The account's URL, once registered, which identifies it in place of its key.
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+impl Account {
+    /// Registers with the CA, or finds the account already registered with the harbor's account key.
+    async fn open(directory_url: &Url) -> Result<Self> {
+        let key_path = acme_path().await?.join("account.pem");
+        let key = match fs::read(&key_path).await {
+            Ok(pem) => PKey::private_key_from_pem(&pem)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let key = new_key()?;
+                write_private(&key_path, &key.private_key_to_pem_pkcs8()?).await?;
+                key
+            },
+            Err(e) => return Err(e.into()),
+        };
+        let client = reqwest::Client::builder().timeout(ACME_TIMEOUT).build()?;
+        let directory = client.get(directory_url.clone()).send().await?.error_for_status()?.json().await?;
+        let mut account = Account { client, directory, key, kid: None, nonce: None };
+
+        let mut registration = serde_json::json!({ "termsOfServiceAgreed": true });
+        if let Some(contact) = &*ACME_CONTACT {
+            registration["contact"] = serde_json::json!([format!("mailto:{}", contact)]);
+        }
+        let new_account = account.directory.new_account.clone();
+        let res = account.post(&new_account, Some(&registration)).await?;
+        account.kid = Some(location(&res)?);
+        Ok(account)
+    }
+
+    fn jwk(&self) -> Result<serde_json::Value> {
+        let ec = self.key.ec_key()?;
+        let (mut x, mut y, mut ctx) = (BigNum::new()?, BigNum::new()?, BigNumContext::new()?);
+        ec.public_key().affine_coordinates_gfp(ec.group(), &mut x, &mut y, &mut ctx)?;
+        // Members in lexicographic order, as the thumbprint requires.
+        Ok(serde_json::json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": b64(&x.to_vec_padded(32)?),
+            "y": b64(&y.to_vec_padded(32)?),
+        }))
+    }
+
+    /// What a challenge's token is answered with: the token and the thumbprint of the account key.
+    fn key_authorization(&self, token: &str) -> Result<String> {
+        let thumbprint = Sha256::digest(serde_json::to_string(&self.jwk()?)?.as_bytes());
+        Ok(format!("{}.{}", token, b64(&thumbprint)))
+    }
+
+    async fn nonce(&mut self) -> Result<String> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+        let res = self.client.head(&self.directory.new_nonce).send().await?;
+        replay_nonce(&res).ok_or_else(|| anyhow!("ACME server sent no nonce"))
+    }
+
+    fn sign(&self, url: &str, nonce: String, payload: Option<&serde_json::Value>) -> Result<serde_json::Value> {
+        let mut protected = serde_json::json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = serde_json::json!(kid),
+            None => protected["jwk"] = self.jwk()?,
+        }
+        let protected = b64(serde_json::to_string(&protected)?.as_bytes());
+        // A POST-as-GET has an empty payload.
+        let payload = match payload {
+            Some(payload) => b64(serde_json::to_string(payload)?.as_bytes()),
+            None => String::new(),
+        };
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.key)?;
+        signer.update(format!("{}.{}", protected, payload).as_bytes())?;
+        // JWS wants the bare r and s, where openssl gives a DER sequence of them.
+        let signature = EcdsaSig::from_der(&signer.sign_to_vec()?)?;
+        let mut raw = signature.r().to_vec_padded(32)?;
+        raw.extend(signature.s().to_vec_padded(32)?);
+        Ok(serde_json::json!({ "protected": protected, "payload": payload, "signature": b64(&raw) }))
+    }
+
+    /// Sends a signed request, retrying once if the CA turns its nonce away, as it may at any time.
+    async fn post(&mut self, url: &str, payload: Option<&serde_json::Value>) -> Result<reqwest::Response> {
+        let mut retried = false;
+        loop {
+            let nonce = self.nonce().await?;
+            let body = self.sign(url, nonce, payload)?;
+            let res = self.client.post(url)
+                .header(header::CONTENT_TYPE, "application/jose+json")
+                .body(serde_json::to_vec(&body)?)
+                .send()
+                .await?;
+            self.nonce = replay_nonce(&res);
+            if res.status().is_success() {
+                return Ok(res);
+            }
+            let status = res.status();
+            let problem: serde_json::Value = res.json().await.unwrap_or_default();
+            if problem["type"] == "urn:ietf:params:acme:error:badNonce" && !retried {
+                retried = true;
+                continue;
+            }
+            bail!("ACME server refused a request with {}: {}", status, problem["detail"].as_str().unwrap_or_default());
+        }
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&mut self, url: &str) -> Result<T> {
+        Ok(self.post(url, None).await?.json().await?)
+    }
+
+    /// Shows the CA that the orchestrator controls the authorization's hostname, if it hasn't already.
+    async fn authorize(&mut self, url: &str) -> Result<()> {
+        let authorization: Authorization = self.get(url).await?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let challenge = authorization.challenges.iter()
+            .find(|challenge| challenge.kind == ACME_CHALLENGE.as_str())
+            .ok_or_else(|| anyhow!("ACME server offers no {} challenge for {}", ACME_CHALLENGE.as_str(),
+                authorization.identifier.value))?;
+        let key_authorization = self.key_authorization(&challenge.token)?;
+        // Wildcard authorizations are for the domain itself, and are answered there.
+        let record = format!("_acme-challenge.{}", authorization.identifier.value);
+        let dns_value = b64(&Sha256::digest(key_authorization.as_bytes()));
+        match *ACME_CHALLENGE {
+            ChallengeType::Http01 => {
+                HTTP_CHALLENGES.lock().unwrap().insert(challenge.token.clone(), key_authorization);
+            },
+            ChallengeType::Dns01 => run_dns_hook("set", &record, &dns_value).await?,
+        }
+
+        let result = self.validate(url, &challenge.url).await;
+
+        match *ACME_CHALLENGE {
+            ChallengeType::Http01 => { HTTP_CHALLENGES.lock().unwrap().remove(&challenge.token); },
+            ChallengeType::Dns01 => {
+                if let Err(e) = run_dns_hook("clear", &record, &dns_value).await {
+                    log::warn!("failed to clear ACME challenge record {}: {:#}", record, e);
+                }
+            },
+        }
+        result
+    }
+
+    /// Tells the CA a challenge is ready, and waits for it to check.
+    async fn validate(&mut self, authorization_url: &str, challenge_url: &str) -> Result<()> {
+        self.post(challenge_url, Some(&serde_json::json!({}))).await?;
+        let deadline = std::time::Instant::now() + VALIDATION_TIMEOUT;
+        loop {
+            let authorization: serde_json::Value = self.get(authorization_url).await?;
+            match authorization["status"].as_str() {
+                Some("valid") => return Ok(()),
+                Some("pending") | Some("processing") if std::time::Instant::now() < deadline => {},
+                _ => {
+                    let error = authorization["challenges"].as_array()
+                        .and_then(|challenges| {
+                            challenges.iter().find_map(|challenge| challenge["error"]["detail"].as_str())
+                        })
+                        .unwrap_or("no reason given");
+                    bail!("ACME server didn't validate {}: {}", authorization["identifier"]["value"], error);
+                },
+            }
+            actix_web::rt::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Orders a certificate for `names`, returning its chain in PEM and its private key.
+    async fn order(&mut self, names: &[String]) -> Result<(Vec<u8>, PKey<Private>)> {
+        let identifiers: Vec<_> = names.iter()
+            .map(|name| serde_json::json!({ "type": "dns", "value": name }))
+            .collect();
+        let new_order = self.directory.new_order.clone();
+        let res = self.post(&new_order, Some(&serde_json::json!({ "identifiers": identifiers }))).await?;
+        let order_url = location(&res)?;
+        let order: Order = res.json().await?;
+        for authorization in &order.authorizations {
+            self.authorize(authorization).await?;
+        }
+
+        let key = new_key()?;
+        let mut csr = X509Req::builder()?;
+        let mut san = SubjectAlternativeName::new();
+        for name in names {
+            san.dns(name);
+        }
+        let mut extensions = Stack::new()?;
+        extensions.push(san.build(&csr.x509v3_context(None))?)?;
+        csr.add_extensions(&extensions)?;
+        csr.set_pubkey(&key)?;
+        csr.sign(&key, MessageDigest::sha256())?;
+        let csr = csr.build().to_der()?;
+        self.post(&order.finalize, Some(&serde_json::json!({ "csr": b64(&csr) }))).await?;
+
+        let deadline = std::time::Instant::now() + VALIDATION_TIMEOUT;
+        let certificate_url = loop {
+            let order: Order = self.get(&order_url).await?;
+            match (order.status.as_str(), order.certificate) {
+                ("valid", Some(url)) => break url,
+                ("pending" | "ready" | "processing" | "valid", _) if std::time::Instant::now() < deadline => {},
+                (status, _) => bail!("ACME order ended up {} rather than issued", status),
+            }
+            actix_web::rt::time::sleep(POLL_INTERVAL).await;
+        };
+        let chain = self.post(&certificate_url, None).await?.bytes().await?;
+        Ok((chain.to_vec(), key))
+    }
+}
+
+fn replay_nonce(res: &reqwest::Response) -> Option<String> {
+    res.headers().get("replay-nonce")?.to_str().ok().map(str::to_owned)
+}
+
+fn location(res: &reqwest::Response) -> Result<String> {
+    res.headers().get(header::LOCATION)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+        .ok_or_else(|| anyhow!("ACME server sent no Location"))
+}
+
+async fn run_dns_hook(action: &str, record: &str, value: &str) -> Result<()> {
+    let hook = ACME_DNS_HOOK.as_ref().ok_or_else(|| anyhow!("NUCLEUS_ACME_DNS_HOOK is not set"))?;
+    let status = reaper::status(tokio::process::Command::new(hook).args([action, record, value])).await?;
+    if !status.success() {
+        bail!("ACME DNS hook failed to {} {}: {}", action, record, status);
+    }
+    Ok(())
+}
+
+/// The hostnames a certificate covers, and whether it has at least `RENEW_BEFORE_DAYS` left.
+fn inspect(chain: &[u8]) -> Result<(BTreeSet<String>, bool)> {
+    let cert = X509::from_pem(chain)?;
+    let names = cert.subject_alt_names()
+        .map(|names| names.iter().filter_map(|name| name.dnsname().map(str::to_owned)).collect())
+        .unwrap_or_default();
+    let fresh = cert.not_after() > Asn1Time::days_from_now(RENEW_BEFORE_DAYS)?;
+    Ok((names, fresh))
+}
+
+fn install(chain: &[u8], key_pem: &[u8]) -> Result<()> {
+    let identity = native_tls::Identity::from_pkcs8(chain, key_pem)?;
+    let acceptor = native_tls::TlsAcceptor::new(identity)?;
+    *ACCEPTOR.write().unwrap() = Some(acceptor.into());
+    Ok(())
+}
+
+/// Makes sure the certificate served covers `names` and isn't about to expire, ordering a new one if need be. The
+/// certificate and its key are kept in the harbor, so that a restart serves them again straight away.
+pub async fn ensure_certificate(directory_url: &Url, names: &BTreeSet<String>) -> Result<()> {
+    if names.is_empty() {
+        return Ok(());
+    }
+    let names: BTreeSet<String> = if names.len() > MAX_NAMES {
+        log::warn!("{} hostnames need certificates, but only the first {} fit on one", names.len(), MAX_NAMES);
+        names.iter().take(MAX_NAMES).cloned().collect()
+    } else {
+        names.clone()
+    };
+    let dir = acme_path().await?;
+    let (chain_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+
+    if let (Ok(chain), Ok(key)) = (fs::read(&chain_path).await, fs::read(&key_path).await) {
+        let current = inspect(&chain).and_then(|(covered, fresh)| {
+            if ACCEPTOR.read().unwrap().is_none() {
+                install(&chain, &key)?;
+            }
+            Ok(fresh && names.is_subset(&covered))
+        });
+        match current {
+            Ok(true) => return Ok(()),
+            Ok(false) => {},
+            Err(e) => log::warn!("replacing unusable certificate {}: {:#}", chain_path.to_string_lossy(), e),
+        }
+    }
+
+    let names: Vec<String> = names.into_iter().collect();
+    log::info!("ordering a certificate for {}", names.join(", "));
+    let mut account = Account::open(directory_url).await?;
+    let (chain, key) = account.order(&names).await?;
+    let key = key.private_key_to_pem_pkcs8()?;
+    install(&chain, &key)?;
+    write_private(&key_path, &key).await?;
+    fs::write(&chain_path, &chain).await?;
+    log::info!("installed a new certificate for {} hostnames", names.len());
+    Ok(())
+}
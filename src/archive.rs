@@ -1,39 +1,236 @@
 #[allow(unused_imports)] use crate::prelude::*;
 
+use actix_web::web::{Bytes, BytesMut};
 use async_std::path::PathBuf as APathBuf;
-use std::os::unix::prelude::OsStrExt;
 use std::path::Path as SPath;
-use std::str;
+use std::process::Stdio;
 use libarchive::archive::{ExtractOption, ExtractOptions, ReadCompression, ReadFilter, ReadFormat};
 use libarchive::{reader, writer};
-use tokio::task;
+use tokio::io::AsyncReadExt;
+use tokio::{process, task};
+
+use crate::reaper;
+
+const CREATE_STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How many entries may have been extracted beyond the last progress report when an extraction is interrupted. When
+/// resuming, any existing files for this many entries past the reported count are removed before being re-extracted.
+pub const EXTRACT_PROGRESS_INTERVAL: usize = 64;
+
+/// An extraction that failed because of the archive itself rather than the host.
+#[derive(Debug)]
+pub enum ExtractError {
+    /// The archive couldn't be read to the end, most often because it is truncated.
+    Unreadable(String),
+    /// Entries whose paths would land outside the destination, being absolute or containing `..`. They are skipped,
+    /// and the extraction fails once every entry has been read, so that all of them are reported.
+    DisallowedPaths(Vec<String>),
+}
+
+impl std::fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtractError::Unreadable(detail) => write!(f, "failed to read archive: {}", detail),
+            ExtractError::DisallowedPaths(paths) => {
+                write!(f, "archive contains disallowed paths: {}", paths.join(", "))
+            },
+        }
+    }
+}
+
+impl StdError for ExtractError {}
+
+fn is_disallowed_path(path: &str) -> bool {
+    let path = SPath::new(path);
+    path.is_absolute() || path.components().any(|component| component == std::path::Component::ParentDir)
+}
+
+/// Extracts `src_path` into `dst_path` entry by entry, calling `on_progress` with the number of entries completed every
+/// `EXTRACT_PROGRESS_INTERVAL` entries and once at the end. Returns the total number of entries.
+///
+/// If `resume_from` is given, that many entries are assumed to have been extracted by an earlier, interrupted call and
+/// are skipped. Because `options` normally refuse to overwrite, whatever exists of the next `EXTRACT_PROGRESS_INTERVAL`
+/// entries is removed before they are extracted again.
+pub fn extract_file_sync<F>(
+    src_path: &SPath,
+    dst_path: &SPath,
+    options: &ExtractOptions,
+    resume_from: Option<usize>,
+    mut on_progress: F,
+) -> Result<usize>
+    where F: FnMut(usize)
+{
+    use libarchive::archive::{Entry, FileType, Handle};
+    use libarchive::reader::ReaderEntry;
+    use libarchive3_sys::ffi;
 
-pub fn extract_file_sync(src_path: &SPath, dst_path: &SPath, options: &ExtractOptions) -> Result<usize> {
     let mut src_builder = reader::Builder::new();
     src_builder.support_compression(ReadCompression::All)?;
     src_builder.support_filter(ReadFilter::All)?;
     src_builder.support_format(ReadFormat::All)?;
 
-    let mut src = src_builder.open_file(src_path)?;
+    let src = src_builder.open_file(src_path).map_err(|e| ExtractError::Unreadable(e.to_string()))?;
 
     let dst = writer::Disk::new();
     dst.set_options(options)?;
 
-    // The libarchive crate uses &str in the function signature, and then immediately converts it back to an OsStr.
-    // No reason to reject destination paths that happen to not be valid utf8 here.
-    let dst_path = unsafe {
-        str::from_utf8_unchecked(dst_path.as_os_str().as_bytes())
-    };
+    // The libarchive crate only exposes extracting a whole archive in one call, so drive libarchive directly to get
+    // at each entry.
+    let write_failed = |dst: &writer::Disk| anyhow!("failed to extract archive: {}", dst.err_msg());
+    let skip = resume_from.unwrap_or(0);
+    let mut count = 0;
+    let mut disallowed_paths = Vec::new();
+    loop {
+        let mut raw_entry = std::ptr::null_mut();
+        match unsafe { ffi::archive_read_next_header(src.handle(), &mut raw_entry) } {
+            ffi::ARCHIVE_EOF => break,
+            ffi::ARCHIVE_OK | ffi::ARCHIVE_WARN => {},
+            // Most notably, a truncated archive.
+            _ => bail!(ExtractError::Unreadable(src.err_msg())),
+        }
+        // The entry is owned by the reader, which reuses it for the next header.
+        let mut entry = ReaderEntry::new(raw_entry);
+        count += 1;
+        if count <= skip {
+            continue;
+        }
+
+        let disallowed = is_disallowed_path(entry.pathname())
+            || entry.hardlink().is_some_and(is_disallowed_path);
+        if disallowed {
+            disallowed_paths.push(entry.pathname().to_owned());
+            continue;
+        }
 
-    Ok(dst.write(&mut src, Some(dst_path))?)
+        let path = dst_path.join(entry.pathname());
+        if resume_from.is_some() && count <= skip + EXTRACT_PROGRESS_INTERVAL {
+            if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+                if !metadata.is_dir() {
+                    std::fs::remove_file(&path)?;
+                }
+            }
+        }
+        entry.set_pathname(&path);
+        if let Some(hardlink) = entry.hardlink() {
+            let hardlink = dst_path.join(hardlink);
+            entry.set_link(&hardlink);
+        }
+        let has_data = entry.size() > 0 && matches!(entry.filetype(), FileType::RegularFile);
+
+        unsafe {
+            if ffi::archive_write_header(dst.handle(), entry.entry()) != ffi::ARCHIVE_OK {
+                return Err(write_failed(&dst));
+            }
+            if has_data {
+                let mut buf = std::ptr::null();
+                let mut size = 0;
+                let mut offset = 0;
+                loop {
+                    match ffi::archive_read_data_block(src.handle(), &mut buf, &mut size, &mut offset) {
+                        ffi::ARCHIVE_EOF => break,
+                        ffi::ARCHIVE_OK => {
+                            if ffi::archive_write_data_block(dst.handle(), buf, size, offset) < 0 {
+                                return Err(write_failed(&dst));
+                            }
+                        },
+                        _ => bail!(ExtractError::Unreadable(src.err_msg())),
+                    }
+                }
+            }
+            if ffi::archive_write_finish_entry(dst.handle()) != ffi::ARCHIVE_OK {
+                return Err(write_failed(&dst));
+            }
+        }
+
+        if count % EXTRACT_PROGRESS_INTERVAL == 0 {
+            on_progress(count);
+        }
+    }
+
+    if !disallowed_paths.is_empty() {
+        bail!(ExtractError::DisallowedPaths(disallowed_paths));
+    }
+    on_progress(count);
+    Ok(count)
 }
 
-pub async fn extract_file(src_path: APathBuf, dst_path: APathBuf, options: ExtractOptions) -> Result<usize> {
+pub async fn extract_file<F>(
+    src_path: APathBuf,
+    dst_path: APathBuf,
+    options: ExtractOptions,
+    resume_from: Option<usize>,
+    on_progress: F,
+) -> Result<usize>
+    where F: FnMut(usize) + Send + 'static
+{
     task::spawn_blocking(move || {
-        extract_file_sync(src_path.as_ref(), dst_path.as_ref(), &options)
+        extract_file_sync(src_path.as_ref(), dst_path.as_ref(), &options, resume_from, on_progress)
     }).await?
 }
 
+fn split_path(path: &SPath) -> Result<(std::path::PathBuf, &std::ffi::OsStr)> {
+    let parent = path.parent()
+        .ok_or_else(|| anyhow!("cannot archive a path with no parent: {}", path.to_string_lossy()))?;
+    let name = path.file_name()
+        .ok_or_else(|| anyhow!("cannot archive a path with no file name: {}", path.to_string_lossy()))?;
+    // Each --directory is relative to the previous one, so make them all absolute.
+    Ok((std::env::current_dir()?.join(parent), name))
+}
+
+/// Streams a gzipped tarball of `src_path` without buffering it on disk or in memory. The archive's root entry is the
+/// final component of `src_path`, or `root_name` if given, and each of `extra_files` is added at the top level of the
+/// archive alongside it.
+///
+/// The libarchive crate only knows how to write archives to a named file, so this shells out to `tar` and forwards its
+/// stdout. A nonzero exit status from `tar` is surfaced as the final item of the stream.
+pub fn create_stream(
+    src_path: &SPath,
+    root_name: Option<&str>,
+    extra_files: &[&SPath],
+) -> Result<impl Stream<Item = Result<Bytes>>> {
+    let (parent, root) = split_path(src_path)?;
+
+    let mut cmd = process::Command::new("tar");
+    cmd.arg("--create")
+        .arg("--gzip")
+        .arg("--file").arg("-");
+    if let Some(root_name) = root_name {
+        // Both names end up in a sed expression, so keep them to characters that mean nothing to sed.
+        let plain = |name: &str| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        let root = root.to_string_lossy();
+        if !plain(&root) || !plain(root_name) {
+            bail!("cannot rename archive root {} to {}", root, root_name);
+        }
+        // Rename the root and everything under it, but leave symlink targets alone.
+        cmd.arg(format!("--transform=s,^{}\\(/\\|$\\),{}\\1,S", root, root_name));
+    }
+    cmd.arg("--directory").arg(&parent).arg(root);
+    for file in extra_files {
+        let (parent, name) = split_path(file)?;
+        cmd.arg("--directory").arg(parent).arg(name);
+    }
+
+    let mut child = reaper::spawn(cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true))?;
+    let tracked = reaper::Tracked::of(&child);
+    let stdout = child.stdout.take().ok_or_else(|| anyhow!("tar stdout was not captured"))?;
+
+    Ok(stream::try_unfold((child, tracked, stdout), |(mut child, tracked, mut stdout)| async move {
+        let mut buf = BytesMut::with_capacity(CREATE_STREAM_CHUNK_SIZE);
+        if stdout.read_buf(&mut buf).await? > 0 {
+            return Ok(Some((buf.freeze(), (child, tracked, stdout))));
+        }
+
+        let status = child.wait().await?;
+        if !status.success() {
+            bail!("tar exited unsuccessfully: {}", status);
+        }
+        Ok(None)
+    }))
+}
+
 pub fn safe_extract_options() -> ExtractOptions {
     use ExtractOption::*;
 
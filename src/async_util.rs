@@ -8,12 +8,17 @@ use pin_project_lite::pin_project;
 use std::task::Poll;
 use sha2::Digest;
 
+use crate::error::PayloadTooLargeError;
+
 pub trait MyStreamExt : Stream {
     fn into_checksum_verify<D: Digest>(
         self,
         checksum: GenericArray<u8, D::OutputSize>
     ) -> ChecksumVerifyStream<Self, D>
         where Self: Sized;
+
+    fn into_bounded(self, limit: u64) -> BoundedStream<Self>
+        where Self: Sized;
 }
 
 impl<S: Stream> MyStreamExt for S {
@@ -25,6 +30,12 @@ impl<S: Stream> MyStreamExt for S {
     {
         ChecksumVerifyStream::new(self, checksum)
     }
+
+    fn into_bounded(self, limit: u64) -> BoundedStream<Self>
+        where Self: Sized
+    {
+        BoundedStream { src: self, limit, seen: 0, exceeded: false }
+    }
 }
 
 pub trait IntoResultAsRefBytes {
@@ -105,7 +116,7 @@ impl<A: IntoResultAsRefBytes, Src: Unpin + Stream<Item = A>, D: Digest> Stream f
             },
             None => {
                 let actual_checksum = this.digest.take().unwrap().finalize();
-                if &actual_checksum[..] == &this.checksum[..] {
+                if actual_checksum[..] == this.checksum[..] {
                     None
                 } else {
                     Some(Err(anyhow!("checksum validation failed")))
@@ -117,4 +128,104 @@ impl<A: IntoResultAsRefBytes, Src: Unpin + Stream<Item = A>, D: Digest> Stream f
     fn size_hint(&self) -> (usize, Option<usize>) {
         self.src.size_hint()
     }
-}
\ No newline at end of file
+}
+
+pin_project! {
+    /// Passes chunks through until more than `limit` bytes have gone by, then yields a `PayloadTooLargeError` and ends
+    /// without polling the source again, so that a consumer spooling it to disk stops at the limit.
+    #[derive(Debug)]
+    #[must_use = "streams do nothing unless polled"]
+    pub struct BoundedStream<Src> {
+        #[pin]
+        src: Src,
+        limit: u64,
+        seen: u64,
+        exceeded: bool,
+    }
+}
+
+impl<A: IntoResultAsRefBytes, Src: Stream<Item = A>> Stream for BoundedStream<Src> {
+    type Item = Result<A::Item>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>
+    ) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if *this.exceeded {
+            return Poll::Ready(None);
+        }
+
+        Poll::Ready(match ready!(this.src.poll_next(cx)) {
+            None => None,
+            Some(b) => match b.into_result_asref_bytes() {
+                Err(e) => Some(Err(e)),
+                Ok(bytes) => {
+                    *this.seen += bytes.as_ref().len() as u64;
+                    if *this.seen > *this.limit {
+                        *this.exceeded = true;
+                        Some(Err(PayloadTooLargeError { limit: *this.limit }.into()))
+                    } else {
+                        Some(Ok(bytes))
+                    }
+                },
+            },
+        })
+    }
+}
+
+/// Large enough that streaming a multi-gigabyte export doesn't take a read and a write per few kilobytes.
+const READ_STREAM_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Adapts an `AsyncRead` into a stream of byte chunks, e.g. for use as a streaming HTTP response body.
+pub fn read_stream<R: AsyncRead + Unpin>(reader: R) -> impl Stream<Item = Result<Bytes>> {
+    stream::try_unfold(reader, |mut reader| async move {
+        let mut buf = vec![0; READ_STREAM_CHUNK_SIZE];
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.truncate(n);
+        Ok(Some((Bytes::from(buf), reader)))
+    })
+}
+
+/// Paces `src` so that it yields no more than `limit()` bytes per second on average, checking the limit before each
+/// chunk so that it can change while the stream is consumed, e.g. with the time of day. None means no limit.
+pub fn throttle<S, L>(src: S, limit: L) -> impl Stream<Item = Result<Bytes>>
+    where S: Stream<Item = Result<Bytes>>,
+          L: Fn() -> Option<u64>,
+{
+    use std::time::{Duration, Instant};
+
+    // `next_send` is when the next chunk may go out, given how much was sent before it.
+    stream::unfold((Box::pin(src), limit, Instant::now()), |(mut src, limit, mut next_send)| async move {
+        let item = src.next().await?;
+        if let (Ok(bytes), Some(limit)) = (&item, limit()) {
+            let now = Instant::now();
+            if next_send > now {
+                actix_web::rt::time::sleep(next_send - now).await;
+            }
+            next_send = next_send.max(now) + Duration::from_secs_f64(bytes.len() as f64 / limit.max(1) as f64);
+        }
+        Some((item, (src, limit, next_send)))
+    })
+}
+
+/// Runs `f` once `src` has been fully consumed without error. Streams that are dropped early (e.g. the client
+/// disconnected) or fail never run it.
+pub fn on_success<S, A, F, Fut>(src: S, f: F) -> impl Stream<Item = Result<A>>
+    where S: Stream<Item = Result<A>>,
+          F: FnOnce() -> Fut,
+          Fut: Future<Output = ()>,
+{
+    let failed = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let failed_inner = failed.clone();
+    src.inspect(move |item| if item.is_err() { failed_inner.store(true, std::sync::atomic::Ordering::Relaxed) })
+        .chain(stream::once(async move {
+            if !failed.load(std::sync::atomic::Ordering::Relaxed) {
+                f().await;
+            }
+            None
+        }).filter_map(future::ready))
+}
@@ -0,0 +1,56 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use async_std::fs::OpenOptions;
+use time::OffsetDateTime;
+
+use crate::ship::HARBOR;
+
+/// Something the orchestrator did to a pier on its own, rather than because it was asked to over the API.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+// The variant names are what the log records, and more actions will join them.
+#[allow(clippy::enum_variant_names)]
+pub enum Action {
+    ExpiryWarned,
+    ExpiryStopped,
+    ExpiryExported,
+    ExpiryTrashed,
+}
+
+/// One line of the harbor's audit log.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Record {
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+    pub pier: String,
+    pub action: Action,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub detail: serde_json::Value,
+}
+
+/// Appends a record to the audit log. Records are single lines of JSON, so that the log can be followed with standard
+/// tools and a record cut short by a crash spoils only itself.
+pub async fn record(pier: &str, action: Action, detail: serde_json::Value) -> Result<()> {
+    let record = Record { at: OffsetDateTime::now_utc(), pier: pier.to_owned(), action, detail };
+    let mut line = serde_json::to_vec(&record)?;
+    line.push(b'\n');
+    let mut log = OpenOptions::new().create(true).append(true).open(HARBOR.audit_log_path()).await?;
+    log.write_all(&line).await?;
+    log.flush().await?;
+    Ok(())
+}
+
+/// The audit log's records, oldest first, optionally only those concerning one pier. Lines that can't be parsed are
+/// skipped.
+pub async fn read(pier: Option<&str>) -> Result<Vec<Record>> {
+    let log = match async_std::fs::read_to_string(HARBOR.audit_log_path()).await {
+        Ok(log) => log,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(log.lines()
+        .filter_map(|line| serde_json::from_str::<Record>(line).ok())
+        .filter(|record| pier.is_none_or(|pier| record.pier == pier))
+        .collect())
+}
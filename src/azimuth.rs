@@ -0,0 +1,131 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use openssl::pkey::{Id, PKey};
+use reqwest::Url;
+use std::env;
+use std::fmt::{self, Display};
+use std::time::Duration;
+
+use crate::keyfile;
+use crate::patp;
+
+lazy_static! {
+    /// The JSON-RPC endpoint of an Azimuth roller, such as `https://roller.urbit.org/v1/roller`, to look ships' keys up
+    /// on before booting them. Rollers serve both layer 1 and layer 2 points. Unset skips the checks.
+    pub static ref AZIMUTH_RPC: Option<Url> = env::var_os("NUCLEUS_AZIMUTH_RPC")
+        .map(|s| s.to_str().unwrap().parse::<Url>().unwrap());
+}
+
+const AZIMUTH_RPC_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// A ship's networking state on Azimuth.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Point {
+    /// How many times its networking keys have been set.
+    pub life: u64,
+    /// How many times it has been breached.
+    pub rift: u64,
+    /// Its ed25519 public keys for signing and encryption, as urbit stores them.
+    pub auth_key: [u8; 32],
+    pub crypt_key: [u8; 32],
+}
+
+/// A keyfile doesn't hold the keys Azimuth has for its ship, so a ship booted from it couldn't be heard on the network.
+#[derive(Debug)]
+pub enum StaleKeyfileError {
+    WrongLife { ship: String, keyfile: u64, azimuth: u64 },
+    WrongKeys { ship: String, life: u64 },
+}
+
+impl Display for StaleKeyfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StaleKeyfileError::WrongLife { ship, keyfile, azimuth } => {
+                write!(f, "keyfile is for life {} of ~{}, but its keys on Azimuth are for life {}", keyfile, ship,
+                    azimuth)
+            },
+            StaleKeyfileError::WrongKeys { ship, life } => {
+                write!(f, "keyfile's keys don't match those of ~{} on Azimuth for life {}", ship, life)
+            },
+        }
+    }
+}
+
+impl StdError for StaleKeyfileError {}
+
+/// Parses a JSON number or a string of decimal digits; the roller sends lifes and rifts as the latter.
+fn number(value: &serde_json::Value) -> Option<u64> {
+    value.as_u64().or_else(|| value.as_str()?.parse().ok())
+}
+
+/// Parses a 32-byte key in hex. Azimuth stores keys as big-endian words but urbit as little-endian atoms, so the
+/// bytes are reversed into the order urbit, and ed25519, uses.
+fn key(value: &serde_json::Value) -> Option<[u8; 32]> {
+    let hex = value.as_str()?.trim_start_matches("0x");
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().rev().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(key)
+}
+
+/// Looks up a galaxy, star or planet on Azimuth. Moons and comets aren't on it.
+pub async fn get_point(rpc: &Url, ship: &str) -> Result<Point> {
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": "nucleus",
+        "method": "getPoint",
+        "params": { "ship": ship },
+    });
+    let response: serde_json::Value = reqwest::Client::new()
+        .post(rpc.clone())
+        .timeout(AZIMUTH_RPC_TIMEOUT)
+        .json(&request)
+        .send().await?
+        .error_for_status()?
+        .json().await?;
+    if let Some(error) = response.get("error") {
+        bail!("Azimuth RPC failed to look up {}: {}", ship, error);
+    }
+    let network = &response["result"]["network"];
+    let keys = &network["keys"];
+    (|| Some(Point {
+        life: number(&keys["life"])?,
+        rift: number(&network["rift"])?,
+        auth_key: key(&keys["auth"])?,
+        crypt_key: key(&keys["crypt"])?,
+    }))().ok_or_else(|| anyhow!("Azimuth RPC sent an unexpected point for {}: {}", ship, response))
+}
+
+/// The ed25519 public key for a 32-byte seed.
+fn public_key(seed: &[u8]) -> Result<[u8; 32]> {
+    let public = PKey::private_key_from_raw_bytes(seed, Id::ED25519)?.raw_public_key()?;
+    Ok(public.as_slice().try_into()?)
+}
+
+/// Checks that a keyfile holds the keys Azimuth has for its ship at its current life.
+pub fn check_keyfile(contents: &str, point: &Point) -> Result<()> {
+    let keyfile = keyfile::parse(contents)?;
+    let ship = patp::render(keyfile.ship);
+    if keyfile.life != point.life {
+        bail!(StaleKeyfileError::WrongLife { ship, keyfile: keyfile.life, azimuth: point.life });
+    }
+    // A ring is the byte 'B' followed by the signing seed and the encryption seed.
+    let mut ring = keyfile.ring;
+    ring.resize(65, 0);
+    if ring[0] != b'B' {
+        bail!(keyfile::InvalidKeyfileError::Malformed("networking key is not a ring".to_owned()));
+    }
+    if public_key(&ring[1..33])? != point.auth_key || public_key(&ring[33..65])? != point.crypt_key {
+        bail!(StaleKeyfileError::WrongKeys { ship, life: point.life });
+    }
+    Ok(())
+}
+
+/// Whether a ship is on Azimuth at all: galaxies, stars and planets are, moons and comets are not.
+pub fn on_azimuth(name: &str) -> bool {
+    patp::parse(name).is_ok_and(|ship| ship <= u32::MAX as u128)
+}
@@ -0,0 +1,650 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use async_std::sync::RwLock;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use time::OffsetDateTime;
+
+use crate::patp;
+use crate::ship::HARBOR;
+use crate::util::parse_duration;
+
+lazy_static! {
+    /// How many snapshots the backup store keeps per ship. Older ones are pruned whenever a new one is taken.
+    pub static ref BACKUP_STORE_KEEP: usize = env::var_os("NUCLEUS_BACKUP_STORE_KEEP")
+        .map(|s| s.to_str().unwrap().parse().unwrap())
+        .unwrap_or(7);
+
+    /// How often to prove a backup restorable, by restoring the newest snapshot of a ship picked at random and booting
+    /// it with local networking until it accepts a login. Unset disables periodic verification.
+    pub static ref BACKUP_VERIFY_INTERVAL: Option<Duration> = env::var_os("NUCLEUS_BACKUP_VERIFY_INTERVAL")
+        .map(|s| parse_duration(s.to_str().unwrap()).unwrap());
+
+    /// Random-looking values for each byte, from which the rolling hash that finds chunk boundaries is built. Changing
+    /// them would move every boundary, so that nothing stored before the change deduplicates against anything after.
+    static ref GEAR: [u64; 256] = {
+        let mut gear = [0; 256];
+        let mut state: u64 = 0;
+        for value in gear.iter_mut() {
+            // splitmix64
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            *value = z ^ (z >> 31);
+        }
+        gear
+    };
+
+    /// Held shared while snapshots are taken or restored, and exclusively while garbage is collected, so that a chunk
+    /// stored by a snapshot whose manifest isn't written yet is never mistaken for garbage.
+    static ref STORE_LOCK: RwLock<()> = RwLock::new(());
+}
+
+const MIN_CHUNK_SIZE: usize = 64 * 1024;
+const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+/// A boundary falls where the top 18 bits of the rolling hash are zero, for chunks of about 256K past the minimum.
+const BOUNDARY_MASK: u64 = !0 << (64 - 18);
+
+/// One file, directory or symlink of a pier, by its path relative to the pier.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SnapshotEntry {
+    #[serde(rename_all = "camelCase")]
+    Dir { path: String, mode: u32 },
+    /// A regular file, as the SHA-256 hashes of its chunks in order.
+    #[serde(rename_all = "camelCase")]
+    File { path: String, mode: u32, size: u64, chunks: Vec<String> },
+    #[serde(rename_all = "camelCase")]
+    Symlink { path: String, target: String },
+}
+
+/// The manifest of one backup of a pier, enough to rebuild the pier from the chunk store.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    pub ship: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub taken_at: OffsetDateTime,
+    /// Parents always come before their children.
+    pub entries: Vec<SnapshotEntry>,
+}
+
+impl Snapshot {
+    fn size(&self) -> u64 {
+        self.entries.iter()
+            .map(|entry| match entry {
+                SnapshotEntry::File { size, .. } => *size,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+/// The outcome of restoring a snapshot into a throwaway pier and booting it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Verification {
+    #[serde(with = "time::serde::rfc3339")]
+    pub verified_at: OffsetDateTime,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A snapshot as listed, without its entries.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotSummary {
+    pub id: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub taken_at: OffsetDateTime,
+    /// The total size of the pier's files, before deduplication.
+    pub size: u64,
+    /// The latest attempt to restore the snapshot, if it has been verified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+}
+
+/// What taking a snapshot cost.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotReport {
+    pub snapshot: String,
+    pub size: u64,
+    pub chunks: usize,
+    /// Chunks that weren't already in the store, and their total size: what the snapshot actually added to the disk.
+    pub new_chunks: usize,
+    pub new_bytes: u64,
+    /// Older snapshots of the same ship removed to stay within `BACKUP_STORE_KEEP`.
+    pub pruned: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GarbageReport {
+    pub chunks_removed: usize,
+    pub bytes_reclaimed: u64,
+}
+
+async fn store_path() -> Result<PathBuf> {
+    Ok(HARBOR.backups_path().await?.into())
+}
+
+fn chunk_path(store: &Path, hash: &str) -> PathBuf {
+    store.join("chunks").join(&hash[..2]).join(hash)
+}
+
+fn snapshots_path(store: &Path, ship: &str) -> PathBuf {
+    store.join("snapshots").join(ship)
+}
+
+fn verification_path(store: &Path, ship: &str, id: &str) -> PathBuf {
+    store.join("verifications").join(ship).join(format!("{}.json", id))
+}
+
+fn snapshot_id(at: OffsetDateTime) -> String {
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        at.year(), at.month() as u8, at.day(), at.hour(), at.minute(), at.second(),
+    )
+}
+
+fn is_valid_snapshot_id(id: &str) -> bool {
+    id.len() == 16 && id.chars().all(|c| c.is_ascii_digit() || c == 'T' || c == 'Z')
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Writes `contents` beside `path` under a name no other writer uses, then renames it into place.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension(format!("{}.tmp", Uuid::new_v4().simple()));
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Splits `src` into content-defined chunks with a gear hash, calling `on_chunk` with each in order. Boundaries depend
+/// only on the bytes around them, so an insertion early in a file moves only the boundaries near it.
+fn split<R: Read>(mut src: R, mut on_chunk: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+    let mut chunk = Vec::with_capacity(MAX_CHUNK_SIZE);
+    let mut buf = vec![0; 64 * 1024];
+    let mut hash: u64 = 0;
+    loop {
+        let read = match src.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for byte in &buf[..read] {
+            chunk.push(*byte);
+            hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+            if chunk.len() >= MAX_CHUNK_SIZE || (chunk.len() >= MIN_CHUNK_SIZE && hash & BOUNDARY_MASK == 0) {
+                on_chunk(&chunk)?;
+                chunk.clear();
+                hash = 0;
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        on_chunk(&chunk)?;
+    }
+    Ok(())
+}
+
+fn relative_path(pier_path: &Path, path: &Path) -> Result<String> {
+    let relative = path.strip_prefix(pier_path)?;
+    relative.to_str()
+        .map(str::to_owned)
+        .ok_or_else(|| anyhow!("pier path is not valid UTF-8: {}", path.to_string_lossy()))
+}
+
+/// Stores every file under `dir` that isn't already stored, appending their entries to `entries`.
+fn snapshot_dir(
+    store: &Path,
+    pier_path: &Path,
+    dir: &Path,
+    entries: &mut Vec<SnapshotEntry>,
+    report: &mut SnapshotReport,
+) -> Result<()> {
+    let mut children = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        let path = child.path();
+        let metadata = fs::symlink_metadata(&path)?;
+        let mode = metadata.permissions().mode() & 0o7777;
+        if metadata.file_type().is_symlink() {
+            let target = fs::read_link(&path)?;
+            let target = target.to_str()
+                .ok_or_else(|| anyhow!("symlink target is not valid UTF-8: {}", target.to_string_lossy()))?;
+            entries.push(SnapshotEntry::Symlink { path: relative_path(pier_path, &path)?, target: target.to_owned() });
+        } else if metadata.is_dir() {
+            entries.push(SnapshotEntry::Dir { path: relative_path(pier_path, &path)?, mode });
+            snapshot_dir(store, pier_path, &path, entries, report)?;
+        } else if metadata.is_file() {
+            let mut chunks = Vec::new();
+            let mut size = 0;
+            split(fs::File::open(&path)?, |chunk| {
+                let hash = hex(&Sha256::digest(chunk));
+                let dst = chunk_path(store, &hash);
+                if !dst.exists() {
+                    fs::create_dir_all(dst.parent().unwrap())?;
+                    write_atomically(&dst, chunk)?;
+                    report.new_chunks += 1;
+                    report.new_bytes += chunk.len() as u64;
+                }
+                size += chunk.len() as u64;
+                chunks.push(hash);
+                Ok(())
+            })?;
+            report.chunks += chunks.len();
+            report.size += size;
+            entries.push(SnapshotEntry::File { path: relative_path(pier_path, &path)?, mode, size, chunks });
+        }
+        // Sockets and fifos, such as the runtime's control socket, only mean anything to a running process.
+    }
+    Ok(())
+}
+
+fn read_snapshot(path: &Path) -> Result<Snapshot> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Ids of the ship's snapshots, oldest first.
+fn snapshot_ids(store: &Path, ship: &str) -> Result<Vec<String>> {
+    let dir = snapshots_path(store, ship);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let name = entry?.file_name();
+        if let Some(id) = name.to_str().and_then(|name| name.strip_suffix(".json")) {
+            ids.push(id.to_owned());
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+/// Stores a snapshot of the pier directory at `pier_path`, then, if `prune`, prunes the ship's oldest snapshots beyond
+/// `BACKUP_STORE_KEEP`. The ship must not be running.
+pub async fn snapshot(ship: &str, pier_path: &Path, prune: bool) -> Result<SnapshotReport> {
+    let ship = patp::render(patp::parse(ship)?);
+    let store = store_path().await?;
+    let pier_path = pier_path.to_owned();
+    let report = {
+        let _guard = STORE_LOCK.read().await;
+        tokio::task::spawn_blocking(move || -> Result<SnapshotReport> {
+            let taken_at = OffsetDateTime::now_utc();
+            let id = snapshot_id(taken_at);
+            let manifest_path = snapshots_path(&store, &ship).join(format!("{}.json", id));
+            if manifest_path.exists() {
+                bail!("a snapshot of {} was already taken at {}", ship, id);
+            }
+
+            let mut report = SnapshotReport { snapshot: id, ..SnapshotReport::default() };
+            let mut entries = Vec::new();
+            snapshot_dir(&store, &pier_path, &pier_path, &mut entries, &mut report)?;
+            let snapshot = Snapshot { ship: ship.clone(), taken_at, entries };
+            fs::create_dir_all(manifest_path.parent().unwrap())?;
+            write_atomically(&manifest_path, &serde_json::to_vec(&snapshot)?)?;
+
+            let ids = if prune { snapshot_ids(&store, &ship)? } else { Vec::new() };
+            for id in &ids[..ids.len().saturating_sub(*BACKUP_STORE_KEEP)] {
+                fs::remove_file(snapshots_path(&store, &ship).join(format!("{}.json", id)))?;
+                match fs::remove_file(verification_path(&store, &ship, id)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {},
+                }
+                report.pruned.push(id.clone());
+            }
+            Ok(report)
+        }).await??
+    };
+
+    if !report.pruned.is_empty() {
+        let garbage = collect_garbage().await?;
+        log::info!(
+            "pruned {} old snapshots, reclaiming {} bytes in {} chunks",
+            report.pruned.len(), garbage.bytes_reclaimed, garbage.chunks_removed,
+        );
+    }
+    Ok(report)
+}
+
+/// The ship's snapshots, newest first.
+pub async fn list(ship: &str) -> Result<Vec<SnapshotSummary>> {
+    let ship = patp::render(patp::parse(ship)?);
+    let store = store_path().await?;
+    tokio::task::spawn_blocking(move || {
+        let mut summaries = Vec::new();
+        for id in snapshot_ids(&store, &ship)?.into_iter().rev() {
+            let snapshot = read_snapshot(&snapshots_path(&store, &ship).join(format!("{}.json", id)))?;
+            let verification = match fs::read(verification_path(&store, &ship, &id)) {
+                Ok(json) => Some(serde_json::from_slice(&json)?),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+            summaries.push(SnapshotSummary { id, taken_at: snapshot.taken_at, size: snapshot.size(), verification });
+        }
+        Ok(summaries)
+    }).await?
+}
+
+/// The newest snapshot of every ship in the store, as (ship, snapshot id) pairs.
+pub async fn latest() -> Result<Vec<(String, String)>> {
+    let store = store_path().await?;
+    tokio::task::spawn_blocking(move || {
+        let snapshots = store.join("snapshots");
+        if !snapshots.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut latest = Vec::new();
+        for ship in fs::read_dir(&snapshots)? {
+            let Ok(ship) = ship?.file_name().into_string() else { continue };
+            if let Some(id) = snapshot_ids(&store, &ship)?.pop() {
+                latest.push((ship, id));
+            }
+        }
+        Ok(latest)
+    }).await?
+}
+
+/// Records how restoring a snapshot went, to be listed with it.
+pub async fn record_verification(ship: &str, id: &str, verification: &Verification) -> Result<()> {
+    let ship = patp::render(patp::parse(ship)?);
+    if !is_valid_snapshot_id(id) {
+        bail!("invalid snapshot id: {:?}", id);
+    }
+    let path = verification_path(&store_path().await?, &ship, id);
+    let json = serde_json::to_vec(verification)?;
+    tokio::task::spawn_blocking(move || {
+        fs::create_dir_all(path.parent().unwrap())?;
+        Ok(write_atomically(&path, &json)?)
+    }).await?
+}
+
+/// The name of a snapshot's manifest relative to the store, as it is laid out on disk and when replicated.
+pub fn snapshot_object_name(ship: &str, id: &str) -> String {
+    format!("snapshots/{}/{}.json", ship, id)
+}
+
+/// The name of a chunk relative to the store, as it is laid out on disk and when replicated.
+pub fn chunk_object_name(hash: &str) -> String {
+    format!("chunks/{}/{}", &hash[..2], hash)
+}
+
+/// A snapshot's manifest exactly as stored, along with the distinct chunks it refers to, for copying it elsewhere.
+/// None if the snapshot doesn't exist, e.g. because it has been pruned since.
+pub async fn manifest(ship: &str, id: &str) -> Result<Option<(Vec<u8>, Vec<String>)>> {
+    let ship = patp::render(patp::parse(ship)?);
+    if !is_valid_snapshot_id(id) {
+        bail!("invalid snapshot id: {:?}", id);
+    }
+    let manifest_path = snapshots_path(&store_path().await?, &ship).join(format!("{}.json", id));
+    tokio::task::spawn_blocking(move || {
+        let json = match fs::read(&manifest_path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let snapshot: Snapshot = serde_json::from_slice(&json)?;
+        let mut seen = HashSet::new();
+        let mut chunks = Vec::new();
+        for entry in snapshot.entries {
+            if let SnapshotEntry::File { chunks: file_chunks, .. } = entry {
+                for hash in file_chunks {
+                    checked_hash(&hash)?;
+                    if seen.insert(hash.clone()) {
+                        chunks.push(hash);
+                    }
+                }
+            }
+        }
+        Ok(Some((json, chunks)))
+    }).await?
+}
+
+/// Reads a chunk from the store, checking it against its hash.
+pub async fn read_chunk(hash: &str) -> Result<Vec<u8>> {
+    let hash = checked_hash(hash)?.to_owned();
+    let path = chunk_path(&store_path().await?, &hash);
+    tokio::task::spawn_blocking(move || {
+        let chunk = fs::read(path).map_err(|e| anyhow!("chunk {} is missing from the backup store: {}", hash, e))?;
+        if hex(&Sha256::digest(&chunk)) != hash {
+            bail!("chunk {} in the backup store is corrupt", hash);
+        }
+        Ok(chunk)
+    }).await?
+}
+
+/// Rebuilds the pier from the given snapshot into `dst`, an empty directory. Every chunk is checked against its hash on
+/// the way.
+pub async fn restore(ship: &str, id: &str, dst: &Path) -> Result<()> {
+    let ship = patp::render(patp::parse(ship)?);
+    if !is_valid_snapshot_id(id) {
+        bail!("invalid snapshot id: {:?}", id);
+    }
+    let store = store_path().await?;
+    let manifest_path = snapshots_path(&store, &ship).join(format!("{}.json", id));
+    let id = id.to_owned();
+    let dst = dst.to_owned();
+
+    let _guard = STORE_LOCK.read().await;
+    tokio::task::spawn_blocking(move || {
+        if !manifest_path.is_file() {
+            bail!("~{} has no snapshot {} in the backup store", ship, id);
+        }
+        let snapshot = read_snapshot(&manifest_path)?;
+        // Directory modes are applied last, so that a read-only directory can still be filled.
+        let mut dir_modes = Vec::new();
+        for entry in &snapshot.entries {
+            match entry {
+                SnapshotEntry::Dir { path, mode } => {
+                    let path = dst.join(checked_relative(path)?);
+                    fs::create_dir(&path)?;
+                    dir_modes.push((path, *mode));
+                },
+                SnapshotEntry::File { path, mode, chunks, .. } => {
+                    let path = dst.join(checked_relative(path)?);
+                    let mut file = fs::File::create(&path)?;
+                    for hash in chunks {
+                        let chunk = fs::read(chunk_path(&store, checked_hash(hash)?))
+                            .map_err(|e| anyhow!("chunk {} is missing from the backup store: {}", hash, e))?;
+                        if hex(&Sha256::digest(&chunk)) != *hash {
+                            bail!("chunk {} in the backup store is corrupt", hash);
+                        }
+                        file.write_all(&chunk)?;
+                    }
+                    file.sync_all()?;
+                    fs::set_permissions(&path, fs::Permissions::from_mode(*mode))?;
+                },
+                SnapshotEntry::Symlink { path, target } => {
+                    std::os::unix::fs::symlink(target, dst.join(checked_relative(path)?))?;
+                },
+            }
+        }
+        for (path, mode) in dir_modes.into_iter().rev() {
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }).await?
+}
+
+/// Guards against manifests that would write outside the destination.
+fn checked_relative(path: &str) -> Result<&Path> {
+    let path = Path::new(path);
+    if path.is_absolute() || path.components().any(|component| component == std::path::Component::ParentDir) {
+        bail!("snapshot contains disallowed path {}", path.to_string_lossy());
+    }
+    Ok(path)
+}
+
+fn checked_hash(hash: &str) -> Result<&str> {
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("snapshot contains invalid chunk hash {:?}", hash);
+    }
+    Ok(hash)
+}
+
+/// Deletes every chunk that no snapshot refers to, along with temporary files left by interrupted writes. Nothing is
+/// deleted if any snapshot can't be read, since its chunks can't be told apart from garbage.
+pub async fn collect_garbage() -> Result<GarbageReport> {
+    let store = store_path().await?;
+    let _guard = STORE_LOCK.write().await;
+    tokio::task::spawn_blocking(move || {
+        let mut referenced = HashSet::new();
+        let snapshots = store.join("snapshots");
+        if snapshots.is_dir() {
+            for ship in fs::read_dir(&snapshots)? {
+                for manifest in fs::read_dir(ship?.path())? {
+                    let manifest = manifest?.path();
+                    if manifest.extension().is_none_or(|ext| ext != "json") {
+                        continue;
+                    }
+                    let snapshot = read_snapshot(&manifest)
+                        .map_err(|e| e.context(format!("failed to read {}", manifest.to_string_lossy())))?;
+                    for entry in snapshot.entries {
+                        if let SnapshotEntry::File { chunks, .. } = entry {
+                            referenced.extend(chunks);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut report = GarbageReport::default();
+        let chunks = store.join("chunks");
+        if !chunks.is_dir() {
+            return Ok(report);
+        }
+        for prefix in fs::read_dir(&chunks)? {
+            for chunk in fs::read_dir(prefix?.path())? {
+                let chunk = chunk?;
+                let name = chunk.file_name();
+                if name.to_str().is_some_and(|name| referenced.contains(name)) {
+                    continue;
+                }
+                report.bytes_reclaimed += chunk.metadata()?.len();
+                report.chunks_removed += 1;
+                fs::remove_file(chunk.path())?;
+            }
+        }
+        Ok(report)
+    }).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{RngCore, SeedableRng};
+
+    use crate::util::TempDir;
+
+    fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        rand::rngs::StdRng::seed_from_u64(seed).fill_bytes(&mut bytes);
+        bytes
+    }
+
+    fn chunks(data: &[u8]) -> Vec<Vec<u8>> {
+        let mut chunks = Vec::new();
+        split(data, |chunk| {
+            chunks.push(chunk.to_vec());
+            Ok(())
+        }).unwrap();
+        chunks
+    }
+
+    #[test]
+    fn splits_within_chunk_size_bounds() {
+        let data = random_bytes(8 * 1024 * 1024, 1);
+        let chunks = chunks(&data);
+        assert!(chunks.len() > 8, "{} chunks", chunks.len());
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(rest.iter().all(|chunk| (MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk.len())));
+        assert!(!last.is_empty() && last.len() <= MAX_CHUNK_SIZE);
+        assert_eq!(chunks.concat(), data);
+        assert_eq!(chunks, self::chunks(&data));
+    }
+
+    #[test]
+    fn splits_small_and_uniform_input() {
+        assert!(chunks(&[]).is_empty());
+        assert_eq!(chunks(b"hello"), [b"hello".to_vec()]);
+
+        // The hash of a run of one byte settles, so boundaries fall only at the maximum size or never.
+        let zeros = vec![0; 3 * MAX_CHUNK_SIZE + 1];
+        let lens: Vec<usize> = chunks(&zeros).iter().map(Vec::len).collect();
+        assert_eq!(lens.iter().sum::<usize>(), zeros.len());
+        assert!(lens.iter().all(|len| *len <= MAX_CHUNK_SIZE));
+    }
+
+    #[test]
+    fn insertions_only_move_nearby_boundaries() {
+        let data = random_bytes(8 * 1024 * 1024, 2);
+        let mut edited = data.clone();
+        edited.splice(1000..1000, random_bytes(100, 3));
+
+        let before: HashSet<Vec<u8>> = chunks(&data).into_iter().collect();
+        let after = chunks(&edited);
+        let shared = after.iter().filter(|chunk| before.contains(*chunk)).count();
+        assert!(shared >= after.len() - 2, "{} of {} chunks shared", shared, after.len());
+    }
+
+    #[test]
+    fn stores_each_chunk_once() {
+        let store = TempDir::new();
+        let pier = TempDir::new();
+        fs::create_dir_all(pier.join(".urb/log")).unwrap();
+        let data = random_bytes(3 * 1024 * 1024, 4);
+        fs::write(pier.join(".urb/log/data.mdb"), &data).unwrap();
+        fs::write(pier.join(".urb/log/copy.mdb"), &data).unwrap();
+        std::os::unix::fs::symlink("data.mdb", pier.join(".urb/log/link.mdb")).unwrap();
+
+        let mut entries = Vec::new();
+        let mut report = SnapshotReport::default();
+        snapshot_dir(&store, &pier, &pier, &mut entries, &mut report).unwrap();
+        assert_eq!(report.size, 2 * data.len() as u64);
+        assert_eq!(report.new_chunks * 2, report.chunks);
+        assert_eq!(report.new_bytes, data.len() as u64);
+
+        let paths: Vec<&str> = entries.iter().map(|entry| match entry {
+            SnapshotEntry::Dir { path, .. } => path.as_str(),
+            SnapshotEntry::File { path, .. } => path.as_str(),
+            SnapshotEntry::Symlink { path, .. } => path.as_str(),
+        }).collect();
+        assert_eq!(paths, [".urb", ".urb/log", ".urb/log/copy.mdb", ".urb/log/data.mdb", ".urb/log/link.mdb"]);
+        let Some(SnapshotEntry::File { chunks, size, .. }) = entries.iter().find(|entry| {
+            matches!(entry, SnapshotEntry::File { path, .. } if path.ends_with("data.mdb"))
+        }) else { panic!("data.mdb wasn't stored") };
+        assert_eq!(*size, data.len() as u64);
+        let stored: Vec<u8> = chunks.iter().flat_map(|hash| fs::read(chunk_path(&store, hash)).unwrap()).collect();
+        assert_eq!(stored, data);
+
+        let mut again = SnapshotReport::default();
+        snapshot_dir(&store, &pier, &pier, &mut Vec::new(), &mut again).unwrap();
+        assert_eq!((again.chunks, again.new_chunks, again.new_bytes), (report.chunks, 0, 0));
+    }
+
+    #[test]
+    fn checks_manifest_paths_and_hashes() {
+        assert!(checked_relative(".urb/log/data.mdb").is_ok());
+        assert!(checked_relative("/etc/passwd").is_err());
+        assert!(checked_relative(".urb/../../etc").is_err());
+        assert!(checked_hash(&hex(&Sha256::digest(b"chunk"))).is_ok());
+        assert!(checked_hash("../../etc/passwd").is_err());
+        assert!(checked_hash(&"a".repeat(63)).is_err());
+    }
+}
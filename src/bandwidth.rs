@@ -0,0 +1,89 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::env;
+use std::str::FromStr;
+use time::OffsetDateTime;
+
+use crate::util::parse_size;
+
+lazy_static! {
+    /// Caps on backup upload bandwidth by time of day, e.g. `06:00-22:00=2M,22:00-06:00=unlimited` for a trickle
+    /// during the day and full speed at night. Rates are bytes per second; times are UTC. Uploads are unlimited outside
+    /// the configured windows, and when this is unset.
+    pub static ref BACKUP_BANDWIDTH: BandwidthSchedule = env::var_os("NUCLEUS_BACKUP_BANDWIDTH")
+        .map(|s| s.to_str().unwrap().parse::<BandwidthSchedule>().unwrap())
+        .unwrap_or_default();
+}
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// A window of the day with its own bandwidth cap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Window {
+    /// Minutes since midnight. A window whose end is before its start wraps around midnight.
+    start: u16,
+    end: u16,
+    /// Bytes per second, or None for no cap.
+    limit: Option<u64>,
+}
+
+impl Window {
+    fn contains(&self, minute: u16) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BandwidthSchedule {
+    windows: Vec<Window>,
+}
+
+fn parse_time_of_day(s: &str) -> Result<u16> {
+    let (hours, minutes) = s.trim().split_once(':').ok_or_else(|| anyhow!("expected HH:MM, got {:?}", s))?;
+    let (hours, minutes): (u16, u16) = (hours.parse()?, minutes.parse()?);
+    if hours > 24 || minutes > 59 || (hours == 24 && minutes > 0) {
+        bail!("invalid time of day: {:?}", s);
+    }
+    Ok(hours * 60 + minutes)
+}
+
+impl FromStr for BandwidthSchedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut windows = Vec::new();
+        for window in s.split(',').map(str::trim).filter(|window| !window.is_empty()) {
+            let (times, limit) = window.split_once('=')
+                .ok_or_else(|| anyhow!("expected HH:MM-HH:MM=RATE, got {:?}", window))?;
+            let (start, end) = times.split_once('-')
+                .ok_or_else(|| anyhow!("expected HH:MM-HH:MM, got {:?}", times))?;
+            let limit = match limit.trim() {
+                "unlimited" => None,
+                limit => Some(parse_size(limit)?.max(1)),
+            };
+            windows.push(Window {
+                start: parse_time_of_day(start)? % MINUTES_PER_DAY,
+                end: parse_time_of_day(end)? % MINUTES_PER_DAY,
+                limit,
+            });
+        }
+        Ok(BandwidthSchedule { windows })
+    }
+}
+
+impl BandwidthSchedule {
+    /// The cap in bytes per second at the given time, from the first window containing it.
+    pub fn limit_at(&self, at: OffsetDateTime) -> Option<u64> {
+        let at = at.to_offset(time::UtcOffset::UTC);
+        let minute = at.hour() as u16 * 60 + at.minute() as u16;
+        self.windows.iter().find(|window| window.contains(minute)).and_then(|window| window.limit)
+    }
+
+    pub fn current_limit(&self) -> Option<u64> {
+        self.limit_at(OffsetDateTime::now_utc())
+    }
+}
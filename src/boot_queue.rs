@@ -0,0 +1,166 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use futures::channel::oneshot;
+use std::env;
+use std::sync::{Arc, Mutex};
+use time::OffsetDateTime;
+
+use crate::ship::BootPriority;
+
+lazy_static! {
+    /// How many runtimes may be booting at once. Booting replays events and is CPU and IO heavy, so booting every pier
+    /// at once after a host reboot would make all of them slow to come up.
+    pub static ref MAX_CONCURRENT_BOOTS: usize = env::var_os("NUCLEUS_MAX_CONCURRENT_BOOTS")
+        .map(|s| s.to_str().unwrap().parse().unwrap())
+        .unwrap_or(4);
+}
+
+#[derive(Debug)]
+struct Waiter {
+    name: String,
+    job_id: Uuid,
+    priority: BootPriority,
+    on_demand: bool,
+    queued_at: OffsetDateTime,
+    seq: u64,
+    wake: oneshot::Sender<()>,
+}
+
+impl Waiter {
+    /// Waiters with the greatest key boot first: wake-on-demand requests, then by priority, then first come first
+    /// served.
+    fn key(&self) -> (bool, BootPriority, std::cmp::Reverse<u64>) {
+        (self.on_demand, self.priority, std::cmp::Reverse(self.seq))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    booting: usize,
+    waiting: Vec<Waiter>,
+    next_seq: u64,
+}
+
+/// Limits how many ships boot at once, handing free slots to queued boots in priority order.
+#[derive(Debug, Default)]
+pub struct BootQueue {
+    inner: Mutex<Inner>,
+}
+
+/// A queued boot, as reported by `GET /boot-queue`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedBoot {
+    pub name: String,
+    pub job_id: Uuid,
+    /// 1 for the next boot to start.
+    pub position: usize,
+    pub priority: BootPriority,
+    pub on_demand: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    pub queued_at: OffsetDateTime,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BootQueueSummary {
+    pub booting: usize,
+    pub max_concurrent_boots: usize,
+    pub queued: Vec<QueuedBoot>,
+}
+
+impl BootQueue {
+    /// Waits for a boot slot. `on_demand` boots, e.g. for a user waiting on the ship, jump ahead of the rest.
+    pub async fn acquire(
+        self: &Arc<Self>,
+        name: &str,
+        job_id: Uuid,
+        priority: BootPriority,
+        on_demand: bool,
+    ) -> BootSlot {
+        let woken = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.booting < *MAX_CONCURRENT_BOOTS && inner.waiting.is_empty() {
+                inner.booting += 1;
+                None
+            } else {
+                let (wake, woken) = oneshot::channel();
+                let seq = inner.next_seq;
+                inner.next_seq += 1;
+                inner.waiting.push(Waiter {
+                    name: name.to_owned(),
+                    job_id,
+                    priority,
+                    on_demand,
+                    queued_at: OffsetDateTime::now_utc(),
+                    seq,
+                    wake,
+                });
+                Some(woken)
+            }
+        };
+        if let Some(woken) = woken {
+            // The sender is only dropped after sending, which hands this waiter the releasing boot's slot.
+            _ = woken.await;
+        }
+        BootSlot { queue: self.clone() }
+    }
+
+    /// Moves a queued boot to the front, as if it had been requested on demand. Returns its job's id, or None if the
+    /// pier isn't queued.
+    pub fn prioritize(&self, name: &str) -> Option<Uuid> {
+        let mut inner = self.inner.lock().unwrap();
+        let waiter = inner.waiting.iter_mut().find(|waiter| waiter.name == name)?;
+        waiter.on_demand = true;
+        Some(waiter.job_id)
+    }
+
+    pub fn is_queued(&self, name: &str) -> bool {
+        self.inner.lock().unwrap().waiting.iter().any(|waiter| waiter.name == name)
+    }
+
+    pub fn summary(&self) -> BootQueueSummary {
+        let inner = self.inner.lock().unwrap();
+        let mut waiting: Vec<&Waiter> = inner.waiting.iter().collect();
+        waiting.sort_by_key(|waiter| std::cmp::Reverse(waiter.key()));
+        BootQueueSummary {
+            booting: inner.booting,
+            max_concurrent_boots: *MAX_CONCURRENT_BOOTS,
+            queued: waiting.iter().enumerate()
+                .map(|(idx, waiter)| QueuedBoot {
+                    name: waiter.name.clone(),
+                    job_id: waiter.job_id,
+                    position: idx + 1,
+                    priority: waiter.priority,
+                    on_demand: waiter.on_demand,
+                    queued_at: waiter.queued_at,
+                })
+                .collect(),
+        }
+    }
+
+    /// Hands a released slot to the first queued boot still waiting for one, or frees it.
+    fn release(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        while let Some((idx, _)) = inner.waiting.iter().enumerate().max_by_key(|(_, waiter)| waiter.key()) {
+            let waiter = inner.waiting.swap_remove(idx);
+            // Fails if the boot was abandoned while queued.
+            if waiter.wake.send(()).is_ok() {
+                return;
+            }
+        }
+        inner.booting -= 1;
+    }
+}
+
+/// Permission to boot one ship, returned to the queue when dropped.
+#[derive(Debug)]
+pub struct BootSlot {
+    queue: Arc<BootQueue>,
+}
+
+impl Drop for BootSlot {
+    fn drop(&mut self) {
+        self.queue.release();
+    }
+}
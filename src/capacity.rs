@@ -0,0 +1,146 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::env;
+use std::fmt::{self, Display};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::ship::HARBOR;
+use crate::usage::{self, FdUsage, FilesystemUsage};
+
+lazy_static! {
+    /// Most ships that may be running or booting on this host at once. Unset for no limit.
+    pub static ref MAX_RUNNING_SHIPS: Option<usize> = env::var_os("NUCLEUS_MAX_RUNNING_SHIPS")
+        .map(|s| s.to_str().unwrap().parse().unwrap());
+
+    /// Most piers this host may manage, running or not, counting piers still being created. Unset for no limit.
+    pub static ref MAX_MANAGED_PIERS: Option<usize> = env::var_os("NUCLEUS_MAX_MANAGED_PIERS")
+        .map(|s| s.to_str().unwrap().parse().unwrap());
+
+    /// Percentage of the harbor filesystem's inodes to keep free. Launches that would dig into them are warned about.
+    pub static ref MIN_FREE_INODES_PERCENT: u64 = env::var_os("NUCLEUS_MIN_FREE_INODES_PERCENT")
+        .map(|s| s.to_str().unwrap().parse().unwrap())
+        .unwrap_or(5);
+
+    /// Percentage of its file descriptor limit the orchestrator may use. Launches that would go past it are warned
+    /// about.
+    pub static ref MAX_FD_PERCENT: u64 = env::var_os("NUCLEUS_MAX_FD_PERCENT")
+        .map(|s| s.to_str().unwrap().parse().unwrap())
+        .unwrap_or(80);
+}
+
+/// Roughly how many inodes a launch may take: a new pier's event log, snapshot and runtime files, with room to spare.
+const INODES_PER_LAUNCH: u64 = 1000;
+
+/// Roughly how many file descriptors the orchestrator holds per running ship: the runtime's pipes, its log file, and
+/// connections to it.
+const FDS_PER_LAUNCH: u64 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    RunningShips,
+    ManagedPiers,
+}
+
+/// Going ahead would take the host past one of its configured limits.
+#[derive(Debug)]
+pub struct CapacityError {
+    pub limit: Limit,
+    pub max: usize,
+}
+
+impl Display for CapacityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.limit {
+            Limit::RunningShips => write!(f, "host is at capacity: at most {} ships may be running", self.max),
+            Limit::ManagedPiers => write!(f, "host is at capacity: at most {} piers may be managed", self.max),
+        }
+    }
+}
+
+impl StdError for CapacityError {}
+
+/// Fails if another ship can't be launched while `running` are running or booting.
+pub fn check_running(running: usize) -> Result<()> {
+    match *MAX_RUNNING_SHIPS {
+        Some(max) if running >= max => bail!(CapacityError { limit: Limit::RunningShips, max }),
+        _ => Ok(()),
+    }
+}
+
+/// Fails if `adding` more piers can't be managed while `managed` are.
+pub fn check_managed(managed: usize, adding: usize) -> Result<()> {
+    match *MAX_MANAGED_PIERS {
+        Some(max) if managed + adding > max => bail!(CapacityError { limit: Limit::ManagedPiers, max }),
+        _ => Ok(()),
+    }
+}
+
+/// Piers being created that aren't managed yet because they are still in the dry dock, where their names aren't
+/// known. They count against `MAX_MANAGED_PIERS`, so that many creations at once can't overshoot it.
+#[derive(Debug, Default)]
+pub struct PendingPiers(AtomicUsize);
+
+impl PendingPiers {
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub fn reserve(self: &Arc<Self>) -> Reservation {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Reservation(self.clone())
+    }
+}
+
+/// A place held for a pier being created, given up when dropped.
+#[derive(Debug)]
+pub struct Reservation(Arc<PendingPiers>);
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.0.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Resources that run out on dense hosts before disk space or memory does.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resources {
+    /// None if the harbor filesystem couldn't be measured.
+    pub harbor_filesystem: Option<FilesystemUsage>,
+    pub fds: Option<FdUsage>,
+    /// What another launch would take past a safe threshold.
+    pub warnings: Vec<String>,
+}
+
+/// Measures the harbor filesystem's inodes and the orchestrator's file descriptors, and whether another launch would
+/// take either past its threshold.
+pub fn resources() -> Resources {
+    let mut resources = Resources::default();
+    match usage::filesystem_usage(HARBOR.as_path()) {
+        Ok(fs) => {
+            let reserved = fs.total_inodes * *MIN_FREE_INODES_PERCENT / 100;
+            if fs.total_inodes > 0 && fs.available_inodes < reserved + INODES_PER_LAUNCH {
+                resources.warnings.push(format!(
+                    "harbor filesystem has {} of {} inodes free, and keeps {}% free",
+                    fs.available_inodes, fs.total_inodes, *MIN_FREE_INODES_PERCENT,
+                ));
+            }
+            resources.harbor_filesystem = Some(fs);
+        },
+        Err(e) => log::warn!("failed to stat harbor filesystem: {}", e),
+    }
+    match usage::fd_usage() {
+        Ok(fds) => {
+            if fds.open + FDS_PER_LAUNCH > fds.limit * *MAX_FD_PERCENT / 100 {
+                resources.warnings.push(format!(
+                    "orchestrator has {} of its {} file descriptors open, and may use {}%",
+                    fds.open, fds.limit, *MAX_FD_PERCENT,
+                ));
+            }
+            resources.fds = Some(fds);
+        },
+        Err(e) => log::warn!("failed to count open file descriptors: {}", e),
+    }
+    resources
+}
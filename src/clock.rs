@@ -0,0 +1,145 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+use crate::events::{Event, EventBus};
+use crate::util::parse_duration;
+
+lazy_static! {
+    /// How far a ship's `now` may be from the host's clock before it is reported as drifting.
+    pub static ref CLOCK_DRIFT_THRESHOLD: Duration = env::var_os("NUCLEUS_CLOCK_DRIFT_THRESHOLD")
+        .map(|s| parse_duration(s.to_str().unwrap()).unwrap())
+        .unwrap_or(Duration::from_secs(30));
+
+    /// How often running ships' clocks and the host's NTP synchronization are checked.
+    pub static ref CLOCK_CHECK_INTERVAL: Duration = env::var_os("NUCLEUS_CLOCK_CHECK_INTERVAL")
+        .map(|s| parse_duration(s.to_str().unwrap()).unwrap())
+        .unwrap_or(Duration::from_secs(5 * 60));
+}
+
+/// The host clock's synchronization state as the kernel's NTP discipline sees it.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostClock {
+    /// Whether an NTP daemon is keeping the clock synchronized.
+    pub synchronized: bool,
+    /// Upper bound on the clock's error, as maintained by the NTP daemon.
+    pub max_error_ms: i64,
+    pub estimated_error_ms: i64,
+}
+
+/// Reads the host clock's synchronization state with `adjtimex`, without adjusting anything.
+pub fn host_clock() -> Result<HostClock> {
+    let mut timex: libc::timex = unsafe { std::mem::zeroed() };
+    let state = unsafe { libc::adjtimex(&mut timex) };
+    if state == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(HostClock {
+        synchronized: state != libc::TIME_ERROR && timex.status & libc::STA_UNSYNC == 0,
+        max_error_ms: timex.maxerror as i64 / 1000,
+        estimated_error_ms: timex.esterror as i64 / 1000,
+    })
+}
+
+/// Parses an absolute date in hoon's `@da` syntax, e.g. `~2024.3.5..14.02.33..a3f1`.
+pub fn parse_da(s: &str) -> Result<OffsetDateTime> {
+    let invalid = || anyhow!("invalid @da: {:?}", s);
+    let s = s.trim().trim_matches('"').strip_prefix('~').ok_or_else(invalid)?;
+    let mut parts = s.splitn(3, "..");
+
+    let date: Vec<&str> = parts.next().ok_or_else(invalid)?.split('.').collect();
+    let [year, month, day] = date[..] else { return Err(invalid()) };
+    let month: u8 = month.parse().map_err(|_| invalid())?;
+    let date = time::Date::from_calendar_date(
+        year.parse().map_err(|_| invalid())?,
+        time::Month::try_from(month).map_err(|_| invalid())?,
+        day.parse().map_err(|_| invalid())?,
+    ).map_err(|_| invalid())?;
+
+    let time = match parts.next() {
+        None => time::Time::MIDNIGHT,
+        Some(hms) => {
+            let hms: Vec<u8> = hms.split('.').map(str::parse).collect::<std::result::Result<_, _>>()
+                .map_err(|_| invalid())?;
+            let [h, m, sec] = hms[..] else { return Err(invalid()) };
+            time::Time::from_hms(h, m, sec).map_err(|_| invalid())?
+        },
+    };
+
+    // Fractions of a second are 16-bit hex groups; only the first is fine enough to matter here.
+    let fraction = match parts.next().and_then(|f| f.split('.').next()) {
+        None => 0.0,
+        Some(group) => u16::from_str_radix(group, 16).map_err(|_| invalid())? as f64 / 65536.0,
+    };
+
+    Ok(date.with_time(time).assume_utc() + time::Duration::seconds_f64(fraction))
+}
+
+/// The outcome of the latest clock check of one ship.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShipClock {
+    /// How far the ship's `now` is ahead of the host's clock; negative if behind.
+    pub drift_ms: i64,
+    pub drifting: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    pub checked_at: OffsetDateTime,
+}
+
+/// Remembers the latest clock checks and publishes events when a clock starts or stops being off.
+#[derive(Debug, Default)]
+pub struct ClockMonitor {
+    host: Mutex<Option<HostClock>>,
+    ships: Mutex<HashMap<String, ShipClock>>,
+}
+
+impl ClockMonitor {
+    pub fn host(&self) -> Option<HostClock> {
+        *self.host.lock().unwrap()
+    }
+
+    pub fn ship(&self, name: &str) -> Option<ShipClock> {
+        self.ships.lock().unwrap().get(name).copied()
+    }
+
+    pub fn record_host(&self, bus: &EventBus, clock: HostClock) {
+        let previous = self.host.lock().unwrap().replace(clock);
+        match (previous.is_none_or(|p| p.synchronized), clock.synchronized) {
+            (true, false) => bus.publish(Event::HostClockUnsynchronized { max_error_ms: clock.max_error_ms }),
+            (false, true) => bus.publish(Event::HostClockSynchronized {}),
+            _ => {},
+        }
+    }
+
+    /// Records a ship's `now` as read between `before` and `after` on the host's clock.
+    pub fn record_ship(
+        &self,
+        bus: &EventBus,
+        name: &str,
+        ship_now: OffsetDateTime,
+        before: OffsetDateTime,
+        after: OffsetDateTime,
+    ) {
+        let host_now = before + (after - before) / 2;
+        let drift_ms = (ship_now - host_now).whole_milliseconds() as i64;
+        let drifting = drift_ms.unsigned_abs() > CLOCK_DRIFT_THRESHOLD.as_millis() as u64;
+
+        let clock = ShipClock { drift_ms, drifting, checked_at: after };
+        let previous = self.ships.lock().unwrap().insert(name.to_owned(), clock);
+        match (previous.is_some_and(|p| p.drifting), drifting) {
+            (false, true) => bus.publish(Event::ClockDrifted { name: name.to_owned(), drift_ms }),
+            (true, false) => bus.publish(Event::ClockDriftResolved { name: name.to_owned(), drift_ms }),
+            _ => {},
+        }
+    }
+
+    /// Forgets ships that are no longer running, so that stale measurements aren't reported.
+    pub fn retain(&self, running: &[String]) {
+        self.ships.lock().unwrap().retain(|name, _| running.contains(name));
+    }
+}
@@ -0,0 +1,118 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use actix_web::web;
+use async_std::sync::RwLock;
+use reqwest::Url;
+use std::env;
+use std::time::Duration;
+
+use crate::nats::{NatsClient, NatsMessage};
+use crate::ship::ExportLayout;
+use crate::{AppState, ExportTarget, PostPierForm};
+
+lazy_static! {
+    /// A `nats://host:port/subject` URL to consume lifecycle commands from. Unset disables the command interface.
+    pub static ref NATS_COMMANDS_URL: Option<Url> = env::var_os("NUCLEUS_NATS_COMMANDS")
+        .map(|s| s.to_str().unwrap().parse::<Url>().unwrap());
+}
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// A lifecycle command received over the message queue. These mirror the HTTP endpoints of the same names; long
+/// operations reply with a job id, just as the HTTP API responds with 202 Accepted.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "camelCase")]
+enum Command {
+    /// Create and boot a pier from a keyfile. Archives are too large to send over the message queue; upload those over
+    /// HTTP.
+    #[serde(rename_all = "camelCase")]
+    Create { name: String, keyfile: String },
+    Start { name: String },
+    Stop { name: String },
+    /// Export the pier to an artifact, which can then be downloaded over HTTP.
+    Backup { name: String },
+}
+
+async fn execute(state: &web::Data<RwLock<AppState>>, command: Command) -> Result<serde_json::Value> {
+    match command {
+        Command::Create { name, keyfile } => {
+            crate::keyfile::check(&keyfile, &name)?;
+            let reservation = state.write().await.reserve_pier()?;
+            let upload = crate::spool_bytes(keyfile.as_bytes()).await?;
+            let form = PostPierForm::FromKeyfile { name };
+            let job_id = crate::spawn_import(state, form, Some(upload), reservation).await;
+            Ok(serde_json::json!({ "jobId": job_id }))
+        },
+        Command::Start { name } => {
+            let job_id = crate::spawn_boot(state, name, false).await?;
+            Ok(serde_json::json!({ "jobId": job_id }))
+        },
+        Command::Stop { name } => {
+            crate::stop(state, &name).await?;
+            Ok(serde_json::json!({}))
+        },
+        Command::Backup { name } => {
+            let job_id = crate::spawn_export(state, name, ExportLayout::Native, ExportTarget::Download).await?;
+            Ok(serde_json::json!({ "jobId": job_id }))
+        },
+    }
+}
+
+async fn handle(state: &web::Data<RwLock<AppState>>, client: &NatsClient, msg: NatsMessage) {
+    let outcome = match serde_json::from_slice::<Command>(&msg.payload) {
+        Ok(command) => {
+            log::info!("received command over nats: {:?}", command);
+            execute(state, command).await
+        },
+        Err(e) => Err(anyhow!("invalid command: {}", e)),
+    };
+
+    let reply = match outcome {
+        Ok(mut body) => {
+            body["ok"] = serde_json::Value::Bool(true);
+            body
+        },
+        Err(e) => serde_json::json!({ "ok": false, "error": format!("{:#}", e) }),
+    };
+
+    match msg.reply_to {
+        Some(reply_to) => {
+            if let Err(e) = client.publish(&reply_to, reply.to_string().as_bytes()).await {
+                log::warn!("failed to reply to nats command on {}: {}", reply_to, e);
+            }
+        },
+        None => {
+            if reply["ok"] == false {
+                log::warn!("nats command on {} failed with no reply subject: {}", msg.subject, reply["error"]);
+            }
+        },
+    }
+}
+
+/// Consumes commands from the configured subject forever, reconnecting whenever the connection drops.
+pub async fn run(state: web::Data<RwLock<AppState>>, url: Url) {
+    let addr = format!("{}:{}", url.host_str().unwrap_or("127.0.0.1"), url.port().unwrap_or(4222));
+    let subject = url.path().trim_start_matches('/').replace('/', ".");
+    if subject.is_empty() {
+        log::error!("nats command url needs a subject as its path: {}", url);
+        return;
+    }
+
+    loop {
+        let result: Result<()> = async {
+            let client = NatsClient::connect(&addr).await?;
+            let mut commands = client.subscribe(&subject).await?;
+            log::info!("consuming commands from nats {} subject {}", addr, subject);
+
+            while let Some(msg) = commands.next().await {
+                handle(&state, &client, msg).await;
+            }
+            bail!("subscription ended")
+        }.await;
+
+        if let Err(e) = result {
+            log::warn!("nats command interface disconnected: {}; reconnecting", e);
+        }
+        actix_web::rt::time::sleep(RECONNECT_DELAY).await;
+    }
+}
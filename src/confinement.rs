@@ -0,0 +1,68 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::env;
+use std::ffi::{CStr, CString};
+
+lazy_static! {
+    /// Mandatory access control applied to every runtime the orchestrator spawns, from `NUCLEUS_SHIP_APPARMOR_PROFILE`
+    /// (a loaded AppArmor profile name) or `NUCLEUS_SHIP_SELINUX_CONTEXT` (an SELinux context to transition to). At
+    /// most one may be set. Unset leaves runtimes with the orchestrator's own confinement.
+    pub static ref SHIP_CONFINEMENT: Option<Confinement> = {
+        let apparmor = env::var_os("NUCLEUS_SHIP_APPARMOR_PROFILE").map(|s| s.to_str().unwrap().to_owned());
+        let selinux = env::var_os("NUCLEUS_SHIP_SELINUX_CONTEXT").map(|s| s.to_str().unwrap().to_owned());
+        match (apparmor, selinux) {
+            (Some(_), Some(_)) => {
+                panic!("NUCLEUS_SHIP_APPARMOR_PROFILE and NUCLEUS_SHIP_SELINUX_CONTEXT are mutually exclusive")
+            },
+            (Some(profile), None) => Some(Confinement::AppArmor(profile)),
+            (None, Some(context)) => Some(Confinement::SeLinux(context)),
+            (None, None) => None,
+        }
+    };
+}
+
+#[derive(Clone, Debug)]
+pub enum Confinement {
+    AppArmor(String),
+    SeLinux(String),
+}
+
+impl Confinement {
+    /// What to write to `/proc/self/attr/exec` for the next exec to enter this confinement.
+    pub fn exec_attr(&self) -> CString {
+        let attr = match self {
+            Confinement::AppArmor(profile) => format!("exec {}", profile),
+            Confinement::SeLinux(context) => context.clone(),
+        };
+        CString::new(attr).expect("confinement label contains a nul byte")
+    }
+}
+
+/// Requests that the calling process's next exec enter the confinement described by `attr`; the exec then fails if the
+/// profile or context can't be entered. Meant to be called between fork and exec, so it only makes async-signal-safe
+/// calls.
+pub fn set_exec_attr(attr: &CStr) -> std::io::Result<()> {
+    const PATH: &[u8] = b"/proc/self/attr/exec\0";
+    unsafe {
+        let fd = libc::open(PATH.as_ptr() as *const libc::c_char, libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let len = attr.to_bytes().len();
+        let written = libc::write(fd, attr.as_ptr() as *const libc::c_void, len);
+        let err = std::io::Error::last_os_error();
+        libc::close(fd);
+        if written != len as isize {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// The security label a process is running under, e.g. `nucleus-ship (enforce)` or `unconfined` under AppArmor. None
+/// if no security module exposes one.
+pub fn current_label(pid: u32) -> Option<String> {
+    let label = std::fs::read_to_string(format!("/proc/{}/attr/current", pid)).ok()?;
+    let label = label.trim_end_matches(['\0', '\n']);
+    (!label.is_empty()).then(|| label.to_owned())
+}
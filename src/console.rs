@@ -0,0 +1,145 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{self, Codec, Frame, Message};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::web::{Bytes, BytesMut, Payload};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use futures::channel::mpsc;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Fans console output out to every browser attached to a pier. Viewers are keyed by pier name rather than by `Ship`,
+/// so a viewer stays attached across ship restarts; commands sent while the ship is down just report an error.
+#[derive(Debug, Default)]
+pub struct ConsoleHub {
+    viewers: Mutex<HashMap<String, Vec<Viewer>>>,
+}
+
+#[derive(Debug)]
+struct Viewer {
+    id: Uuid,
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+impl ConsoleHub {
+    pub fn attach(&self, name: &str) -> (Uuid, mpsc::UnboundedReceiver<Message>) {
+        let id = Uuid::new_v4();
+        let (tx, rx) = mpsc::unbounded();
+
+        self.viewers.lock().unwrap()
+            .entry(name.to_owned())
+            .or_default()
+            .push(Viewer { id, tx });
+
+        (id, rx)
+    }
+
+    pub fn detach(&self, name: &str, id: Uuid) {
+        let mut viewers = self.viewers.lock().unwrap();
+        if let Some(pier_viewers) = viewers.get_mut(name) {
+            pier_viewers.retain(|viewer| viewer.id != id);
+            if pier_viewers.is_empty() {
+                viewers.remove(name);
+            }
+        }
+    }
+
+    /// Sends a line of text to every viewer attached to the pier, forgetting viewers whose connections have gone away.
+    pub fn broadcast(&self, name: &str, line: &str) {
+        let mut viewers = self.viewers.lock().unwrap();
+        if let Some(pier_viewers) = viewers.get_mut(name) {
+            pier_viewers.retain(|viewer| viewer.tx.unbounded_send(Message::Text(line.into())).is_ok());
+        }
+    }
+
+    fn send_to(&self, name: &str, id: Uuid, msg: Message) {
+        let viewers = self.viewers.lock().unwrap();
+        if let Some(viewer) = viewers.get(name).and_then(|vs| vs.iter().find(|viewer| viewer.id == id)) {
+            _ = viewer.tx.unbounded_send(msg);
+        }
+    }
+}
+
+/// Validates a websocket upgrade request and starts the 101 response. The caller supplies the body with `streaming`.
+pub fn handshake(req: &HttpRequest) -> Result<HttpResponseBuilder, ws::HandshakeError> {
+    ws::verify_handshake(req.head())?;
+
+    // verify_handshake guarantees the key header is present.
+    let key = req.headers().get(header::SEC_WEBSOCKET_KEY).unwrap();
+    let accept = ws::hash_key(key.as_bytes());
+
+    let mut res = HttpResponse::SwitchingProtocols();
+    res.upgrade("websocket")
+        .insert_header((header::SEC_WEBSOCKET_ACCEPT, HeaderValue::from_bytes(&accept).unwrap()));
+    Ok(res)
+}
+
+/// Encodes a viewer's outgoing messages as websocket frames, suitable for `HttpResponseBuilder::streaming`.
+pub fn encode_stream(rx: mpsc::UnboundedReceiver<Message>) -> impl Stream<Item = Result<Bytes>> {
+    let mut codec = Codec::new();
+    rx.map(move |msg| {
+        let mut buf = BytesMut::new();
+        codec.encode(msg, &mut buf)?;
+        Ok(buf.freeze())
+    })
+}
+
+fn decode_stream(payload: Payload) -> impl Stream<Item = Result<Frame>> {
+    stream::try_unfold(
+        (payload, BytesMut::new(), Codec::new()),
+        |(mut payload, mut buf, mut codec)| async move {
+            loop {
+                if let Some(frame) = codec.decode(&mut buf)? {
+                    return Ok(Some((frame, (payload, buf, codec))));
+                }
+                match payload.next().await {
+                    Some(chunk) => buf.extend_from_slice(&chunk?),
+                    None => return Ok(None),
+                }
+            }
+        },
+    )
+}
+
+/// Reads commands from a viewer's websocket until it closes, evaluating each with `exec` and broadcasting both the
+/// command and its output to every viewer of the pier.
+pub async fn run_viewer<F, Fut>(hub: &ConsoleHub, name: &str, id: Uuid, payload: Payload, exec: F)
+    where F: Fn(String) -> Fut,
+          Fut: Future<Output = Result<String>>,
+{
+    let frames = decode_stream(payload);
+    futures::pin_mut!(frames);
+
+    while let Some(frame) = frames.next().await {
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => {
+                log::warn!("console connection for {} failed: {}", name, e);
+                break
+            },
+        };
+
+        match frame {
+            Frame::Text(bytes) => {
+                let command = String::from_utf8_lossy(&bytes).trim().to_owned();
+                if command.is_empty() {
+                    continue
+                }
+                hub.broadcast(name, &format!("> {}", command));
+                match exec(command).await {
+                    Ok(output) => hub.broadcast(name, &output),
+                    Err(e) => hub.broadcast(name, &format!("error: {}", e)),
+                }
+            },
+            Frame::Ping(bytes) => hub.send_to(name, id, Message::Pong(bytes)),
+            Frame::Close(reason) => {
+                hub.send_to(name, id, Message::Close(reason));
+                break
+            },
+            Frame::Binary(_) | Frame::Continuation(_) | Frame::Pong(_) => {},
+        }
+    }
+
+    hub.detach(name, id);
+}
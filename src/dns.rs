@@ -0,0 +1,424 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::net::UdpSocket;
+
+use crate::s3::{hex, hmac};
+
+lazy_static! {
+    /// Where ships' hostnames are published, pointing at the orchestrator's public addresses: `cloudflare://<zone id>`,
+    /// `route53://<hosted zone id>` or `rfc2136://<server>:<port>/<zone>`, with credentials in the provider's own
+    /// variables. Unset leaves DNS alone.
+    pub static ref DNS_PROVIDER: Option<Url> = env::var_os("NUCLEUS_DNS_PROVIDER")
+        .map(|s| s.to_str().unwrap().parse::<Url>().unwrap());
+
+    /// Services that answer with the address a request came from, as plain text, to learn the orchestrator's public
+    /// IPv4 and IPv6 addresses. An empty value skips that family.
+    pub static ref PUBLIC_IPV4_URL: Option<Url> = public_ip_url("NUCLEUS_PUBLIC_IPV4_URL", "https://api.ipify.org");
+    pub static ref PUBLIC_IPV6_URL: Option<Url> = public_ip_url("NUCLEUS_PUBLIC_IPV6_URL", "https://api6.ipify.org");
+
+    /// How often the public addresses are checked for changes.
+    pub static ref DNS_UPDATE_INTERVAL: Duration = env::var_os("NUCLEUS_DNS_UPDATE_INTERVAL")
+        .map(|s| crate::util::parse_duration(s.to_str().unwrap()).unwrap())
+        .unwrap_or(Duration::from_secs(5 * 60));
+
+    /// The TTL of published records, short so that address changes are picked up quickly.
+    pub static ref DNS_TTL: u32 = env::var_os("NUCLEUS_DNS_TTL")
+        .map(|s| s.to_str().unwrap().parse().unwrap())
+        .unwrap_or(300);
+}
+
+fn public_ip_url(var: &str, default: &str) -> Option<Url> {
+    let url = env::var_os(var).map(|s| s.to_str().unwrap().to_owned()).unwrap_or_else(|| default.to_owned());
+    (!url.is_empty()).then(|| url.parse::<Url>().unwrap())
+}
+
+fn required_var(name: &str) -> Result<String> {
+    env::var(name).map_err(|_| anyhow!("{} must be set along with NUCLEUS_DNS_PROVIDER", name))
+}
+
+const DNS_TIMEOUT: Duration = Duration::from_secs(15);
+
+fn record_type(ip: IpAddr) -> &'static str {
+    match ip {
+        IpAddr::V4(_) => "A",
+        IpAddr::V6(_) => "AAAA",
+    }
+}
+
+/// A DNS service that ships' hostnames are published with.
+#[async_trait(?Send)]
+pub trait DnsProvider {
+    fn describe(&self) -> String;
+
+    /// Points `hostname`'s A or AAAA record, whichever `ip` calls for, at `ip` alone.
+    async fn set_address(&self, hostname: &str, ip: IpAddr) -> Result<()>;
+}
+
+/// Cloudflare's API, with a token from `NUCLEUS_CLOUDFLARE_API_TOKEN` allowed to edit the zone's DNS.
+#[derive(Debug)]
+pub struct Cloudflare {
+    zone_id: String,
+    token: String,
+}
+
+#[async_trait(?Send)]
+impl DnsProvider for Cloudflare {
+    fn describe(&self) -> String {
+        format!("Cloudflare zone {}", self.zone_id)
+    }
+
+    async fn set_address(&self, hostname: &str, ip: IpAddr) -> Result<()> {
+        let client = reqwest::Client::builder().timeout(DNS_TIMEOUT).build()?;
+        let records_url = format!("https://api.cloudflare.com/client/v4/zones/{}/dns_records", self.zone_id);
+        let existing: serde_json::Value = client.get(&records_url)
+            .bearer_auth(&self.token)
+            .query(&[("type", record_type(ip)), ("name", hostname)])
+            .send().await?
+            .json().await?;
+        if existing["success"] != true {
+            bail!("Cloudflare refused to list records for {}: {}", hostname, existing["errors"]);
+        }
+        let record = serde_json::json!({
+            "type": record_type(ip),
+            "name": hostname,
+            "content": ip.to_string(),
+            "ttl": *DNS_TTL,
+            "proxied": false,
+        });
+        let request = match existing["result"][0]["id"].as_str() {
+            Some(id) => client.put(format!("{}/{}", records_url, id)),
+            None => client.post(&records_url),
+        };
+        let res: serde_json::Value = request.bearer_auth(&self.token).json(&record).send().await?.json().await?;
+        if res["success"] != true {
+            bail!("Cloudflare refused to update {}: {}", hostname, res["errors"]);
+        }
+        Ok(())
+    }
+}
+
+/// Amazon Route 53, with keys from `NUCLEUS_ROUTE53_ACCESS_KEY_ID` and `NUCLEUS_ROUTE53_SECRET_ACCESS_KEY`.
+pub struct Route53 {
+    hosted_zone_id: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+impl std::fmt::Debug for Route53 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Route53").field("hosted_zone_id", &self.hosted_zone_id).finish_non_exhaustive()
+    }
+}
+
+const ROUTE53_HOST: &str = "route53.amazonaws.com";
+
+#[async_trait(?Send)]
+impl DnsProvider for Route53 {
+    fn describe(&self) -> String {
+        format!("Route 53 hosted zone {}", self.hosted_zone_id)
+    }
+
+    async fn set_address(&self, hostname: &str, ip: IpAddr) -> Result<()> {
+        let body = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+            <ChangeResourceRecordSetsRequest xmlns=\"https://route53.amazonaws.com/doc/2013-04-01/\">\
+            <ChangeBatch><Changes><Change><Action>UPSERT</Action><ResourceRecordSet>\
+            <Name>{}.</Name><Type>{}</Type><TTL>{}</TTL>\
+            <ResourceRecords><ResourceRecord><Value>{}</Value></ResourceRecord></ResourceRecords>\
+            </ResourceRecordSet></Change></Changes></ChangeBatch></ChangeResourceRecordSetsRequest>",
+            hostname.trim_end_matches('.'), record_type(ip), *DNS_TTL, ip,
+        );
+        let path = format!("/2013-04-01/hostedzone/{}/rrset/", self.hosted_zone_id);
+
+        // Signed with AWS Signature Version 4, as for S3. Route 53 is global, but signs as us-east-1.
+        let now = OffsetDateTime::now_utc();
+        let date = format!("{:04}{:02}{:02}", now.year(), now.month() as u8, now.day());
+        let amz_date = format!("{}T{:02}{:02}{:02}Z", date, now.hour(), now.minute(), now.second());
+        let canonical_request = format!(
+            "POST\n{}\n\nhost:{}\nx-amz-date:{}\n\nhost;x-amz-date\n{}",
+            path, ROUTE53_HOST, amz_date, hex(&Sha256::digest(body.as_bytes())),
+        );
+        let scope = format!("{}/us-east-1/route53/aws4_request", date);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date, scope, hex(&Sha256::digest(canonical_request.as_bytes())),
+        );
+        let mut signing_key = hmac(format!("AWS4{}", self.secret_access_key).as_bytes(), date.as_bytes())?;
+        for part in ["us-east-1", "route53", "aws4_request"] {
+            signing_key = hmac(&signing_key, part.as_bytes())?;
+        }
+        let signature = hex(&hmac(&signing_key, string_to_sign.as_bytes())?);
+
+        let res = reqwest::Client::new()
+            .post(format!("https://{}{}", ROUTE53_HOST, path))
+            .timeout(DNS_TIMEOUT)
+            .header("x-amz-date", amz_date)
+            .header("authorization", format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-date, Signature={}",
+                self.access_key_id, scope, signature,
+            ))
+            .header("content-type", "text/xml")
+            .body(body)
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            let status = res.status();
+            bail!("Route 53 refused to update {} with {}: {}", hostname, status, res.text().await.unwrap_or_default());
+        }
+        Ok(())
+    }
+}
+
+/// A DNS server that takes RFC 2136 dynamic updates, such as BIND or Knot, authenticated with a TSIG key
+/// (HMAC-SHA256) named by `NUCLEUS_RFC2136_KEY_NAME`, with its secret in base64 in `NUCLEUS_RFC2136_KEY_SECRET`.
+pub struct Rfc2136 {
+    server: SocketAddr,
+    zone: String,
+    key_name: String,
+    key_secret: Vec<u8>,
+}
+
+impl std::fmt::Debug for Rfc2136 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rfc2136")
+            .field("server", &self.server)
+            .field("zone", &self.zone)
+            .field("key_name", &self.key_name)
+            .finish_non_exhaustive()
+    }
+}
+
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+const TYPE_SOA: u16 = 6;
+const TYPE_TSIG: u16 = 250;
+const TSIG_ALGORITHM: &str = "hmac-sha256";
+/// How far apart the orchestrator's and the server's clocks may be for the server to accept the update.
+const TSIG_FUDGE: u16 = 300;
+
+/// A name in DNS wire format: length-prefixed labels, ending with the empty root label.
+fn wire_name(name: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for label in name.trim_end_matches('.').split('.').filter(|label| !label.is_empty()) {
+        if label.len() > 63 {
+            bail!("DNS label too long: {}", label);
+        }
+        out.push(label.len() as u8);
+        out.extend(label.to_ascii_lowercase().as_bytes());
+    }
+    out.push(0);
+    Ok(out)
+}
+
+/// Appends a resource record.
+fn push_record(message: &mut Vec<u8>, name: &[u8], kind: u16, class: u16, ttl: u32, data: &[u8]) {
+    message.extend(name);
+    message.extend(kind.to_be_bytes());
+    message.extend(class.to_be_bytes());
+    message.extend(ttl.to_be_bytes());
+    message.extend((data.len() as u16).to_be_bytes());
+    message.extend(data);
+}
+
+impl Rfc2136 {
+    /// An update replacing the hostname's records of `ip`'s type with `ip`, signed with TSIG (RFC 8945).
+    fn update_message(&self, id: u16, hostname: &str, ip: IpAddr) -> Result<Vec<u8>> {
+        let (kind, address) = match ip {
+            IpAddr::V4(ip) => (1u16, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (28u16, ip.octets().to_vec()),
+        };
+        let name = wire_name(hostname)?;
+
+        // Opcode UPDATE; one zone, no prerequisites, two updates.
+        let mut message = Vec::new();
+        message.extend(id.to_be_bytes());
+        message.extend(0x2800u16.to_be_bytes());
+        for count in [1u16, 0, 2, 0] {
+            message.extend(count.to_be_bytes());
+        }
+        message.extend(wire_name(&self.zone)?);
+        message.extend(TYPE_SOA.to_be_bytes());
+        message.extend(CLASS_IN.to_be_bytes());
+        // Deleting the RRset, then adding the one address.
+        push_record(&mut message, &name, kind, CLASS_ANY, 0, &[]);
+        push_record(&mut message, &name, kind, CLASS_IN, *DNS_TTL, &address);
+
+        let key_name = wire_name(&self.key_name)?;
+        let algorithm = wire_name(TSIG_ALGORITHM)?;
+        let time_signed = (OffsetDateTime::now_utc().unix_timestamp() as u64).to_be_bytes();
+        let mut signed = message.clone();
+        signed.extend(&key_name);
+        signed.extend(CLASS_ANY.to_be_bytes());
+        signed.extend(0u32.to_be_bytes());
+        signed.extend(&algorithm);
+        signed.extend(&time_signed[2..]);
+        signed.extend(TSIG_FUDGE.to_be_bytes());
+        // No error, no other data.
+        signed.extend([0u8; 4]);
+        let key = PKey::hmac(&self.key_secret)?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+        signer.update(&signed)?;
+        let mac = signer.sign_to_vec()?;
+
+        let mut tsig = algorithm;
+        tsig.extend(&time_signed[2..]);
+        tsig.extend(TSIG_FUDGE.to_be_bytes());
+        tsig.extend((mac.len() as u16).to_be_bytes());
+        tsig.extend(&mac);
+        tsig.extend(id.to_be_bytes());
+        tsig.extend([0u8; 4]);
+        push_record(&mut message, &key_name, TYPE_TSIG, CLASS_ANY, 0, &tsig);
+        // One additional record, the TSIG.
+        message[11] = 1;
+        Ok(message)
+    }
+}
+
+/// What a DNS server answered with, per RFC 6895.
+fn rcode_name(rcode: u8) -> &'static str {
+    match rcode {
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        _ => "an error",
+    }
+}
+
+#[async_trait(?Send)]
+impl DnsProvider for Rfc2136 {
+    fn describe(&self) -> String {
+        format!("zone {} on {}", self.zone, self.server)
+    }
+
+    async fn set_address(&self, hostname: &str, ip: IpAddr) -> Result<()> {
+        let id = rand::random::<u16>();
+        let message = self.update_message(id, hostname, ip)?;
+        let socket = UdpSocket::bind(match self.server {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        }).await?;
+        socket.connect(self.server).await?;
+        socket.send(&message).await?;
+        let mut response = [0u8; 4096];
+        let len = loop {
+            let len = actix_web::rt::time::timeout(DNS_TIMEOUT, socket.recv(&mut response)).await
+                .map_err(|_| anyhow!("{} didn't answer the update for {}", self.server, hostname))??;
+            // Anything else is a stray or spoofed datagram.
+            if len >= 12 && response[..2] == id.to_be_bytes() {
+                break len;
+            }
+        };
+        match response[..len][3] & 0x0f {
+            0 => Ok(()),
+            rcode => bail!("{} refused the update for {} with {}", self.server, hostname, rcode_name(rcode)),
+        }
+    }
+}
+
+/// The provider `NUCLEUS_DNS_PROVIDER` names, with its credentials from the environment.
+pub fn from_url(url: &Url) -> Result<Box<dyn DnsProvider>> {
+    let host = url.host_str().filter(|host| !host.is_empty());
+    match url.scheme() {
+        "cloudflare" => Ok(Box::new(Cloudflare {
+            zone_id: host.ok_or_else(|| anyhow!("cloudflare provider needs a zone id: {}", url))?.to_owned(),
+            token: required_var("NUCLEUS_CLOUDFLARE_API_TOKEN")?,
+        })),
+        "route53" => Ok(Box::new(Route53 {
+            // URLs lowercase their hosts, but hosted zone ids are uppercase.
+            hosted_zone_id: host.ok_or_else(|| anyhow!("route53 provider needs a hosted zone id: {}", url))?
+                .to_ascii_uppercase(),
+            access_key_id: required_var("NUCLEUS_ROUTE53_ACCESS_KEY_ID")?,
+            secret_access_key: required_var("NUCLEUS_ROUTE53_SECRET_ACCESS_KEY")?,
+        })),
+        "rfc2136" => {
+            let host = host.ok_or_else(|| anyhow!("rfc2136 provider needs a server: {}", url))?;
+            let ip: IpAddr = host.trim_start_matches('[').trim_end_matches(']').parse()
+                .map_err(|_| anyhow!("rfc2136 provider needs the server's IP address: {}", url))?;
+            let zone = url.path().trim_start_matches('/');
+            if zone.is_empty() {
+                bail!("rfc2136 provider needs a zone as its path: {}", url);
+            }
+            Ok(Box::new(Rfc2136 {
+                server: SocketAddr::new(ip, url.port().unwrap_or(53)),
+                zone: zone.to_owned(),
+                key_name: required_var("NUCLEUS_RFC2136_KEY_NAME")?,
+                key_secret: base64::decode(required_var("NUCLEUS_RFC2136_KEY_SECRET")?)
+                    .map_err(|e| anyhow!("NUCLEUS_RFC2136_KEY_SECRET is not base64: {}", e))?,
+            }))
+        },
+        scheme => bail!("unknown DNS provider: {}", scheme),
+    }
+}
+
+/// The address a what's-my-IP service sees requests from.
+async fn public_ip(url: &Url, ipv6: bool) -> Result<IpAddr> {
+    let text = reqwest::Client::new().get(url.clone()).timeout(DNS_TIMEOUT).send().await?.error_for_status()?
+        .text().await?;
+    let ip: IpAddr = text.trim().parse().map_err(|_| anyhow!("{} answered with no IP address: {}", url, text))?;
+    if ip.is_ipv6() != ipv6 {
+        bail!("{} answered with {}, of the wrong family", url, ip);
+    }
+    Ok(ip)
+}
+
+/// Keeps each ship's hostname pointed at the orchestrator's public addresses, updating only records whose address has
+/// changed since they were last published, or which haven't been published since startup.
+pub struct Updater {
+    provider: Box<dyn DnsProvider>,
+    published: HashMap<(String, bool), IpAddr>,
+}
+
+impl Updater {
+    pub fn new(provider: Box<dyn DnsProvider>) -> Self {
+        log::info!("publishing ships' hostnames with {}", provider.describe());
+        Updater { provider, published: HashMap::new() }
+    }
+
+    pub async fn update(&mut self, hostnames: &BTreeSet<String>) {
+        let mut addresses = Vec::new();
+        if let Some(url) = &*PUBLIC_IPV4_URL {
+            match public_ip(url, false).await {
+                Ok(ip) => addresses.push(ip),
+                Err(e) => log::warn!("failed to learn the public IPv4 address: {:#}", e),
+            }
+        }
+        // Hosts without IPv6 connectivity are common, so its absence isn't worth a warning.
+        if let Some(url) = &*PUBLIC_IPV6_URL {
+            match public_ip(url, true).await {
+                Ok(ip) => addresses.push(ip),
+                Err(e) => log::debug!("failed to learn the public IPv6 address: {:#}", e),
+            }
+        }
+
+        for hostname in hostnames {
+            for &ip in &addresses {
+                let key = (hostname.clone(), ip.is_ipv6());
+                if self.published.get(&key) == Some(&ip) {
+                    continue;
+                }
+                match self.provider.set_address(hostname, ip).await {
+                    Ok(()) => {
+                        log::info!("pointed {} at {}", hostname, ip);
+                        self.published.insert(key, ip);
+                    },
+                    Err(e) => log::warn!("failed to point {} at {}: {:#}", hostname, ip, e),
+                }
+            }
+        }
+        self.published.retain(|(hostname, _), _| hostnames.contains(hostname));
+    }
+}
@@ -0,0 +1,259 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use std::fmt::{self, Display};
+
+use crate::azimuth::StaleKeyfileError;
+use crate::capacity::CapacityError;
+use crate::import::{InvalidPierArchiveError, NotAdoptableError};
+use crate::keyfile::InvalidKeyfileError;
+use crate::net_util::PortsExhaustedError;
+use crate::patp::InvalidPatpError;
+use crate::proxy::ChannelExpiredError;
+use crate::sigil::NoSigilError;
+use crate::runtime::UnsupportedFeatureError;
+use crate::signed_links::InvalidSignatureError;
+
+/// No pier by this name (or dry dock id) is managed by the orchestrator.
+#[derive(Debug)]
+pub struct PierNotFoundError(pub String);
+
+impl Display for PierNotFoundError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no such pier: {}", self.0)
+    }
+}
+
+impl StdError for PierNotFoundError {}
+
+/// Another handle already holds the pier's lockfile.
+#[derive(Debug)]
+pub struct PierLockedError(pub String);
+
+impl Display for PierLockedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pier is locked by another handle: {}", self.0)
+    }
+}
+
+impl StdError for PierLockedError {}
+
+/// The ship is paused, so it can't answer until it is resumed.
+#[derive(Debug)]
+pub struct ShipPausedError(pub String);
+
+impl Display for ShipPausedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ship is paused: {}", self.0)
+    }
+}
+
+impl StdError for ShipPausedError {}
+
+/// A streamed request body went past the largest size accepted for it.
+#[derive(Debug)]
+pub struct PayloadTooLargeError {
+    pub limit: u64,
+}
+
+impl Display for PayloadTooLargeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "payload exceeds the limit of {} bytes", self.limit)
+    }
+}
+
+impl StdError for PayloadTooLargeError {}
+
+/// The error type returned by every HTTP handler. It renders as `{code, message, detail}`, where `code` is a stable
+/// camelCase identifier clients can match on, `message` is a human-readable summary, and `detail` optionally carries
+/// the full chain of underlying causes.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    detail: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiErrorBody<'a> {
+    code: &'a str,
+    message: &'a str,
+    detail: Option<&'a str>,
+}
+
+impl ApiError {
+    pub fn new<S: Into<String>>(status: StatusCode, code: &'static str, message: S) -> Self {
+        ApiError { status, code, message: message.into(), detail: None }
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn with_detail<S: Into<String>>(mut self, detail: S) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// The same error with its message in another language, from a message catalog.
+    pub fn translated(&self, message: &str) -> Self {
+        ApiError { status: self.status, code: self.code, message: message.to_owned(), detail: self.detail.clone() }
+    }
+
+    pub fn bad_request<S: Into<String>>(message: S) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "badRequest", message)
+    }
+
+    pub fn forbidden<S: Into<String>>(message: S) -> Self {
+        Self::new(StatusCode::FORBIDDEN, "forbidden", message)
+    }
+
+    pub fn pier_not_found(name: &str) -> Self {
+        PierNotFoundError(name.to_owned()).into()
+    }
+
+    pub fn job_not_found(id: Uuid) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "jobNotFound", format!("no such job: {}", id))
+    }
+
+    pub fn plan_not_found(name: &str) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "planNotFound", format!("no such plan: {}", name))
+    }
+
+    pub fn pier_busy(name: &str) -> Self {
+        Self::new(StatusCode::CONFLICT, "pierBusy", format!("pier is busy: {}", name))
+    }
+
+    pub fn ship_running(name: &str) -> Self {
+        Self::new(StatusCode::CONFLICT, "shipRunning", format!("ship is running: {}", name))
+    }
+
+    pub fn ship_not_running(name: &str) -> Self {
+        Self::new(StatusCode::CONFLICT, "shipNotRunning", format!("ship is not running: {}", name))
+    }
+
+    pub fn ship_stopping(name: &str) -> Self {
+        Self::new(StatusCode::SERVICE_UNAVAILABLE, "shipStopping", format!("ship is stopping: {}", name))
+    }
+
+    pub fn ship_paused(name: &str) -> Self {
+        Error::from(ShipPausedError(name.to_owned())).into()
+    }
+
+    pub fn idempotency_key_in_use() -> Self {
+        Self::new(StatusCode::CONFLICT, "idempotencyKeyInUse", "a request with this key is still in progress")
+    }
+
+    pub fn payload_too_large<S: Into<String>>(message: S) -> Self {
+        Self::new(StatusCode::PAYLOAD_TOO_LARGE, "payloadTooLarge", message)
+    }
+
+    pub fn not_ready() -> Self {
+        Self::new(StatusCode::SERVICE_UNAVAILABLE, "notReady", "the orchestrator is still starting up")
+    }
+
+    /// The ship itself failed or timed out while handling a request forwarded to it.
+    pub fn ship_error(e: Error) -> Self {
+        if e.downcast_ref::<ShipPausedError>().is_some() {
+            return e.into();
+        }
+        Self::new(StatusCode::BAD_GATEWAY, "shipError", "the ship failed to handle the request")
+            .with_detail(format!("{:#}", e))
+    }
+}
+
+impl From<Error> for ApiError {
+    /// Recognizes the crate's typed errors anywhere in the chain; anything else is an internal error, which is logged and
+    /// answered with a generic message.
+    fn from(e: Error) -> Self {
+        if let Some(not_found) = e.downcast_ref::<PierNotFoundError>() {
+            return Self::new(StatusCode::NOT_FOUND, "pierNotFound", not_found.to_string());
+        }
+        if let Some(locked) = e.downcast_ref::<PierLockedError>() {
+            return Self::new(StatusCode::CONFLICT, "pierLocked", locked.to_string());
+        }
+        if let Some(paused) = e.downcast_ref::<ShipPausedError>() {
+            return Self::new(StatusCode::CONFLICT, "shipPaused", paused.to_string());
+        }
+        if let Some(expired) = e.downcast_ref::<ChannelExpiredError>() {
+            return Self::new(StatusCode::GONE, "channelExpired", expired.to_string());
+        }
+        if let Some(exhausted) = e.downcast_ref::<PortsExhaustedError>() {
+            return Self::new(StatusCode::SERVICE_UNAVAILABLE, "portsExhausted", exhausted.to_string());
+        }
+        if let Some(at_capacity) = e.downcast_ref::<CapacityError>() {
+            return Self::new(StatusCode::SERVICE_UNAVAILABLE, "atCapacity", at_capacity.to_string());
+        }
+        if let Some(too_large) = e.downcast_ref::<PayloadTooLargeError>() {
+            return Self::payload_too_large(too_large.to_string());
+        }
+        if let Some(invalid) = e.downcast_ref::<InvalidPatpError>() {
+            return Self::new(StatusCode::BAD_REQUEST, "invalidName", invalid.to_string());
+        }
+        if let Some(invalid) = e.downcast_ref::<InvalidSignatureError>() {
+            return Self::new(StatusCode::FORBIDDEN, "invalidSignature", invalid.to_string());
+        }
+        if let Some(stale) = e.downcast_ref::<StaleKeyfileError>() {
+            return Self::new(StatusCode::CONFLICT, "staleKeyfile", stale.to_string());
+        }
+        if let Some(invalid) = e.downcast_ref::<InvalidKeyfileError>() {
+            return Self::new(StatusCode::UNPROCESSABLE_ENTITY, invalid.code(), invalid.to_string());
+        }
+        if let Some(invalid) = e.downcast_ref::<InvalidPierArchiveError>() {
+            return Self::new(StatusCode::UNPROCESSABLE_ENTITY, invalid.code(), invalid.to_string());
+        }
+        if let Some(unsupported) = e.downcast_ref::<UnsupportedFeatureError>() {
+            return Self::new(StatusCode::UNPROCESSABLE_ENTITY, "unsupportedByRuntime", unsupported.to_string());
+        }
+        if let Some(no_sigil) = e.downcast_ref::<NoSigilError>() {
+            return Self::new(StatusCode::UNPROCESSABLE_ENTITY, "noSigil", no_sigil.to_string());
+        }
+        if let Some(not_adoptable) = e.downcast_ref::<NotAdoptableError>() {
+            return Self::new(StatusCode::UNPROCESSABLE_ENTITY, "notAdoptable", not_adoptable.to_string());
+        }
+
+        // The chain can name paths, hosts and commands, which are for the operator rather than the client.
+        let id = Uuid::new_v4();
+        log::error!("internal error {}: {:#}", id, e);
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal", format!("internal error; logged as {}", id))
+    }
+}
+
+impl From<PierNotFoundError> for ApiError {
+    fn from(e: PierNotFoundError) -> Self {
+        Error::from(e).into()
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+        if let Some(ref detail) = self.detail {
+            if *detail != self.message {
+                write!(f, " ({})", detail)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StdError for ApiError {}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(ApiErrorBody {
+            code: self.code,
+            message: &self.message,
+            detail: self.detail.as_deref(),
+        })
+    }
+}
+
+pub type ApiResult<T> = std::result::Result<T, ApiError>;
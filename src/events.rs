@@ -0,0 +1,214 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use actix_web::web::Bytes;
+use futures::channel::mpsc;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use time::OffsetDateTime;
+
+use crate::async_util;
+use crate::ship_events::ShipEvent;
+
+/// Something that happened to a pier or ship which front-ends may want to react to.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Event {
+    #[serde(rename_all = "camelCase")]
+    PierCreated { id: Uuid, name: Option<String> },
+    #[serde(rename_all = "camelCase")]
+    ShipBooted { name: String, http_port: u16, ames_port: u16 },
+    #[serde(rename_all = "camelCase")]
+    ShipStopped { name: String },
+    #[serde(rename_all = "camelCase")]
+    ShipCrashed { name: String, status: String },
+    /// The ship's restart policy will relaunch it after `delay_secs`.
+    #[serde(rename_all = "camelCase")]
+    ShipRestartScheduled { name: String, attempt: u32, delay_secs: u64 },
+    /// The ship kept exiting and its restart policy ran out of retries.
+    #[serde(rename_all = "camelCase")]
+    ShipRestartsExhausted { name: String, attempts: u32 },
+    #[serde(rename_all = "camelCase")]
+    ShipPaused { name: String },
+    #[serde(rename_all = "camelCase")]
+    ShipResumed { name: String },
+    #[serde(rename_all = "camelCase")]
+    ExportStarted { name: String },
+    #[serde(rename_all = "camelCase")]
+    ExportCompleted { name: String },
+    /// A snapshot in the backup store was restored into a throwaway pier and booted, or failed to be.
+    #[serde(rename_all = "camelCase")]
+    BackupVerified { name: String, snapshot: String, ok: bool, error: Option<String> },
+    /// Automatic pruning of the pier's backups was suspended, until the hold is released.
+    #[serde(rename_all = "camelCase")]
+    LegalHoldPlaced { name: String, reason: Option<String> },
+    #[serde(rename_all = "camelCase")]
+    LegalHoldReleased { name: String },
+    #[serde(rename_all = "camelCase")]
+    SloBreached { name: String, window: String, uptime_percent: f64, target_percent: f64 },
+    #[serde(rename_all = "camelCase")]
+    SloRecovered { name: String, window: String, uptime_percent: f64, target_percent: f64 },
+    /// The ship's `now` is further from the host's clock than `CLOCK_DRIFT_THRESHOLD`.
+    #[serde(rename_all = "camelCase")]
+    ClockDrifted { name: String, drift_ms: i64 },
+    #[serde(rename_all = "camelCase")]
+    ClockDriftResolved { name: String, drift_ms: i64 },
+    /// The host's clock is no longer kept synchronized by NTP, so every ship's sense of time is suspect.
+    #[serde(rename_all = "camelCase")]
+    HostClockUnsynchronized { max_error_ms: i64 },
+    #[serde(rename_all = "camelCase")]
+    HostClockSynchronized {},
+    /// The pier expires within `EXPIRY_WARNING`.
+    #[serde(rename_all = "camelCase")]
+    PierExpiring {
+        name: String,
+        #[serde(with = "time::serde::rfc3339")]
+        expires_at: OffsetDateTime,
+    },
+    /// The pier expired and its ship was stopped. It is exported and moved to the trash after `EXPIRY_GRACE`.
+    #[serde(rename_all = "camelCase")]
+    PierExpired { name: String },
+    /// The pier was moved out of port into the trash, and is no longer managed.
+    #[serde(rename_all = "camelCase")]
+    PierTrashed { name: String },
+    /// The pier's ship was breached on Azimuth since the pier last booted, so the network no longer hears from it. It
+    /// needs a factory reset with a keyfile for its new life.
+    #[serde(rename_all = "camelCase")]
+    PierBreached { name: String, rift: u64 },
+    /// Something the ship's runtime printed, such as an OTA being applied or an event failing with `%crud`.
+    #[serde(rename_all = "camelCase")]
+    ShipOutput { name: String, event: ShipEvent },
+}
+
+impl Event {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::PierCreated { .. } => "pierCreated",
+            Event::ShipBooted { .. } => "shipBooted",
+            Event::ShipStopped { .. } => "shipStopped",
+            Event::ShipCrashed { .. } => "shipCrashed",
+            Event::ShipRestartScheduled { .. } => "shipRestartScheduled",
+            Event::ShipRestartsExhausted { .. } => "shipRestartsExhausted",
+            Event::ShipPaused { .. } => "shipPaused",
+            Event::ShipResumed { .. } => "shipResumed",
+            Event::ExportStarted { .. } => "exportStarted",
+            Event::ExportCompleted { .. } => "exportCompleted",
+            Event::BackupVerified { .. } => "backupVerified",
+            Event::LegalHoldPlaced { .. } => "legalHoldPlaced",
+            Event::LegalHoldReleased { .. } => "legalHoldReleased",
+            Event::SloBreached { .. } => "sloBreached",
+            Event::SloRecovered { .. } => "sloRecovered",
+            Event::ClockDrifted { .. } => "clockDrifted",
+            Event::ClockDriftResolved { .. } => "clockDriftResolved",
+            Event::HostClockUnsynchronized { .. } => "hostClockUnsynchronized",
+            Event::HostClockSynchronized {} => "hostClockSynchronized",
+            Event::PierExpiring { .. } => "pierExpiring",
+            Event::PierExpired { .. } => "pierExpired",
+            Event::PierTrashed { .. } => "pierTrashed",
+            Event::PierBreached { .. } => "pierBreached",
+            Event::ShipOutput { .. } => "shipOutput",
+        }
+    }
+
+    /// The name of the pier the event concerns, if any.
+    pub fn pier_name(&self) -> Option<&str> {
+        match self {
+            Event::PierCreated { name, .. } => name.as_deref(),
+            Event::ShipBooted { name, .. }
+            | Event::ShipStopped { name }
+            | Event::ShipCrashed { name, .. }
+            | Event::ShipRestartScheduled { name, .. }
+            | Event::ShipRestartsExhausted { name, .. }
+            | Event::ShipPaused { name }
+            | Event::ShipResumed { name }
+            | Event::ExportStarted { name }
+            | Event::ExportCompleted { name }
+            | Event::BackupVerified { name, .. }
+            | Event::LegalHoldPlaced { name, .. }
+            | Event::LegalHoldReleased { name }
+            | Event::SloBreached { name, .. }
+            | Event::SloRecovered { name, .. }
+            | Event::ClockDrifted { name, .. }
+            | Event::ClockDriftResolved { name, .. }
+            | Event::PierExpiring { name, .. }
+            | Event::PierExpired { name }
+            | Event::PierTrashed { name }
+            | Event::PierBreached { name, .. }
+            | Event::ShipOutput { name, .. } => Some(name),
+            Event::HostClockUnsynchronized { .. } | Event::HostClockSynchronized {} => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    pub seq: u64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl Envelope {
+    /// Formats the envelope as a single server-sent events message.
+    pub fn to_sse(&self) -> Result<Bytes> {
+        let data = serde_json::to_string(self)?;
+        Ok(Bytes::from(format!("id: {}\nevent: {}\ndata: {}\n\n", self.seq, self.event.kind(), data)))
+    }
+}
+
+/// How long published events are kept in memory for history queries such as uptime tracking.
+const HISTORY_RETENTION: time::Duration = time::Duration::days(31);
+const HISTORY_MAX_LEN: usize = 100_000;
+
+/// In-process publish/subscribe for lifecycle events. Publishing never blocks; subscribers that have gone away are
+/// dropped on the next publish. Recent events are also retained for history queries.
+#[derive(Debug, Default)]
+pub struct EventBus {
+    next_seq: AtomicU64,
+    subscribers: Mutex<Vec<mpsc::UnboundedSender<Arc<Envelope>>>>,
+    history: Mutex<VecDeque<Arc<Envelope>>>,
+}
+
+impl EventBus {
+    pub fn publish(&self, event: Event) {
+        let envelope = Arc::new(Envelope {
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            at: OffsetDateTime::now_utc(),
+            event,
+        });
+        log::info!("event: {:?}", envelope.event);
+
+        {
+            let mut history = self.history.lock().unwrap();
+            let horizon = envelope.at - HISTORY_RETENTION;
+            while history.front().is_some_and(|old| old.at < horizon) || history.len() >= HISTORY_MAX_LEN {
+                history.pop_front();
+            }
+            history.push_back(envelope.clone());
+        }
+
+        self.subscribers.lock().unwrap()
+            .retain(|tx| tx.unbounded_send(envelope.clone()).is_ok());
+    }
+
+    /// A snapshot of retained events, oldest first.
+    pub fn history(&self) -> Vec<Arc<Envelope>> {
+        self.history.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<Arc<Envelope>> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Publishes `event` once `src` has been fully consumed without error. Streams that are dropped early (e.g. the
+    /// client disconnected) or fail do not publish.
+    pub fn publish_on_completion<S, A>(self: Arc<Self>, src: S, event: Event) -> impl Stream<Item = Result<A>>
+        where S: Stream<Item = Result<A>>
+    {
+        async_util::on_success(src, move || async move { self.publish(event) })
+    }
+}
@@ -0,0 +1,69 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::env;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+lazy_static! {
+    /// How long before a pier expires that a `pierExpiring` event warns of it.
+    pub static ref EXPIRY_WARNING: Duration = env::var_os("NUCLEUS_EXPIRY_WARNING")
+        .map(|s| crate::util::parse_duration(s.to_str().unwrap()).unwrap())
+        .unwrap_or(Duration::from_secs(3 * 24 * 60 * 60));
+
+    /// How long an expired pier is kept, stopped, before it is exported and moved to the trash.
+    pub static ref EXPIRY_GRACE: Duration = env::var_os("NUCLEUS_EXPIRY_GRACE")
+        .map(|s| crate::util::parse_duration(s.to_str().unwrap()).unwrap())
+        .unwrap_or(Duration::from_secs(7 * 24 * 60 * 60));
+}
+
+/// How often piers are checked for expiry steps that have come due.
+pub const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// When a pier, such as a trial, expires, and how far its expiry has got.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Expiry {
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+    #[serde(default, with = "time::serde::rfc3339::option", skip_serializing_if = "Option::is_none")]
+    pub warned_at: Option<OffsetDateTime>,
+    /// When the ship was stopped for having expired. It can't be booted again unless its expiry is extended.
+    #[serde(default, with = "time::serde::rfc3339::option", skip_serializing_if = "Option::is_none")]
+    pub stopped_at: Option<OffsetDateTime>,
+}
+
+/// The next thing to do about an expiring pier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    Warn,
+    Stop,
+    /// Export the pier and move it to the trash.
+    Trash,
+}
+
+impl Expiry {
+    pub fn new(expires_at: OffsetDateTime) -> Self {
+        Expiry { expires_at, warned_at: None, stopped_at: None }
+    }
+
+    pub fn expired(&self, now: OffsetDateTime) -> bool {
+        now >= self.expires_at
+    }
+
+    /// When the pier is exported and moved to the trash, once the grace period after its expiry is up.
+    pub fn trash_at(&self) -> OffsetDateTime {
+        self.expires_at + *EXPIRY_GRACE
+    }
+
+    /// The step that has come due by `now`, if any. Steps are taken in order, so a pier that expires while the
+    /// orchestrator is down is still stopped before it is trashed.
+    pub fn due(&self, now: OffsetDateTime) -> Option<Step> {
+        match self.stopped_at {
+            Some(stopped_at) if now >= self.trash_at().max(stopped_at) => Some(Step::Trash),
+            Some(_) => None,
+            None if self.expired(now) => Some(Step::Stop),
+            None if self.warned_at.is_none() && now + *EXPIRY_WARNING >= self.expires_at => Some(Step::Warn),
+            None => None,
+        }
+    }
+}
@@ -0,0 +1,285 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use futures::stream::{self, BoxStream};
+use reqwest::{header, Method, RequestBuilder, StatusCode};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const EYRE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A logged-in session with a ship's web interface, eyre. The session logs in with the ship's `+code` the first time
+/// it is used, and again whenever eyre stops honouring its cookie, as it does after `|code %reset`.
+pub struct Session {
+    port: u16,
+    /// The ship's name, without the leading sig.
+    ship: String,
+    client: reqwest::Client,
+    /// The `urbauth-~ship=...` cookie, once logged in.
+    cookie: Mutex<Option<String>>,
+    next_id: AtomicU64,
+}
+
+/// One server-sent event from an eyre channel.
+#[derive(Debug)]
+struct ChannelEvent {
+    event_id: u64,
+    data: serde_json::Value,
+}
+
+impl std::fmt::Debug for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Session").field("port", &self.port).field("ship", &self.ship).finish_non_exhaustive()
+    }
+}
+
+impl Session {
+    pub fn new(port: u16, ship: &str) -> Self {
+        Session {
+            port,
+            ship: ship.to_owned(),
+            client: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .unwrap(),
+            cookie: Mutex::new(None),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.port, path)
+    }
+
+    /// Logs in with `code`, replacing any cookie the session had.
+    pub async fn login(&self, code: &str) -> Result<()> {
+        let res = self.client
+            .post(self.url("/~/login"))
+            .form(&[("password", code)])
+            .timeout(EYRE_TIMEOUT)
+            .send()
+            .await?;
+        let cookie = res.headers().get_all(header::SET_COOKIE).iter()
+            .filter_map(|cookie| cookie.to_str().ok())
+            .find(|cookie| cookie.starts_with("urbauth-"))
+            .and_then(|cookie| cookie.split(';').next())
+            .ok_or_else(|| anyhow!("logging into eyre with +code was refused with {}", res.status()))?;
+        *self.cookie.lock().unwrap() = Some(cookie.to_owned());
+        Ok(())
+    }
+
+    /// The session's login cookie, logging in first if it hasn't.
+    pub async fn cookie<C, F>(&self, code: C) -> Result<String>
+    where
+        C: Fn() -> F,
+        F: Future<Output = Result<String>>,
+    {
+        if let Some(cookie) = self.cookie.lock().unwrap().clone() {
+            return Ok(cookie);
+        }
+        self.login(&code().await?).await?;
+        Ok(self.cookie.lock().unwrap().clone().unwrap_or_default())
+    }
+
+    /// Drops the session's cookie after eyre has turned it away elsewhere, so that it logs in again next time.
+    pub fn forget_cookie(&self) {
+        *self.cookie.lock().unwrap() = None;
+    }
+
+    /// Sends an authenticated request, logging in first if the session hasn't, and logging in again and retrying once
+    /// if eyre turns the cookie away.
+    async fn send<B, C, F>(&self, build: B, code: C) -> Result<reqwest::Response>
+    where
+        B: Fn(&reqwest::Client) -> RequestBuilder,
+        C: Fn() -> F,
+        F: Future<Output = Result<String>>,
+    {
+        let mut logged_in = false;
+        loop {
+            let cookie = self.cookie.lock().unwrap().clone();
+            let cookie = match cookie {
+                Some(cookie) => cookie,
+                None => {
+                    self.login(&code().await?).await?;
+                    logged_in = true;
+                    continue;
+                },
+            };
+            let res = build(&self.client).header(header::COOKIE, cookie).send().await?;
+            // Eyre answers unauthenticated requests with 403, or for pages, a redirect to its login page.
+            let refused = res.status() == StatusCode::FORBIDDEN || res.status().is_redirection();
+            if !refused {
+                return Ok(res.error_for_status()?);
+            }
+            if logged_in {
+                bail!("eyre refused a fresh +code login with {}", res.status());
+            }
+            *self.cookie.lock().unwrap() = None;
+        }
+    }
+
+    /// Scries `path` of `app` through eyre's `/~/scry`, for a path that has a `json` mark conversion.
+    pub async fn scry<C, F>(&self, app: &str, path: &str, code: C) -> Result<serde_json::Value>
+    where
+        C: Fn() -> F,
+        F: Future<Output = Result<String>>,
+    {
+        let url = self.url(&format!("/~/scry/{}{}.json", app, path));
+        let res = self.send(|client| client.get(&url).timeout(EYRE_TIMEOUT), code).await?;
+        Ok(res.json().await?)
+    }
+
+    fn channel_url(&self) -> String {
+        self.url(&format!("/~/channel/nucleus-{}", Uuid::new_v4().simple()))
+    }
+
+    fn action_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Pokes `app` with `json` in `mark`, and waits for the poke to be acked. Each poke gets a channel of its own,
+    /// deleted once it is acked, so that it can't be held up by or hold up any subscription.
+    pub async fn poke<C, F>(&self, app: &str, mark: &str, json: serde_json::Value, code: C) -> Result<()>
+    where
+        C: Fn() -> F,
+        F: Future<Output = Result<String>>,
+    {
+        let channel = self.channel_url();
+        let id = self.action_id();
+        let poke = serde_json::json!([{
+            "id": id,
+            "action": "poke",
+            "ship": self.ship,
+            "app": app,
+            "mark": mark,
+            "json": json,
+        }]);
+        self.send(|client| client.put(&channel).timeout(EYRE_TIMEOUT).json(&poke), &code).await?;
+        let res = self.send(|client| client.get(&channel).timeout(EYRE_TIMEOUT), &code).await?;
+        let mut events = channel_events(res);
+        let acked = loop {
+            let Some(event) = events.next().await.transpose()? else {
+                break Err(anyhow!("eyre closed the channel before acking the poke"));
+            };
+            if event.data["id"] == id && event.data["response"] == "poke" {
+                break match event.data.get("err") {
+                    Some(err) => Err(anyhow!("{} nacked the poke: {}", app, nack_trace(err))),
+                    None => Ok(()),
+                };
+            }
+        };
+        drop(events);
+        let delete = serde_json::json!([{ "id": self.action_id(), "action": "delete" }]);
+        if let Err(e) = self.send(|client| client.put(&channel).timeout(EYRE_TIMEOUT).json(&delete), &code).await {
+            log::warn!("failed to delete eyre channel on {}: {:#}", self.ship, e);
+        }
+        acked
+    }
+
+    /// Subscribes to `path` of `app`, yielding the facts it sends, until it kicks the subscription or the stream is
+    /// dropped. Each subscription gets a channel of its own, which eyre deletes once it has gone unused for a while.
+    pub async fn subscribe<C, F>(
+        &self,
+        app: &str,
+        path: &str,
+        code: C,
+    ) -> Result<BoxStream<'static, Result<serde_json::Value>>>
+    where
+        C: Fn() -> F,
+        F: Future<Output = Result<String>>,
+    {
+        let channel = self.channel_url();
+        let id = self.action_id();
+        let subscribe = serde_json::json!([{
+            "id": id,
+            "action": "subscribe",
+            "ship": self.ship,
+            "app": app,
+            "path": path,
+        }]);
+        self.send(|client| client.put(&channel).timeout(EYRE_TIMEOUT).json(&subscribe), &code).await?;
+        // Without a timeout, as the stream stays open for as long as the subscription does.
+        let res = self.send(|client| client.get(&channel), &code).await?;
+        let cookie = self.cookie.lock().unwrap().clone().unwrap_or_default();
+        let (client, app, next_id) = (self.client.clone(), app.to_owned(), AtomicU64::new(id + 1));
+        let facts = channel_events(res).and_then(move |event| {
+            // Eyre holds on to events until they are acked, so that a client that reconnects gets them again.
+            let ack = serde_json::json!([{
+                "id": next_id.fetch_add(1, Ordering::Relaxed),
+                "action": "ack",
+                "event-id": event.event_id,
+            }]);
+            let ack = client.request(Method::PUT, &channel).header(header::COOKIE, cookie.clone()).json(&ack);
+            let app = app.clone();
+            async move {
+                if let Err(e) = ack.timeout(EYRE_TIMEOUT).send().await {
+                    log::warn!("failed to ack eyre event {}: {}", event.event_id, e);
+                }
+                if event.data["id"] != id {
+                    return Ok(Response::Other);
+                }
+                match event.data["response"].as_str() {
+                    Some("subscribe") => match event.data.get("err") {
+                        Some(err) => Err(anyhow!("{} refused the subscription: {}", app, nack_trace(err))),
+                        None => Ok(Response::Other),
+                    },
+                    Some("diff") => Ok(Response::Fact(event.data["json"].clone())),
+                    Some("quit") => Ok(Response::Kick),
+                    _ => Ok(Response::Other),
+                }
+            }
+        });
+        Ok(facts
+            .try_take_while(|response| future::ok(!matches!(response, Response::Kick)))
+            .try_filter_map(|response| future::ok(match response {
+                Response::Fact(fact) => Some(fact),
+                _ => None,
+            }))
+            .boxed())
+    }
+}
+
+/// What an event on a subscription's channel says about it.
+enum Response {
+    Fact(serde_json::Value),
+    /// The app ended the subscription.
+    Kick,
+    Other,
+}
+
+/// Eyre sends nacks as a tang of lines of text.
+fn nack_trace(err: &serde_json::Value) -> String {
+    err.as_str().map(str::to_owned).unwrap_or_else(|| err.to_string())
+}
+
+/// Parses an eyre channel's server-sent events, each an `id:` line with the event's id and a `data:` line of JSON.
+fn channel_events(res: reqwest::Response) -> BoxStream<'static, Result<ChannelEvent>> {
+    let chunks = res.bytes_stream().map_err(Error::from).boxed();
+    stream::try_unfold((chunks, Vec::<u8>::new()), |(mut chunks, mut buf)| async move {
+        loop {
+            if let Some(end) = buf.windows(2).position(|window| window == b"\n\n") {
+                let block: Vec<u8> = buf.drain(..end + 2).collect();
+                let block = String::from_utf8_lossy(&block);
+                let mut event_id = None;
+                let mut data = String::new();
+                for line in block.lines() {
+                    if let Some(id) = line.strip_prefix("id:") {
+                        event_id = id.trim().parse().ok();
+                    } else if let Some(line) = line.strip_prefix("data:") {
+                        data.push_str(line.trim_start());
+                    }
+                }
+                // Blocks without both are keepalive comments.
+                if let (Some(event_id), false) = (event_id, data.is_empty()) {
+                    let data = serde_json::from_str(&data)?;
+                    return Ok(Some((ChannelEvent { event_id, data }, (chunks, buf))));
+                }
+                continue;
+            }
+            match chunks.next().await.transpose()? {
+                Some(chunk) => buf.extend_from_slice(&chunk),
+                None => return Ok(None),
+            }
+        }
+    }).boxed()
+}
@@ -1,51 +1,123 @@
 use anyhow::Result;
 use async_std::path::PathBuf;
 use async_std::fs;
+use async_std::io::{ErrorKind, WriteExt};
 use log::error;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct FileLock {
     path: PathBuf,
     released: bool,
+    /// Whether the lock was taken over from a process that died holding it.
+    recovered: bool,
 }
 
-const POLL_INTERVAL_MILLIS: u64 = 50;
+/// A lockfile without readable owner metadata is only presumed stale once it is this old, so that a lock whose owner is
+/// between creating the file and writing to it isn't stolen.
+const UNREADABLE_LOCK_GRACE: Duration = Duration::from_secs(5);
+
+/// Identifies the process holding a lock. The start time guards against the pid having been reused since the lock was
+/// taken.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Owner {
+    pid: u32,
+    start_time: u64,
+}
+
+impl Owner {
+    fn current() -> Option<Self> {
+        let pid = std::process::id();
+        Some(Owner { pid, start_time: process_start_time(pid)? })
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        let mut words = s.split_ascii_whitespace();
+        let pid = words.next()?.parse().ok()?;
+        let start_time = words.next()?.parse().ok()?;
+        Some(Owner { pid, start_time })
+    }
+
+    fn is_alive(&self) -> bool {
+        process_start_time(self.pid) == Some(self.start_time)
+    }
+}
+
+/// Start time of a process in clock ticks since boot, from field 22 of /proc/<pid>/stat.
+fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name field is parenthesized and may contain spaces, so count fields from the last ')'.
+    stat[stat.rfind(')')? + 1..].split_ascii_whitespace().nth(19)?.parse().ok()
+}
 
 impl FileLock {
     pub async fn try_acquire<P: ToOwned<Owned = PathBuf>>(path: P) -> Result<Option<FileLock>> {
         let path = path.to_owned();
 
-        if path.exists().await {
-            return Ok(None);
+        if Self::try_create(&path).await? {
+            return Ok(Some(FileLock { path, released: false, recovered: false }));
         }
 
-        _ = fs::File::create(&path);
+        if Self::recover_if_stale(&path).await? && Self::try_create(&path).await? {
+            return Ok(Some(FileLock { path, released: false, recovered: true }));
+        }
 
-        Ok(Some(FileLock {
-            path: path,
-            released: false,
-        }))
+        Ok(None)
     }
 
-    pub async fn acquire<P: ToOwned<Owned = PathBuf>>(path: P) -> Result<FileLock> {
-        let path = path.to_owned();
+    pub fn recovered(&self) -> bool {
+        self.recovered
+    }
+
+    /// Atomically creates the lockfile and records this process as its owner. Returns false if it already exists.
+    async fn try_create(path: &PathBuf) -> Result<bool> {
+        let mut file = match fs::OpenOptions::new().write(true).create_new(true).open(path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
 
-        while path.exists().await {
-            tokio::time::sleep(tokio::time::Duration::from_millis(POLL_INTERVAL_MILLIS)).await;
+        if let Some(owner) = Owner::current() {
+            file.write_all(format!("{} {}\n", owner.pid, owner.start_time).as_bytes()).await?;
+            file.sync_all().await?;
         }
+        Ok(true)
+    }
+
+    /// Removes the lockfile if the process that took it is gone, e.g. because the orchestrator was killed without a
+    /// chance to clean up. Returns whether the lock was recovered.
+    async fn recover_if_stale(path: &PathBuf) -> Result<bool> {
+        let contents = match fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e.into()),
+        };
 
-        _ = fs::File::create(&path);
+        let reason = match Owner::parse(&contents) {
+            Some(owner) if owner.is_alive() => return Ok(false),
+            Some(owner) => format!("owning process {} is no longer running", owner.pid),
+            None => {
+                let age = fs::metadata(path).await?.modified()?.elapsed().unwrap_or_default();
+                if age < UNREADABLE_LOCK_GRACE {
+                    return Ok(false);
+                }
+                "lockfile records no owning process".to_owned()
+            },
+        };
 
-        Ok(FileLock {
-            path: path,
-            released: false,
-        })
+        match fs::remove_file(path).await {
+            Ok(()) => {},
+            Err(e) if e.kind() == ErrorKind::NotFound => {},
+            Err(e) => return Err(e.into()),
+        }
+        log::warn!("recovered stale lock {}: {}", path.to_string_lossy(), reason);
+        Ok(true)
     }
 
     pub async fn release(mut self) -> Result<()> {
-        let result = fs::remove_file(&self.path).await?;
+        fs::remove_file(&self.path).await?;
         self.released = true;
-        Ok(result)
+        Ok(())
     }
 }
 
@@ -61,4 +133,4 @@ impl Drop for FileLock {
             }
         }
     }
-}
\ No newline at end of file
+}
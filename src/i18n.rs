@@ -0,0 +1,83 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::{self, HeaderValue, LanguageTag, Preference};
+use actix_web::ResponseError;
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+
+use crate::error::ApiError;
+
+lazy_static! {
+    /// Translations of API error messages, from a directory of catalogs named after their languages, such as `de.json`
+    /// or `pt-br.json`, each mapping error codes to messages. Unset for English only.
+    pub static ref MESSAGE_CATALOGS: Catalogs = env::var_os("NUCLEUS_MESSAGE_CATALOGS")
+        .map(|dir| Catalogs::load(Path::new(&dir)).unwrap())
+        .unwrap_or_default();
+}
+
+/// Messages by error code, by lowercase language tag.
+#[derive(Debug, Default)]
+pub struct Catalogs(HashMap<String, HashMap<String, String>>);
+
+impl Catalogs {
+    fn load(dir: &Path) -> Result<Self> {
+        let mut catalogs = HashMap::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let Some(language) = path.file_stem().and_then(|stem| stem.to_str()) else { continue };
+            let messages: HashMap<String, String> = serde_json::from_slice(&std::fs::read(&path)?)
+                .map_err(|e| anyhow!("invalid message catalog {}: {}", path.to_string_lossy(), e))?;
+            catalogs.insert(language.to_ascii_lowercase(), messages);
+        }
+        log::info!("loaded message catalogs for {} languages", catalogs.len());
+        Ok(Catalogs(catalogs))
+    }
+
+    /// The message for `code` in the first of `languages`, most preferred first, that has a catalog with one, and that
+    /// catalog's language. Each language falls back to its primary subtag, such as `pt-br` to `pt`. None if English,
+    /// which the messages are written in, comes first.
+    pub fn translate(&self, languages: &[Preference<LanguageTag>], code: &str) -> Option<(&str, &str)> {
+        for language in languages {
+            let Preference::Specific(tag) = language else { return None };
+            let tag = tag.as_str().to_ascii_lowercase();
+            let primary = tag.split('-').next().unwrap_or_default();
+            for candidate in [tag.as_str(), primary] {
+                if let Some((language, messages)) = self.0.get_key_value(candidate) {
+                    if let Some(message) = messages.get(code) {
+                        return Some((language, message));
+                    }
+                }
+            }
+            if primary == "en" {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+/// Replaces the message of an API error response with its translation into the client's preferred language, from its
+/// `Accept-Language` header, if a catalog has one.
+pub fn localize<B: MessageBody>(
+    res: ServiceResponse<B>,
+    languages: &[Preference<LanguageTag>],
+) -> ServiceResponse<EitherBody<B>> {
+    let localized = res.response().error()
+        .and_then(|e| e.as_error::<ApiError>())
+        .and_then(|e| {
+            let (language, message) = MESSAGE_CATALOGS.translate(languages, e.code())?;
+            let mut response = e.translated(message).error_response();
+            response.headers_mut().insert(header::CONTENT_LANGUAGE, HeaderValue::from_str(language).ok()?);
+            Some(response)
+        });
+    match localized {
+        Some(response) => res.into_response(response).map_into_right_body(),
+        None => res.map_into_left_body(),
+    }
+}
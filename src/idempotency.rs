@@ -0,0 +1,85 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::util;
+
+lazy_static! {
+    /// How long a completed request's `Idempotency-Key` is remembered, and retries with it replayed.
+    pub static ref IDEMPOTENCY_KEY_TTL: Duration = env::var_os("NUCLEUS_IDEMPOTENCY_KEY_TTL")
+        .map(|s| util::parse_duration(s.to_str().unwrap()).unwrap())
+        .unwrap_or(Duration::from_secs(24 * 60 * 60));
+}
+
+/// Longest `Idempotency-Key` accepted.
+pub const MAX_KEY_LEN: usize = 255;
+
+#[derive(Clone, Copy, Debug)]
+enum Entry {
+    /// A request with this key is still being handled.
+    InFlight,
+    /// A request with this key started the given job.
+    Completed { job_id: Uuid, at: Instant },
+}
+
+/// What to do with a request carrying an `Idempotency-Key`.
+pub enum Begin {
+    /// The key is new. Handle the request, then complete the reservation with its outcome.
+    Started(Reservation),
+    /// An earlier request with this key started this job; respond as it did.
+    Replay(Uuid),
+    /// An earlier request with this key is still being handled, e.g. its upload is still arriving.
+    InFlight,
+}
+
+/// Remembers the outcome of recent requests carrying an `Idempotency-Key`, so that a client retrying a request whose
+/// response it never saw gets the original response instead of a duplicate pier.
+#[derive(Debug, Default)]
+pub struct IdempotencyKeys {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl IdempotencyKeys {
+    pub fn begin(self: &Arc<Self>, key: &str) -> Begin {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| match entry {
+            Entry::InFlight => true,
+            Entry::Completed { at, .. } => at.elapsed() < *IDEMPOTENCY_KEY_TTL,
+        });
+
+        match entries.get(key) {
+            Some(Entry::Completed { job_id, .. }) => Begin::Replay(*job_id),
+            Some(Entry::InFlight) => Begin::InFlight,
+            None => {
+                entries.insert(key.to_owned(), Entry::InFlight);
+                Begin::Started(Reservation { keys: self.clone(), key: Some(key.to_owned()) })
+            },
+        }
+    }
+}
+
+/// A key claimed by an in-flight request. If it is dropped without being completed, e.g. because the request failed
+/// before starting a job, the key is released so that a retry is handled afresh.
+#[derive(Debug)]
+pub struct Reservation {
+    keys: Arc<IdempotencyKeys>,
+    key: Option<String>,
+}
+
+impl Reservation {
+    pub fn complete(mut self, job_id: Uuid) {
+        let key = self.key.take().unwrap();
+        self.keys.entries.lock().unwrap().insert(key, Entry::Completed { job_id, at: Instant::now() });
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.keys.entries.lock().unwrap().remove(&key);
+        }
+    }
+}
@@ -0,0 +1,482 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use async_std::path::{Path, PathBuf};
+use std::ffi::OsStr;
+use std::fmt::{self, Display};
+
+use crate::util::CopyReport;
+
+/// How many directories deep below the unpack root to look for a pier. Deep enough for the layouts other hosts
+/// produce (e.g. `backup/piers/sampel-palnet/`), shallow enough that we don't walk a whole pier looking for another.
+const MAX_SEARCH_DEPTH: usize = 4;
+
+/// Where an archive import stands, persisted in its dry dock entry so that an import interrupted by a restart can be
+/// picked up again. The file is removed once the pier has been extracted.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "phase", rename_all = "camelCase")]
+pub enum ImportProgress {
+    /// The archive is still being copied into the dry dock. This can't be resumed, as the upload is gone by then.
+    Receiving,
+    /// The archive is stored in full, and at least `entries_completed` of its entries have been extracted.
+    #[serde(rename_all = "camelCase")]
+    Extracting { entries_completed: usize },
+}
+
+impl ImportProgress {
+    fn path(meta_path: &std::path::Path) -> std::path::PathBuf {
+        meta_path.join("import.json")
+    }
+
+    pub async fn load(meta_path: &Path) -> Result<Option<Self>> {
+        match async_std::fs::read(Self::path(meta_path.as_ref())).await {
+            Ok(buf) => Ok(Some(serde_json::from_slice(&buf)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Saves the progress without blocking on an async runtime, for use from the extraction thread.
+    pub fn save_sync(&self, meta_path: &std::path::Path) -> Result<()> {
+        let path = Self::path(meta_path);
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        std::fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    pub async fn save(&self, meta_path: &Path) -> Result<()> {
+        let progress = *self;
+        let meta_path = meta_path.to_owned();
+        tokio::task::spawn_blocking(move || progress.save_sync(meta_path.as_ref())).await?
+    }
+
+    pub async fn clear(meta_path: &Path) -> Result<()> {
+        match async_std::fs::remove_file(Self::path(meta_path.as_ref())).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What was done to an archive's contents to turn it into a pier. Returned in the import job's result.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub layout: ArchiveLayout,
+    /// Paths, relative to the pier, of junk files removed before the pier was first booted here.
+    pub stripped: Vec<String>,
+}
+
+/// Where a pier was found inside an unpacked archive, relative to the unpack root.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ArchiveLayout {
+    /// The archive's root is the pier itself, i.e. `.urb` sits at the top level.
+    Bare,
+    /// The pier is the archive's single top-level directory, as produced by `tar -czf x.tgz sampel-palnet`.
+    Wrapped,
+    /// The pier is nested under one or more further directories, possibly alongside loose metadata files.
+    Nested,
+    /// The archive holds the contents of a pier's `.urb` directory but not the directory itself, as produced by
+    /// archiving from inside it. The pier is rebuilt around it.
+    LooseUrb,
+}
+
+/// Why an uploaded archive couldn't be imported as a pier. Each case is the uploader's to fix.
+#[derive(Debug)]
+pub enum InvalidPierArchiveError {
+    /// No directory containing `.urb` was found. Holds the archive's top-level entries, debris excluded, to show what
+    /// was uploaded in place of a pier.
+    NoPier { top_level: Vec<String> },
+    /// More than one pier was found, at these paths relative to the archive's root.
+    MultiplePiers { piers: Vec<String> },
+    /// The archive ended early or is corrupt, as reported while reading it.
+    Truncated { detail: String },
+    /// Entries that would have been extracted outside the pier, being absolute or containing `..`.
+    DisallowedPaths { paths: Vec<String> },
+}
+
+/// How many paths an error message lists before summarizing the rest.
+const MAX_LISTED_PATHS: usize = 10;
+
+fn list_paths(paths: &[String]) -> String {
+    let mut list = paths.iter().take(MAX_LISTED_PATHS).cloned().collect::<Vec<_>>().join(", ");
+    if paths.len() > MAX_LISTED_PATHS {
+        list.push_str(&format!(" and {} more", paths.len() - MAX_LISTED_PATHS));
+    }
+    list
+}
+
+impl InvalidPierArchiveError {
+    /// The stable error code clients can match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            InvalidPierArchiveError::NoPier { .. } => "noPierInArchive",
+            InvalidPierArchiveError::MultiplePiers { .. } => "multiplePiersInArchive",
+            InvalidPierArchiveError::Truncated { .. } => "archiveTruncated",
+            InvalidPierArchiveError::DisallowedPaths { .. } => "archivePathsDisallowed",
+        }
+    }
+}
+
+impl Display for InvalidPierArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidPierArchiveError::NoPier { top_level } if top_level.is_empty() => {
+                write!(f, "no pier (a directory containing .urb) found in archive; the archive is empty")
+            },
+            InvalidPierArchiveError::NoPier { top_level } => write!(
+                f,
+                "no pier (a directory containing .urb) found in archive; its top level contains: {}",
+                list_paths(top_level),
+            ),
+            InvalidPierArchiveError::MultiplePiers { piers } => {
+                write!(f, "archive contains {} piers, expected one: {}", piers.len(), list_paths(piers))
+            },
+            InvalidPierArchiveError::Truncated { detail } => {
+                write!(f, "archive is truncated or corrupt, re-upload it: {}", detail)
+            },
+            InvalidPierArchiveError::DisallowedPaths { paths } => write!(
+                f,
+                "archive contains {} entries with absolute or `..` paths: {}",
+                paths.len(),
+                list_paths(paths),
+            ),
+        }
+    }
+}
+
+impl StdError for InvalidPierArchiveError {}
+
+/// How a pier directory already on the host is taken into the harbor.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AdoptMode {
+    /// Renames the directory into the harbor. Instant, but only within one filesystem, and the original path is gone.
+    #[default]
+    Move,
+    /// Copies the directory, reflinking where the filesystem supports it, and leaves the original alone. Hard links
+    /// aren't offered: the runtime writes to its files in place, so the original would change along with the copy.
+    Copy,
+}
+
+/// What was done to adopt a pier directory. Returned in the adopt job's result.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdoptReport {
+    pub mode: AdoptMode,
+    /// Paths, relative to the pier, of junk files removed before the pier was first booted here.
+    pub stripped: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub copied: Option<CopyReport>,
+}
+
+/// Why a directory on the host can't be adopted as a pier.
+#[derive(Debug)]
+pub struct NotAdoptableError {
+    pub path: String,
+    pub reason: String,
+}
+
+impl Display for NotAdoptableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot adopt {}: {}", self.path, self.reason)
+    }
+}
+
+impl StdError for NotAdoptableError {}
+
+/// Checks that `path` is a pier the orchestrator can take over: an absolute path to a directory containing `.urb`,
+/// outside the harbor, that no runtime is running. Returns the path with symlinks resolved.
+pub async fn check_adoptable(path: &Path, harbor: &Path) -> Result<PathBuf> {
+    let not_adoptable = |reason: String| NotAdoptableError { path: path.to_string_lossy().into_owned(), reason };
+    if !path.is_absolute() {
+        bail!(not_adoptable("the path must be absolute".to_owned()));
+    }
+    let path = path.canonicalize().await.map_err(|e| not_adoptable(e.to_string()))?;
+    if !path.is_dir().await {
+        bail!(not_adoptable("not a directory".to_owned()));
+    }
+    if !path.join(".urb").is_dir().await {
+        bail!(not_adoptable("not a pier: there is no .urb directory in it".to_owned()));
+    }
+    let harbor = harbor.canonicalize().await?;
+    if path.starts_with(&harbor) || harbor.starts_with(&path) {
+        bail!(not_adoptable("the path overlaps the harbor".to_owned()));
+    }
+    if let Ok(pid) = async_std::fs::read_to_string(path.join(".vere.lock")).await {
+        if let Ok(pid) = pid.trim().parse::<libc::pid_t>() {
+            if unsafe { libc::kill(pid, 0) } == 0 {
+                bail!(not_adoptable(format!("a runtime is running it as process {}; stop it first", pid)));
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// Entries that archivers and operating systems add alongside the files the user meant to archive. They're never a
+/// pier, and are skipped when searching for one.
+fn is_archiver_debris(name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    // macOS Archive Utility stores resource forks under __MACOSX/ and as ._name AppleDouble files.
+    name == "__MACOSX"
+        || name.starts_with("._")
+        || name == ".Spotlight-V100"
+        || name == ".Trashes"
+        || name == ".fseventsd"
+        || name == "lost+found"
+}
+
+fn is_pier(dir: &std::path::Path) -> bool {
+    dir.join(".urb").is_dir()
+}
+
+/// Whether a directory looks like the inside of a `.urb` directory: the event log and the snapshot.
+fn is_urb_contents(dir: &std::path::Path) -> bool {
+    dir.join("log").is_dir() && dir.join("chk").is_dir()
+}
+
+fn search(dir: &std::path::Path, depth: usize, found: &mut Vec<(std::path::PathBuf, usize)>) -> std::io::Result<()> {
+    if is_pier(dir) {
+        found.push((dir.to_owned(), depth));
+        return Ok(());
+    }
+    if depth == MAX_SEARCH_DEPTH {
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        // file_type() doesn't follow symlinks, so a symlink can't lead the search outside the unpack root.
+        if !entry.file_type()?.is_dir() || is_archiver_debris(&entry.file_name()) {
+            continue;
+        }
+        search(&entry.path(), depth + 1, found)?;
+    }
+    Ok(())
+}
+
+/// The directories directly inside `dir`, debris excluded.
+fn subdirectories(dir: &std::path::Path) -> std::io::Result<Vec<std::path::PathBuf>> {
+    let mut dirs = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && !is_archiver_debris(&entry.file_name()) {
+            dirs.push(entry.path());
+        }
+    }
+    Ok(dirs)
+}
+
+fn top_level_entries(dir: &std::path::Path) -> std::io::Result<Vec<String>> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if is_archiver_debris(&entry.file_name()) {
+            continue;
+        }
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            name.push('/');
+        }
+        entries.push(name);
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+fn find(root: &std::path::Path) -> Result<(std::path::PathBuf, ArchiveLayout)> {
+    let mut found = Vec::new();
+    search(root, 0, &mut found)?;
+
+    match found.len() {
+        1 => {
+            let (path, depth) = found.pop().unwrap();
+            let layout = match depth {
+                0 => ArchiveLayout::Bare,
+                1 => ArchiveLayout::Wrapped,
+                _ => ArchiveLayout::Nested,
+            };
+            return Ok((path, layout));
+        },
+        0 => {},
+        _ => {
+            let mut piers: Vec<String> = found.iter()
+                .map(|(path, _)| path.strip_prefix(root).unwrap_or(path).to_string_lossy().into_owned())
+                .map(|path| if path.is_empty() { ".".to_owned() } else { path })
+                .collect();
+            piers.sort();
+            bail!(InvalidPierArchiveError::MultiplePiers { piers });
+        },
+    }
+
+    // No .urb anywhere: the archive may hold a .urb directory's contents, at the root or in a single wrapper.
+    if is_urb_contents(root) {
+        return Ok((root.to_owned(), ArchiveLayout::LooseUrb));
+    }
+    if let [wrapper] = &subdirectories(root)?[..] {
+        if is_urb_contents(wrapper) {
+            return Ok((wrapper.clone(), ArchiveLayout::LooseUrb));
+        }
+    }
+
+    bail!(InvalidPierArchiveError::NoPier { top_level: top_level_entries(root)? })
+}
+
+/// Locates the pier directory inside an unpacked archive, tolerating the layouts produced by other hosting providers
+/// and tools: the pier at the root, wrapped in any number of directories, accompanied by metadata files, mixed with
+/// macOS resource forks, or only the contents of its `.urb` directory. Fails with an `InvalidPierArchiveError` if
+/// there is no pier, or more than one.
+pub async fn find_extracted_pier(unpack_path: &Path) -> Result<(PathBuf, ArchiveLayout)> {
+    let root = unpack_path.to_owned();
+    let (path, layout) = tokio::task::spawn_blocking(move || find(root.as_ref())).await??;
+    Ok((PathBuf::from(path), layout))
+}
+
+/// Files that are never part of a healthy pier: desktop metadata, editor swap and backup files, and state the runtime
+/// leaves behind for its own running instance, which would mislead a fresh one.
+fn is_junk(name: &OsStr, file_type: std::fs::FileType) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    if file_type.is_socket() {
+        return true;
+    }
+    let name = name.to_string_lossy();
+    matches!(&*name, ".DS_Store" | "Thumbs.db" | "desktop.ini" | "__MACOSX" | ".http.ports" | ".vere.lock")
+        || name.starts_with("._")
+        // vim swap files, emacs lock and autosave files, and backup files
+        || name.ends_with(".swp") || name.ends_with(".swo") || name.ends_with(".swx")
+        || name.starts_with(".#")
+        || (name.starts_with('#') && name.ends_with('#'))
+        || name.ends_with('~')
+}
+
+fn strip(root: &std::path::Path, dir: &std::path::Path, stripped: &mut Vec<String>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        if is_junk(&entry.file_name(), file_type) {
+            if file_type.is_dir() {
+                std::fs::remove_dir_all(&path)?;
+            } else {
+                std::fs::remove_file(&path)?;
+            }
+            stripped.push(path.strip_prefix(root).unwrap_or(&path).to_string_lossy().into_owned());
+        } else if file_type.is_dir() {
+            strip(root, &path, stripped)?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes junk files from an extracted pier so that it starts clean, returning the paths removed relative to the pier.
+pub async fn strip_junk(pier_path: &Path) -> Result<Vec<String>> {
+    let root = pier_path.to_owned();
+    let mut stripped = tokio::task::spawn_blocking(move || {
+        let mut stripped = Vec::new();
+        strip(root.as_ref(), root.as_ref(), &mut stripped)?;
+        Ok::<_, std::io::Error>(stripped)
+    }).await??;
+    stripped.sort();
+
+    if !stripped.is_empty() {
+        log::info!("stripped {} junk files from imported pier at {}", stripped.len(), pier_path.to_string_lossy());
+    }
+    Ok(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    /// Creates each path under `root`: a trailing slash makes a directory, anything else an empty file.
+    fn layout(root: &std::path::Path, paths: &[&str]) {
+        for path in paths {
+            let full = root.join(path.trim_end_matches('/'));
+            if path.ends_with('/') {
+                std::fs::create_dir_all(&full).unwrap();
+            } else {
+                std::fs::create_dir_all(full.parent().unwrap()).unwrap();
+                std::fs::write(&full, b"").unwrap();
+            }
+        }
+    }
+
+    fn find_in(paths: &[&str]) -> (TempDir, Result<(std::path::PathBuf, ArchiveLayout)>) {
+        let root = TempDir::new();
+        layout(&root, paths);
+        let found = find(&root);
+        (root, found)
+    }
+
+    fn invalid(result: Result<(std::path::PathBuf, ArchiveLayout)>) -> InvalidPierArchiveError {
+        result.unwrap_err().downcast().unwrap()
+    }
+
+    #[test]
+    fn finds_bare_pier() {
+        let (root, found) = find_in(&[".urb/log/", ".urb/chk/"]);
+        assert_eq!(found.unwrap(), (root.to_owned(), ArchiveLayout::Bare));
+    }
+
+    #[test]
+    fn finds_wrapped_pier() {
+        let (root, found) = find_in(&["sampel-palnet/.urb/log/", "sampel-palnet/.urb/chk/"]);
+        assert_eq!(found.unwrap(), (root.join("sampel-palnet"), ArchiveLayout::Wrapped));
+    }
+
+    #[test]
+    fn finds_nested_pier() {
+        let (root, found) = find_in(&["backup/README.txt", "backup/2023/sampel-palnet/.urb/log/"]);
+        assert_eq!(found.unwrap(), (root.join("backup/2023/sampel-palnet"), ArchiveLayout::Nested));
+    }
+
+    #[test]
+    fn ignores_archiver_debris() {
+        let (root, found) = find_in(&[
+            "__MACOSX/sampel-palnet/.urb/log/",
+            "._sampel-palnet/.urb/",
+            ".DS_Store",
+            "sampel-palnet/.DS_Store",
+            "sampel-palnet/.urb/log/",
+        ]);
+        assert_eq!(found.unwrap(), (root.join("sampel-palnet"), ArchiveLayout::Wrapped));
+    }
+
+    #[test]
+    fn finds_loose_urb_contents() {
+        let (root, found) = find_in(&["log/", "chk/", "get/"]);
+        assert_eq!(found.unwrap(), (root.to_owned(), ArchiveLayout::LooseUrb));
+
+        let (root, found) = find_in(&["urb/log/", "urb/chk/", "__MACOSX/urb/log/", ".DS_Store"]);
+        assert_eq!(found.unwrap(), (root.join("urb"), ArchiveLayout::LooseUrb));
+    }
+
+    #[test]
+    fn rejects_multiple_piers() {
+        let (_root, found) = find_in(&["sampel-palnet/.urb/log/", "a/b/marzod/.urb/", "a/zod/.urb/"]);
+        match invalid(found) {
+            InvalidPierArchiveError::MultiplePiers { piers } => {
+                assert_eq!(piers, ["a/b/marzod", "a/zod", "sampel-palnet"]);
+            },
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn rejects_archive_without_pier() {
+        let (_root, found) = find_in(&[]);
+        match invalid(found) {
+            InvalidPierArchiveError::NoPier { top_level } => assert!(top_level.is_empty()),
+            err => panic!("unexpected error: {}", err),
+        }
+
+        let (_root, found) = find_in(&["notes.txt", "photos/cat.jpg", "__MACOSX/", "log/"]);
+        match invalid(found) {
+            InvalidPierArchiveError::NoPier { top_level } => assert_eq!(top_level, ["log/", "notes.txt", "photos/"]),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+}
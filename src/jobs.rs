@@ -0,0 +1,153 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use time::OffsetDateTime;
+
+use crate::error::ApiError;
+use crate::metrics::Histogram;
+
+/// Finished jobs are forgotten this long after they complete.
+const FINISHED_JOB_RETENTION: time::Duration = time::Duration::hours(24);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobState {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+    pub id: Uuid,
+    pub kind: String,
+    pub pier: Option<String>,
+    pub state: JobState,
+    /// Human-readable description of the step the job is currently on.
+    pub progress: Option<String>,
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    /// For failed jobs, the code an error response with the same cause would carry, e.g. `noPierInArchive`.
+    pub error_code: Option<&'static str>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub finished_at: Option<OffsetDateTime>,
+}
+
+/// Tracks long-running operations (imports, exports, boots) so that HTTP handlers can return immediately with a job
+/// id that clients poll for completion.
+#[derive(Debug, Default)]
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<Uuid, JobStatus>>,
+    /// How long finished jobs ran, by kind and final state. Unlike `jobs`, this is never pruned.
+    durations: Mutex<HashMap<(String, JobState), Histogram>>,
+}
+
+/// Given to a job's body so it can report progress against its registry entry.
+#[derive(Clone, Debug)]
+pub struct JobHandle {
+    id: Uuid,
+    registry: Arc<JobRegistry>,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn progress<S: Into<String>>(&self, progress: S) {
+        self.registry.update(self.id, |job| job.progress = Some(progress.into()));
+    }
+}
+
+impl JobRegistry {
+    /// Registers a job and runs `body` on the current arbiter. The value the body resolves to becomes the job's result;
+    /// an error or panic marks the job failed.
+    pub fn spawn<F, Fut>(self: &Arc<Self>, kind: &str, pier: Option<String>, body: F) -> Uuid
+        where F: 'static + FnOnce(JobHandle) -> Fut,
+              Fut: 'static + Future<Output = Result<serde_json::Value>>,
+    {
+        let id = Uuid::new_v4();
+
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            let horizon = OffsetDateTime::now_utc() - FINISHED_JOB_RETENTION;
+            jobs.retain(|_, job| job.finished_at.is_none_or(|at| at > horizon));
+            jobs.insert(id, JobStatus {
+                id,
+                kind: kind.to_owned(),
+                pier,
+                state: JobState::Pending,
+                progress: None,
+                result: None,
+                error: None,
+                error_code: None,
+                created_at: OffsetDateTime::now_utc(),
+                finished_at: None,
+            });
+        }
+
+        let handle = JobHandle { id, registry: self.clone() };
+        let registry = self.clone();
+        actix_web::rt::spawn(async move {
+            registry.update(id, |job| job.state = JobState::Running);
+            let started = Instant::now();
+
+            let outcome = AssertUnwindSafe(async move { body(handle).await })
+                .catch_unwind()
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("job panicked")));
+
+            registry.update(id, |job| {
+                job.finished_at = Some(OffsetDateTime::now_utc());
+                match outcome {
+                    Ok(result) => {
+                        job.state = JobState::Succeeded;
+                        job.result = Some(result);
+                    },
+                    Err(e) => {
+                        log::error!("job {} ({}) failed: {:#}", id, job.kind, e);
+                        job.state = JobState::Failed;
+                        job.error = Some(format!("{:#}", e));
+                        job.error_code = Some(ApiError::from(e).code());
+                    },
+                }
+                registry.durations.lock().unwrap()
+                    .entry((job.kind.clone(), job.state))
+                    .or_default()
+                    .observe(started.elapsed().as_secs_f64());
+            });
+        });
+
+        id
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<JobStatus> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<JobStatus> {
+        let mut result: Vec<JobStatus> = self.jobs.lock().unwrap().values().cloned().collect();
+        result.sort_by_key(|job| job.created_at);
+        result
+    }
+
+    /// Run time histograms of finished jobs, by kind and final state.
+    pub fn durations(&self) -> Vec<(String, JobState, Histogram)> {
+        self.durations.lock().unwrap().iter()
+            .map(|((kind, state), histogram)| (kind.clone(), *state, histogram.clone()))
+            .collect()
+    }
+
+    fn update<F: FnOnce(&mut JobStatus)>(&self, id: Uuid, f: F) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            f(job);
+        }
+    }
+}
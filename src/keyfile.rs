@@ -0,0 +1,359 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::fmt::{self, Display};
+use std::rc::Rc;
+
+use crate::patp;
+
+/// Why an uploaded keyfile was rejected.
+#[derive(Debug)]
+pub enum InvalidKeyfileError {
+    /// The file isn't a jammed noun in @uw, or the noun isn't shaped like a keyfile.
+    Malformed(String),
+    /// The keyfile is for a different ship than the one it was uploaded for.
+    WrongShip { expected: String, found: String },
+}
+
+impl InvalidKeyfileError {
+    /// The stable error code clients can match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            InvalidKeyfileError::Malformed(_) => "invalidKeyfile",
+            InvalidKeyfileError::WrongShip { .. } => "keyfileShipMismatch",
+        }
+    }
+}
+
+impl Display for InvalidKeyfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidKeyfileError::Malformed(reason) => write!(f, "not an urbit keyfile: {}", reason),
+            InvalidKeyfileError::WrongShip { expected, found } => {
+                write!(f, "keyfile is for ~{}, not ~{}", found, expected)
+            },
+        }
+    }
+}
+
+impl StdError for InvalidKeyfileError {}
+
+fn malformed<S: Into<String>>(reason: S) -> Error {
+    InvalidKeyfileError::Malformed(reason.into()).into()
+}
+
+/// Decodes hoon's @uw: `0w` followed by base-64 digits in dot-separated groups of five, most significant first.
+/// Returns the atom's bytes, least significant first.
+fn parse_uw(text: &str) -> Result<Vec<u8>> {
+    let digits = text.strip_prefix("0w").ok_or_else(|| malformed("expected @uw text starting with 0w"))?;
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in digits.chars().filter(|c| *c != '.') {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '-' => 62,
+            '~' => 63,
+            _ => return Err(malformed(format!("invalid @uw digit {:?}", c))),
+        };
+        // Shift the whole atom up by six bits and add the digit.
+        let mut carry = digit;
+        for byte in bytes.iter_mut() {
+            let shifted = ((*byte as u32) << 6) | carry;
+            *byte = shifted as u8;
+            carry = shifted >> 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    Ok(bytes)
+}
+
+#[derive(Debug)]
+enum Noun {
+    /// Bytes, least significant first.
+    Atom(Vec<u8>),
+    Cell(Rc<Noun>, Rc<Noun>),
+}
+
+impl Noun {
+    fn as_u128(&self) -> Option<u128> {
+        let Noun::Atom(bytes) = self else { return None };
+        let len = bytes.iter().rposition(|byte| *byte != 0).map_or(0, |idx| idx + 1);
+        if len > 16 {
+            return None;
+        }
+        Some(bytes[..len].iter().rev().fold(0, |n, byte| (n << 8) | *byte as u128))
+    }
+}
+
+/// Reads a jammed noun bit by bit, least significant first, like hoon's `+cue`.
+struct Cue<'a> {
+    bytes: &'a [u8],
+    /// Nouns already decoded, by the bit offset they started at, for back-references.
+    seen: std::collections::HashMap<u64, Rc<Noun>>,
+}
+
+impl<'a> Cue<'a> {
+    fn bit(&self, at: u64) -> Result<bool> {
+        let byte = self.bytes.get((at / 8) as usize).ok_or_else(|| malformed("jammed noun ends early"))?;
+        Ok((byte >> (at % 8)) & 1 == 1)
+    }
+
+    fn bits(&self, at: u64, len: u64) -> Result<Vec<u8>> {
+        if at.checked_add(len).is_none_or(|end| end > self.bytes.len() as u64 * 8) {
+            return Err(malformed("jammed noun ends early"));
+        }
+        let mut out = vec![0u8; len.div_ceil(8) as usize];
+        for i in 0..len {
+            if self.bit(at + i)? {
+                out[(i / 8) as usize] |= 1 << (i % 8);
+            }
+        }
+        Ok(out)
+    }
+
+    fn small(&self, at: u64, len: u64) -> Result<u64> {
+        if len > 64 {
+            return Err(malformed("jammed noun has an implausibly long length"));
+        }
+        Ok(self.bits(at, len)?.iter().rev().fold(0, |n, byte| (n << 8) | *byte as u64))
+    }
+
+    /// Decodes a length-prefixed atom at `at`, returning how many bits it took up and the atom.
+    fn rub(&self, at: u64) -> Result<(u64, Vec<u8>)> {
+        let mut zeros = 0;
+        while !self.bit(at + zeros)? {
+            zeros += 1;
+        }
+        if zeros == 0 {
+            return Ok((1, Vec::new()));
+        }
+        let len_at = at + zeros + 1;
+        let len = self.small(len_at, zeros - 1)? | 1 << (zeros - 1);
+        Ok((zeros + zeros + len, self.bits(len_at + zeros - 1, len)?))
+    }
+
+    /// Decodes the noun at `at`, returning how many bits it took up and the noun.
+    fn noun(&mut self, at: u64) -> Result<(u64, Rc<Noun>)> {
+        let (size, noun) = if !self.bit(at)? {
+            let (size, atom) = self.rub(at + 1)?;
+            (size + 1, Rc::new(Noun::Atom(atom)))
+        } else if !self.bit(at + 1)? {
+            let (head_size, head) = self.noun(at + 2)?;
+            let (tail_size, tail) = self.noun(at + 2 + head_size)?;
+            (2 + head_size + tail_size, Rc::new(Noun::Cell(head, tail)))
+        } else {
+            let (size, offset) = self.rub(at + 2)?;
+            let offset = Noun::Atom(offset).as_u128()
+                .and_then(|offset| u64::try_from(offset).ok())
+                .ok_or_else(|| malformed("jammed noun has an invalid back-reference"))?;
+            let noun = self.seen.get(&offset).cloned()
+                .ok_or_else(|| malformed("jammed noun has an invalid back-reference"))?;
+            return Ok((size + 2, noun));
+        };
+        self.seen.insert(at, noun.clone());
+        Ok((size, noun))
+    }
+}
+
+fn cue(bytes: &[u8]) -> Result<Rc<Noun>> {
+    Ok(Cue { bytes, seen: Default::default() }.noun(0)?.1)
+}
+
+/// What a keyfile holds: the ship it belongs to, and its latest life and the networking key (a `+ring:jael`,
+/// least significant byte first) for that life.
+#[derive(Debug)]
+pub struct Keyfile {
+    pub ship: u128,
+    pub life: u64,
+    pub ring: Vec<u8>,
+}
+
+fn life_and_ring(lyf: &Noun, key: &Noun) -> Result<(u64, Vec<u8>)> {
+    let life = lyf.as_u128()
+        .and_then(|life| u64::try_from(life).ok())
+        .ok_or_else(|| malformed("life is not an atom of at most 64 bits"))?;
+    let Noun::Atom(ring) = key else {
+        return Err(malformed("networking key is not an atom"));
+    };
+    Ok((life, ring.clone()))
+}
+
+/// Parses a keyfile. Keyfiles are a jammed `+seed:jael`, `[who=ship lyf=life key=ring sig=...]`, or a jammed
+/// `+feed:jael`, `[[%vers ~] who=ship kyz=(list [lyf=life key=ring])]`, printed in @uw.
+pub fn parse(contents: &str) -> Result<Keyfile> {
+    let jammed = parse_uw(contents.trim())?;
+    let noun = cue(&jammed)?;
+    let Noun::Cell(head, tail) = &*noun else {
+        return Err(malformed("expected a cell"));
+    };
+    let (who, keys) = match (&**head, &**tail) {
+        (Noun::Atom(_), Noun::Cell(lyf, rest)) => {
+            let Noun::Cell(key, _) = &**rest else { return Err(malformed("expected a networking key")) };
+            (head.clone(), life_and_ring(lyf, key)?)
+        },
+        (Noun::Cell(..), Noun::Cell(who, kyz)) => {
+            // The latest life is the one the ship boots into.
+            let mut latest: Option<(u64, Vec<u8>)> = None;
+            let mut list = kyz.clone();
+            while let Noun::Cell(item, rest) = &*list.clone() {
+                let Noun::Cell(lyf, key) = &**item else { return Err(malformed("expected a life and key")) };
+                let keys = life_and_ring(lyf, key)?;
+                if latest.as_ref().is_none_or(|(life, _)| keys.0 > *life) {
+                    latest = Some(keys);
+                }
+                list = rest.clone();
+            }
+            (who.clone(), latest.ok_or_else(|| malformed("keyfile has no keys"))?)
+        },
+        _ => return Err(malformed("expected a ship after the keyfile version")),
+    };
+    let ship = who.as_u128().ok_or_else(|| malformed("ship is not an atom of at most 128 bits"))?;
+    if ship > u64::MAX as u128 {
+        return Err(malformed("comets don't have keyfiles"));
+    }
+    let (life, ring) = keys;
+    Ok(Keyfile { ship, life, ring })
+}
+
+/// Finds the ship a keyfile belongs to.
+pub fn parse_ship(contents: &str) -> Result<u128> {
+    Ok(parse(contents)?.ship)
+}
+
+/// Checks that a keyfile is well formed and belongs to the ship `name`, with or without its leading sig.
+pub fn check(contents: &str, name: &str) -> Result<()> {
+    let expected = patp::parse(name)?;
+    let found = parse_ship(contents)?;
+    if found != expected {
+        bail!(InvalidKeyfileError::WrongShip { expected: patp::render(expected), found: patp::render(found) });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    enum Tree {
+        Atom(u128),
+        Bytes(Vec<u8>),
+        Cell(Box<Tree>, Box<Tree>),
+    }
+
+    fn cell(head: Tree, tail: Tree) -> Tree {
+        Tree::Cell(Box::new(head), Box::new(tail))
+    }
+
+    /// Jams `tree`, without back-references, as bits least significant first.
+    fn jam(tree: &Tree, bits: &mut Vec<bool>) {
+        match tree {
+            Tree::Atom(n) => jam(&Tree::Bytes(n.to_le_bytes().to_vec()), bits),
+            Tree::Bytes(bytes) => {
+                bits.push(false);
+                let atom: Vec<bool> = bytes.iter().flat_map(|byte| (0..8).map(move |i| byte >> i & 1 == 1)).collect();
+                let len = atom.iter().rposition(|bit| *bit).map_or(0, |idx| idx + 1);
+                if len == 0 {
+                    bits.push(true);
+                    return;
+                }
+                let len_len = usize::BITS - len.leading_zeros();
+                bits.extend((0..len_len).map(|_| false));
+                bits.push(true);
+                bits.extend((0..len_len - 1).map(|i| len >> i & 1 == 1));
+                bits.extend(&atom[..len]);
+            },
+            Tree::Cell(head, tail) => {
+                bits.extend([true, false]);
+                jam(head, bits);
+                jam(tail, bits);
+            },
+        }
+    }
+
+    /// Prints jammed bits as @uw.
+    fn uw(bits: &[bool]) -> String {
+        let len = bits.iter().rposition(|bit| *bit).map_or(0, |idx| idx + 1);
+        let digits = "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ-~".as_bytes();
+        let mut out: Vec<char> = bits[..len].chunks(6)
+            .map(|chunk| chunk.iter().rev().fold(0, |n, bit| n << 1 | *bit as usize))
+            .map(|digit| digits[digit] as char)
+            .collect();
+        if out.is_empty() {
+            out.push('0');
+        }
+        out.reverse();
+        format!("0w{}", out.into_iter().collect::<String>())
+    }
+
+    fn keyfile(tree: Tree) -> String {
+        let mut bits = Vec::new();
+        jam(&tree, &mut bits);
+        uw(&bits)
+    }
+
+    fn seed(ship: u128, life: u128, ring: &[u8]) -> String {
+        keyfile(cell(Tree::Atom(ship), cell(Tree::Atom(life), cell(Tree::Bytes(ring.to_vec()), Tree::Atom(0)))))
+    }
+
+    fn invalid(result: Result<()>) -> InvalidKeyfileError {
+        result.unwrap_err().downcast().unwrap()
+    }
+
+    #[test]
+    fn parses_uw() {
+        assert_eq!(parse_uw("0w0").unwrap(), Vec::<u8>::new());
+        assert_eq!(parse_uw("0w1").unwrap(), [1]);
+        assert_eq!(parse_uw("0w10").unwrap(), [64]);
+        assert_eq!(parse_uw("0w~").unwrap(), [63]);
+        assert_eq!(parse_uw("0w1.00000").unwrap(), [0, 0, 0, 0x40]);
+        assert!(parse_uw("1w0").is_err());
+        assert!(parse_uw("0w!").is_err());
+    }
+
+    #[test]
+    fn checks_seeds() {
+        let planet = patp::parse("sampel-palnet").unwrap();
+        let contents = seed(planet, 3, &[0xaa; 65]);
+        let parsed = parse(&contents).unwrap();
+        assert_eq!((parsed.ship, parsed.life, parsed.ring), (planet, 3, vec![0xaa; 65]));
+
+        check(&contents, "sampel-palnet").unwrap();
+        check(&format!(" {}\n", contents), "~sampel-palnet").unwrap();
+        match invalid(check(&contents, "marzod")) {
+            InvalidKeyfileError::WrongShip { expected, found } => {
+                assert_eq!((expected.as_str(), found.as_str()), ("marzod", "sampel-palnet"));
+            },
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn checks_feeds_by_latest_life() {
+        let star = patp::parse("marzod").unwrap();
+        let keys = [(1, 0x11), (4, 0x44), (2, 0x22)].into_iter().rev()
+            .fold(Tree::Atom(0), |list, (life, key)| cell(cell(Tree::Atom(life), Tree::Atom(key)), list));
+        let contents = keyfile(cell(cell(Tree::Atom(0x7372_6576), Tree::Atom(0)), cell(Tree::Atom(star), keys)));
+
+        let parsed = parse(&contents).unwrap();
+        assert_eq!((parsed.ship, parsed.life, parsed.ring), (star, 4, vec![0x44]));
+        check(&contents, "marzod").unwrap();
+        assert_eq!(invalid(check(&contents, "zod")).code(), "keyfileShipMismatch");
+
+        let empty = keyfile(cell(cell(Tree::Atom(0x7372_6576), Tree::Atom(0)), cell(Tree::Atom(star), Tree::Atom(0))));
+        assert_eq!(invalid(check(&empty, "marzod")).code(), "invalidKeyfile");
+    }
+
+    #[test]
+    fn rejects_malformed_keyfiles() {
+        let planet = patp::parse("sampel-palnet").unwrap();
+        for contents in ["", "hello", "0w0", &keyfile(Tree::Atom(planet)), &seed(planet, 1, &[1])[..10]] {
+            assert_eq!(invalid(check(contents, "sampel-palnet")).code(), "invalidKeyfile", "{:?}", contents);
+        }
+        let comet = seed(1 << 100, 1, &[1]);
+        assert_eq!(invalid(check(&comet, "sampel-palnet")).code(), "invalidKeyfile");
+        assert!(check(&seed(planet, 1, &[1]), "not-a-ship").is_err());
+    }
+}
@@ -1,51 +1,4504 @@
+// The OpenAPI document is one large `json!` invocation per section.
+#![recursion_limit = "256"]
+
 #[allow(unused_imports)] use crate::prelude::*;
 
-use actix_web::{middleware, get, post, web, App, HttpServer, Responder};
-use actix_multipart::Multipart;
-// use std::sync::RwLock;
+use actix_web::{middleware, delete, get, patch, post, put, route, web};
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, ResponseError};
+use actix_web::http::header::{self, ContentDisposition, DispositionParam, DispositionType};
+use actix_web::http::StatusCode;
+use actix_multipart::{Field, Multipart};
+use async_std::fs;
+use async_std::path::PathBuf;
+use async_std::sync::{Mutex, RwLock};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
+mod acme;
 mod archive;
 mod async_util;
+mod audit;
+mod azimuth;
+mod backup_store;
+mod bandwidth;
+mod boot_queue;
+mod capacity;
+mod clock;
+mod commands;
+mod confinement;
+mod console;
+mod dns;
+mod error;
+mod events;
+mod expiry;
+mod eyre;
 mod filelock;
+mod i18n;
+mod idempotency;
+mod import;
+mod jobs;
+mod keyfile;
+mod metrics;
+mod nats;
 mod net_util;
-// mod patp;
+mod openapi;
+mod ownership;
+mod patp;
+mod plans;
 mod prelude;
+mod privsep;
+mod proxy;
+mod queries;
+mod reaper;
+mod replication;
+mod retry;
 mod runtime;
+mod s3;
+mod seal;
+mod secrets;
 mod ship;
+mod ship_events;
+mod shiplog;
+mod sigil;
+mod signed_links;
+mod sinks;
+mod slo;
+mod startup_report;
+mod storage;
+mod supervisor;
+mod usage;
 mod util;
+mod vhost;
+
+use error::{ApiError, ApiResult};
+use net_util::{ListenAddr, PortIssuer};
+
+lazy_static! {
+    /// Address for the orchestrator's API: `host:port`, or `unix:/path` to listen on a unix domain socket instead.
+    static ref LISTEN_ADDR: ListenAddr = env::var_os("NUCLEUS_LISTEN")
+        .map(|s| s.to_str().unwrap().parse::<ListenAddr>().unwrap())
+        .unwrap_or(ListenAddr::Tcp("127.0.0.1".to_owned(), 8000));
+
+    /// Public base URL of each ship's web interface, with `{name}` standing for the ship's @p without the sig, e.g.
+    /// `https://{name}.ships.example.com`. Used to build login links when the vhost proxy isn't configured; when it is,
+    /// links point at the ship's hostname through the proxy.
+    static ref SHIP_URL_TEMPLATE: Option<String> = env::var_os("NUCLEUS_SHIP_URL_TEMPLATE")
+        .map(|s| s.to_str().unwrap().to_owned());
+
+    /// Largest pier archive accepted by POST /pier, e.g. `50G`. Archives are streamed to disk, so unset accepts any size
+    /// the harbor has room for.
+    static ref MAX_ARCHIVE_UPLOAD_SIZE: Option<u64> = env::var_os("NUCLEUS_MAX_ARCHIVE_UPLOAD_SIZE")
+        .map(|s| util::parse_size(s.to_str().unwrap()).unwrap());
+
+    /// Largest JSON or other in-memory request body accepted by any endpoint, e.g. `256K`.
+    static ref MAX_JSON_SIZE: usize = env::var_os("NUCLEUS_MAX_JSON_SIZE")
+        .map(|s| util::parse_size(s.to_str().unwrap()).unwrap() as usize)
+        .unwrap_or(64 * 1024);
+
+    /// Octal permission bits applied to the API socket when listening on a unix domain socket.
+    static ref LISTEN_SOCKET_MODE: u32 = env::var_os("NUCLEUS_LISTEN_SOCKET_MODE")
+        .map(|s| u32::from_str_radix(s.to_str().unwrap(), 8).unwrap())
+        .unwrap_or(0o660);
+}
 
 struct AppState {
     off: Vec<ship::PierState>,
     on: Vec<ship::Ship>,
+    /// Names of piers currently checked out of `off` by a job (booting, exporting, ...).
+    busy: HashSet<String>,
+    /// The busy piers whose runtimes are being launched or have been but aren't ready yet, and how far they have got.
+    booting: HashMap<String, ship::BootProgress>,
+    /// Stopped piers whose runtimes last exited unexpectedly. Cleared when they boot again.
+    crashed: HashSet<String>,
+    /// Consecutive relaunches of piers by their restart policies, while a relaunch is pending or the ship has yet to
+    /// stay up for `RESTART_RESET_AFTER`.
+    restart_attempts: HashMap<String, u32>,
+    pending_piers: Arc<capacity::PendingPiers>,
+    console: Arc<console::ConsoleHub>,
+    events: Arc<events::EventBus>,
+    jobs: Arc<jobs::JobRegistry>,
+    usage: Arc<usage::UsageCollector>,
+    metrics: Arc<metrics::Metrics>,
+    idempotency_keys: Arc<idempotency::IdempotencyKeys>,
+    clocks: Arc<clock::ClockMonitor>,
+    boot_queue: Arc<boot_queue::BootQueue>,
+    replication: Arc<replication::Replicator>,
+    proxy_traffic: Arc<proxy::Traffic>,
+    plans: plans::Plans,
+    /// Filled in once startup has finished.
+    startup_report: Option<startup_report::StartupReport>,
+    http_ports: Arc<Mutex<PortIssuer>>,
+    ames_ports: Arc<Mutex<PortIssuer>>,
+}
+
+impl AppState {
+    fn new() -> Self {
+        AppState {
+            off: Vec::new(),
+            on: Vec::new(),
+            busy: HashSet::new(),
+            booting: HashMap::new(),
+            crashed: HashSet::new(),
+            restart_attempts: HashMap::new(),
+            pending_piers: Arc::default(),
+            console: Arc::default(),
+            events: Arc::default(),
+            jobs: Arc::default(),
+            usage: Arc::default(),
+            metrics: Arc::default(),
+            idempotency_keys: Arc::default(),
+            clocks: Arc::default(),
+            boot_queue: Arc::default(),
+            replication: Arc::default(),
+            proxy_traffic: Arc::default(),
+            plans: plans::Plans::default(),
+            startup_report: None,
+            http_ports: Arc::new(Mutex::new(PortIssuer::tcp(ship::HTTP_PORT_RANGE.clone()))),
+            ames_ports: Arc::new(Mutex::new(PortIssuer::udp(ship::AMES_PORT_RANGE.clone()))),
+        }
+    }
+
+    /// Loads every pier in the harbor's port into `off`, reserving the ports each one keeps between launches.
+    async fn scan_harbor(&mut self, report: &mut startup_report::StartupReport) -> Result<()> {
+        for name in ship::HARBOR.piers_in_port().await? {
+            match ship::PierState::load_from_port(&name).await {
+                Ok(pier) => {
+                    if let Some(ports) = pier.ports() {
+                        self.http_ports.lock().await.reserve(ports.http);
+                        self.ames_ports.lock().await.reserve(ports.ames);
+                    }
+                    if pier.recovered_stale_lock() {
+                        report.locks_recovered.push(name.clone());
+                    }
+                    if let Some(pid) = pier.live_runtime_pid().await {
+                        log::warn!("pier '{}' is still being run by process {}", name, pid);
+                        report.stray_runtimes.push(startup_report::StrayRuntime { name: name.clone(), pid });
+                    }
+                    report.loaded.push(name);
+                    self.off.push(pier);
+                },
+                Err(e) => {
+                    log::error!("failed to load pier '{}' from harbor port: {}", name, e);
+                    report.quarantined.push(startup_report::QuarantinedPier { name, error: format!("{:#}", e) });
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the named ship out of `on` to be stopped with `shut_down` once the state lock is released, as the shutdown
+    /// ladder can take most of a minute. Its pier is marked busy meanwhile so that nothing else claims it. None if the
+    /// ship isn't running.
+    fn take_for_stop(&mut self, name: &str) -> Option<ship::Ship> {
+        self.restart_attempts.remove(name);
+        let idx = self.on.iter().position(|ship| ship.pier().name() == Some(name))?;
+        self.busy.insert(name.to_owned());
+        Some(self.on.swap_remove(idx))
+    }
+
+    /// Checks the named pier out for a job, as `checkout` does, taking its ship to be stopped if it is running. None if
+    /// no such pier is in `on` or `off`.
+    fn checkout_stopping(&mut self, name: &str) -> Option<Stopping> {
+        match self.take_for_stop(name) {
+            Some(ship) => Some(Stopping::Running(ship)),
+            None => self.checkout(name).map(Stopping::Stopped),
+        }
+    }
+
+    /// Returns a ship's ports to the issuers once it is no longer running.
+    async fn release_ports(&self, ship: &ship::Ship) {
+        self.http_ports.lock().await.release(ship.http_port());
+        self.ames_ports.lock().await.release(ship.ames_port());
+    }
+
+    /// Takes a stopped pier out of `off` for exclusive use by a job, marking it busy until it is checked back in or
+    /// booted.
+    fn checkout(&mut self, name: &str) -> Option<ship::PierState> {
+        let idx = self.off.iter().position(|pier| pier.name() == Some(name))?;
+        self.busy.insert(name.to_owned());
+        Some(self.off.swap_remove(idx))
+    }
+
+    fn checkin(&mut self, pier: ship::PierState) {
+        if let Some(name) = pier.name() {
+            self.busy.remove(name);
+        }
+        self.off.push(pier);
+    }
+
+    fn has_pier(&self, name: &str) -> bool {
+        self.on.iter().any(|ship| ship.pier().name() == Some(name))
+            || self.off.iter().any(|pier| pier.name() == Some(name))
+            || self.busy.contains(name)
+    }
+
+    fn pier_names(&self) -> Vec<String> {
+        self.on.iter().map(|ship| ship.pier())
+            .chain(self.off.iter())
+            .filter_map(|pier| pier.name().map(str::to_owned))
+            .chain(self.busy.iter().cloned())
+            .collect()
+    }
+
+    /// A managed pier that isn't checked out by a job, whether or not its ship is running.
+    fn pier_mut(&mut self, name: &str) -> Option<&mut ship::PierState> {
+        match self.on.iter_mut().find(|ship| ship.pier().name() == Some(name)) {
+            Some(ship) => Some(ship.pier_mut()),
+            None => self.off.iter_mut().find(|pier| pier.name() == Some(name)),
+        }
+    }
+
+    fn running_ship(&self, name: &str) -> Option<&ship::Ship> {
+        self.on.iter().find(|ship| ship.pier().name() == Some(name))
+    }
+
+    /// Whether the named ship isn't running but is on its way up: queued to boot, booting, awaiting a relaunch by its
+    /// restart policy, or being drained of proxied requests ahead of a stop that may be a restart.
+    fn coming_up(&self, name: &str) -> bool {
+        self.running_ship(name).is_none()
+            && (self.booting.contains_key(name)
+                || self.boot_queue.is_queued(name)
+                || self.restart_attempts.contains_key(name)
+                || self.proxy_traffic.draining(name))
+    }
+
+    /// Ships running or launched and still booting, which count against `capacity::MAX_RUNNING_SHIPS`.
+    fn running_count(&self) -> usize {
+        self.on.len() + self.booting.len()
+    }
+
+    /// Fails if `adding` more piers would take the host past `capacity::MAX_MANAGED_PIERS`.
+    fn check_pier_capacity(&self, adding: usize) -> Result<()> {
+        capacity::check_managed(self.pier_names().len() + self.pending_piers.count(), adding)
+    }
+
+    /// Holds a place for a pier about to be created in the dry dock, until it is released into port with a name.
+    fn reserve_pier(&mut self) -> Result<capacity::Reservation> {
+        self.check_pier_capacity(1)?;
+        Ok(self.pending_piers.reserve())
+    }
+}
+
+/// A pier checked out with `AppState::checkout_stopping`, whose ship may still have to be shut down.
+enum Stopping {
+    Stopped(ship::PierState),
+    Running(ship::Ship),
+}
+
+impl Stopping {
+    /// The checked-out pier, once its ship has stopped. Call without the state lock held.
+    async fn stopped(self, state: &web::Data<RwLock<AppState>>) -> Result<ship::PierState> {
+        match self {
+            Stopping::Stopped(pier) => Ok(pier),
+            Stopping::Running(ship) => shut_down(state, ship).await,
+        }
+    }
+}
+
+/// Shuts down a ship taken with `AppState::take_for_stop`, without the state lock held, and records that it stopped.
+/// Returns its pier, still checked out. If the shutdown fails, the pier is reloaded from the harbor and checked back in.
+async fn shut_down(state: &web::Data<RwLock<AppState>>, ship: ship::Ship) -> Result<ship::PierState> {
+    let name = ship.pier().name().unwrap_or_default().to_owned();
+    let (http_port, ames_port) = (ship.http_port(), ship.ames_port());
+    let stopped = ship.shutdown().await;
+
+    let mut state = state.write().await;
+    state.http_ports.lock().await.release(http_port);
+    state.ames_ports.lock().await.release(ames_port);
+    match stopped {
+        Ok(pier) => {
+            state.console.broadcast(&name, "ship stopped");
+            state.events.publish(events::Event::ShipStopped { name });
+            Ok(pier)
+        },
+        Err(e) => {
+            state.busy.remove(&name);
+            match ship::PierState::load_from_port(&name).await {
+                Ok(pier) => state.off.push(pier),
+                Err(e) => log::error!("failed to reload pier '{}' after failed stop: {}", name, e),
+            }
+            Err(e)
+        },
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "method")]
 #[serde(rename_all = "camelCase")]
 #[derive(Debug)]
+// The variant names are the `method` tags clients send.
+#[allow(clippy::enum_variant_names)]
 enum PostPierForm {
     FromKeyfile {
         name: String,
     },
     FromPierArchive {
+    },
+    /// Mines a new comet on first boot. Takes no file part.
+    FromComet {
+    },
+    /// Boots a new fake ship for development, which talks only to other fake ships on this host. Takes no file part.
+    FromFake {
+        name: String,
+    },
+    /// Restores a snapshot from the backup store, as listed by `/pier/{name}/backups`. Takes no file part.
+    FromBackup {
+        name: String,
+        snapshot: String,
+    },
+}
+
+impl PostPierForm {
+    fn takes_file(&self) -> bool {
+        !matches!(self, PostPierForm::FromComet {} | PostPierForm::FromFake { .. } | PostPierForm::FromBackup { .. })
+    }
+}
+
+/// Waits for a slot in the boot queue, then launches `pier` and records the resulting ship as running.
+/// `PierState::launch` consumes the pier, so if the launch fails the pier is reloaded from the harbor and returned to
+/// `off`. `on_demand` boots jump the queue. Once the ship is up, its secrets are injected and its startup commands
+/// run, and how each command went is returned.
+async fn boot_pier(
+    state: &web::Data<RwLock<AppState>>,
+    job: &jobs::JobHandle,
+    pier: ship::PierState,
+    on_demand: bool,
+) -> Result<Vec<ship::StartupCommandOutcome>> {
+    let name = pier.name().map(str::to_owned);
+    if let Some(expiry) = pier.expiry().filter(|expiry| expiry.expired(time::OffsetDateTime::now_utc())) {
+        let error = anyhow!("{} expired at {}; extend its expiry to boot it", name.as_deref().unwrap_or_default(),
+            expiry.expires_at);
+        state.write().await.checkin(pier);
+        return Err(error);
+    }
+    let mut pier = pier;
+    if let Err(e) = check_azimuth(state, job, &mut pier).await {
+        state.write().await.checkin(pier);
+        return Err(e);
+    }
+    let (http_ports, ames_ports, boot_queue) = {
+        let state = state.read().await;
+        (state.http_ports.clone(), state.ames_ports.clone(), state.boot_queue.clone())
+    };
+
+    job.progress("queued to boot");
+    let slot = boot_queue.acquire(name.as_deref().unwrap_or_default(), job.id(), pier.boot_priority(), on_demand).await;
+    let progress = ship::BootProgress::default();
+    let admitted = {
+        let mut state = state.write().await;
+        let admitted = capacity::check_running(state.running_count());
+        if let (Ok(()), Some(name)) = (&admitted, &name) {
+            state.booting.insert(name.clone(), progress.clone());
+        }
+        admitted
+    };
+    if let Err(e) = admitted {
+        drop(slot);
+        state.write().await.checkin(pier);
+        return Err(e);
+    }
+    for warning in capacity::resources().warnings {
+        log::warn!("launching {} low on resources: {}", name.as_deref().unwrap_or_default(), warning);
+    }
+    job.progress("booting");
+    let launched = match pier.launch(&http_ports, &ames_ports).await {
+        Ok(ship) => ship.ready(&progress).await,
+        Err(e) => Err(e),
+    };
+    drop(slot);
+
+    let state_handle = state;
+    let mut state = state.write().await;
+    if let Some(ref name) = name {
+        state.busy.remove(name);
+        state.booting.remove(name);
+    }
+
+    match launched {
+        Ok(mut ship) => {
+            if let Some(name) = &name {
+                state.console.broadcast(name, "ship booted");
+                state.events.publish(events::Event::ShipBooted {
+                    name: name.clone(),
+                    http_port: ship.http_port(),
+                    ames_port: ship.ames_port(),
+                });
+                state.crashed.remove(name);
+                if let Some(mut output_events) = ship.take_output_events() {
+                    let events = state.events.clone();
+                    let name = name.clone();
+                    tokio::spawn(async move {
+                        while let Some(event) = output_events.next().await {
+                            events.publish(events::Event::ShipOutput { name: name.clone(), event });
+                        }
+                    });
+                }
+                // This runs once the ship is in `on` and the write lock is released.
+                let exited = ship.exited();
+                let state = state_handle.clone();
+                let monitored = name.clone();
+                tokio::spawn(async move { monitor_ship(&state, &monitored, exited).await });
+            }
+            state.on.push(ship);
+            drop(state);
+
+            let Some(name) = name else { return Ok(Vec::new()) };
+            // Secrets first, as startup commands may rely on them.
+            inject_secrets(state_handle, &name).await;
+            job.progress("running startup commands");
+            Ok(run_startup_commands(state_handle, &name).await)
+        },
+        Err(e) => {
+            if let Some(name) = name {
+                match ship::PierState::load_from_port(&name).await {
+                    Ok(pier) => state.off.push(pier),
+                    Err(reload_err) => log::error!("failed to reload pier '{}' after failed launch: {}", name, reload_err),
+                }
+            }
+            Err(e)
+        },
+    }
+}
+
+/// Checks a pier against its ship's point on Azimuth, if a roller is configured. A pier booting from its keyfile must
+/// hold the ship's current keys, and a pier whose ship has been breached since it last booted is warned about on every
+/// boot until it is factory reset. Failing to reach the roller doesn't hold up the boot.
+async fn check_azimuth(
+    state: &web::Data<RwLock<AppState>>,
+    job: &jobs::JobHandle,
+    pier: &mut ship::PierState,
+) -> Result<()> {
+    let Some(rpc) = azimuth::AZIMUTH_RPC.as_ref() else { return Ok(()) };
+    let Some(name) = pier.name().filter(|name| pier.networked() && azimuth::on_azimuth(name)).map(str::to_owned)
+        else { return Ok(()) };
+    job.progress("checking keys on azimuth");
+    let point = match azimuth::get_point(rpc, &format!("~{}", name)).await {
+        Ok(point) => point,
+        Err(e) => {
+            log::warn!("failed to look up {} on azimuth, booting it unchecked: {:#}", name, e);
+            return Ok(());
+        },
+    };
+    match pier.rift() {
+        _ if !pier.initialized() => {
+            if let Some(keyfile) = pier.keyfile().await? {
+                azimuth::check_keyfile(&keyfile, &point)?;
+            }
+        },
+        Some(rift) if point.rift > rift => {
+            log::warn!("{} was breached on azimuth since it last booted (rift {} to {})", name, rift, point.rift);
+            state.read().await.events.publish(events::Event::PierBreached { name, rift: point.rift });
+            return Ok(());
+        },
+        // Piers booted before their rift was recorded start from their ship's current one.
+        _ => {},
+    }
+    pier.set_rift(point.rift);
+    Ok(())
+}
+
+/// Waits for a running ship's runtime to exit. If its ship is still in `on` by then, nothing stopped it: its pier is
+/// returned to `off`, marked crashed unless it exited cleanly, and its ports are released. Its restart policy may then
+/// relaunch it.
+async fn monitor_ship(state: &web::Data<RwLock<AppState>>, name: &str, exited: ship::ExitWatch) {
+    let status = exited.await;
+
+    let state_handle = state;
+    let mut state = state.write().await;
+    // Stopping a ship takes it out of `on` before it exits, and a ship booted since is still running.
+    let Some(idx) = state.on.iter().position(|ship| ship.pier().name() == Some(name) && ship.exit_status().is_some())
+        else { return };
+    let ship = state.on.swap_remove(idx);
+    state.release_ports(&ship).await;
+
+    match ship.shutdown().await {
+        Ok(pier) => state.off.push(pier),
+        Err(e) => {
+            log::error!("failed to clean up after {} exited: {:#}", name, e);
+            match ship::PierState::load_from_port(name).await {
+                Ok(pier) => state.off.push(pier),
+                Err(e) => log::error!("failed to reload pier '{}' after it exited: {}", name, e),
+            }
+        },
+    }
+
+    let clean_exit = status.is_some_and(|status| status.success());
+    if clean_exit {
+        log::warn!("{} exited on its own", name);
+        state.console.broadcast(name, "ship stopped");
+        state.events.publish(events::Event::ShipStopped { name: name.to_owned() });
+    } else {
+        let status = ship::describe_exit(status);
+        log::error!("{} crashed: {}", name, status);
+        state.console.broadcast(name, &format!("ship crashed: {}", status));
+        state.crashed.insert(name.to_owned());
+        state.events.publish(events::Event::ShipCrashed { name: name.to_owned(), status });
+    }
+
+    let Some(pier) = state.off.iter().find(|pier| pier.name() == Some(name)) else { return };
+    let policy = pier.restart_policy().clone();
+    if !policy.applies_to(clean_exit) {
+        return;
+    }
+    let recovered = pier.lifecycle().last_launched_at
+        .is_none_or(|at| time::OffsetDateTime::now_utc() - at >= supervisor::RESTART_RESET_AFTER);
+    let attempt = if recovered { 1 } else { state.restart_attempts.get(name).copied().unwrap_or(0) + 1 };
+    schedule_restart(&mut state, state_handle, name, &policy, attempt);
+}
+
+/// Relaunches the named pier after its restart policy's backoff, unless it is started or stopped by hand first.
+fn schedule_restart(
+    state: &mut AppState,
+    state_handle: &web::Data<RwLock<AppState>>,
+    name: &str,
+    policy: &supervisor::RestartPolicy,
+    attempt: u32,
+) {
+    if !policy.allows_attempt(attempt) {
+        log::error!("{} failed to stay up after {} restart(s); giving up", name, attempt - 1);
+        state.restart_attempts.remove(name);
+        state.events.publish(events::Event::ShipRestartsExhausted { name: name.to_owned(), attempts: attempt - 1 });
+        return;
+    }
+
+    let delay = policy.backoff(attempt);
+    log::info!("restarting {} in {:?} (attempt {})", name, delay, attempt);
+    state.restart_attempts.insert(name.to_owned(), attempt);
+    state.events.publish(events::Event::ShipRestartScheduled {
+        name: name.to_owned(),
+        attempt,
+        delay_secs: delay.as_secs(),
+    });
+
+    let state = state_handle.clone();
+    let name = name.to_owned();
+    actix_web::rt::spawn(async move {
+        actix_web::rt::time::sleep(delay).await;
+        relaunch(&state, name, attempt).await;
+    });
+}
+
+async fn relaunch(state: &web::Data<RwLock<AppState>>, name: String, attempt: u32) {
+    let (pier, jobs) = {
+        let mut state = state.write().await;
+        // Starting or stopping the pier by hand cancels the relaunch.
+        if state.restart_attempts.get(&name) != Some(&attempt) {
+            return;
+        }
+        let Some(pier) = state.checkout(&name) else { return };
+        (pier, state.jobs.clone())
+    };
+
+    let state = state.clone();
+    jobs.spawn("boot", Some(name.clone()), move |job| async move {
+        let booted = boot_pier(&state, &job, pier, false).await;
+        if booted.is_err() {
+            let mut state_guard = state.write().await;
+            let policy = state_guard.off.iter()
+                .find(|pier| pier.name() == Some(&name))
+                .map(|pier| pier.restart_policy().clone());
+            if let Some(policy) = policy {
+                schedule_restart(&mut state_guard, &state, &name, &policy, attempt + 1);
+            }
+        }
+        let startup_commands = booted?;
+        Ok(serde_json::json!({ "name": name, "attempt": attempt, "startupCommands": startup_commands }))
+    });
+}
+
+fn accepted(job_id: Uuid) -> HttpResponse {
+    HttpResponse::Accepted()
+        .insert_header((header::LOCATION, format!("/jobs/{}", job_id)))
+        .json(serde_json::json!({ "jobId": job_id }))
+}
+
+const MAX_FORM_SIZE: usize = 64 * 1024;
+
+/// Keyfiles are a single line of hex; anything much bigger isn't one.
+const MAX_KEYFILE_SIZE: u64 = 64 * 1024;
+
+async fn create_spool_file() -> Result<(PathBuf, fs::File)> {
+    let path = ship::HARBOR.uploads_path().await?.join(Uuid::new_v4().hyphenated().to_string());
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .await?;
+    Ok((path, file))
+}
+
+/// Streams a multipart field into the upload spool, giving up with a `PayloadTooLargeError` past `limit` bytes. Nothing
+/// is left in the spool on failure.
+async fn spool_upload(field: &mut Field, limit: Option<u64>) -> Result<PathBuf> {
+    let (path, mut file) = create_spool_file().await?;
+
+    let written: Result<()> = async {
+        let mut chunks = field.into_bounded(limit.unwrap_or(u64::MAX));
+        while let Some(chunk) = chunks.next().await {
+            file.write_all(&chunk?).await?;
+        }
+        file.sync_all().await?;
+        Ok(())
+    }.await;
+
+    if let Err(e) = written {
+        _ = fs::remove_file(&path).await;
+        return Err(e);
     }
+    Ok(path)
+}
+
+/// Writes an in-memory upload (e.g. a keyfile received over a message queue) into the upload spool.
+async fn spool_bytes(bytes: &[u8]) -> Result<PathBuf> {
+    let (path, mut file) = create_spool_file().await?;
+    file.write_all(bytes).await?;
+    file.sync_all().await?;
+    Ok(path)
 }
 
+/// Creates a pier from a multipart upload. The `form` part holds a JSON `PostPierForm` and the `file` part holds the
+/// keyfile or pier archive, and is omitted for comets. The upload is spooled to disk during the request; unpacking and
+/// booting happen in a job.
+///
+/// A client may send an `Idempotency-Key` header so that retrying a request whose response it never saw doesn't create
+/// a second pier: a retry with the key of a request that started a job gets that request's response back.
 #[post("/pier")]
-async fn greet(form: web::Json<PostPierForm>, payload: Multipart) -> impl Responder {
-    format!("Hello!")
+async fn create_pier(
+    state: web::Data<RwLock<AppState>>,
+    req: HttpRequest,
+    mut payload: Multipart,
+) -> ApiResult<HttpResponse> {
+    let reservation = match req.headers().get("Idempotency-Key") {
+        None => None,
+        Some(key) => {
+            let key = key.to_str().ok()
+                .filter(|key| !key.is_empty() && key.len() <= idempotency::MAX_KEY_LEN)
+                .ok_or_else(|| ApiError::bad_request(format!(
+                    "Idempotency-Key must be 1 to {} visible ASCII characters", idempotency::MAX_KEY_LEN)))?;
+            let keys = state.read().await.idempotency_keys.clone();
+            match keys.begin(key) {
+                idempotency::Begin::Started(reservation) => Some(reservation),
+                idempotency::Begin::Replay(job_id) => {
+                    let mut response = accepted(job_id);
+                    response.headers_mut().insert(
+                        header::HeaderName::from_static("idempotent-replayed"),
+                        header::HeaderValue::from_static("true"));
+                    return Ok(response);
+                },
+                idempotency::Begin::InFlight => return Err(ApiError::idempotency_key_in_use()),
+            }
+        },
+    };
+    // Before the upload is received, so that a host at capacity doesn't take in an archive only to turn it away.
+    let pier_reservation = state.write().await.reserve_pier()?;
+
+    let mut form: Option<PostPierForm> = None;
+    let mut upload: Option<PathBuf> = None;
+
+    let parsed: ApiResult<()> = async {
+        while let Some(field) = payload.next().await {
+            let mut field = field.map_err(|e| ApiError::bad_request(e.to_string()))?;
+            match field.name() {
+                "form" => {
+                    let mut buf = Vec::new();
+                    while let Some(chunk) = field.next().await {
+                        buf.extend_from_slice(&chunk.map_err(|e| ApiError::bad_request(e.to_string()))?);
+                        if buf.len() > MAX_FORM_SIZE {
+                            return Err(ApiError::payload_too_large("form part is too large"));
+                        }
+                    }
+                    form = Some(serde_json::from_slice(&buf)
+                        .map_err(|e| ApiError::bad_request(format!("invalid form part: {}", e)))?);
+                },
+                "file" => {
+                    if upload.is_some() {
+                        return Err(ApiError::bad_request("multiple file parts"));
+                    }
+                    // The form part normally comes first. If it doesn't, the upload is held to the larger limit.
+                    let limit = match form {
+                        Some(PostPierForm::FromKeyfile { .. }) => Some(MAX_KEYFILE_SIZE),
+                        _ => *MAX_ARCHIVE_UPLOAD_SIZE,
+                    };
+                    upload = Some(spool_upload(&mut field, limit).await?);
+                },
+                other => {
+                    return Err(ApiError::bad_request(format!("unexpected multipart field: {}", other)));
+                },
+            }
+        }
+        Ok(())
+    }.await;
+
+    let (form, upload) = match (parsed, form, upload) {
+        (Ok(()), Some(form), upload) if form.takes_file() == upload.is_some() => (form, upload),
+        (parsed, form, upload) => {
+            if let Some(upload) = upload {
+                _ = fs::remove_file(&upload).await;
+            }
+            parsed?;
+            return Err(ApiError::bad_request(match form {
+                None => "missing form part",
+                Some(form) if form.takes_file() => "missing file part",
+                Some(_) => "unexpected file part",
+            }));
+        },
+    };
+
+    // Catch a keyfile for the wrong ship now rather than when the pier first fails to boot.
+    if let (PostPierForm::FromKeyfile { name }, Some(upload)) = (&form, &upload) {
+        let checked = match fs::read_to_string(upload).await {
+            Ok(key) => keyfile::check(&key, name),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = checked {
+            _ = fs::remove_file(upload).await;
+            return Err(e.into());
+        }
+    }
+
+    if let PostPierForm::FromFake { name } = &form {
+        patp::parse(name)?;
+    }
+
+    let job_id = spawn_import(&state, form, upload, pier_reservation).await;
+    if let Some(reservation) = reservation {
+        reservation.complete(job_id);
+    }
+    Ok(accepted(job_id))
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    // let ship
-    HttpServer::new(|| {
-        App::new()
-            .wrap(middleware::Logger::default())
-            .wrap(middleware::NormalizePath::new(
-                middleware::TrailingSlash::MergeOnly,
-            ))
-            .route("/hello", web::get().to(|| async { "Hello World!" }))
-            .service(greet)
-    }).bind(("127.0.0.1", 8000))?.run().await
+/// Starts a job creating a pier from a spooled upload, if the form takes one, and booting it. The job takes ownership
+/// of the upload file.
+async fn spawn_import(
+    state: &web::Data<RwLock<AppState>>,
+    form: PostPierForm,
+    upload: Option<PathBuf>,
+    reservation: capacity::Reservation,
+) -> Uuid {
+    let jobs = state.read().await.jobs.clone();
+    let state = state.clone();
+    jobs.spawn("import", None, move |job| import_pier(state, job, form, upload, reservation))
+}
+
+async fn import_pier(
+    state: web::Data<RwLock<AppState>>,
+    job: jobs::JobHandle,
+    form: PostPierForm,
+    upload: Option<PathBuf>,
+    reservation: capacity::Reservation,
+) -> Result<serde_json::Value> {
+    let comet = matches!(form, PostPierForm::FromComet {});
+    job.progress(match form {
+        PostPierForm::FromComet {} => "creating comet",
+        PostPierForm::FromFake { .. } => "creating fake ship",
+        PostPierForm::FromBackup { .. } => "restoring snapshot",
+        _ => "unpacking upload",
+    });
+    let created = async {
+        match &form {
+            PostPierForm::FromComet {} => return ship::PierState::new_comet().await.map(|pier| (pier, None)),
+            PostPierForm::FromFake { name } => return ship::PierState::new_fake(name).await.map(|pier| (pier, None)),
+            PostPierForm::FromBackup { name, snapshot } => {
+                return ship::PierState::new_from_backup(name, snapshot).await.map(|pier| (pier, None));
+            },
+            _ => {},
+        }
+        let upload = upload.as_ref().ok_or_else(|| anyhow!("missing upload"))?;
+        let mut infile = fs::File::open(upload).await?;
+        match form {
+            PostPierForm::FromKeyfile { name } => ship::PierState::new_from_keyfile(&mut infile, name).await
+                .map(|pier| (pier, None)),
+            PostPierForm::FromPierArchive {} => ship::PierState::new_from_pier_archive(&mut infile).await
+                .map(|(pier, report)| (pier, Some(report))),
+            PostPierForm::FromComet {} | PostPierForm::FromFake { .. } | PostPierForm::FromBackup { .. } => {
+                unreachable!()
+            },
+        }
+    }.await;
+    if let Some(upload) = upload {
+        _ = fs::remove_file(&upload).await;
+    }
+    let (pier, report) = created?;
+
+    let events = state.read().await.events.clone();
+    events.publish(events::Event::PierCreated { id: pier.id(), name: pier.name().map(str::to_owned) });
+
+    // A comet's first boot mines its address, which can take several minutes.
+    job.progress(if comet { "mining comet" } else { "identifying ship in dry dock" });
+    release_and_boot(state, job, pier, report, Some(reservation)).await
+}
+
+/// Moves a newly created pier out of the dry dock, learning its name in the process, and boots it. The pier's
+/// capacity reservation, if it holds one, is given up once it is counted by name.
+async fn release_and_boot<R: Serialize>(
+    state: web::Data<RwLock<AppState>>,
+    job: jobs::JobHandle,
+    pier: ship::PierState,
+    report: Option<R>,
+    reservation: Option<capacity::Reservation>,
+) -> Result<serde_json::Value> {
+    let id = pier.id();
+    let (http_ports, ames_ports) = {
+        let state = state.read().await;
+        (state.http_ports.clone(), state.ames_ports.clone())
+    };
+    let pier = pier.release_from_dry_dock(&http_ports, &ames_ports).await?;
+    let name = pier.name().unwrap().to_owned();
+
+    state.write().await.busy.insert(name.clone());
+    drop(reservation);
+    let startup_commands = boot_pier(&state, &job, pier, false).await?;
+
+    Ok(serde_json::json!({ "id": id, "name": name, "import": report, "startupCommands": startup_commands }))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AdoptForm {
+    /// Absolute path of the pier directory on the host.
+    path: String,
+    #[serde(default)]
+    mode: import::AdoptMode,
+}
+
+/// Takes over a pier directory already on the host, such as one run by hand with `urbit`, without archiving and
+/// uploading it. The directory is checked during the request; moving or copying it and booting it happen in a job.
+#[post("/pier/adopt")]
+async fn adopt_pier(
+    state: web::Data<RwLock<AppState>>,
+    form: web::Json<AdoptForm>,
+) -> ApiResult<HttpResponse> {
+    let AdoptForm { path, mode } = form.into_inner();
+    let path = PathBuf::from(path);
+    import::check_adoptable(&path, ship::HARBOR.root()).await?;
+
+    let (jobs, reservation) = {
+        let mut state = state.write().await;
+        (state.jobs.clone(), state.reserve_pier()?)
+    };
+    let state = state.clone();
+    Ok(accepted(jobs.spawn("adopt", None, move |job| async move {
+        job.progress(match mode {
+            import::AdoptMode::Move => "moving pier into the harbor",
+            import::AdoptMode::Copy => "copying pier into the harbor",
+        });
+        let (pier, report) = ship::PierState::adopt(&path, mode).await?;
+
+        let events = state.read().await.events.clone();
+        events.publish(events::Event::PierCreated { id: pier.id(), name: pier.name().map(str::to_owned) });
+
+        job.progress("identifying ship in dry dock");
+        release_and_boot(state, job, pier, Some(report), Some(reservation)).await
+    })))
+}
+
+#[derive(Deserialize, Debug)]
+struct CloneQuery {
+    /// Name for the clone; by default the original's name with a random suffix.
+    #[serde(rename = "as")]
+    clone_name: Option<String>,
+}
+
+/// Copies a stopped pier into a new development ship, which runs with local networking only so that the original's
+/// peers never hear from it. The original stays busy until the copy is complete; the clone is left stopped.
+#[post("/pier/{name}/clone")]
+async fn clone_pier(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    query: web::Query<CloneQuery>,
+) -> ApiResult<HttpResponse> {
+    let name = name.into_inner();
+    let clone_name = query.into_inner().clone_name
+        .unwrap_or_else(|| format!("{}-clone-{}", name, &Uuid::new_v4().simple().to_string()[..8]));
+    ship::validate_clone_name(&clone_name).map_err(|e| ApiError::bad_request(format!("{:#}", e)))?;
+
+    let (jobs, pier) = {
+        let mut state = state.write().await;
+        if state.on.iter().any(|ship| ship.pier().name() == Some(&name)) {
+            return Err(ApiError::ship_running(&name));
+        }
+        if state.busy.contains(&name) {
+            return Err(ApiError::pier_busy(&name));
+        }
+        if state.has_pier(&clone_name) {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT, "pierExists", format!("a pier named {} already exists", clone_name),
+            ));
+        }
+        if !state.off.iter().any(|pier| pier.name() == Some(&name)) {
+            return Err(ApiError::pier_not_found(&name));
+        }
+        state.check_pier_capacity(1)?;
+        let pier = state.checkout(&name).unwrap();
+        state.busy.insert(clone_name.clone());
+        (state.jobs.clone(), pier)
+    };
+
+    let state = state.clone();
+    Ok(accepted(jobs.spawn("clone", Some(name), move |job| async move {
+        job.progress("copying pier");
+        let cloned = pier.new_clone(&clone_name).await;
+
+        let mut state = state.write().await;
+        state.checkin(pier);
+        state.busy.remove(&clone_name);
+        let (clone, copied) = cloned?;
+        let id = clone.id();
+        state.events.publish(events::Event::PierCreated { id, name: Some(clone_name.clone()) });
+        state.off.push(clone);
+
+        Ok(serde_json::json!({ "id": id, "name": clone_name, "copied": copied }))
+    })))
+}
+
+/// Most fake planets one request can provision, as each is a runtime of its own.
+const MAX_FAKE_CLUSTER_PLANETS: u16 = 32;
+
+fn default_fake_galaxy() -> String {
+    "zod".to_owned()
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FakeClusterForm {
+    /// Names the group; its piers are tagged `fake-cluster:{name}`.
+    name: String,
+    #[serde(default = "default_fake_galaxy")]
+    galaxy: String,
+    #[serde(default)]
+    planets: u16,
+}
+
+/// Provisions a fake galaxy and fake planets under its first star, so that app developers get a private network in one
+/// call. Fake ships find each other on the loopback interface by galaxy, so the planets reach the galaxy without any
+/// wiring. The galaxy boots first and the planets after it, one at a time; if one fails, the ones before it are kept.
+#[post("/fake-cluster")]
+async fn create_fake_cluster(
+    state: web::Data<RwLock<AppState>>,
+    form: web::Json<FakeClusterForm>,
+) -> ApiResult<HttpResponse> {
+    let FakeClusterForm { name, galaxy, planets } = form.into_inner();
+    let galaxy = patp::parse(&galaxy)?;
+    if patp::class(galaxy) != ship::ShipClass::Galaxy {
+        return Err(ApiError::bad_request(format!("~{} is not a galaxy", patp::render(galaxy))));
+    }
+    if planets > MAX_FAKE_CLUSTER_PLANETS {
+        return Err(ApiError::bad_request(format!("at most {} planets per cluster", MAX_FAKE_CLUSTER_PLANETS)));
+    }
+    let tag = format!("fake-cluster:{}", name);
+    let metadata = ship::PierMetadata { tags: BTreeSet::from([tag.clone()]), ..Default::default() };
+    metadata.validate().map_err(|e| ApiError::bad_request(format!("{:#}", e)))?;
+
+    let star = 1 << 8 | galaxy;
+    let names: Vec<String> = std::iter::once(galaxy)
+        .chain((1..=planets as u128).map(|idx| idx << 16 | star))
+        .map(patp::render)
+        .collect();
+
+    let jobs = {
+        let mut state = state.write().await;
+        if let Some(taken) = names.iter().find(|name| state.has_pier(name)) {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT, "pierExists", format!("a pier named {} already exists", taken),
+            ));
+        }
+        state.check_pier_capacity(names.len())?;
+        state.busy.extend(names.iter().cloned());
+        state.jobs.clone()
+    };
+
+    let state = state.clone();
+    Ok(accepted(jobs.spawn("fakeCluster", None, move |job| async move {
+        for (idx, ship) in names.iter().enumerate() {
+            job.progress(format!("provisioning ~{} ({} of {})", ship, idx + 1, names.len()));
+            let created = async {
+                let mut pier = ship::PierState::new_fake(ship).await?;
+                pier.set_metadata(metadata.clone()).await?;
+                state.read().await.events.publish(events::Event::PierCreated {
+                    id: pier.id(),
+                    name: Some(ship.clone()),
+                });
+                release_and_boot(state.clone(), job.clone(), pier, None::<()>, None).await
+            }.await;
+            if let Err(e) = created {
+                let mut state = state.write().await;
+                for name in &names[idx..] {
+                    state.busy.remove(name);
+                }
+                return Err(e.context(format!("failed to provision ~{}", ship)));
+            }
+        }
+
+        Ok(serde_json::json!({
+            "name": name,
+            "tag": tag,
+            "galaxy": names[0],
+            "planets": names[1..],
+        }))
+    })))
+}
+
+/// What the orchestrator found in the harbor when it started, and what it recovered.
+#[get("/startup-report")]
+async fn get_startup_report(state: web::Data<RwLock<AppState>>) -> ApiResult<HttpResponse> {
+    let state = state.read().await;
+    let report = state.startup_report.as_ref().ok_or_else(ApiError::not_ready)?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Starts jobs finishing the archive imports that were interrupted by the last shutdown. Imports that were interrupted
+/// before the archive was fully received can't be resumed, and are removed.
+async fn resume_interrupted_imports(
+    state: &web::Data<RwLock<AppState>>,
+    report: &mut startup_report::StartupReport,
+) -> Result<()> {
+    let jobs = state.read().await.jobs.clone();
+    let dry_dock_path = ship::HARBOR.dry_dock_path().await?;
+
+    for id in ship::HARBOR.piers_in_dry_dock().await? {
+        let meta_path = dry_dock_path.join(id.hyphenated().to_string());
+        match import::ImportProgress::load(&meta_path).await {
+            Ok(Some(import::ImportProgress::Extracting { .. })) => {
+                let state = state.clone();
+                let job_id = jobs.spawn("import", None, move |job| async move {
+                    job.progress("resuming extraction");
+                    let (pier, report) = ship::PierState::resume_pier_archive_import(id).await?;
+                    job.progress("identifying ship in dry dock");
+                    release_and_boot(state, job, pier, Some(report), None).await
+                });
+                log::info!("resuming interrupted import {} as job {}", id, job_id);
+                report.resumed_imports.push(id);
+                continue;
+            },
+            Ok(Some(import::ImportProgress::Receiving)) => {
+                log::warn!("removing dry dock entry {}, whose archive upload was interrupted", id);
+                report.removed_imports.push(id);
+            },
+            // Possibly a pier that has already booted once, such as a freshly mined comet, so leave it for an operator.
+            Ok(None) => {
+                log::warn!("dry dock entry {} was left behind by an interrupted job", id);
+                report.left_in_dry_dock.push(id);
+                continue;
+            },
+            Err(e) => {
+                log::warn!("dry dock entry {} has unreadable import progress: {}", id, e);
+                report.left_in_dry_dock.push(id);
+                continue;
+            },
+        }
+        if let Err(e) = fs::remove_dir_all(&meta_path).await {
+            log::error!("failed to remove dry dock entry {}: {}", id, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Starts a job booting the named pier.
+async fn spawn_boot(state: &web::Data<RwLock<AppState>>, name: String, on_demand: bool) -> ApiResult<Uuid> {
+    let (pier, jobs) = {
+        let mut state = state.write().await;
+        if state.running_ship(&name).is_some() {
+            return Err(ApiError::ship_running(&name));
+        }
+        state.restart_attempts.remove(&name);
+        if state.busy.contains(&name) {
+            // Waking a pier that is already queued to boot just moves it up the queue.
+            if let Some(job_id) = on_demand.then(|| state.boot_queue.prioritize(&name)).flatten() {
+                return Ok(job_id);
+            }
+            return Err(ApiError::pier_busy(&name));
+        }
+        if !state.off.iter().any(|pier| pier.name() == Some(&name)) {
+            return Err(ApiError::pier_not_found(&name));
+        }
+        // Checked again when the boot leaves the queue; this turns the boot away without queueing it.
+        capacity::check_running(state.running_count())?;
+        let pier = state.checkout(&name).unwrap();
+        (pier, state.jobs.clone())
+    };
+
+    let state = state.clone();
+    Ok(jobs.spawn("boot", Some(name.clone()), move |job| async move {
+        let startup_commands = boot_pier(&state, &job, pier, on_demand).await?;
+        Ok(serde_json::json!({ "name": name, "startupCommands": startup_commands }))
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct StartQuery {
+    /// Boot ahead of everything else in the boot queue, e.g. because a user is waiting on the ship.
+    #[serde(default)]
+    on_demand: bool,
+}
+
+#[post("/pier/{name}/start")]
+async fn start_pier(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    query: web::Query<StartQuery>,
+) -> ApiResult<HttpResponse> {
+    Ok(accepted(spawn_boot(&state, name.into_inner(), query.on_demand).await?))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SignedLinkForm {
+    action: signed_links::SignedAction,
+    /// How long the link stays valid for; `DEFAULT_SIGNED_LINK_TTL` if left out.
+    ttl_secs: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SignedLink {
+    action: signed_links::SignedAction,
+    /// Relative to the orchestrator's root, wherever the provider exposes it.
+    url: String,
+    #[serde(with = "time::serde::rfc3339")]
+    expires_at: time::OffsetDateTime,
+}
+
+/// Mints a link that lets whoever holds it take one action on this pier until it expires, such as a customer
+/// restarting their ship from an email, without being able to do anything else.
+#[post("/pier/{name}/signed-links")]
+async fn create_signed_link(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    form: web::Json<SignedLinkForm>,
+) -> ApiResult<HttpResponse> {
+    managed_pier(&*state.read().await, &name)?;
+    let ttl = form.ttl_secs.map_or(signed_links::DEFAULT_SIGNED_LINK_TTL, Duration::from_secs);
+    if ttl.is_zero() || ttl > *signed_links::MAX_SIGNED_LINK_TTL {
+        return Err(ApiError::bad_request(format!(
+            "ttlSecs must be between 1 and {}", signed_links::MAX_SIGNED_LINK_TTL.as_secs())));
+    }
+    // Whole seconds, as the link carries them.
+    let expires_at = time::OffsetDateTime::now_utc().unix_timestamp() + ttl.as_secs() as i64;
+    let expires_at = time::OffsetDateTime::from_unix_timestamp(expires_at).map_err(Error::from)?;
+    let url = signed_links::sign(form.action, &name, expires_at).await?;
+    Ok(HttpResponse::Created().json(SignedLink { action: form.action, url, expires_at }))
+}
+
+#[derive(Deserialize, Debug)]
+struct SignedLinkQuery {
+    expires: i64,
+    signature: String,
+}
+
+/// Restarts the ship, or boots it if it is stopped, for whoever holds a link signed for restarting this pier. The
+/// only route that doesn't expect to be reached solely by the provider, it is a POST so that scanners following links
+/// in emails can't set it off.
+#[post("/signed/pier/{name}/restart")]
+async fn signed_restart(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    query: web::Query<SignedLinkQuery>,
+) -> ApiResult<HttpResponse> {
+    let name = name.into_inner();
+    signed_links::verify(signed_links::SignedAction::Restart, &name, query.expires, &query.signature).await?;
+
+    log::info!("restarting {} for a signed link", name);
+    stop(&state, &name).await?;
+    Ok(accepted(spawn_boot(&state, name, true).await?))
+}
+
+#[get("/boot-queue")]
+async fn get_boot_queue(state: web::Data<RwLock<AppState>>) -> HttpResponse {
+    HttpResponse::Ok().json(state.read().await.boot_queue.summary())
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BootPriorityForm {
+    priority: ship::BootPriority,
+}
+
+/// Sets where the stopped pier's boots go in the boot queue relative to others'.
+#[put("/pier/{name}/boot-priority")]
+async fn set_boot_priority(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    form: web::Json<BootPriorityForm>,
+) -> ApiResult<HttpResponse> {
+    let name = name.into_inner();
+
+    let mut state = state.write().await;
+    if state.on.iter().any(|ship| ship.pier().name() == Some(&name)) {
+        return Err(ApiError::ship_running(&name));
+    }
+    let pier = state.off.iter_mut()
+        .find(|pier| pier.name() == Some(&name))
+        .ok_or_else(|| ApiError::pier_not_found(&name))?;
+
+    pier.set_boot_priority(form.priority).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Turns away new proxied requests for the named ship and lets those in flight finish, up to
+/// `proxy::DRAIN_TIMEOUT`, so that a planned stop doesn't cut them off. Hold the guard until the ship is stopped.
+async fn drain_proxied(state: &web::Data<RwLock<AppState>>, name: &str, timeout: Duration) -> proxy::Draining {
+    let traffic = state.read().await.proxy_traffic.clone();
+    traffic.drain(name, timeout).await
+}
+
+async fn stop(state: &web::Data<RwLock<AppState>>, name: &str) -> ApiResult<()> {
+    let _draining = drain_proxied(state, name, *proxy::DRAIN_TIMEOUT).await;
+    let ship = {
+        let mut state = state.write().await;
+        let ship = state.take_for_stop(name);
+        if ship.is_none() && !state.off.iter().any(|pier| pier.name() == Some(name)) {
+            return Err(ApiError::pier_not_found(name));
+        }
+        ship
+    };
+    if let Some(ship) = ship {
+        let pier = shut_down(state, ship).await?;
+        state.write().await.checkin(pier);
+    }
+    Ok(())
+}
+
+#[post("/pier/{name}/stop")]
+async fn stop_pier(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    stop(&state, &name).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Freezes a misbehaving ship in place for investigation: it keeps its memory but gets no CPU and answers nothing.
+#[post("/pier/{name}/pause")]
+async fn pause_pier(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let mut state = state.write().await;
+    let ship = state.on.iter_mut()
+        .find(|ship| ship.pier().name() == Some(&name))
+        .ok_or_else(|| ApiError::ship_not_running(&name))?;
+    if !ship.paused() {
+        ship.pause()?;
+        state.console.broadcast(&name, "ship paused");
+        state.events.publish(events::Event::ShipPaused { name: name.into_inner() });
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/pier/{name}/resume")]
+async fn resume_pier(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let mut state = state.write().await;
+    let ship = state.on.iter_mut()
+        .find(|ship| ship.pier().name() == Some(&name))
+        .ok_or_else(|| ApiError::ship_not_running(&name))?;
+    if ship.paused() {
+        ship.resume()?;
+        state.console.broadcast(&name, "ship resumed");
+        state.events.publish(events::Event::ShipResumed { name: name.into_inner() });
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RollingRestartForm {
+    /// How many ships are down at once.
+    #[serde(default = "RollingRestartForm::default_batch_size")]
+    batch_size: usize,
+    /// How long each restarted ship has to answer on dojo before it counts as failed.
+    #[serde(default = "RollingRestartForm::default_health_check_timeout_secs")]
+    health_check_timeout_secs: u64,
+    /// Stop after the first batch with a failure, leaving the remaining ships untouched.
+    #[serde(default = "RollingRestartForm::default_abort_on_failure")]
+    abort_on_failure: bool,
+}
+
+impl RollingRestartForm {
+    fn default_batch_size() -> usize { 1 }
+    fn default_health_check_timeout_secs() -> u64 { 300 }
+    fn default_abort_on_failure() -> bool { true }
+}
+
+const HEALTH_CHECK_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Waits for the named ship to be running and answering on dojo.
+async fn wait_until_healthy(state: &web::Data<RwLock<AppState>>, name: &str, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let lens = state.read().await.running_ship(name).map(ship::Ship::lens);
+        let checked = match lens {
+            Some(lens) => lens.now().await.map(|_| ()),
+            None => Err(anyhow!("ship is not running")),
+        };
+        match checked {
+            Ok(()) => return Ok(()),
+            Err(e) if Instant::now() >= deadline => bail!("not healthy after {:?}: {:#}", timeout, e),
+            Err(_) => actix_web::rt::time::sleep(HEALTH_CHECK_POLL_INTERVAL).await,
+        }
+    }
+}
+
+/// Stops and reboots one ship, then waits for it to come back healthy. Ships that are no longer running when their turn
+/// comes are skipped, returning None.
+async fn restart_ship(
+    state: &web::Data<RwLock<AppState>>,
+    job: &jobs::JobHandle,
+    name: &str,
+    timeout: Duration,
+) -> Option<Result<()>> {
+    let _draining = drain_proxied(state, name, *proxy::DRAIN_TIMEOUT).await;
+    let ship = state.write().await.take_for_stop(name)?;
+    Some(async {
+        let pier = shut_down(state, ship).await?;
+        boot_pier(state, job, pier, false).await?;
+        wait_until_healthy(state, name, timeout).await
+    }.await)
+}
+
+/// Restarts every running ship a batch at a time, waiting for each batch to come back healthy before starting the
+/// next, e.g. to apply host-level changes with little aggregate downtime.
+#[post("/piers/rolling-restart")]
+async fn rolling_restart(
+    state: web::Data<RwLock<AppState>>,
+    form: web::Json<RollingRestartForm>,
+) -> ApiResult<HttpResponse> {
+    let form = form.into_inner();
+    if form.batch_size == 0 {
+        return Err(ApiError::bad_request("batchSize must be at least 1"));
+    }
+
+    let (mut names, jobs) = {
+        let state = state.read().await;
+        let names: Vec<String> = state.on.iter().filter_map(|ship| ship.pier().name().map(str::to_owned)).collect();
+        (names, state.jobs.clone())
+    };
+    names.sort();
+
+    let state = state.clone();
+    Ok(accepted(jobs.spawn("restart", None, move |job| async move {
+        let timeout = Duration::from_secs(form.health_check_timeout_secs);
+        let batches: Vec<&[String]> = names.chunks(form.batch_size).collect();
+        let mut restarted = Vec::new();
+        let mut failed = Vec::new();
+        let mut skipped = Vec::new();
+
+        for (idx, batch) in batches.iter().enumerate() {
+            if form.abort_on_failure && !failed.is_empty() {
+                skipped.extend(batch.iter().cloned());
+                continue;
+            }
+            job.progress(format!("restarting batch {} of {}: {}", idx + 1, batches.len(), batch.join(", ")));
+
+            let outcomes = future::join_all(batch.iter().map(|name| restart_ship(&state, &job, name, timeout))).await;
+            for (name, outcome) in batch.iter().zip(outcomes) {
+                match outcome {
+                    None => skipped.push(name.clone()),
+                    Some(Ok(())) => restarted.push(name.clone()),
+                    Some(Err(e)) => {
+                        log::warn!("rolling restart of {} failed: {:#}", name, e);
+                        failed.push(serde_json::json!({ "name": name, "error": format!("{:#}", e) }));
+                    },
+                }
+            }
+        }
+
+        if form.abort_on_failure && !failed.is_empty() {
+            bail!(
+                "aborted after {} ship(s) failed to restart: {}; {} ship(s) were not restarted",
+                failed.len(), serde_json::Value::from(failed), skipped.len(),
+            );
+        }
+        Ok(serde_json::json!({ "restarted": restarted, "failed": failed, "skipped": skipped }))
+    })))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct StopAllQuery {
+    /// Roughly how long the whole stop may take, e.g. `30s`, before stragglers are killed.
+    grace: Option<String>,
+}
+
+/// Stops every running ship at once within a deadline, for emergencies like an overheating host or an imminent
+/// migration. Ships that don't exit in time are killed. Responds with how each ship's shutdown went.
+#[post("/piers/stop-all")]
+async fn stop_all(
+    state: web::Data<RwLock<AppState>>,
+    query: web::Query<StopAllQuery>,
+) -> ApiResult<HttpResponse> {
+    let grace = match &query.grace {
+        Some(grace) => util::parse_duration(grace).map_err(|e| ApiError::bad_request(format!("invalid grace: {}", e)))?,
+        None => Duration::from_secs(30),
+    };
+
+    // Proxied requests get up to a quarter of the grace to finish, and the ships the rest to exit.
+    let started = Instant::now();
+    let running: Vec<String> = state.read().await.on.iter()
+        .filter_map(|ship| ship.pier().name().map(str::to_owned))
+        .collect();
+    let drain_timeout = proxy::DRAIN_TIMEOUT.min(grace / 4);
+    let _draining = future::join_all(running.iter().map(|name| drain_proxied(&state, name, drain_timeout))).await;
+    let grace = grace.saturating_sub(started.elapsed());
+
+    // The ships are taken out and marked busy, so that the API stays up while they shut down.
+    let ships = {
+        let mut state = state.write().await;
+        let ships = std::mem::take(&mut state.on);
+        for ship in &ships {
+            let name = ship.pier().name().unwrap_or_default().to_owned();
+            state.restart_attempts.remove(&name);
+            state.busy.insert(name);
+        }
+        ships
+    };
+    let names: Vec<String> = ships.iter().map(|ship| ship.pier().name().unwrap_or_default().to_owned()).collect();
+    let mut ports: HashMap<String, (u16, u16)> = ships.iter()
+        .map(|ship| (ship.pier().name().unwrap_or_default().to_owned(), (ship.http_port(), ship.ames_port())))
+        .collect();
+    log::warn!("stopping all {} running ship(s) within {:?}", ships.len(), grace);
+    let outcomes = future::join_all(ships.into_iter().map(|ship| ship.shutdown_within(grace))).await;
+
+    let mut state = state.write().await;
+    let mut report = Vec::new();
+    for (name, outcome) in names.into_iter().zip(outcomes) {
+        if let Some((http_port, ames_port)) = ports.remove(&name) {
+            state.http_ports.lock().await.release(http_port);
+            state.ames_ports.lock().await.release(ames_port);
+        }
+        state.busy.remove(&name);
+        match outcome {
+            Ok((pier, outcome)) => {
+                state.off.push(pier);
+                state.console.broadcast(&name, "ship stopped");
+                state.events.publish(events::Event::ShipStopped { name: name.clone() });
+                report.push(serde_json::json!({ "name": name, "outcome": outcome }));
+            },
+            Err(e) => {
+                log::error!("failed to stop {}: {:#}", name, e);
+                match ship::PierState::load_from_port(&name).await {
+                    Ok(pier) => state.off.push(pier),
+                    Err(e) => log::error!("failed to reload pier '{}' after failed stop: {}", name, e),
+                }
+                report.push(serde_json::json!({ "name": name, "outcome": "failed", "error": format!("{:#}", e) }));
+            },
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Starts a job exporting the named pier, stopping its ship first if necessary. Exports for download go to an artifact
+/// in the harbor; S3 exports are streamed straight into the configured bucket.
+async fn spawn_export(
+    state: &web::Data<RwLock<AppState>>,
+    name: String,
+    layout: ship::ExportLayout,
+    target: ExportTarget,
+) -> ApiResult<Uuid> {
+    if target == ExportTarget::S3 && s3::S3.is_none() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "s3NotConfigured", "no S3 bucket is configured"));
+    }
+    if target == ExportTarget::Store && layout != ship::ExportLayout::Native {
+        return Err(ApiError::bad_request("only the native layout can be exported to the backup store"));
+    }
+
+    let (pier, jobs) = {
+        let mut state = state.write().await;
+        if state.busy.contains(&name) {
+            return Err(ApiError::pier_busy(&name));
+        }
+        let pier = state.checkout_stopping(&name).ok_or_else(|| ApiError::pier_not_found(&name))?;
+        (pier, state.jobs.clone())
+    };
+
+    let object_name = export_object_name(&name, time::OffsetDateTime::now_utc());
+    let state = state.clone();
+    Ok(jobs.spawn("export", Some(name.clone()), move |job| async move {
+        let mut pier = pier.stopped(&state).await?;
+        let events = state.read().await.events.clone();
+        events.publish(events::Event::ExportStarted { name: name.clone() });
+
+        job.progress("archiving pier");
+        let written: Result<(u64, serde_json::Value)> = async {
+            match target {
+                ExportTarget::Download => {
+                    let artifact = export_artifact_path(job.id()).await?;
+                    let written = pier.export_to_file(&artifact, layout).await;
+                    if written.is_err() {
+                        _ = fs::remove_file(&artifact).await;
+                    }
+                    Ok((written?, serde_json::json!({ "artifact": format!("/jobs/{}/artifact", job.id()) })))
+                },
+                ExportTarget::S3 => {
+                    let s3 = s3::S3.as_ref().unwrap();
+                    let key = s3.key(&object_name);
+                    let body = async_util::throttle(
+                        pier.export_stream(layout).await?,
+                        || bandwidth::BACKUP_BANDWIDTH.current_limit(),
+                    );
+                    let progress = |uploaded| job.progress(format!("uploaded {} bytes", uploaded));
+                    let written = s3.upload_stream(&key, body, progress).await?;
+                    Ok((written, serde_json::json!({ "bucket": s3.bucket, "key": key })))
+                },
+                ExportTarget::Store => {
+                    let report = pier.snapshot_to_store().await?;
+                    Ok((report.size, serde_json::to_value(report)?))
+                },
+            }
+        }.await;
+        if written.is_ok() {
+            if let Err(e) = pier.record_backup().await {
+                log::warn!("failed to record backup of {}: {:#}", name, e);
+            }
+        }
+        state.write().await.checkin(pier);
+        let (written, mut result) = written?;
+
+        let replicate = match target {
+            ExportTarget::Download => None,
+            ExportTarget::S3 => Some(replication::Item::Export { name: object_name }),
+            ExportTarget::Store => Some(replication::Item::Snapshot {
+                ship: name.clone(),
+                id: result["snapshot"].as_str().unwrap_or_default().to_owned(),
+            }),
+        };
+        if let Some(item) = replicate {
+            let replication = state.read().await.replication.clone();
+            if let Err(e) = replication.enqueue(item).await {
+                log::warn!("failed to queue the backup of {} for replication: {:#}", name, e);
+            }
+        }
+
+        events.publish(events::Event::ExportCompleted { name: name.clone() });
+        result["name"] = name.into();
+        result["size"] = written.into();
+        Ok(result)
+    }))
+}
+
+/// The object an S3 export is stored as, under the configured prefix: one folder per ship, one object per export.
+fn export_object_name(name: &str, at: time::OffsetDateTime) -> String {
+    format!(
+        "{}/{:04}{:02}{:02}T{:02}{:02}{:02}Z.tar.gz",
+        name, at.year(), at.month() as u8, at.day(), at.hour(), at.minute(), at.second(),
+    )
+}
+
+/// Starts a job packing or melding the named pier. Running ships do it live through dojo; stopped piers are checked out
+/// and processed offline by the runtime. The pier directory's size before and after is reported in the job result.
+async fn spawn_maintenance(
+    state: &web::Data<RwLock<AppState>>,
+    name: String,
+    subcommand: runtime::Subcommand,
+) -> ApiResult<Uuid> {
+    let kind = subcommand.name();
+    let (pier, jobs) = {
+        let mut state = state.write().await;
+        if state.busy.contains(&name) {
+            return Err(ApiError::pier_busy(&name));
+        }
+        let pier = match state.running_ship(&name) {
+            Some(_) => None,
+            None => Some(state.checkout(&name).ok_or_else(|| ApiError::pier_not_found(&name))?),
+        };
+        (pier, state.jobs.clone())
+    };
+
+    let state = state.clone();
+    Ok(jobs.spawn(kind, Some(name.clone()), move |job| async move {
+        let pier_path = ship::HARBOR.pier_path_in_port(&name).await?;
+        let size_before = util::dir_size(&pier_path).await?;
+
+        let output = match pier {
+            Some(pier) => {
+                job.progress(format!("running urbit {} offline", kind));
+                let output = pier.run_subcommand(subcommand).await;
+                state.write().await.checkin(pier);
+                output?
+            },
+            None => {
+                job.progress(format!("running |{} on the live ship", kind));
+                let lens = state.read().await.running_ship(&name)
+                    .ok_or_else(|| anyhow!("ship stopped before |{} could run: {}", kind, name))?
+                    .lens();
+                match subcommand {
+                    runtime::Subcommand::Pack => lens.pack().await?,
+                    runtime::Subcommand::Meld => lens.meld().await?,
+                    other => bail!("urbit {} can only run on a stopped pier", other.name()),
+                }
+            },
+        };
+
+        let size_after = util::dir_size(&pier_path).await?;
+        Ok(serde_json::json!({
+            "name": name,
+            "output": output,
+            "sizeBefore": size_before,
+            "sizeAfter": size_after,
+        }))
+    }))
+}
+
+#[post("/pier/{name}/pack")]
+async fn pack_pier(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    Ok(accepted(spawn_maintenance(&state, name.into_inner(), runtime::Subcommand::Pack).await?))
+}
+
+#[post("/pier/{name}/meld")]
+async fn meld_pier(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    Ok(accepted(spawn_maintenance(&state, name.into_inner(), runtime::Subcommand::Meld).await?))
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct MoonForm {
+    /// The moon to spawn. A random one is chosen if omitted.
+    name: Option<String>,
+}
+
+/// Spawns a moon of a running ship and creates and boots a pier for it. The job result is the same as for POST /pier.
+#[post("/pier/{name}/moons")]
+async fn create_moon(
+    state: web::Data<RwLock<AppState>>,
+    parent: web::Path<String>,
+    form: Option<web::Json<MoonForm>>,
+) -> ApiResult<HttpResponse> {
+    let parent = parent.into_inner();
+    let moon = form.map(|form| form.into_inner()).unwrap_or_default().name;
+    if let Some(moon) = &moon {
+        if ship::ShipClass::of_name(moon) != Some(ship::ShipClass::Moon) {
+            return Err(ApiError::bad_request(format!("not the @p of a moon: {}", moon)));
+        }
+    }
+
+    let (jobs, reservation) = {
+        let mut state = state.write().await;
+        if state.running_ship(&parent).is_none() {
+            return Err(if state.busy.contains(&parent) || state.off.iter().any(|pier| pier.name() == Some(&parent)) {
+                ApiError::ship_not_running(&parent)
+            } else {
+                ApiError::pier_not_found(&parent)
+            });
+        }
+        (state.jobs.clone(), state.reserve_pier()?)
+    };
+
+    let state = state.clone();
+    let job_id = jobs.spawn("moon", Some(parent.clone()), move |job| async move {
+        job.progress("spawning moon");
+        let lens = state.read().await.running_ship(&parent)
+            .ok_or_else(|| anyhow!("ship is not running: {}", parent))?
+            .lens();
+        let (name, keyfile) = lens.moon(moon.as_deref()).await?;
+        let upload = spool_bytes(keyfile.as_bytes()).await?;
+        import_pier(state, job, PostPierForm::FromKeyfile { name }, Some(upload), reservation).await
+    });
+
+    Ok(accepted(job_id))
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct ChopQuery {
+    /// Start a new event log epoch before chopping, so that the whole log up to now can be removed.
+    #[serde(default)]
+    roll: bool,
+}
+
+/// Truncates a stopped pier's event log with the runtime's offline `chop`, optionally after `roll`. The job result
+/// reports the event log and pier sizes before and after.
+#[post("/pier/{name}/chop")]
+async fn chop_pier(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    query: web::Query<ChopQuery>,
+) -> ApiResult<HttpResponse> {
+    let name = name.into_inner();
+    let (pier, jobs) = {
+        let mut state = state.write().await;
+        if state.running_ship(&name).is_some() {
+            return Err(ApiError::ship_running(&name));
+        }
+        if state.busy.contains(&name) {
+            return Err(ApiError::pier_busy(&name));
+        }
+        let version = managed_pier(&state, &name)?.runtime_version();
+        if query.roll {
+            version.require(runtime::Feature::Roll)?;
+        }
+        version.require(runtime::Feature::Chop)?;
+        let pier = state.checkout(&name).ok_or_else(|| ApiError::pier_not_found(&name))?;
+        (pier, state.jobs.clone())
+    };
+
+    let roll = query.roll;
+    let state = state.clone();
+    Ok(accepted(jobs.spawn("chop", Some(name.clone()), move |job| async move {
+        let result = async {
+            let before = usage::measure(&pier.pier_path(), &pier.scratch_path()).await?;
+
+            let mut output = String::new();
+            if roll {
+                job.progress("rolling event log");
+                output += &pier.run_subcommand(runtime::Subcommand::Roll).await?;
+            }
+            job.progress("chopping event log");
+            output += &pier.run_subcommand(runtime::Subcommand::Chop).await?;
+
+            let after = usage::measure(&pier.pier_path(), &pier.scratch_path()).await?;
+            Ok(serde_json::json!({
+                "name": name,
+                "output": output,
+                "eventLogSizeBefore": before.event_log_bytes,
+                "eventLogSizeAfter": after.event_log_bytes,
+                "sizeBefore": before.total_bytes,
+                "sizeAfter": after.total_bytes,
+            }))
+        }.await;
+        let mut state = state.write().await;
+        state.usage.invalidate(&pier.pier_path());
+        state.checkin(pier);
+        result
+    })))
+}
+
+/// Exports the pier to an artifact in the harbor instead of streaming it, for clients that would rather poll a job and
+/// download the finished archive from `/jobs/{id}/artifact`.
+#[post("/pier/{name}/export")]
+async fn start_export(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    query: web::Query<ExportQuery>,
+) -> ApiResult<HttpResponse> {
+    Ok(accepted(spawn_export(&state, name.into_inner(), query.layout, query.target).await?))
+}
+
+async fn export_artifact_path(job_id: Uuid) -> Result<PathBuf> {
+    Ok(ship::HARBOR.exports_path().await?.join(format!("{}.tar.gz", job_id.hyphenated())))
+}
+
+/// The ship's snapshots in the backup store, newest first. Listed whether or not the pier is still managed, so that
+/// removed ships can be restored.
+#[get("/pier/{name}/backups")]
+async fn list_backups(name: web::Path<String>) -> ApiResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(backup_store::list(&name).await?))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct VerifyBackupQuery {
+    name: Option<String>,
+    snapshot: Option<String>,
+}
+
+/// Starts a job proving a snapshot in the backup store restorable. Without `name`, the newest snapshot of a ship picked
+/// at random is verified; without `snapshot`, the newest snapshot of the named ship.
+#[post("/backups/verify")]
+async fn verify_backup(
+    state: web::Data<RwLock<AppState>>,
+    query: web::Query<VerifyBackupQuery>,
+) -> ApiResult<HttpResponse> {
+    let query = query.into_inner();
+    let name = query.name.map(|name| patp::parse(&name).map(patp::render)).transpose()?;
+    let (name, snapshot) = match (name, query.snapshot) {
+        (Some(name), Some(snapshot)) => (name, snapshot),
+        (Some(name), None) => {
+            let latest = backup_store::list(&name).await?.into_iter().next().ok_or_else(|| {
+                ApiError::new(StatusCode::NOT_FOUND, "noBackups", format!("~{} has no snapshots", name))
+            })?;
+            (name, latest.id)
+        },
+        (None, None) => pick_backup_to_verify().await?
+            .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "noBackups", "the backup store is empty"))?,
+        (None, Some(_)) => return Err(ApiError::bad_request("snapshot requires name")),
+    };
+    Ok(accepted(spawn_backup_verification(&state, name, snapshot).await))
+}
+
+/// The newest snapshot of a ship picked at random, if the backup store has any.
+async fn pick_backup_to_verify() -> Result<Option<(String, String)>> {
+    use rand::seq::SliceRandom;
+    Ok(backup_store::latest().await?.choose(&mut rand::thread_rng()).cloned())
+}
+
+async fn spawn_backup_verification(state: &web::Data<RwLock<AppState>>, name: String, snapshot: String) -> Uuid {
+    let jobs = state.read().await.jobs.clone();
+    let state = state.clone();
+    jobs.spawn("verify", Some(name.clone()), move |job| async move {
+        let verified = boot_backup(&state, &job, &name, &snapshot).await;
+        let verification = backup_store::Verification {
+            verified_at: time::OffsetDateTime::now_utc(),
+            ok: verified.is_ok(),
+            error: verified.as_ref().err().map(|e| format!("{:#}", e)),
+        };
+        if let Err(e) = backup_store::record_verification(&name, &snapshot, &verification).await {
+            log::warn!("failed to record verification of {} snapshot {}: {:#}", name, snapshot, e);
+        }
+        state.read().await.events.publish(events::Event::BackupVerified {
+            name: name.clone(),
+            snapshot: snapshot.clone(),
+            ok: verification.ok,
+            error: verification.error,
+        });
+        verified?;
+        Ok(serde_json::json!({ "name": name, "snapshot": snapshot }))
+    })
+}
+
+/// Restores the snapshot into a throwaway pier in the dry dock, boots it with its networking confined to the host so
+/// that it can't interfere with the live ship, and logs in with its `+code`. The pier is removed afterwards either way.
+async fn boot_backup(
+    state: &web::Data<RwLock<AppState>>,
+    job: &jobs::JobHandle,
+    name: &str,
+    snapshot: &str,
+) -> Result<()> {
+    job.progress("restoring snapshot");
+    let mut pier = ship::PierState::new_from_backup(name, snapshot).await?;
+    let id = pier.id();
+    pier.set_local_networking(true);
+
+    let (http_ports, ames_ports, boot_queue) = {
+        let state = state.read().await;
+        (state.http_ports.clone(), state.ames_ports.clone(), state.boot_queue.clone())
+    };
+    job.progress("queued to boot");
+    let slot = boot_queue.acquire(name, job.id(), ship::BootPriority::Low, false).await;
+    job.progress("booting");
+    let launched = match pier.launch(&http_ports, &ames_ports).await {
+        Ok(ship) => ship.ready(&ship::BootProgress::default()).await,
+        Err(e) => Err(e),
+    };
+    drop(slot);
+
+    let checked = match launched {
+        Ok(ship) => {
+            job.progress("logging in");
+            let checked = ship.check_login().await;
+            let (http_port, ames_port) = (ship.http_port(), ship.ames_port());
+            let stopped = ship.shutdown().await;
+            http_ports.lock().await.release(http_port);
+            ames_ports.lock().await.release(ames_port);
+            checked.and(stopped.map(drop))
+        },
+        Err(e) => Err(e),
+    };
+    if let Err(e) = ship::PierState::remove_from_dry_dock(id).await {
+        log::warn!("failed to remove dry dock entry {} after verifying a backup of {}: {:#}", id, name, e);
+    }
+    checked
+}
+
+/// Periodically verifies a random ship's newest snapshot, every `BACKUP_VERIFY_INTERVAL`.
+async fn verify_backups(state: web::Data<RwLock<AppState>>, every: Duration) {
+    let mut interval = actix_web::rt::time::interval(every);
+    // The first tick completes immediately, and startup is busy enough already.
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        match pick_backup_to_verify().await {
+            Ok(Some((name, snapshot))) => {
+                let job_id = spawn_backup_verification(&state, name.clone(), snapshot.clone()).await;
+                log::info!("verifying {} snapshot {} as job {}", name, snapshot, job_id);
+            },
+            Ok(None) => {},
+            Err(e) => log::warn!("failed to pick a backup to verify: {:#}", e),
+        }
+    }
+}
+
+/// Starts a job deleting the chunks in the backup store that no snapshot refers to any more.
+#[post("/backups/gc")]
+async fn collect_backup_garbage(state: web::Data<RwLock<AppState>>) -> HttpResponse {
+    let jobs = state.read().await.jobs.clone();
+    accepted(jobs.spawn("gc", None, |job| async move {
+        job.progress("collecting garbage");
+        Ok(serde_json::to_value(backup_store::collect_garbage().await?)?)
+    }))
+}
+
+/// How far the replica bucket is behind the backups taken here.
+#[get("/backups/replication")]
+async fn get_replication_status(state: web::Data<RwLock<AppState>>) -> HttpResponse {
+    let replication = state.read().await.replication.clone();
+    HttpResponse::Ok().json(replication.status().await)
+}
+
+#[get("/jobs")]
+async fn list_jobs(state: web::Data<RwLock<AppState>>) -> HttpResponse {
+    HttpResponse::Ok().json(state.read().await.jobs.list())
+}
+
+#[get("/jobs/{id}")]
+async fn get_job(
+    state: web::Data<RwLock<AppState>>,
+    id: web::Path<Uuid>,
+) -> ApiResult<HttpResponse> {
+    let job = state.read().await.jobs.get(*id)
+        .ok_or_else(|| ApiError::job_not_found(*id))?;
+
+    Ok(HttpResponse::Ok().json(job))
+}
+
+/// Downloads an export's archive. Single byte ranges are supported, with a strong ETag for If-Range, so that clients
+/// on high-latency links can fetch several ranges in parallel and resume interrupted downloads.
+#[get("/jobs/{id}/artifact")]
+async fn get_job_artifact(
+    state: web::Data<RwLock<AppState>>,
+    req: HttpRequest,
+    id: web::Path<Uuid>,
+) -> ApiResult<HttpResponse> {
+    let job = state.read().await.jobs.get(*id)
+        .ok_or_else(|| ApiError::job_not_found(*id))?;
+    if job.kind != "export" || job.state != jobs::JobState::Succeeded {
+        return Err(ApiError::new(StatusCode::CONFLICT, "noArtifact", "job has no artifact"));
+    }
+
+    let path = export_artifact_path(*id).await?;
+    let mut file = fs::File::open(&path).await
+        .map_err(|_| ApiError::new(StatusCode::GONE, "artifactRemoved", "export artifact has been removed"))?;
+    let metadata = file.metadata().await.map_err(|e| ApiError::from(Error::from(e)))?;
+    let size = metadata.len();
+    let modified = metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    // Artifacts are never rewritten in place, so this identifies the exact bytes and ranges can be safely combined.
+    let etag = header::EntityTag::new_strong(format!("{}-{:x}-{:x}", id.simple(), size, modified.as_nanos()));
+
+    let etag_str = etag.to_string();
+    let if_none_match = req.headers().get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok());
+    if if_none_match.is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag_str || tag.trim() == "*")) {
+        return Ok(HttpResponse::NotModified().insert_header(header::ETag(etag)).finish());
+    }
+
+    // A range only applies if the client's copy is still current, as told by If-Range.
+    let if_range = req.headers().get(header::IF_RANGE).and_then(|value| value.to_str().ok());
+    let range = match req.headers().get(header::RANGE).and_then(|value| value.to_str().ok()) {
+        Some(range) if if_range.is_none_or(|tag| tag.trim() == etag_str) => {
+            match util::parse_byte_range(range, size) {
+                Ok(range) => range,
+                Err(e) => {
+                    let mut res = ApiError::new(StatusCode::RANGE_NOT_SATISFIABLE, "rangeNotSatisfiable", e.to_string())
+                        .error_response();
+                    res.headers_mut().insert(header::CONTENT_RANGE, header::HeaderValue::from_str(
+                        &format!("bytes */{}", size),
+                    ).unwrap());
+                    return Ok(res);
+                },
+            }
+        },
+        _ => None,
+    };
+
+    let mut res = match &range {
+        Some(range) => {
+            let mut res = HttpResponse::PartialContent();
+            res.insert_header((header::CONTENT_RANGE, format!("bytes {}-{}/{}", range.start, range.end - 1, size)));
+            res
+        },
+        None => HttpResponse::Ok(),
+    };
+    res.content_type("application/gzip")
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header(header::ETag(etag))
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!("{}.tar.gz", job.pier.unwrap_or_default()))],
+        });
+
+    let range = range.unwrap_or(0..size);
+    file.seek(std::io::SeekFrom::Start(range.start)).await.map_err(|e| ApiError::from(Error::from(e)))?;
+    let body = async_util::read_stream(file.take(range.end - range.start));
+    // Send a Content-Length rather than chunking, so that clients can plan their parallel ranges.
+    Ok(res.no_chunking(range.end - range.start).streaming(body))
+}
+
+/// Where an export goes.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+enum ExportTarget {
+    /// To the client: streamed in the response to GET, or kept as a job artifact for POST.
+    #[default]
+    Download,
+    /// Into the S3 bucket configured by `NUCLEUS_S3_*`, by a job.
+    S3,
+    /// Into the deduplicated backup store in the harbor, by a job. Only the native layout can be stored.
+    Store,
+}
+
+/// A pier checked out of the app state for as long as a streamed response body uses it. The body may be dropped at any
+/// point if the client goes away, so the pier is checked back in when this is dropped, if not before.
+struct CheckedOut {
+    state: web::Data<RwLock<AppState>>,
+    pier: Option<ship::PierState>,
+}
+
+impl CheckedOut {
+    fn new(state: &web::Data<RwLock<AppState>>, pier: ship::PierState) -> Self {
+        CheckedOut { state: state.clone(), pier: Some(pier) }
+    }
+
+    async fn checkin(mut self) {
+        if let Some(pier) = self.pier.take() {
+            self.state.write().await.checkin(pier);
+        }
+    }
+}
+
+impl std::ops::Deref for CheckedOut {
+    type Target = ship::PierState;
+
+    fn deref(&self) -> &ship::PierState {
+        self.pier.as_ref().unwrap()
+    }
+}
+
+impl std::ops::DerefMut for CheckedOut {
+    fn deref_mut(&mut self) -> &mut ship::PierState {
+        self.pier.as_mut().unwrap()
+    }
+}
+
+impl Drop for CheckedOut {
+    fn drop(&mut self) {
+        if let Some(pier) = self.pier.take() {
+            let state = self.state.clone();
+            actix_web::rt::spawn(async move { state.write().await.checkin(pier) });
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ExportQuery {
+    #[serde(default)]
+    layout: ship::ExportLayout,
+    #[serde(default)]
+    target: ExportTarget,
+}
+
+#[get("/pier/{name}/export")]
+async fn export_pier(
+    app_state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    query: web::Query<ExportQuery>,
+) -> ApiResult<HttpResponse> {
+    let name = name.into_inner();
+    if query.target != ExportTarget::Download {
+        return Ok(accepted(spawn_export(&app_state, name, query.layout, query.target).await?));
+    }
+
+    let (pier, events) = {
+        let mut state = app_state.write().await;
+        if state.busy.contains(&name) {
+            return Err(ApiError::pier_busy(&name));
+        }
+        let pier = state.checkout_stopping(&name).ok_or_else(|| ApiError::pier_not_found(&name))?;
+        (pier, state.events.clone())
+    };
+    let pier = CheckedOut::new(&app_state, pier.stopped(&app_state).await?);
+    let body = pier.export_stream(query.layout).await?;
+
+    events.publish(events::Event::ExportStarted { name: name.clone() });
+    let body = events.publish_on_completion(body, events::Event::ExportCompleted { name: name.clone() });
+    let body = async_util::on_success(body, move || async move {
+        let mut pier = pier;
+        if let Err(e) = pier.record_backup().await {
+            log::warn!("failed to record backup of {}: {:#}", pier.name().unwrap_or_default(), e);
+        }
+        pier.checkin().await;
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/gzip")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!("{}.tar.gz", name))],
+        })
+        .streaming(body))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum PierStatus {
+    Running,
+    /// Running, but frozen with `POST /pier/{name}/pause`.
+    Paused,
+    Stopped,
+    /// Launched, but not yet through `NUCLEUS_READINESS_PROBE`.
+    Booting,
+    /// Stopped because its runtime exited unexpectedly.
+    Crashed,
+    Busy,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PierSummary {
+    name: String,
+    /// None for busy piers, which are checked out by a job.
+    id: Option<Uuid>,
+    status: PierStatus,
+    /// For booting piers, how far the boot has got.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    boot_stage: Option<ship::BootStage>,
+    class: Option<ship::ShipClass>,
+    /// The ship's parent by address, e.g. a moon's planet, for grouping ships under their parents.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<String>,
+    /// For moons, whether the parent is managed by this orchestrator too. A moon whose parent isn't can't be helped
+    /// with a breach or key change from here.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent_managed: Option<bool>,
+    /// The last check of a running ship's clock.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clock: Option<clock::ShipClock>,
+    /// Security label the runtime is running under, for running ships on hosts with AppArmor or SELinux.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confinement: Option<String>,
+    #[serde(flatten)]
+    metadata: ship::PierMetadata,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    legal_hold: Option<ship::LegalHold>,
+    /// For development clones, the pier this one was copied from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clone_of: Option<ship::CloneOrigin>,
+    fake: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plan: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiry: Option<expiry::Expiry>,
+    #[serde(flatten)]
+    lifecycle: ship::Lifecycle,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum PierSortKey {
+    #[default]
+    Name,
+    CreatedAt,
+    FirstBootedAt,
+    LastLaunchedAt,
+    LastBackupAt,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ListPiersQuery {
+    #[serde(default)]
+    sort: PierSortKey,
+    /// Sort in descending order, e.g. most recently backed up first.
+    #[serde(default)]
+    desc: bool,
+    /// Only piers with all of these comma-separated tags.
+    tag: Option<String>,
+    /// Only piers with this owner.
+    owner: Option<String>,
+}
+
+/// Lists every pier the orchestrator manages. Piers missing the timestamp being sorted on sort first. Busy piers, whose
+/// metadata is checked out with them, never match a tag or owner filter.
+#[get("/pier")]
+async fn list_piers(
+    state: web::Data<RwLock<AppState>>,
+    query: web::Query<ListPiersQuery>,
+) -> HttpResponse {
+    let state = state.read().await;
+
+    let lineage = |name: &str| {
+        let ship = patp::parse(name).ok();
+        let parent = ship.and_then(patp::parent).map(patp::render);
+        let parent_managed = ship.filter(|ship| patp::class(*ship) == ship::ShipClass::Moon)
+            .and(parent.as_ref())
+            .map(|parent| state.has_pier(parent));
+        (parent, parent_managed)
+    };
+    let summarize = |pier: &ship::PierState, status, pid: Option<u32>| {
+        let (parent, parent_managed) = lineage(pier.name().unwrap_or_default());
+        PierSummary {
+            name: pier.name().unwrap_or_default().to_owned(),
+            id: Some(pier.id()),
+            status,
+            boot_stage: None,
+            class: pier.name().and_then(ship::ShipClass::of_name),
+            parent,
+            parent_managed,
+            clock: pid.and(pier.name()).and_then(|name| state.clocks.ship(name)),
+            confinement: pid.and_then(confinement::current_label),
+            metadata: pier.metadata().clone(),
+            legal_hold: pier.legal_hold().cloned(),
+            clone_of: pier.clone_of().cloned(),
+            fake: pier.fake(),
+            plan: pier.plan().map(str::to_owned),
+            expiry: pier.expiry().cloned(),
+            lifecycle: pier.lifecycle().clone(),
+        }
+    };
+    let mut piers: Vec<PierSummary> = state.on.iter()
+        .map(|ship| {
+            let status = if ship.paused() { PierStatus::Paused } else { PierStatus::Running };
+            summarize(ship.pier(), status, ship.pid())
+        })
+        .chain(state.off.iter().map(|pier| {
+            let crashed = pier.name().is_some_and(|name| state.crashed.contains(name));
+            summarize(pier, if crashed { PierStatus::Crashed } else { PierStatus::Stopped }, None)
+        }))
+        .chain(state.busy.iter().map(|name| {
+            let (parent, parent_managed) = lineage(name);
+            PierSummary {
+                name: name.clone(),
+                id: None,
+                status: if state.booting.contains_key(name) { PierStatus::Booting } else { PierStatus::Busy },
+                boot_stage: state.booting.get(name).map(ship::BootProgress::stage),
+                class: ship::ShipClass::of_name(name),
+                parent,
+                parent_managed,
+                clock: None,
+                confinement: None,
+                metadata: ship::PierMetadata::default(),
+                legal_hold: None,
+                clone_of: None,
+                fake: false,
+                plan: None,
+                expiry: None,
+                lifecycle: ship::Lifecycle::default(),
+            }
+        }))
+        .collect();
+
+    if let Some(tags) = &query.tag {
+        piers.retain(|pier| tags.split(',').filter(|tag| !tag.is_empty()).all(|tag| pier.metadata.tags.contains(tag)));
+    }
+    if let Some(owner) = &query.owner {
+        piers.retain(|pier| pier.metadata.owner.as_ref() == Some(owner));
+    }
+
+    match query.sort {
+        PierSortKey::Name => piers.sort_by(|a, b| a.name.cmp(&b.name)),
+        PierSortKey::CreatedAt => piers.sort_by_key(|pier| pier.lifecycle.created_at),
+        PierSortKey::FirstBootedAt => piers.sort_by_key(|pier| pier.lifecycle.first_booted_at),
+        PierSortKey::LastLaunchedAt => piers.sort_by_key(|pier| pier.lifecycle.last_launched_at),
+        PierSortKey::LastBackupAt => piers.sort_by_key(|pier| pier.lifecycle.last_backup_at),
+    }
+    if query.desc {
+        piers.reverse();
+    }
+
+    HttpResponse::Ok().json(piers)
+}
+
+#[derive(Deserialize, Debug)]
+struct DojoRequest {
+    command: String,
+    /// How long to wait for the ship to answer; `NUCLEUS_LENS_TIMEOUT` if absent.
+    timeout_ms: Option<u64>,
+    /// Permit commands that may change the ship's state (pokes, hood generators, clay writes, threads).
+    #[serde(default)]
+    allow_writes: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct DojoResponse {
+    command: String,
+    output: String,
+    elapsed_ms: u128,
+}
+
+#[post("/pier/{name}/dojo")]
+async fn dojo(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    req: web::Json<DojoRequest>,
+) -> ApiResult<HttpResponse> {
+    let name = name.into_inner();
+    let req = req.into_inner();
+
+    if !req.allow_writes && !ship::dojo_is_read_only(&req.command) {
+        return Err(ApiError::forbidden("command may modify ship state; set allow_writes to run it"));
+    }
+
+    let lens = {
+        let state = state.read().await;
+        let ship = state.running_ship(&name).ok_or_else(|| ApiError::ship_not_running(&name))?;
+        if ship.paused() {
+            return Err(ApiError::ship_paused(&name));
+        }
+        ship.lens()
+    };
+
+    let started = Instant::now();
+    let output = lens.dojo_with_timeout(&req.command, req.timeout_ms.map(Duration::from_millis)).await
+        .map_err(ApiError::ship_error)?;
+
+    Ok(HttpResponse::Ok().json(DojoResponse {
+        command: req.command,
+        output,
+        elapsed_ms: started.elapsed().as_millis(),
+    }))
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CodeResponse {
+    code: String,
+    /// Whether the code was served from the cache because the ship is stopped.
+    cached: bool,
+}
+
+/// Where to get a ship's web login code: from the ship while it runs, or else from the cache kept from the last time it
+/// was retrieved, so that stopped ships aren't booted.
+enum CodeSource {
+    Ship(ship::Lens),
+    Cache(ship::CodeCache),
+}
+
+fn code_source(state: &AppState, name: &str) -> ApiResult<CodeSource> {
+    if let Some(ship) = state.running_ship(name) {
+        return Ok(CodeSource::Ship(ship.lens()));
+    }
+    if state.busy.contains(name) {
+        return Err(ApiError::pier_busy(name));
+    }
+
+    let pier = state.off.iter()
+        .find(|pier| pier.name() == Some(name))
+        .ok_or_else(|| ApiError::pier_not_found(name))?;
+    Ok(CodeSource::Cache(pier.code_cache()))
+}
+
+/// The ship's web login code, and whether it came from the cache. The source is found with `code_source` beforehand so
+/// that the state lock needn't be held while the code is retrieved.
+async fn login_code(source: CodeSource, name: &str) -> ApiResult<(String, bool)> {
+    match source {
+        CodeSource::Ship(lens) => Ok((lens.code().await.map_err(ApiError::ship_error)?, false)),
+        CodeSource::Cache(cache) => {
+            let code = cache.load().await?.ok_or_else(|| ApiError::new(
+                StatusCode::CONFLICT,
+                "codeNotCached",
+                format!("ship is not running and its code has not been retrieved before: {}", name),
+            ))?;
+            Ok((code, true))
+        },
+    }
+}
+
+/// The named ship if it is running, or the error to respond with.
+fn require_running<'a>(state: &'a AppState, name: &str) -> ApiResult<&'a ship::Ship> {
+    state.running_ship(name).ok_or_else(|| if state.has_pier(name) {
+        ApiError::ship_not_running(name)
+    } else {
+        ApiError::pier_not_found(name)
+    })
+}
+
+/// The desks installed on the ship, parsed from `+vats`.
+#[get("/pier/{name}/vats")]
+async fn get_vats(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let lens = require_running(&*state.read().await, &name)?.lens();
+    let vats = lens.vats().await.map_err(ApiError::ship_error)?;
+    Ok(HttpResponse::Ok().json(vats))
+}
+
+/// The desks installed on the ship and their status, parsed from `+vats`.
+#[get("/pier/{name}/desks")]
+async fn list_desks(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let lens = require_running(&*state.read().await, &name)?.lens();
+    let desks = lens.vats().await.map_err(ApiError::ship_error)?;
+    Ok(HttpResponse::Ok().json(desks))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "action", rename_all = "camelCase")]
+enum DeskAction {
+    /// Installs `desk` from the ship `source`, e.g. `%groups` from `~sogryp-dister-dozzod-dozzod`.
+    #[serde(rename_all = "camelCase")]
+    Install { source: String, desk: String, local: Option<String> },
+    Suspend { desk: String },
+    Revive { desk: String },
+}
+
+/// Installs, suspends or revives one of the ship's desks through the lens, and returns what dojo printed. Installs
+/// finish in the background; `GET /pier/{name}/desks` shows how far they have got.
+#[post("/pier/{name}/desks")]
+async fn manage_desk(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    action: web::Json<DeskAction>,
+) -> ApiResult<HttpResponse> {
+    let action = action.into_inner();
+    let desks = match &action {
+        DeskAction::Install { desk, local, .. } => vec![desk, local.as_ref().unwrap_or(desk)],
+        DeskAction::Suspend { desk } | DeskAction::Revive { desk } => vec![desk],
+    };
+    if !desks.iter().all(|desk| queries::is_valid_desk(desk)) {
+        return Err(ApiError::bad_request("desk names must be lowercase letters, digits and hyphens"));
+    }
+    if let DeskAction::Install { source, .. } = &action {
+        patp::parse(source)?;
+    }
+
+    let lens = require_running(&*state.read().await, &name)?.lens();
+    let (desk, output) = match &action {
+        DeskAction::Install { source, desk, local } => {
+            (local.as_ref().unwrap_or(desk), lens.install_desk(source, desk, local.as_deref()).await)
+        },
+        DeskAction::Suspend { desk } => (desk, lens.suspend_desk(desk).await),
+        DeskAction::Revive { desk } => (desk, lens.revive_desk(desk).await),
+    };
+    let output = output.map_err(ApiError::ship_error)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "desk": desk, "output": output })))
+}
+
+/// Where the ship gets kernel updates from and what its `%base` desk is at.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OtaStatus {
+    source_ship: Option<String>,
+    /// The trailing characters of `%base`'s base hash, as `+vats` abbreviates it.
+    base_hash: Option<String>,
+    pending_updates: Option<String>,
+    /// `%base` isn't syncing from any ship, as after `|pause-updates`.
+    updates_paused: bool,
+}
+
+/// The ship's OTA source and `%base` hash, parsed from `+vats %base`.
+#[get("/pier/{name}/ota")]
+async fn get_ota(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let lens = require_running(&*state.read().await, &name)?.lens();
+    let base = lens.ota_status().await.map_err(ApiError::ship_error)?;
+    Ok(HttpResponse::Ok().json(OtaStatus {
+        updates_paused: base.source_ship.is_none(),
+        source_ship: base.source_ship,
+        base_hash: base.base_hash,
+        pending_updates: base.pending_updates,
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "action", rename_all = "camelCase")]
+enum OtaAction {
+    Pause,
+    Resume,
+    /// Gets updates from the ship `source` from now on.
+    SetSource { source: String },
+}
+
+/// Pauses or resumes the ship's kernel updates, or changes where it gets them from, so that OTAs can be staged across
+/// the fleet rather than reaching every ship at once. Returns what dojo printed.
+#[post("/pier/{name}/ota")]
+async fn manage_ota(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    action: web::Json<OtaAction>,
+) -> ApiResult<HttpResponse> {
+    if let OtaAction::SetSource { source } = &*action {
+        patp::parse(source)?;
+    }
+
+    let lens = require_running(&*state.read().await, &name)?.lens();
+    let output = match &*action {
+        OtaAction::Pause => lens.pause_updates().await,
+        OtaAction::Resume => lens.resume_updates().await,
+        OtaAction::SetSource { source } => lens.set_ota_source(source).await,
+    };
+    let output = output.map_err(ApiError::ship_error)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "output": output })))
+}
+
+/// Scries one of the ship's agents through eyre, for paths with a `json` mark conversion, such as an agent's own health
+/// check.
+#[get("/pier/{name}/scry/{app}/{path:.*}")]
+async fn scry_agent(
+    state: web::Data<RwLock<AppState>>,
+    path: web::Path<(String, String, String)>,
+) -> ApiResult<HttpResponse> {
+    let (name, app, path) = path.into_inner();
+    if !queries::is_valid_desk(&app) {
+        return Err(ApiError::bad_request("agent names must be lowercase letters, digits and hyphens"));
+    }
+    let lens = require_running(&*state.read().await, &name)?.lens();
+    let result = lens.scry(&app, &format!("/{}", path)).await.map_err(ApiError::ship_error)?;
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Deserialize, Debug)]
+struct PokeForm {
+    app: String,
+    mark: String,
+    json: serde_json::Value,
+}
+
+/// Pokes one of the ship's agents through eyre, responding once the agent acks the poke.
+#[post("/pier/{name}/poke")]
+async fn poke_agent(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    form: web::Json<PokeForm>,
+) -> ApiResult<HttpResponse> {
+    let form = form.into_inner();
+    if !queries::is_valid_desk(&form.app) || !queries::is_valid_desk(&form.mark) {
+        return Err(ApiError::bad_request("agent and mark names must be lowercase letters, digits and hyphens"));
+    }
+    let lens = require_running(&*state.read().await, &name)?.lens();
+    lens.poke(&form.app, &form.mark, form.json).await.map_err(ApiError::ship_error)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Subscribes to a path of one of the ship's agents through eyre, streaming its facts as server-sent events until the
+/// agent kicks the subscription or the client disconnects.
+#[get("/pier/{name}/subscribe/{app}/{path:.*}")]
+async fn subscribe_agent(
+    state: web::Data<RwLock<AppState>>,
+    path: web::Path<(String, String, String)>,
+) -> ApiResult<HttpResponse> {
+    let (name, app, path) = path.into_inner();
+    if !queries::is_valid_desk(&app) {
+        return Err(ApiError::bad_request("agent names must be lowercase letters, digits and hyphens"));
+    }
+    let lens = require_running(&*state.read().await, &name)?.lens();
+    let facts = lens.subscribe(&app, &format!("/{}", path)).await.map_err(ApiError::ship_error)?;
+    let facts = facts.map(move |fact| match fact {
+        Ok(fact) => Ok(web::Bytes::from(format!("event: fact\ndata: {}\n\n", fact))),
+        Err(e) => {
+            log::warn!("subscription to {} on {} failed: {:#}", app, name, e);
+            Err(actix_web::error::ErrorBadGateway(e.to_string()))
+        },
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(facts))
+}
+
+/// Picks the running ship a proxied request is for with `pick`. If the ship isn't running but is on its way up, the
+/// request is held for up to `proxy::BOOT_WAIT` for it to be, with the ship's boot moved to the front of the queue.
+async fn hold_for_boot<T>(
+    state: &web::Data<RwLock<AppState>>,
+    name: &str,
+    pick: impl Fn(&AppState) -> ApiResult<T>,
+) -> ApiResult<T> {
+    let deadline = proxy::BOOT_WAIT.map(|wait| Instant::now() + wait);
+    let mut prioritized = false;
+    loop {
+        {
+            let state = state.read().await;
+            let err = match pick(&state) {
+                Ok(picked) => return Ok(picked),
+                Err(err) => err,
+            };
+            match deadline {
+                Some(deadline) if Instant::now() < deadline && state.coming_up(name) => {
+                    if !prioritized {
+                        state.boot_queue.prioritize(name);
+                        prioritized = true;
+                    }
+                },
+                _ => return Err(err),
+            }
+        }
+        actix_web::rt::time::sleep(proxy::BOOT_WAIT_POLL_INTERVAL).await;
+    }
+}
+
+/// Passes requests through to the ship's own web interface, logged in as the ship, so that dashboards can reach
+/// Landscape and the channel API through the orchestrator.
+#[route(
+    "/pier/{name}/eyre/{path:.*}",
+    method = "GET", method = "HEAD", method = "POST", method = "PUT", method = "DELETE", method = "PATCH",
+    method = "OPTIONS",
+)]
+async fn eyre_proxy(
+    req: HttpRequest,
+    payload: web::Payload,
+    state: web::Data<RwLock<AppState>>,
+    path: web::Path<(String, String)>,
+) -> ApiResult<HttpResponse> {
+    let (name, path) = path.into_inner();
+    let (port, in_flight) = hold_for_boot(&state, &name, |state| {
+        let ship = require_running(state, &name)?;
+        state.proxy_traffic.check_channel(&name, ship.pid().unwrap_or_default(), req.method(), &format!("/{}", path))?;
+        let in_flight = state.proxy_traffic.begin(&name).ok_or_else(|| ApiError::ship_stopping(&name))?;
+        Ok((ship.http_port(), in_flight))
+    }).await?;
+    let lens = require_running(&*state.read().await, &name)?.lens();
+    let cookie = lens.eyre_cookie().await.map_err(ApiError::ship_error)?;
+    let path_and_query = match req.query_string() {
+        "" => format!("/{}", path),
+        query => format!("/{}?{}", path, query),
+    };
+    let prefix = format!("/pier/{}/eyre", name);
+    let res = proxy::forward(&req, payload, port, &path_and_query, Some(&cookie), &prefix, in_flight).await
+        .map_err(ApiError::ship_error)?;
+    if res.status() == StatusCode::FORBIDDEN {
+        lens.forget_eyre_cookie();
+    }
+    Ok(res)
+}
+
+/// Serves running ships' web interfaces on the vhost listener, picking the ship by the hostname each request is for.
+/// Fake ships and clones, which are kept off the network, aren't served.
+async fn vhost_proxy(
+    req: HttpRequest,
+    payload: web::Payload,
+    state: web::Data<RwLock<AppState>>,
+) -> ApiResult<HttpResponse> {
+    let host = req.connection_info().host().to_owned();
+    let unknown_host = || ApiError::new(StatusCode::NOT_FOUND, "unknownHost", format!("no ship is served at {}", host));
+    let name = vhost::VHOST_TEMPLATE.ship_for_host(&host).ok_or_else(unknown_host)?;
+    let (port, in_flight) = hold_for_boot(&state, &name, |state| match state.running_ship(&name) {
+        Some(ship) if ship.pier().networked() => {
+            state.proxy_traffic.check_channel(&name, ship.pid().unwrap_or_default(), req.method(), req.uri().path())?;
+            let in_flight = state.proxy_traffic.begin(&name).ok_or_else(|| ApiError::ship_stopping(&name))?;
+            Ok((ship.http_port(), in_flight))
+        },
+        None if state.off.iter().any(|pier| pier.name() == Some(&name) && pier.networked()) => {
+            Err(ApiError::ship_not_running(&name))
+        },
+        _ => Err(unknown_host()),
+    }).await?;
+    let path_and_query = req.uri().path_and_query().map_or("/", |path| path.as_str()).to_owned();
+    proxy::forward(&req, payload, port, &path_and_query, None, "", in_flight).await.map_err(ApiError::ship_error)
+}
+
+/// Answers the CA's `http-01` challenges for the vhost proxy's certificate.
+#[get("/.well-known/acme-challenge/{token}")]
+async fn acme_challenge(token: web::Path<String>) -> HttpResponse {
+    match acme::http_challenge(&token) {
+        Some(key_authorization) => HttpResponse::Ok().content_type("application/octet-stream").body(key_authorization),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Every networked ship's hostname, running or not, so that starting one needs no new certificate or DNS record.
+fn ship_hostnames(state: &AppState) -> BTreeSet<String> {
+    state.on.iter().map(|ship| ship.pier())
+        .chain(state.off.iter())
+        .filter(|pier| pier.networked())
+        .filter_map(|pier| pier.name())
+        .map(|name| vhost::VHOST_TEMPLATE.hostname(name))
+        .collect()
+}
+
+/// The hostnames the vhost proxy's certificate must cover: a wildcard if the CA can be shown control of the whole
+/// domain, or else each ship's.
+fn certificate_names(state: &AppState) -> BTreeSet<String> {
+    if let (acme::ChallengeType::Dns01, Some(wildcard)) = (*acme::ACME_CHALLENGE, vhost::VHOST_TEMPLATE.wildcard()) {
+        return BTreeSet::from([wildcard]);
+    }
+    ship_hostnames(state)
+}
+
+/// Keeps ships' hostnames pointed at the orchestrator's public addresses as they change.
+async fn update_dns(state: web::Data<RwLock<AppState>>, mut updater: dns::Updater) {
+    let mut interval = actix_web::rt::time::interval(*dns::DNS_UPDATE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let hostnames = ship_hostnames(&*state.read().await);
+        updater.update(&hostnames).await;
+    }
+}
+
+/// How often the vhost proxy's certificate is checked for hostnames it lacks and for nearing expiry.
+const CERTIFICATE_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Keeps the vhost proxy's certificate covering every ship and renewed ahead of expiry.
+async fn manage_certificate(state: web::Data<RwLock<AppState>>, directory: reqwest::Url) {
+    let mut interval = actix_web::rt::time::interval(CERTIFICATE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let names = certificate_names(&*state.read().await);
+        if let Err(e) = acme::ensure_certificate(&directory, &names).await {
+            log::error!("failed to get a certificate for the vhost proxy: {:#}", e);
+        }
+    }
+}
+
+/// The hostnames the vhost listener serves running ships' web interfaces at.
+#[get("/vhosts")]
+async fn list_vhosts(state: web::Data<RwLock<AppState>>) -> ApiResult<HttpResponse> {
+    if vhost::VHOST_LISTEN.is_none() {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "vhostsDisabled", "NUCLEUS_VHOST_LISTEN is not set"));
+    }
+    let state = state.read().await;
+    let vhosts: Vec<vhost::VirtualHost> = state.on.iter()
+        .filter(|ship| ship.pier().networked())
+        .filter_map(|ship| {
+            let name = ship.pier().name()?.to_owned();
+            let hostname = vhost::VHOST_TEMPLATE.hostname(&name);
+            Some(vhost::VirtualHost { name, hostname, http_port: ship.http_port() })
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(vhosts))
+}
+
+/// The `%cz` hash of one of the ship's desks, which changes whenever any file in it does.
+#[get("/pier/{name}/desks/{desk}/hash")]
+async fn get_desk_hash(
+    state: web::Data<RwLock<AppState>>,
+    path: web::Path<(String, String)>,
+) -> ApiResult<HttpResponse> {
+    let (name, desk) = path.into_inner();
+    if !queries::is_valid_desk(&desk) {
+        return Err(ApiError::bad_request("desk names must be lowercase letters, digits and hyphens"));
+    }
+    let lens = require_running(&*state.read().await, &name)?.lens();
+    let hash = lens.desk_hash(&desk).await.map_err(ApiError::ship_error)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "desk": desk, "hash": hash })))
+}
+
+/// The ship's @p, rank, sponsor and `%base` hash, as the ship itself sees them.
+#[get("/pier/{name}/identity")]
+async fn get_identity(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let lens = require_running(&*state.read().await, &name)?.lens();
+    let identity = lens.identity().await.map_err(ApiError::ship_error)?;
+    Ok(HttpResponse::Ok().json(identity))
+}
+
+/// Returns the ship's web login code, so hosting front-ends can hand it to the user.
+#[get("/pier/{name}/code")]
+async fn get_code(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let source = code_source(&*state.read().await, &name)?;
+    let (code, cached) = login_code(source, &name).await?;
+    Ok(HttpResponse::Ok().json(CodeResponse { code, cached }))
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LoginLink {
+    /// The ship's public base URL.
+    url: String,
+    /// Eyre's login endpoint. POSTing `form` to it as `application/x-www-form-urlencoded` logs the browser in.
+    login_url: String,
+    form: HashMap<&'static str, String>,
+    code: String,
+}
+
+/// Everything a front-end needs to log a user into their ship in one click, typically by rendering `form` as a hidden,
+/// auto-submitting HTML form. Eyre sets its session cookie on the ship's own domain, so the login must be submitted by
+/// the user's browser rather than performed here.
+#[get("/pier/{name}/login-link")]
+async fn login_link(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let url = match (vhost::ship_url(&name), SHIP_URL_TEMPLATE.as_ref()) {
+        (Some(url), _) => url,
+        (None, Some(template)) => template.replace("{name}", &name).trim_end_matches('/').to_owned(),
+        (None, None) => return Err(ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "notConfigured",
+            "neither NUCLEUS_VHOST_LISTEN nor NUCLEUS_SHIP_URL_TEMPLATE is set, so ships have no public URL",
+        )),
+    };
+    let source = code_source(&*state.read().await, &name)?;
+    let (code, _) = login_code(source, &name).await?;
+
+    let mut form = HashMap::new();
+    form.insert("password", code.clone());
+    form.insert("redirect", "/".to_owned());
+
+    Ok(HttpResponse::Ok().json(LoginLink {
+        login_url: format!("{}/~/login", url),
+        url,
+        form,
+        code,
+    }))
+}
+
+/// Replaces the ship's web login code, invalidating existing sessions, and returns the new code.
+#[post("/pier/{name}/code/reset")]
+async fn reset_code(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let lens = state.read().await.running_ship(&name).ok_or_else(|| ApiError::ship_not_running(&name))?.lens();
+    let code = lens.reset_code().await.map_err(ApiError::ship_error)?;
+
+    Ok(HttpResponse::Ok().json(CodeResponse { code, cached: false }))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AmesPortForm {
+    port: Option<u16>,
+}
+
+#[put("/pier/{name}/ames-port")]
+async fn set_ames_port(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    form: web::Json<AmesPortForm>,
+) -> ApiResult<HttpResponse> {
+    let name = name.into_inner();
+
+    let mut state = state.write().await;
+    if state.on.iter().any(|ship| ship.pier().name() == Some(&name)) {
+        return Err(ApiError::ship_running(&name));
+    }
+    let pier = state.off.iter_mut()
+        .find(|pier| pier.name() == Some(&name))
+        .ok_or_else(|| ApiError::pier_not_found(&name))?;
+
+    pier.set_fixed_ames_port(form.port).await
+        .map_err(|e| ApiError::bad_request(format!("{:#}", e)))?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The extra environment variables the ship's runtime is started with.
+#[get("/pier/{name}/env")]
+async fn get_env(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let state = state.read().await;
+    Ok(HttpResponse::Ok().json(managed_pier(&state, &name)?.env()))
+}
+
+/// Replaces the extra environment variables the ship's runtime is started with. The ship must be stopped.
+#[put("/pier/{name}/env")]
+async fn set_env(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    env: web::Json<BTreeMap<String, String>>,
+) -> ApiResult<HttpResponse> {
+    let name = name.into_inner();
+
+    let mut state = state.write().await;
+    if state.on.iter().any(|ship| ship.pier().name() == Some(&name)) {
+        return Err(ApiError::ship_running(&name));
+    }
+    let pier = state.off.iter_mut()
+        .find(|pier| pier.name() == Some(&name))
+        .ok_or_else(|| ApiError::pier_not_found(&name))?;
+
+    pier.set_env(env.into_inner()).await
+        .map_err(|e| ApiError::bad_request(format!("{:#}", e)))?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/pier/{name}/restart-policy")]
+async fn get_restart_policy(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let state = state.read().await;
+    Ok(HttpResponse::Ok().json(managed_pier(&state, &name)?.restart_policy()))
+}
+
+/// Sets what happens when the ship's runtime exits without being stopped. Takes effect immediately, even for a running
+/// ship.
+#[put("/pier/{name}/restart-policy")]
+async fn set_restart_policy(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    policy: web::Json<supervisor::RestartPolicy>,
+) -> ApiResult<HttpResponse> {
+    let mut state = state.write().await;
+    let pier = managed_pier_mut(&mut state, &name)?;
+    pier.set_restart_policy(policy.into_inner()).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BreachForm {
+    /// The keyfile for the ship's new life, issued after the breach.
+    keyfile: String,
+}
+
+/// Factory-resets a pier whose ship was breached: exports it for safekeeping, discards its event log and state, and
+/// boots it from scratch with the new keyfile. The pier keeps its id, ports, config, metadata and secrets.
+#[post("/pier/{name}/breach")]
+async fn breach_pier(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    form: web::Json<BreachForm>,
+) -> ApiResult<HttpResponse> {
+    let name = name.into_inner();
+    let key = form.into_inner().keyfile.trim().to_owned();
+    keyfile::check(&key, &name)?;
+
+    let (pier, jobs) = {
+        let mut state = state.write().await;
+        let pier = managed_pier(&state, &name)?;
+        if pier.fake() || pier.clone_of().is_some() {
+            return Err(ApiError::bad_request("fake ships and clones can't be breached"));
+        }
+        if pier.legal_hold().is_some() {
+            return Err(ApiError::new(StatusCode::CONFLICT, "underLegalHold", "piers under legal hold can't be reset"));
+        }
+        let pier = state.checkout_stopping(&name).ok_or_else(|| ApiError::pier_not_found(&name))?;
+        (pier, state.jobs.clone())
+    };
+
+    let state = state.clone();
+    let job_id = jobs.spawn("breach", Some(name.clone()), move |job| async move {
+        let mut pier = pier.stopped(&state).await?;
+        let reset = async {
+            let export = export_for_safekeeping(&state, &job, &mut pier, &name).await?;
+            job.progress("discarding the old pier");
+            pier.reset_for_breach(&key).await?;
+            Ok::<_, Error>(export)
+        }.await;
+        let export = match reset {
+            Ok(export) => export,
+            Err(e) => {
+                state.write().await.checkin(pier);
+                return Err(e);
+            },
+        };
+
+        let startup_commands = boot_pier(&state, &job, pier, true).await?;
+        Ok(serde_json::json!({ "name": name, "export": export, "startupCommands": startup_commands }))
+    });
+    Ok(accepted(job_id))
+}
+
+/// When the pier expires and how far its expiry has got, or null if it never does.
+#[get("/pier/{name}/expiry")]
+async fn get_expiry(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let state = state.read().await;
+    Ok(HttpResponse::Ok().json(managed_pier(&state, &name)?.expiry()))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ExpiryForm {
+    #[serde(with = "time::serde::rfc3339::option")]
+    expires_at: Option<time::OffsetDateTime>,
+}
+
+/// Sets when the pier expires, such as at the end of a trial, or with null that it never does. Either way its expiry
+/// starts over, so extending the expiry of a pier stopped for having expired lets it boot again.
+#[put("/pier/{name}/expiry")]
+async fn set_expiry(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    form: web::Json<ExpiryForm>,
+) -> ApiResult<HttpResponse> {
+    let mut state = state.write().await;
+    let pier = managed_pier_mut(&mut state, &name)?;
+    pier.set_expiry(form.expires_at).await?;
+    Ok(HttpResponse::Ok().json(pier.expiry()))
+}
+
+#[derive(Deserialize, Debug)]
+struct AuditQuery {
+    pier: Option<String>,
+}
+
+/// What the orchestrator has done to piers on its own, such as expiring them, oldest first.
+#[get("/audit")]
+async fn get_audit_log(query: web::Query<AuditQuery>) -> ApiResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(audit::read(query.pier.as_deref()).await?))
+}
+
+/// The piers a plan's settings were applied to.
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct PlanApplication {
+    applied: Vec<String>,
+    /// Piers on the plan that couldn't be updated, and why.
+    failed: BTreeMap<String, String>,
+}
+
+impl AppState {
+    /// Applies a plan again to every pier on it. Piers checked out by a job can't be updated and keep their settings
+    /// until the plan is next applied.
+    async fn reapply_plan(&mut self, name: &str, plan: &plans::Plan) -> PlanApplication {
+        let mut application = PlanApplication::default();
+        let piers = self.on.iter_mut().map(ship::Ship::pier_mut).chain(self.off.iter_mut());
+        for pier in piers.filter(|pier| pier.plan() == Some(name)) {
+            let pier_name = pier.name().unwrap_or_default().to_owned();
+            match pier.set_plan(Some((name, plan))).await {
+                Ok(()) => application.applied.push(pier_name),
+                Err(e) => {
+                    application.failed.insert(pier_name, format!("{:#}", e));
+                },
+            }
+        }
+        application
+    }
+}
+
+/// The plans piers can be put on, by name.
+#[get("/plans")]
+async fn list_plans(state: web::Data<RwLock<AppState>>) -> HttpResponse {
+    HttpResponse::Ok().json(state.read().await.plans.all())
+}
+
+/// Defines a plan, or redefines it and applies its new settings to every pier already on it.
+#[put("/plans/{plan}")]
+async fn put_plan(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    plan: web::Json<plans::Plan>,
+) -> ApiResult<HttpResponse> {
+    let plan = plan.into_inner();
+    plans::validate_plan_name(&name).map_err(|e| ApiError::bad_request(format!("{:#}", e)))?;
+    plan.validate().map_err(|e| ApiError::bad_request(format!("{:#}", e)))?;
+
+    let mut state = state.write().await;
+    state.plans.insert(&name, plan.clone()).await?;
+    Ok(HttpResponse::Ok().json(state.reapply_plan(&name, &plan).await))
+}
+
+/// Deletes a plan that no pier is on.
+#[delete("/plans/{plan}")]
+async fn delete_plan(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let mut state = state.write().await;
+    let in_use = state.on.iter().map(ship::Ship::pier).chain(state.off.iter()).any(|pier| pier.plan() == Some(&name));
+    if in_use {
+        return Err(ApiError::new(StatusCode::CONFLICT, "planInUse", format!("piers are still on plan {}", name)));
+    }
+    match state.plans.remove(&name).await? {
+        Some(_) => Ok(HttpResponse::NoContent().finish()),
+        None => Err(ApiError::plan_not_found(&name)),
+    }
+}
+
+/// Applies a plan's settings again to every pier on it, such as ones that were busy when it was last changed.
+#[post("/plans/{plan}/apply")]
+async fn apply_plan(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let mut state = state.write().await;
+    let plan = state.plans.get(&name).cloned().ok_or_else(|| ApiError::plan_not_found(&name))?;
+    Ok(HttpResponse::Ok().json(state.reapply_plan(&name, &plan).await))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PierPlanForm {
+    plan: Option<String>,
+}
+
+/// Puts the pier on a plan, taking over the plan's settings, or takes it off its plan with null. Flags take effect the
+/// next time the ship is launched.
+#[put("/pier/{name}/plan")]
+async fn set_pier_plan(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    form: web::Json<PierPlanForm>,
+) -> ApiResult<HttpResponse> {
+    let mut state = state.write().await;
+    let plan = match &form.plan {
+        Some(plan_name) => {
+            let plan = state.plans.get(plan_name).cloned().ok_or_else(|| ApiError::plan_not_found(plan_name))?;
+            Some((plan_name.as_str(), plan))
+        },
+        None => None,
+    };
+    let pier = managed_pier_mut(&mut state, &name)?;
+    pier.set_plan(plan.as_ref().map(|(name, plan)| (*name, plan))).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// How a pier's runtime is launched and what is run on it once it is up.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RuntimeConfig<'a> {
+    #[serde(flatten)]
+    flags: &'a runtime::RuntimeFlags,
+    startup_commands: &'a [String],
+}
+
+impl<'a> RuntimeConfig<'a> {
+    fn of(pier: &'a ship::PierState) -> Self {
+        RuntimeConfig { flags: pier.runtime_flags(), startup_commands: pier.startup_commands() }
+    }
+}
+
+/// Loom size, snapshot cadence, verbosity and extra arguments the pier's runtime is launched with, and the dojo
+/// commands run after it boots.
+#[get("/pier/{name}/config")]
+async fn get_runtime_config(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let state = state.read().await;
+    Ok(HttpResponse::Ok().json(RuntimeConfig::of(managed_pier(&state, &name)?)))
+}
+
+/// Changes to a pier's runtime config. Fields left out are left as they are; `loomBits` and `snapshotIntervalSecs` are
+/// reset to the runtime's defaults with null.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RuntimeConfigPatch {
+    #[serde(default, deserialize_with = "util::deserialize_some")]
+    loom_bits: Option<Option<u8>>,
+    #[serde(default, deserialize_with = "util::deserialize_some")]
+    snapshot_interval_secs: Option<Option<u32>>,
+    verbosity: Option<runtime::Verbosity>,
+    conn_socket: Option<bool>,
+    /// Replaces all of the extra arguments.
+    extra_args: Option<Vec<String>>,
+    /// Replaces all of the startup commands.
+    startup_commands: Option<Vec<String>>,
+}
+
+/// Updates the pier's runtime config and returns the result. A running ship keeps the flags it was launched with, and
+/// doesn't run new startup commands, until it is next started.
+#[patch("/pier/{name}/config")]
+async fn patch_runtime_config(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    patch: web::Json<RuntimeConfigPatch>,
+) -> ApiResult<HttpResponse> {
+    let patch = patch.into_inner();
+    let mut state = state.write().await;
+    let pier = managed_pier_mut(&mut state, &name)?;
+
+    let mut flags = pier.runtime_flags().clone();
+    if let Some(loom_bits) = patch.loom_bits {
+        flags.loom_bits = loom_bits;
+    }
+    if let Some(secs) = patch.snapshot_interval_secs {
+        flags.snapshot_interval_secs = secs;
+    }
+    if let Some(verbosity) = patch.verbosity {
+        flags.verbosity = verbosity;
+    }
+    if let Some(conn_socket) = patch.conn_socket {
+        flags.conn_socket = conn_socket;
+    }
+    if let Some(extra_args) = patch.extra_args {
+        flags.extra_args = extra_args;
+    }
+    flags.validate().map_err(|e| ApiError::bad_request(format!("{:#}", e)))?;
+    if let Some(Some(_)) = patch.loom_bits {
+        pier.runtime_version().require(runtime::Feature::LoomSize)?;
+    }
+    if patch.conn_socket == Some(true) {
+        pier.runtime_version().require(runtime::Feature::ConnSocket)?;
+    }
+    if let Some(commands) = &patch.startup_commands {
+        ship::validate_startup_commands(commands).map_err(|e| ApiError::bad_request(format!("{:#}", e)))?;
+    }
+    pier.set_runtime_flags(flags).await?;
+    if let Some(commands) = patch.startup_commands {
+        pier.set_startup_commands(commands).await?;
+    }
+    Ok(HttpResponse::Ok().json(RuntimeConfig::of(pier)))
+}
+
+/// Tags, owner and notes attached to the pier by operators.
+#[get("/pier/{name}/metadata")]
+async fn get_metadata(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let state = state.read().await;
+    Ok(HttpResponse::Ok().json(managed_pier(&state, &name)?.metadata()))
+}
+
+/// Changes to a pier's metadata. Fields left out are left as they are; `owner` and `notes` are cleared with null.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MetadataPatch {
+    /// Replaces all of the pier's tags.
+    tags: Option<BTreeSet<String>>,
+    #[serde(default, deserialize_with = "util::deserialize_some")]
+    owner: Option<Option<String>>,
+    #[serde(default, deserialize_with = "util::deserialize_some")]
+    notes: Option<Option<String>>,
+}
+
+/// Updates the pier's metadata, whether or not the ship is running, and returns the result.
+#[patch("/pier/{name}/metadata")]
+async fn patch_metadata(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    patch: web::Json<MetadataPatch>,
+) -> ApiResult<HttpResponse> {
+    let patch = patch.into_inner();
+    let mut state = state.write().await;
+    let pier = managed_pier_mut(&mut state, &name)?;
+
+    let mut metadata = pier.metadata().clone();
+    if let Some(tags) = patch.tags {
+        metadata.tags = tags;
+    }
+    if let Some(owner) = patch.owner {
+        metadata.owner = owner;
+    }
+    if let Some(notes) = patch.notes {
+        metadata.notes = notes;
+    }
+    metadata.validate().map_err(|e| ApiError::bad_request(format!("{:#}", e)))?;
+    pier.set_metadata(metadata).await?;
+    Ok(HttpResponse::Ok().json(pier.metadata()))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LegalHoldRequest {
+    reason: Option<String>,
+}
+
+/// Places the pier under legal hold, running or not, so that none of its backups are pruned until it is released.
+#[put("/pier/{name}/legal-hold")]
+async fn place_legal_hold(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    request: web::Json<LegalHoldRequest>,
+) -> ApiResult<HttpResponse> {
+    let reason = request.into_inner().reason;
+    let mut state = state.write().await;
+    let pier = managed_pier_mut(&mut state, &name)?;
+    let since = pier.legal_hold().map_or_else(time::OffsetDateTime::now_utc, |hold| hold.since);
+    let hold = ship::LegalHold { since, reason: reason.clone() };
+    pier.set_legal_hold(Some(hold.clone())).await?;
+
+    log::info!("placed {} under legal hold: {}", name, reason.as_deref().unwrap_or("no reason given"));
+    state.events.publish(events::Event::LegalHoldPlaced { name: name.into_inner(), reason });
+    Ok(HttpResponse::Ok().json(hold))
+}
+
+/// Releases the pier's legal hold, so that its backups are pruned as usual again.
+#[delete("/pier/{name}/legal-hold")]
+async fn release_legal_hold(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let mut state = state.write().await;
+    let pier = managed_pier_mut(&mut state, &name)?;
+    if pier.legal_hold().is_some() {
+        pier.set_legal_hold(None).await?;
+        log::info!("released the legal hold on {}", name);
+        state.events.publish(events::Event::LegalHoldReleased { name: name.into_inner() });
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The orchestrator's own records of its piers, which aren't part of the piers themselves: settings, metadata, legal
+/// holds, port assignments and lifecycle history. Restoring the harbor's piers from archives or the backup store and
+/// then importing this brings a rebuilt host back with all of it.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct StateDump {
+    #[serde(with = "time::serde::rfc3339")]
+    exported_at: time::OffsetDateTime,
+    /// Every pier's config by name. Busy piers are left out, as their configs are checked out.
+    piers: BTreeMap<String, ship::PierConfig>,
+}
+
+#[get("/state/export")]
+async fn export_state(state: web::Data<RwLock<AppState>>) -> HttpResponse {
+    let state = state.read().await;
+    let piers = state.on.iter().map(ship::Ship::pier)
+        .chain(state.off.iter())
+        .filter_map(|pier| Some((pier.name()?.to_owned(), pier.config().clone())))
+        .collect();
+    HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("nucleus-state.json".to_owned())],
+        })
+        .json(StateDump { exported_at: time::OffsetDateTime::now_utc(), piers })
+}
+
+/// What importing a state dump did, by pier name.
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct StateImportReport {
+    restored: Vec<String>,
+    /// Piers in the dump that aren't in the harbor.
+    missing: Vec<String>,
+    /// Piers that are running or busy, and have to be stopped first.
+    skipped: Vec<String>,
+    /// Piers whose records couldn't be restored, with why.
+    failed: BTreeMap<String, String>,
+}
+
+/// Restores the records in a state dump onto the stopped piers of the same names. Piers keep their own ids.
+#[post("/state/import")]
+async fn import_state(
+    state: web::Data<RwLock<AppState>>,
+    dump: web::Json<StateDump>,
+) -> ApiResult<HttpResponse> {
+    let mut state = state.write().await;
+    let mut report = StateImportReport::default();
+    for (name, config) in &dump.piers {
+        if state.running_ship(name).is_some() || state.busy.contains(name) {
+            report.skipped.push(name.clone());
+            continue;
+        }
+        let Some(pier) = state.off.iter_mut().find(|pier| pier.name() == Some(name)) else {
+            report.missing.push(name.clone());
+            continue;
+        };
+        let previous_ports = pier.ports();
+        match pier.restore_config(config).await {
+            Ok(()) => {
+                let ports = pier.ports();
+                if previous_ports != ports {
+                    let mut http_ports = state.http_ports.lock().await;
+                    let mut ames_ports = state.ames_ports.lock().await;
+                    if let Some(previous) = previous_ports {
+                        http_ports.unreserve(previous.http);
+                        ames_ports.unreserve(previous.ames);
+                    }
+                    if let Some(ports) = ports {
+                        http_ports.reserve(ports.http);
+                        ames_ports.reserve(ports.ames);
+                    }
+                }
+                report.restored.push(name.clone());
+            },
+            Err(e) => {
+                report.failed.insert(name.clone(), format!("{:#}", e));
+            },
+        }
+    }
+    log::info!(
+        "imported state from {}: {} piers restored, {} missing, {} skipped, {} failed",
+        dump.exported_at, report.restored.len(), report.missing.len(), report.skipped.len(), report.failed.len(),
+    );
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// The pier of a running or stopped ship, for reading or writing its metadata.
+fn managed_pier<'a>(state: &'a AppState, name: &str) -> ApiResult<&'a ship::PierState> {
+    if let Some(ship) = state.running_ship(name) {
+        return Ok(ship.pier());
+    }
+    if state.busy.contains(name) {
+        return Err(ApiError::pier_busy(name));
+    }
+    state.off.iter()
+        .find(|pier| pier.name() == Some(name))
+        .ok_or_else(|| ApiError::pier_not_found(name))
+}
+
+/// `managed_pier`, for changing settings that can be changed while the ship runs.
+fn managed_pier_mut<'a>(state: &'a mut AppState, name: &str) -> ApiResult<&'a mut ship::PierState> {
+    if state.busy.contains(name) {
+        return Err(ApiError::pier_busy(name));
+    }
+    match state.on.iter_mut().find(|ship| ship.pier().name() == Some(name)) {
+        Some(ship) => Ok(ship.pier_mut()),
+        None => state.off.iter_mut()
+            .find(|pier| pier.name() == Some(name))
+            .ok_or_else(|| ApiError::pier_not_found(name)),
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SecretSummary {
+    name: String,
+    command: String,
+}
+
+/// Lists the secrets handed to the ship after every boot. Values are never returned.
+#[get("/pier/{name}/secrets")]
+async fn list_secrets(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let secrets_file = managed_pier(&*state.read().await, &name)?.secrets_file();
+    let secrets = secrets_file.load().await?;
+    Ok(HttpResponse::Ok().json(secrets.into_iter()
+        .map(|(name, secret)| SecretSummary { name, command: secret.command })
+        .collect::<Vec<_>>()))
+}
+
+/// Runs the ship's startup commands, telling its console how each went. Called after every boot.
+async fn run_startup_commands(state: &web::Data<RwLock<AppState>>, name: &str) -> Vec<ship::StartupCommandOutcome> {
+    let (lens, commands, console) = {
+        let state = state.read().await;
+        let Some(ship) = state.running_ship(name) else { return Vec::new() };
+        (ship.lens(), ship.pier().startup_commands().to_vec(), state.console.clone())
+    };
+    let outcomes = lens.run_startup_commands(&commands).await;
+    for outcome in &outcomes {
+        let status = if outcome.ok { "ran" } else { "failed" };
+        console.broadcast(name, &format!("startup command {}: {}", status, outcome.command));
+    }
+    outcomes
+}
+
+/// Hands the ship's secrets to it, logging which couldn't be. Called after every boot.
+async fn inject_secrets(state: &web::Data<RwLock<AppState>>, name: &str) {
+    let (lens, secrets_file, console) = {
+        let state = state.read().await;
+        let Some(ship) = state.running_ship(name) else { return };
+        (ship.lens(), ship.pier().secrets_file(), state.console.clone())
+    };
+    let injected = match secrets_file.load().await {
+        Ok(secrets) => lens.inject_secrets(secrets).await,
+        Err(e) => Err(e),
+    };
+    match injected {
+        Ok(injected) if injected.is_empty() => {},
+        Ok(injected) => console.broadcast(name, &format!("injected secrets: {}", injected.join(", "))),
+        Err(e) => {
+            log::warn!("failed to inject secrets into {}: {:#}", name, e);
+            console.broadcast(name, &format!("{:#}", e));
+        },
+    }
+}
+
+/// Stores a secret, replacing any of the same name, and hands it to the ship right away if it is running.
+#[put("/pier/{name}/secrets/{secret}")]
+async fn put_secret(
+    state: web::Data<RwLock<AppState>>,
+    path: web::Path<(String, String)>,
+    secret: web::Json<secrets::Secret>,
+) -> ApiResult<HttpResponse> {
+    let (name, secret_name) = path.into_inner();
+    let secret = secret.into_inner();
+    if !secrets::is_valid_name(&secret_name) {
+        return Err(ApiError::bad_request("secret names must be lowercase letters, digits and hyphens"));
+    }
+    secret.validate().map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+    let command = secret.render();
+    {
+        // The write lock serializes updates to the secrets file.
+        let state = state.write().await;
+        let secrets_file = managed_pier(&state, &name)?.secrets_file();
+        let mut secrets = secrets_file.load().await?;
+        secrets.insert(secret_name, secret);
+        secrets_file.save(&secrets).await?;
+    }
+
+    let lens = state.read().await.running_ship(&name).map(ship::Ship::lens);
+    if let Some(lens) = lens {
+        lens.dojo(&command).await.map_err(ApiError::ship_error)?;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Forgets a secret. The ship keeps whatever it was already given.
+#[delete("/pier/{name}/secrets/{secret}")]
+async fn delete_secret(
+    state: web::Data<RwLock<AppState>>,
+    path: web::Path<(String, String)>,
+) -> ApiResult<HttpResponse> {
+    let (name, secret_name) = path.into_inner();
+    let state = state.write().await;
+    let secrets_file = managed_pier(&state, &name)?.secrets_file();
+    let mut secrets = secrets_file.load().await?;
+    if secrets.remove(&secret_name).is_none() {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "secretNotFound", format!("no such secret: {}", secret_name)));
+    }
+    secrets_file.save(&secrets).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ConsoleQuery {
+    /// Permit commands that may change the ship's state, as with `allow_writes` on the dojo endpoint.
+    #[serde(default)]
+    allow_writes: bool,
+}
+
+/// How long a command sent from the console may take before the viewer is told it failed.
+const CONSOLE_COMMAND_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+#[get("/pier/{name}/console")]
+async fn console_attach(
+    req: HttpRequest,
+    payload: web::Payload,
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    query: web::Query<ConsoleQuery>,
+) -> ApiResult<HttpResponse> {
+    let name = name.into_inner();
+    let allow_writes = query.allow_writes;
+
+    let hub = {
+        let state = state.read().await;
+        if !state.has_pier(&name) {
+            return Err(ApiError::pier_not_found(&name));
+        }
+        if state.running_ship(&name).is_some_and(|ship| ship.paused()) {
+            return Err(ApiError::ship_paused(&name));
+        }
+        state.console.clone()
+    };
+
+    let mut res = console::handshake(&req).map_err(|e| ApiError::bad_request(e.to_string()))?;
+    let (id, rx) = hub.attach(&name);
+
+    let state = state.into_inner();
+    actix_web::rt::spawn(async move {
+        let exec = |command: String| {
+            let state = state.clone();
+            let name = name.clone();
+            async move {
+                if !allow_writes && !ship::dojo_is_read_only(&command) {
+                    bail!("command may modify ship state; attach with allowWrites=true to run it");
+                }
+                let lens = state.read().await.running_ship(&name).ok_or_else(|| anyhow!("ship is not running"))?.lens();
+                lens.dojo_with_timeout(&command, Some(CONSOLE_COMMAND_TIMEOUT)).await
+            }
+        };
+        console::run_viewer(&hub, &name, id, payload, exec).await;
+    });
+
+    Ok(res.streaming(console::encode_stream(rx)))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LogsQuery {
+    /// Keep the response open and stream output as the runtime writes it.
+    #[serde(default)]
+    follow: bool,
+}
+
+/// The ship's runtime output since its log was last rotated, e.g. to see why it failed to boot.
+#[get("/pier/{name}/logs")]
+async fn pier_logs(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    query: web::Query<LogsQuery>,
+) -> ApiResult<HttpResponse> {
+    let logs_path = managed_pier(&*state.read().await, &name)?.logs_path();
+
+    if query.follow {
+        return Ok(HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .insert_header(("Cache-Control", "no-cache"))
+            .streaming(shiplog::follow(logs_path)));
+    }
+
+    let log = match fs::read(shiplog::current_log_path(&logs_path)).await {
+        Ok(log) => log,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(Error::from(e).into()),
+    };
+    Ok(HttpResponse::Ok().content_type("text/plain; charset=utf-8").body(log))
+}
+
+#[get("/events")]
+async fn event_stream(state: web::Data<RwLock<AppState>>) -> HttpResponse {
+    let rx = state.read().await.events.subscribe();
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(rx.map(|envelope| envelope.to_sse()))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SigilQuery {
+    #[serde(default = "SigilQuery::default_size")]
+    size: u32,
+    #[serde(default = "SigilQuery::default_foreground")]
+    foreground: String,
+    #[serde(default = "SigilQuery::default_background")]
+    background: String,
+}
+
+impl SigilQuery {
+    fn default_size() -> u32 { 128 }
+    fn default_foreground() -> String { "#ffffff".to_owned() }
+    fn default_background() -> String { "#000000".to_owned() }
+}
+
+/// The ship's sigil as an SVG, for dashboards to show next to it.
+#[get("/pier/{name}/sigil")]
+async fn pier_sigil(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    query: web::Query<SigilQuery>,
+) -> ApiResult<HttpResponse> {
+    if !state.read().await.has_pier(&name) {
+        return Err(ApiError::pier_not_found(&name));
+    }
+    if !(sigil::MIN_SIZE..=sigil::MAX_SIZE).contains(&query.size) {
+        return Err(ApiError::bad_request(format!("size must be from {} to {}", sigil::MIN_SIZE, sigil::MAX_SIZE)));
+    }
+    let foreground = sigil::parse_color(&query.foreground)
+        .map_err(|e| ApiError::bad_request(format!("invalid foreground: {}", e)))?;
+    let background = sigil::parse_color(&query.background)
+        .map_err(|e| ApiError::bad_request(format!("invalid background: {}", e)))?;
+    let glyphs = sigil::glyphs().await?
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "sigilsDisabled", "NUCLEUS_SIGIL_GLYPHS is not set"))?;
+
+    let svg = sigil::render(&glyphs, patp::parse(&name)?, query.size, &foreground, &background)?;
+    Ok(HttpResponse::Ok()
+        .content_type("image/svg+xml")
+        .insert_header(("Cache-Control", "public, max-age=86400"))
+        .body(svg))
+}
+
+#[get("/pier/{name}/uptime")]
+async fn pier_uptime(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let state = state.read().await;
+    if !state.has_pier(&name) {
+        return Err(ApiError::pier_not_found(&name));
+    }
+
+    Ok(HttpResponse::Ok().json(slo::pier_uptime(&state.events, &name)))
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct FleetSummary {
+    running: usize,
+    /// Running ships that are paused, also counted in `running`.
+    paused: usize,
+    stopped: usize,
+    busy: usize,
+    by_class: HashMap<ship::ShipClass, usize>,
+    unclassified: usize,
+    harbor_disk_bytes: Option<u64>,
+    crashes_last_24h: usize,
+    pending_jobs: usize,
+    running_jobs: usize,
+    max_running_ships: Option<usize>,
+    max_managed_piers: Option<usize>,
+    host_clock: Option<clock::HostClock>,
+    resources: capacity::Resources,
+}
+
+#[get("/summary")]
+async fn fleet_summary(state: web::Data<RwLock<AppState>>) -> HttpResponse {
+    let (mut summary, events) = {
+        let state = state.read().await;
+
+        let mut summary = FleetSummary {
+            running: state.on.len(),
+            paused: state.on.iter().filter(|ship| ship.paused()).count(),
+            stopped: state.off.len(),
+            busy: state.busy.len(),
+            host_clock: state.clocks.host(),
+            max_running_ships: *capacity::MAX_RUNNING_SHIPS,
+            max_managed_piers: *capacity::MAX_MANAGED_PIERS,
+            resources: capacity::resources(),
+            ..FleetSummary::default()
+        };
+        for name in state.pier_names() {
+            match ship::ShipClass::of_name(&name) {
+                Some(class) => *summary.by_class.entry(class).or_default() += 1,
+                None => summary.unclassified += 1,
+            }
+        }
+        for job in state.jobs.list() {
+            match job.state {
+                jobs::JobState::Pending => summary.pending_jobs += 1,
+                jobs::JobState::Running => summary.running_jobs += 1,
+                _ => {},
+            }
+        }
+
+        (summary, state.events.clone())
+    };
+
+    let horizon = time::OffsetDateTime::now_utc() - time::Duration::hours(24);
+    summary.crashes_last_24h = events.history().iter()
+        .filter(|e| e.at >= horizon && matches!(e.event, events::Event::ShipCrashed { .. }))
+        .count();
+
+    summary.harbor_disk_bytes = match util::dir_size(ship::HARBOR.as_path()).await {
+        Ok(size) => Some(size),
+        Err(e) => {
+            log::warn!("failed to measure harbor disk usage: {}", e);
+            None
+        },
+    };
+
+    HttpResponse::Ok().json(summary)
+}
+
+/// Ports of running ships that fall within `range`.
+fn ports_in_use(state: &AppState, range: &std::ops::Range<u16>, port: fn(&ship::Ship) -> u16) -> usize {
+    state.on.iter().filter(|ship| range.contains(&port(ship))).count()
+}
+
+/// Prometheus metrics, in the text exposition format.
+#[get("/metrics")]
+async fn metrics_endpoint(state: web::Data<RwLock<AppState>>) -> HttpResponse {
+    let mut out = metrics::Exposition::default();
+
+    let (targets, collector, replication) = {
+        let state = state.read().await;
+
+        let paused = state.on.iter().filter(|ship| ship.paused()).count();
+        out.family("nucleus_ships", "gauge", "Piers by status.")
+            .sample("nucleus_ships", &[("status", "running")], state.on.len() - paused)
+            .sample("nucleus_ships", &[("status", "paused")], paused)
+            .sample("nucleus_ships", &[("status", "stopped")], state.off.len())
+            .sample("nucleus_ships", &[("status", "busy")], state.busy.len());
+        out.family("nucleus_ship_boots_total", "counter", "Ships booted since the orchestrator started.")
+            .sample("nucleus_ship_boots_total", &[], state.metrics.ship_boots());
+        out.family("nucleus_ship_crashes_total", "counter", "Ships that exited unexpectedly since startup.")
+            .sample("nucleus_ship_crashes_total", &[], state.metrics.ship_crashes());
+        out.family("nucleus_lens_retries_total", "counter", "Lens calls retried since startup.")
+            .sample("nucleus_lens_retries_total", &[], ship::lens_retries());
+
+        out.family("nucleus_port_pool_size", "gauge", "Number of ports in each port pool.")
+            .sample("nucleus_port_pool_size", &[("pool", "http")], ship::HTTP_PORT_RANGE.len())
+            .sample("nucleus_port_pool_size", &[("pool", "ames")], ship::AMES_PORT_RANGE.len());
+        let http_in_use = ports_in_use(&state, &ship::HTTP_PORT_RANGE, ship::Ship::http_port);
+        let ames_in_use = ports_in_use(&state, &ship::AMES_PORT_RANGE, ship::Ship::ames_port);
+        out.family("nucleus_port_pool_in_use", "gauge", "Ports in each port pool bound by running ships.")
+            .sample("nucleus_port_pool_in_use", &[("pool", "http")], http_in_use)
+            .sample("nucleus_port_pool_in_use", &[("pool", "ames")], ames_in_use);
+
+        let mut durations = state.jobs.durations();
+        durations.sort_by(|a, b| a.0.cmp(&b.0));
+        let durations: Vec<_> = durations.into_iter()
+            .map(|(kind, job_state, histogram)| {
+                let outcome = serde_json::to_value(job_state).unwrap().as_str().unwrap().to_owned();
+                (kind, outcome, histogram)
+            })
+            .collect();
+        out.family("nucleus_jobs_total", "counter", "Finished jobs, such as imports and exports, by kind and outcome.");
+        for (kind, outcome, histogram) in &durations {
+            out.sample("nucleus_jobs_total", &[("kind", kind), ("outcome", outcome)], histogram.count());
+        }
+        out.family("nucleus_job_duration_seconds", "histogram", "How long finished jobs ran, by kind and outcome.");
+        for (kind, outcome, histogram) in &durations {
+            out.histogram("nucleus_job_duration_seconds", &[("kind", kind), ("outcome", outcome)], histogram);
+        }
+
+        (usage_targets(&state), state.usage.clone(), state.replication.clone())
+    };
+
+    let resources = capacity::resources();
+    if let Some(fs) = resources.harbor_filesystem {
+        out.family("nucleus_harbor_inodes", "gauge", "Inodes of the harbor filesystem, total and available.")
+            .sample("nucleus_harbor_inodes", &[("state", "total")], fs.total_inodes)
+            .sample("nucleus_harbor_inodes", &[("state", "available")], fs.available_inodes);
+    }
+    if let Some(fds) = resources.fds {
+        out.family("nucleus_open_fds", "gauge", "File descriptors the orchestrator has open.")
+            .sample("nucleus_open_fds", &[], fds.open);
+        out.family("nucleus_max_fds", "gauge", "The orchestrator's file descriptor limit.")
+            .sample("nucleus_max_fds", &[], fds.limit);
+    }
+
+    if s3::S3_REPLICA.is_some() {
+        let replication = replication.status().await;
+        out.family("nucleus_backup_replication_pending", "gauge", "Backups waiting to be copied to the replica bucket.")
+            .sample("nucleus_backup_replication_pending", &[], replication.pending);
+        out.family("nucleus_backup_replication_lag_seconds", "gauge", "Age of the oldest backup not yet replicated.")
+            .sample("nucleus_backup_replication_lag_seconds", &[], replication.lag_secs);
+    }
+
+    // Disk usage is cached by the usage collector, so frequent scrapes don't walk every pier each time.
+    let mut usages = Vec::with_capacity(targets.len());
+    for target in targets {
+        usages.push(measure_usage(&collector, target).await);
+    }
+    out.family("nucleus_pier_disk_bytes", "gauge", "On-disk size of each pier.");
+    for usage in &usages {
+        if let Some(disk) = usage.disk {
+            out.sample("nucleus_pier_disk_bytes", &[("pier", &usage.name), ("part", "total")], disk.total_bytes)
+                .sample("nucleus_pier_disk_bytes", &[("pier", &usage.name), ("part", "eventLog")], disk.event_log_bytes)
+                .sample("nucleus_pier_disk_bytes", &[("pier", &usage.name), ("part", "snapshot")], disk.snapshot_bytes);
+        }
+    }
+    out.family("nucleus_ship_rss_bytes", "gauge", "Resident memory of each running ship's runtime and serfs.");
+    for usage in &usages {
+        if let Some(rss) = usage.rss_bytes {
+            out.sample("nucleus_ship_rss_bytes", &[("pier", &usage.name)], rss);
+        }
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(out.into_string())
+}
+
+#[get("/openapi.json")]
+async fn openapi_document() -> HttpResponse {
+    HttpResponse::Ok().json(&*openapi::DOCUMENT)
+}
+
+#[get("/docs")]
+async fn swagger_ui() -> ApiResult<HttpResponse> {
+    if !*openapi::SWAGGER_UI {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "notFound", "swagger ui is disabled"));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(openapi::SWAGGER_UI_HTML))
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PierUsage {
+    name: String,
+    status: PierStatus,
+    disk: Option<usage::DiskUsage>,
+    /// The pier's own dataset, with a storage backend that gives it one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dataset: Option<storage::DatasetUsage>,
+    /// Resident memory of the runtime and its serfs; None when the ship isn't running.
+    rss_bytes: Option<u64>,
+    /// Output of `|mass`, when requested and the ship is running.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mass: Option<String>,
+}
+
+/// Identifies what to measure for each pier without holding the state lock while measuring.
+struct UsageTarget {
+    name: String,
+    status: PierStatus,
+    pier_path: async_std::path::PathBuf,
+    scratch_path: async_std::path::PathBuf,
+    pid: Option<u32>,
+}
+
+fn usage_targets(state: &AppState) -> Vec<UsageTarget> {
+    state.on.iter()
+        .map(|ship| UsageTarget {
+            name: ship.pier().name().unwrap_or_default().to_owned(),
+            status: if ship.paused() { PierStatus::Paused } else { PierStatus::Running },
+            pier_path: ship.pier().pier_path(),
+            scratch_path: ship.pier().scratch_path(),
+            pid: ship.pid(),
+        })
+        .chain(state.off.iter().map(|pier| UsageTarget {
+            name: pier.name().unwrap_or_default().to_owned(),
+            status: PierStatus::Stopped,
+            pier_path: pier.pier_path(),
+            scratch_path: pier.scratch_path(),
+            pid: None,
+        }))
+        .collect()
+}
+
+async fn measure_usage(collector: &usage::UsageCollector, target: UsageTarget) -> PierUsage {
+    let disk = match collector.disk_usage(&target.pier_path, &target.scratch_path).await {
+        Ok(disk) => Some(disk),
+        Err(e) => {
+            log::warn!("failed to measure disk usage of {}: {}", target.name, e);
+            None
+        },
+    };
+    let dataset = storage::STORAGE.dataset_usage(&target.pier_path).await
+        .map_err(|e| log::warn!("failed to measure dataset usage of {}: {:#}", target.name, e))
+        .ok()
+        .flatten();
+    let rss_bytes = target.pid.and_then(|pid| usage::group_rss(pid)
+        .map_err(|e| log::warn!("failed to measure memory usage of {}: {}", target.name, e))
+        .ok());
+
+    PierUsage { name: target.name, status: target.status, disk, dataset, rss_bytes, mass: None }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PierUsageQuery {
+    /// Also ask the running ship for its `|mass` memory report, which is slow on large ships.
+    #[serde(default)]
+    mass: bool,
+}
+
+#[get("/pier/{name}/usage")]
+async fn pier_usage(
+    state: web::Data<RwLock<AppState>>,
+    name: web::Path<String>,
+    query: web::Query<PierUsageQuery>,
+) -> ApiResult<HttpResponse> {
+    let (target, collector) = {
+        let state = state.read().await;
+        if state.busy.contains(name.as_str()) {
+            return Err(ApiError::pier_busy(&name));
+        }
+        let target = usage_targets(&state).into_iter()
+            .find(|target| target.name == *name)
+            .ok_or_else(|| ApiError::pier_not_found(&name))?;
+        (target, state.usage.clone())
+    };
+
+    let mut result = measure_usage(&collector, target).await;
+    if query.mass {
+        let lens = state.read().await.running_ship(&name).map(ship::Ship::lens);
+        if let Some(lens) = lens {
+            result.mass = Some(lens.mass().await.map_err(ApiError::ship_error)?);
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SystemUsage {
+    total_disk_bytes: u64,
+    total_rss_bytes: u64,
+    harbor_filesystem: Option<usage::FilesystemUsage>,
+    piers: Vec<PierUsage>,
+}
+
+#[get("/system/usage")]
+async fn system_usage(state: web::Data<RwLock<AppState>>) -> HttpResponse {
+    let (targets, collector) = {
+        let state = state.read().await;
+        (usage_targets(&state), state.usage.clone())
+    };
+
+    let mut piers = Vec::with_capacity(targets.len());
+    for target in targets {
+        piers.push(measure_usage(&collector, target).await);
+    }
+
+    let harbor_filesystem = usage::filesystem_usage(ship::HARBOR.as_path())
+        .map_err(|e| log::warn!("failed to stat harbor filesystem: {}", e))
+        .ok();
+
+    HttpResponse::Ok().json(SystemUsage {
+        total_disk_bytes: piers.iter().filter_map(|pier| pier.disk).map(|disk| disk.total_bytes).sum(),
+        total_rss_bytes: piers.iter().filter_map(|pier| pier.rss_bytes).sum(),
+        harbor_filesystem,
+        piers,
+    })
+}
+
+const SLO_EVALUATION_INTERVAL: Duration = Duration::from_secs(60);
+
+async fn evaluate_slos(state: web::Data<RwLock<AppState>>) {
+    let tracker = slo::SloTracker::default();
+    let mut interval = actix_web::rt::time::interval(SLO_EVALUATION_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        let state = state.read().await;
+        tracker.evaluate(&state.events, &state.pier_names());
+    }
+}
+
+/// Periodically checks the host clock's NTP synchronization and compares each running ship's `now` against it.
+async fn check_clocks(state: web::Data<RwLock<AppState>>) {
+    let mut interval = actix_web::rt::time::interval(*clock::CLOCK_CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        let running: Vec<String> = {
+            let state = state.read().await;
+            match clock::host_clock() {
+                Ok(host) => state.clocks.record_host(&state.events, host),
+                Err(e) => log::warn!("failed to read host clock synchronization state: {}", e),
+            }
+            state.on.iter().filter_map(|ship| ship.pier().name().map(str::to_owned)).collect()
+        };
+
+        // The lock is only held to find each ship's lens, so that writers aren't held up behind a slow dojo.
+        for name in &running {
+            let (lens, clocks, events) = {
+                let state = state.read().await;
+                let Some(ship) = state.running_ship(name).filter(|ship| !ship.paused()) else { continue };
+                (ship.lens(), state.clocks.clone(), state.events.clone())
+            };
+            let before = time::OffsetDateTime::now_utc();
+            match lens.now().await {
+                Ok(now) => clocks.record_ship(&events, name, now, before, time::OffsetDateTime::now_utc()),
+                Err(e) => log::warn!("failed to read {}'s clock: {}", name, e),
+            }
+        }
+        state.read().await.clocks.retain(&running);
+    }
+}
+
+/// Warns of, stops, and finally exports and trashes piers as the steps of their expiry come due, recording each in the
+/// audit log. Piers checked out by a job are caught up with once they are checked back in.
+async fn enforce_expiry(state: web::Data<RwLock<AppState>>) {
+    let mut interval = actix_web::rt::time::interval(expiry::EXPIRY_CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        let now = time::OffsetDateTime::now_utc();
+        let due: Vec<(String, expiry::Expiry, expiry::Step)> = {
+            let state = state.read().await;
+            state.on.iter().map(ship::Ship::pier)
+                .chain(state.off.iter())
+                .filter_map(|pier| {
+                    let expiry = pier.expiry()?;
+                    Some((pier.name()?.to_owned(), expiry.clone(), expiry.due(now)?))
+                })
+                .collect()
+        };
+
+        for (name, expiry, step) in due {
+            if let Err(e) = take_expiry_step(&state, &name, expiry, step).await {
+                log::warn!("failed to take expiry step {:?} for {}: {:#}", step, name, e);
+            }
+        }
+    }
+}
+
+async fn take_expiry_step(
+    state: &web::Data<RwLock<AppState>>,
+    name: &str,
+    mut expiry: expiry::Expiry,
+    step: expiry::Step,
+) -> Result<()> {
+    let now = time::OffsetDateTime::now_utc();
+    match step {
+        expiry::Step::Warn => {
+            let mut state = state.write().await;
+            let pier = state.pier_mut(name).ok_or_else(|| anyhow!("pier went away: {}", name))?;
+            expiry.warned_at = Some(now);
+            pier.update_expiry(expiry.clone()).await?;
+            state.events.publish(events::Event::PierExpiring { name: name.to_owned(), expires_at: expiry.expires_at });
+            audit::record(name, audit::Action::ExpiryWarned, serde_json::json!({ "expiry": expiry })).await?;
+        },
+        expiry::Step::Stop => {
+            let ship = state.write().await.take_for_stop(name);
+            if let Some(ship) = ship {
+                let pier = shut_down(state, ship).await?;
+                state.write().await.checkin(pier);
+            }
+            let mut state = state.write().await;
+            let pier = state.pier_mut(name).ok_or_else(|| anyhow!("pier went away: {}", name))?;
+            expiry.stopped_at = Some(now);
+            pier.update_expiry(expiry.clone()).await?;
+            log::info!("{} expired; stopped it", name);
+            state.console.broadcast(name, "pier expired");
+            state.events.publish(events::Event::PierExpired { name: name.to_owned() });
+            audit::record(name, audit::Action::ExpiryStopped, serde_json::json!({ "expiry": expiry })).await?;
+        },
+        expiry::Step::Trash => {
+            if state.read().await.off.iter().any(|pier| pier.name() == Some(name) && pier.legal_hold().is_some()) {
+                log::debug!("not trashing expired pier {}, which is under legal hold", name);
+                return Ok(());
+            }
+            spawn_expire(state, name.to_owned()).await?;
+        },
+    }
+    Ok(())
+}
+
+/// Exports a checked-out pier before it is reset or done away with: to S3 if a bucket is configured, and to the backup
+/// store otherwise. The export counts as a backup and is queued for replication. Returns where it went.
+async fn export_for_safekeeping(
+    state: &web::Data<RwLock<AppState>>,
+    job: &jobs::JobHandle,
+    pier: &mut ship::PierState,
+    name: &str,
+) -> Result<serde_json::Value> {
+    job.progress("exporting pier for safekeeping");
+    let (export, item) = match s3::S3.as_ref() {
+        Some(s3) => {
+            let object_name = export_object_name(name, time::OffsetDateTime::now_utc());
+            let key = s3.key(&object_name);
+            let body = async_util::throttle(
+                pier.export_stream(ship::ExportLayout::Native).await?,
+                || bandwidth::BACKUP_BANDWIDTH.current_limit(),
+            );
+            let progress = |uploaded| job.progress(format!("uploaded {} bytes", uploaded));
+            s3.upload_stream(&key, body, progress).await?;
+            let export = serde_json::json!({ "bucket": s3.bucket, "key": key });
+            (export, replication::Item::Export { name: object_name })
+        },
+        None => {
+            let report = pier.snapshot_to_store().await?;
+            let item = replication::Item::Snapshot { ship: name.to_owned(), id: report.snapshot.clone() };
+            (serde_json::to_value(report)?, item)
+        },
+    };
+    if let Err(e) = pier.record_backup().await {
+        log::warn!("failed to record backup of {}: {:#}", name, e);
+    }
+    let replication = state.read().await.replication.clone();
+    if let Err(e) = replication.enqueue(item).await {
+        log::warn!("failed to queue the export of {} for replication: {:#}", name, e);
+    }
+    Ok(export)
+}
+
+/// Starts a job that exports an expired pier, to S3 if a bucket is configured and to the backup store otherwise, then
+/// moves it to the trash.
+async fn spawn_expire(state: &web::Data<RwLock<AppState>>, name: String) -> Result<Uuid> {
+    let (pier, jobs) = {
+        let mut state = state.write().await;
+        if state.busy.contains(&name) {
+            bail!("pier is busy: {}", name);
+        }
+        let pier = state.checkout_stopping(&name).ok_or_else(|| anyhow!("no such pier: {}", name))?;
+        (pier, state.jobs.clone())
+    };
+
+    let state = state.clone();
+    Ok(jobs.spawn("expire", Some(name.clone()), move |job| async move {
+        let mut pier = pier.stopped(&state).await?;
+        let export = match export_for_safekeeping(&state, &job, &mut pier, &name).await {
+            Ok(export) => export,
+            Err(e) => {
+                state.write().await.checkin(pier);
+                return Err(e);
+            },
+        };
+        audit::record(&name, audit::Action::ExpiryExported, serde_json::json!({ "export": export })).await?;
+
+        job.progress("moving pier to the trash");
+        let trashed = pier.trash().await;
+        let mut state_guard = state.write().await;
+        state_guard.busy.remove(&name);
+        let trash_path = match trashed {
+            Ok(path) => path,
+            Err(e) => {
+                match ship::PierState::load_from_port(&name).await {
+                    Ok(pier) => state_guard.off.push(pier),
+                    Err(reload_err) => {
+                        log::error!("failed to reload pier '{}' after failed trash: {}", name, reload_err);
+                    },
+                }
+                return Err(e);
+            },
+        };
+        state_guard.crashed.remove(&name);
+        state_guard.events.publish(events::Event::PierTrashed { name: name.clone() });
+        drop(state_guard);
+        log::info!("{} expired and was moved to {}", name, trash_path.to_string_lossy());
+        let trash_path = trash_path.to_string_lossy().into_owned();
+        audit::record(&name, audit::Action::ExpiryTrashed, serde_json::json!({ "path": trash_path })).await?;
+
+        Ok(serde_json::json!({ "name": name, "export": export, "trashedTo": trash_path }))
+    }))
+}
+
+/// Set once startup has finished scanning the harbor. Until then, every request other than the health probes is
+/// refused, as the orchestrator doesn't yet know which piers exist.
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Liveness probe: the process is up and serving requests.
+#[get("/healthz")]
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Readiness probe: the harbor has been scanned and the orchestrator is accepting API requests.
+#[get("/readyz")]
+async fn readyz() -> ApiResult<HttpResponse> {
+    if !READY.load(Ordering::Acquire) {
+        return Err(ApiError::not_ready());
+    }
+    // Reported rather than failing the probe, as ships already running are unaffected.
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "ready", "resources": capacity::resources() })))
+}
+
+/// Loads the harbor and starts the background tasks that depend on it, then marks the orchestrator ready.
+async fn start_up(state: web::Data<RwLock<AppState>>, dns_updater: Option<dns::Updater>) {
+    log::info!("storing piers as {}", storage::STORAGE.describe());
+    let mut report = startup_report::StartupReport::new();
+    match ship::HARBOR.migrate_port_layout().await {
+        Ok(moved) if !moved.is_empty() => {
+            log::info!("moved {} piers into the {:?} port layout", moved.len(), *ship::PORT_LAYOUT);
+        },
+        Ok(_) => {},
+        Err(e) => {
+            log::error!("failed to move piers into the {:?} port layout: {:#}", *ship::PORT_LAYOUT, e);
+            std::process::exit(1);
+        },
+    }
+    if let Err(e) = state.write().await.scan_harbor(&mut report).await {
+        log::error!("failed to scan harbor: {}", e);
+        std::process::exit(1);
+    }
+    match plans::Plans::load().await {
+        Ok(plans) => state.write().await.plans = plans,
+        Err(e) => {
+            log::error!("failed to load plans: {:#}", e);
+            std::process::exit(1);
+        },
+    }
+
+    if let Err(e) = resume_interrupted_imports(&state, &mut report).await {
+        log::error!("failed to check the dry dock for interrupted imports: {}", e);
+    }
+
+    report.finish();
+    if report.clean {
+        log::info!("startup: {}", report.summary());
+    } else {
+        log::warn!("startup needed attention: {}", report.summary());
+    }
+    state.write().await.startup_report = Some(report);
+
+    if let Some(url) = commands::NATS_COMMANDS_URL.clone() {
+        actix_web::rt::spawn(commands::run(state.clone(), url));
+    }
+
+    actix_web::rt::spawn(evaluate_slos(state.clone()));
+    actix_web::rt::spawn(check_clocks(state.clone()));
+    actix_web::rt::spawn(enforce_expiry(state.clone()));
+    if let Some(every) = *backup_store::BACKUP_VERIFY_INTERVAL {
+        actix_web::rt::spawn(verify_backups(state.clone(), every));
+    }
+    if let Some(replica) = s3::S3_REPLICA.as_ref() {
+        let replication = state.read().await.replication.clone();
+        if let Err(e) = replication.load().await {
+            log::error!("failed to load the replication queue: {:#}", e);
+        }
+        actix_web::rt::spawn(replication.run(replica));
+    }
+
+    if let Some(updater) = dns_updater {
+        actix_web::rt::spawn(update_dns(state.clone(), updater));
+    }
+    if let (Some(_), Some(directory)) = (*vhost::VHOST_TLS_LISTEN, acme::ACME_DIRECTORY.clone()) {
+        actix_web::rt::spawn(manage_certificate(state.clone(), directory));
+    }
+
+    READY.store(true, Ordering::Release);
+    log::info!("ready");
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    if let Err(e) = reaper::become_subreaper() {
+        log::warn!("could not become a child subreaper; orphaned serf processes will not be reaped: {}", e);
+    }
+    actix_web::rt::spawn(reaper::run());
+
+    let state = web::Data::new(RwLock::new(AppState::new()));
+
+    let (events, metrics) = {
+        let state = state.read().await;
+        (state.events.clone(), state.metrics.clone())
+    };
+    actix_web::rt::spawn(metrics.run(events.clone()));
+    for url in sinks::EVENT_SINKS.iter() {
+        let sink = sinks::from_url(url)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        actix_web::rt::spawn(sinks::run(events.clone(), sink));
+    }
+    let dns_updater = match &*dns::DNS_PROVIDER {
+        Some(url) => {
+            let provider = dns::from_url(url).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            Some(dns::Updater::new(provider))
+        },
+        None => None,
+    };
+
+    let startup_state = state.clone();
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .app_data(web::JsonConfig::default()
+                .limit(*MAX_JSON_SIZE)
+                .error_handler(|e, _| match e {
+                    actix_web::error::JsonPayloadError::OverflowKnownLength { .. }
+                    | actix_web::error::JsonPayloadError::Overflow { .. } => {
+                        ApiError::payload_too_large(e.to_string()).into()
+                    },
+                    e => ApiError::bad_request(e.to_string()).into(),
+                }))
+            .app_data(web::PayloadConfig::default()
+                .limit(*MAX_JSON_SIZE))
+            .app_data(web::PathConfig::default()
+                .error_handler(|e, _| ApiError::bad_request(e.to_string()).into()))
+            .app_data(web::QueryConfig::default()
+                .error_handler(|e, _| ApiError::bad_request(e.to_string()).into()))
+            .wrap_fn(|req, srv| {
+                use actix_web::dev::Service;
+                if READY.load(Ordering::Acquire) || matches!(req.path(), "/healthz" | "/readyz") {
+                    future::Either::Left(srv.call(req).map_ok(|res| res.map_into_left_body()))
+                } else {
+                    // Built from the error, so that it is still attached to be translated.
+                    let res = HttpResponse::from_error(ApiError::not_ready());
+                    future::Either::Right(future::ok(req.into_response(res).map_into_right_body()))
+                }
+            })
+            .wrap_fn(|req, srv| {
+                use actix_web::dev::Service;
+                use actix_web::HttpMessage;
+                let languages = req.get_header::<header::AcceptLanguage>()
+                    .map(|accept| accept.ranked())
+                    .unwrap_or_default();
+                srv.call(req).map_ok(move |res| i18n::localize(res, &languages))
+            })
+            .wrap(middleware::Logger::default())
+            .wrap(middleware::NormalizePath::new(
+                middleware::TrailingSlash::MergeOnly,
+            ))
+            .route("/hello", web::get().to(|| async { "Hello World!" }))
+            .service(healthz)
+            .service(readyz)
+            .service(get_startup_report)
+            .service(adopt_pier)
+            .service(clone_pier)
+            .service(create_fake_cluster)
+            .service(create_pier)
+            .service(list_piers)
+            .service(start_pier)
+            .service(get_boot_queue)
+            .service(set_boot_priority)
+            .service(stop_pier)
+            .service(pause_pier)
+            .service(resume_pier)
+            .service(rolling_restart)
+            .service(stop_all)
+            .service(export_pier)
+            .service(start_export)
+            .service(list_backups)
+            .service(collect_backup_garbage)
+            .service(verify_backup)
+            .service(get_replication_status)
+            .service(pack_pier)
+            .service(meld_pier)
+            .service(chop_pier)
+            .service(create_moon)
+            .service(list_jobs)
+            .service(get_job)
+            .service(get_job_artifact)
+            .service(dojo)
+            .service(get_code)
+            .service(get_vats)
+            .service(get_ota)
+            .service(scry_agent)
+            .service(poke_agent)
+            .service(subscribe_agent)
+            .service(eyre_proxy)
+            .service(list_vhosts)
+            .service(manage_ota)
+            .service(list_desks)
+            .service(manage_desk)
+            .service(get_desk_hash)
+            .service(get_identity)
+            .service(reset_code)
+            .service(login_link)
+            .service(set_ames_port)
+            .service(get_env)
+            .service(get_metadata)
+            .service(patch_metadata)
+            .service(place_legal_hold)
+            .service(release_legal_hold)
+            .service(export_state)
+            .service(import_state)
+            .service(get_restart_policy)
+            .service(set_restart_policy)
+            .service(breach_pier)
+            .service(create_signed_link)
+            .service(signed_restart)
+            .service(get_expiry)
+            .service(set_expiry)
+            .service(get_audit_log)
+            .service(list_plans)
+            .service(put_plan)
+            .service(delete_plan)
+            .service(apply_plan)
+            .service(set_pier_plan)
+            .service(get_runtime_config)
+            .service(patch_runtime_config)
+            .service(set_env)
+            .service(list_secrets)
+            .service(put_secret)
+            .service(delete_secret)
+            .service(console_attach)
+            .service(event_stream)
+            .service(pier_logs)
+            .service(pier_uptime)
+            .service(pier_sigil)
+            .service(fleet_summary)
+            .service(pier_usage)
+            .service(system_usage)
+            .service(metrics_endpoint)
+            .service(openapi_document)
+            .service(swagger_ui)
+    });
+
+    let server = match &*LISTEN_ADDR {
+        ListenAddr::Tcp(host, port) => server.bind((host.as_str(), *port))?,
+        ListenAddr::Unix(path) => {
+            // A socket left behind by a previous run would make the bind fail. Anything else there is left alone.
+            if let Ok(meta) = std::fs::symlink_metadata(path) {
+                if std::os::unix::fs::FileTypeExt::is_socket(&meta.file_type()) {
+                    std::fs::remove_file(path)?;
+                }
+            }
+            let server = server.bind_uds(path)?;
+            std::fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(*LISTEN_SOCKET_MODE))?;
+            server
+        },
+    };
+    log::info!("listening on {}", *LISTEN_ADDR);
+
+    if vhost::VHOST_TLS_LISTEN.is_some() && (vhost::VHOST_LISTEN.is_none() || acme::ACME_DIRECTORY.is_none()) {
+        log::warn!("NUCLEUS_VHOST_TLS_LISTEN needs NUCLEUS_VHOST_LISTEN and NUCLEUS_ACME_DIRECTORY; not serving HTTPS");
+    }
+    let vhost_server = match &*vhost::VHOST_LISTEN {
+        Some(addr) => {
+            let state = startup_state.clone();
+            let server = HttpServer::new(move || {
+                App::new()
+                    .app_data(state.clone())
+                    .wrap(middleware::Logger::default())
+                    .service(acme_challenge)
+                    .default_service(web::to(vhost_proxy))
+            });
+            let mut server = match addr {
+                ListenAddr::Tcp(host, port) => server.listen(vhost::bind_host(host, *port)?)?,
+                ListenAddr::Unix(path) => server.bind_uds(path)?,
+            };
+            log::info!("serving ships' web interfaces at {} on {}", vhost::VHOST_TEMPLATE.hostname("{name}"), addr);
+            if let (Some(tls_addr), Some(_)) = (*vhost::VHOST_TLS_LISTEN, &*acme::ACME_DIRECTORY) {
+                // HTTPS is decrypted and relayed to a loopback listener of the vhost server's own.
+                let upstream = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0))?;
+                let upstream_addr = upstream.local_addr()?;
+                server = server.listen(upstream)?;
+                actix_web::rt::spawn(async move {
+                    if let Err(e) = vhost::serve_tls(tls_addr, upstream_addr).await {
+                        log::error!("stopped serving HTTPS on {}: {:#}", tls_addr, e);
+                    }
+                });
+                log::info!("serving ships' web interfaces over HTTPS on {}", tls_addr);
+            }
+            Some(server.run())
+        },
+        None => None,
+    };
+
+    actix_web::rt::spawn(start_up(startup_state, dns_updater));
+    match vhost_server {
+        Some(vhost_server) => future::try_join(server.run(), vhost_server).await.map(drop),
+        None => server.run().await,
+    }
 }
\ No newline at end of file
@@ -0,0 +1,115 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::events::{Event, EventBus};
+
+/// Upper bounds, in seconds, of the buckets job durations are counted in. Imports and exports of large piers can take
+/// the better part of an hour.
+pub const DURATION_BUCKETS: &[f64] = &[1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 1800.0, 3600.0];
+
+/// A Prometheus-style histogram over `DURATION_BUCKETS`.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    /// Non-cumulative count per bucket, plus a final overflow bucket.
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram { counts: vec![0; DURATION_BUCKETS.len() + 1], sum: 0.0 }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&mut self, value: f64) {
+        let idx = DURATION_BUCKETS.iter().position(|bound| value <= *bound).unwrap_or(DURATION_BUCKETS.len());
+        self.counts[idx] += 1;
+        self.sum += value;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+/// Counters fed from the event bus. Everything else exposed at /metrics is read from the app state when scraped.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    ship_boots: AtomicU64,
+    ship_crashes: AtomicU64,
+}
+
+impl Metrics {
+    pub fn ship_boots(&self) -> u64 {
+        self.ship_boots.load(Ordering::Relaxed)
+    }
+
+    pub fn ship_crashes(&self) -> u64 {
+        self.ship_crashes.load(Ordering::Relaxed)
+    }
+
+    /// Counts events from `events` until the bus goes away.
+    pub async fn run(self: Arc<Self>, events: Arc<EventBus>) {
+        let mut rx = events.subscribe();
+        while let Some(envelope) = rx.next().await {
+            match envelope.event {
+                Event::ShipBooted { .. } => { self.ship_boots.fetch_add(1, Ordering::Relaxed); },
+                Event::ShipCrashed { .. } => { self.ship_crashes.fetch_add(1, Ordering::Relaxed); },
+                _ => {},
+            }
+        }
+    }
+}
+
+/// Builds a document in the Prometheus text exposition format.
+#[derive(Debug, Default)]
+pub struct Exposition(String);
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+impl Exposition {
+    /// Starts a metric family. Its samples must follow before the next family is started.
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) -> &mut Self {
+        _ = writeln!(self.0, "# HELP {} {}", name, help);
+        _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+        self
+    }
+
+    pub fn sample<V: std::fmt::Display>(&mut self, name: &str, labels: &[(&str, &str)], value: V) -> &mut Self {
+        self.0.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+                .collect();
+            _ = write!(self.0, "{{{}}}", labels.join(","));
+        }
+        _ = writeln!(self.0, " {}", value);
+        self
+    }
+
+    /// Writes the `_bucket`, `_sum` and `_count` samples of a histogram family.
+    pub fn histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Histogram) -> &mut Self {
+        let bucket_name = format!("{}_bucket", name);
+        let mut cumulative: u64 = 0;
+        for (idx, count) in histogram.counts.iter().enumerate() {
+            cumulative += count;
+            let bound = DURATION_BUCKETS.get(idx).map_or("+Inf".to_owned(), |bound| bound.to_string());
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", &bound));
+            self.sample(&bucket_name, &bucket_labels, cumulative);
+        }
+        self.sample(&format!("{}_sum", name), labels, histogram.sum);
+        self.sample(&format!("{}_count", name), labels, histogram.count());
+        self
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
@@ -0,0 +1,122 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use actix_web::web::Bytes;
+use async_std::sync::Mutex;
+use futures::channel::mpsc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+/// A message delivered on a subscription.
+#[derive(Clone, Debug)]
+pub struct NatsMessage {
+    pub subject: String,
+    pub reply_to: Option<String>,
+    pub payload: Bytes,
+}
+
+type Subscriptions = Arc<std::sync::Mutex<HashMap<u64, mpsc::UnboundedSender<NatsMessage>>>>;
+
+/// A minimal client for the NATS text protocol: publish, subscribe, and keepalive. There is no reconnection logic;
+/// when the connection drops, subscriptions end and publishes fail, and the owner is expected to connect again.
+#[derive(Debug)]
+pub struct NatsClient {
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    subscriptions: Subscriptions,
+    next_sid: AtomicU64,
+}
+
+impl NatsClient {
+    pub async fn connect(addr: &str) -> Result<Self> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        let writer = Arc::new(Mutex::new(writer));
+        let subscriptions: Subscriptions = Arc::default();
+
+        writer.lock().await
+            .write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"native-planet-orchestrator\"}\r\n")
+            .await?;
+
+        let reader_writer = writer.clone();
+        let reader_subscriptions = subscriptions.clone();
+        let addr = addr.to_owned();
+        actix_web::rt::spawn(async move {
+            if let Err(e) = read_loop(reader, reader_writer, reader_subscriptions.clone()).await {
+                log::warn!("nats connection to {} failed: {}", addr, e);
+            }
+            // Dropping the senders ends every subscription stream.
+            reader_subscriptions.lock().unwrap().clear();
+        });
+
+        Ok(NatsClient { writer, subscriptions, next_sid: AtomicU64::new(1) })
+    }
+
+    pub async fn publish(&self, subject: &str, payload: &[u8]) -> Result<()> {
+        let mut frame = format!("PUB {} {}\r\n", subject, payload.len()).into_bytes();
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(b"\r\n");
+
+        self.writer.lock().await.write_all(&frame).await?;
+        Ok(())
+    }
+
+    pub async fn subscribe(&self, subject: &str) -> Result<mpsc::UnboundedReceiver<NatsMessage>> {
+        let sid = self.next_sid.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::unbounded();
+        self.subscriptions.lock().unwrap().insert(sid, tx);
+
+        self.writer.lock().await
+            .write_all(format!("SUB {} {}\r\n", subject, sid).as_bytes())
+            .await?;
+        Ok(rx)
+    }
+}
+
+async fn read_loop(reader: OwnedReadHalf, writer: Arc<Mutex<OwnedWriteHalf>>, subscriptions: Subscriptions) -> Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            bail!("connection closed by server");
+        }
+        let mut words = line.split_ascii_whitespace();
+
+        match words.next() {
+            Some("PING") => writer.lock().await.write_all(b"PONG\r\n").await?,
+            Some("-ERR") => log::warn!("nats server error: {}", line.trim_end()),
+            Some("MSG") => {
+                let args: Vec<&str> = words.collect();
+                let (subject, sid, reply_to, len) = match args[..] {
+                    [subject, sid, len] => (subject, sid, None, len),
+                    [subject, sid, reply_to, len] => (subject, sid, Some(reply_to), len),
+                    _ => bail!("malformed MSG line: {}", line.trim_end()),
+                };
+                let sid: u64 = sid.parse()?;
+                let len: usize = len.parse()?;
+
+                // The payload is followed by a CRLF which isn't counted in its length.
+                let mut payload = vec![0; len + 2];
+                reader.read_exact(&mut payload).await?;
+                payload.truncate(len);
+
+                let msg = NatsMessage {
+                    subject: subject.to_owned(),
+                    reply_to: reply_to.map(str::to_owned),
+                    payload: Bytes::from(payload),
+                };
+                let mut subscriptions = subscriptions.lock().unwrap();
+                if let Some(tx) = subscriptions.get(&sid) {
+                    if tx.unbounded_send(msg).is_err() {
+                        subscriptions.remove(&sid);
+                    }
+                }
+            },
+            // INFO, PONG, +OK
+            _ => {},
+        }
+    }
+}
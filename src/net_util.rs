@@ -1,33 +1,270 @@
 #[allow(unused_imports)] use crate::prelude::*;
 
+use std::collections::BTreeSet;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::ops::Range;
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, UdpSocket};
 
-pub async fn tcp_port_available(port: u16) -> bool {
-    match TcpListener::bind(("127.0.0.1", port)).await {
+/// Loopback addresses of both families. A port is only considered available if it is free on every family the host
+/// supports, since the runtime and any clients may pick either one.
+const LOOPBACKS: [IpAddr; 2] = [IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(Ipv6Addr::LOCALHOST)];
+
+/// Hosts with IPv6 disabled fail to bind `::1` with EADDRNOTAVAIL; that family simply doesn't count against the port.
+/// IPv4 loopback is always expected, so failing to bind it for any reason means the port isn't available.
+fn bind_result_available<T>(addr: IpAddr, result: io::Result<T>) -> bool {
+    match result {
         Ok(_) => true,
-        Err(_) => false,
+        Err(e) => addr.is_ipv6() && e.kind() == io::ErrorKind::AddrNotAvailable,
+    }
+}
+
+pub async fn tcp_port_available(port: u16) -> bool {
+    for addr in LOOPBACKS {
+        if !bind_result_available(addr, TcpListener::bind((addr, port)).await) {
+            return false;
+        }
+    }
+    true
+}
+
+pub async fn udp_port_available(port: u16) -> bool {
+    for addr in LOOPBACKS {
+        if !bind_result_available(addr, UdpSocket::bind((addr, port)).await) {
+            return false;
+        }
     }
+    true
+}
+
+/// Whether any process has a unix socket bound at `path`, per /proc/net/unix, which lists every bound socket's path.
+pub fn unix_socket_bound(path: &std::path::Path) -> io::Result<bool> {
+    let sockets = std::fs::read_to_string("/proc/net/unix")?;
+    Ok(sockets.lines()
+        .skip(1)
+        .filter_map(|line| line.split_ascii_whitespace().nth(7))
+        .any(|bound| std::path::Path::new(bound) == path))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Tcp,
+    Udp,
 }
 
 #[derive(Debug)]
-pub struct TcpPortIssuer {
+pub struct PortIssuer {
+    /// Ports not yet handed out.
     range: Range<u16>,
+    /// Where the range started, to tell which released ports are this issuer's.
+    first: u16,
+    /// Ports handed back by ships that exited, reissued before fresh ones.
+    released: BTreeSet<u16>,
+    /// Ports in `range` that were handed out early because a pier asked for them by number.
+    issued_ahead: BTreeSet<u16>,
+    /// Ports piers keep between launches. Other piers are only issued them once every other port is taken.
+    reserved: BTreeSet<u16>,
+    transport: Transport,
 }
 
-impl TcpPortIssuer {
-    pub fn new(range: Range<u16>) -> Self {
-        TcpPortIssuer { range }
+impl PortIssuer {
+    pub fn new(range: Range<u16>, transport: Transport) -> Self {
+        PortIssuer {
+            first: range.start,
+            range,
+            released: BTreeSet::new(),
+            issued_ahead: BTreeSet::new(),
+            reserved: BTreeSet::new(),
+            transport,
+        }
+    }
+
+    pub fn tcp(range: Range<u16>) -> Self {
+        Self::new(range, Transport::Tcp)
+    }
+
+    pub fn udp(range: Range<u16>) -> Self {
+        Self::new(range, Transport::Udp)
+    }
+
+    pub async fn port_available(&self, port: u16) -> bool {
+        match self.transport {
+            Transport::Tcp => tcp_port_available(port).await,
+            Transport::Udp => udp_port_available(port).await,
+        }
     }
 
     pub async fn get_port(&mut self) -> Result<u16> {
-        // TODO do better
-        for port in self.range.by_ref() {
-            if tcp_port_available(port).await {
-                self.range = (port + 1) .. self.range.end;
+        let unreserved: Vec<u16> = self.released.difference(&self.reserved).copied().collect();
+        for port in unreserved {
+            self.released.remove(&port);
+            if self.port_available(port).await {
                 return Ok(port)
             }
         }
-        bail!("no ports available!")
+        while let Some(port) = self.range.next() {
+            if self.issued_ahead.remove(&port) {
+                continue;
+            }
+            // Set aside for the pier that reserved it, unless nothing else is left.
+            if self.reserved.contains(&port) {
+                self.released.insert(port);
+                continue;
+            }
+            if self.port_available(port).await {
+                return Ok(port)
+            }
+        }
+        while let Some(port) = self.released.pop_first() {
+            if self.port_available(port).await {
+                return Ok(port)
+            }
+        }
+        Err(PortsExhaustedError(self.transport).into())
+    }
+
+    /// Reissues `preferred` if it hasn't been handed out or was released, and is still free, e.g. so that a relaunched
+    /// ship keeps its ports, and otherwise issues any port.
+    pub async fn get_port_preferring(&mut self, preferred: Option<u16>) -> Result<u16> {
+        if let Some(port) = preferred {
+            if self.released.remove(&port) && self.port_available(port).await {
+                return Ok(port)
+            }
+            let unissued = self.range.contains(&port) && !self.issued_ahead.contains(&port);
+            if unissued && self.port_available(port).await {
+                self.issued_ahead.insert(port);
+                return Ok(port)
+            }
+        }
+        self.get_port().await
+    }
+
+    /// Returns a port handed out by this issuer so that it can be reissued. Other ports are ignored.
+    pub fn release(&mut self, port: u16) {
+        if self.issued_ahead.remove(&port) {
+            // Back among the ports not yet handed out.
+            return;
+        }
+        if (self.first..self.range.start).contains(&port) {
+            self.released.insert(port);
+        }
     }
-}
\ No newline at end of file
+
+    /// Sets a port aside for the pier that keeps it. Ports outside the issuer's range are ignored.
+    pub fn reserve(&mut self, port: u16) {
+        if (self.first..self.range.end).contains(&port) {
+            self.reserved.insert(port);
+        }
+    }
+
+    pub fn unreserve(&mut self, port: u16) {
+        self.reserved.remove(&port);
+    }
+}
+
+/// Every port in a `PortIssuer`'s range has been handed out or is bound by another process.
+#[derive(Debug)]
+pub struct PortsExhaustedError(Transport);
+
+impl std::fmt::Display for PortsExhaustedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Transport::Tcp => f.write_str("no tcp ports available"),
+            Transport::Udp => f.write_str("no udp ports available"),
+        }
+    }
+}
+
+impl StdError for PortsExhaustedError {}
+
+/// Where the orchestrator's own HTTP API listens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(String, u16),
+    Unix(std::path::PathBuf),
+}
+
+impl std::str::FromStr for ListenAddr {
+    type Err = anyhow::Error;
+
+    /// Accepts `unix:/path/to/socket`, `host:port`, or `[v6addr]:port`.
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                bail!("unix listen address needs a socket path: {}", s);
+            }
+            return Ok(ListenAddr::Unix(std::path::PathBuf::from(path)));
+        }
+
+        let (host, port) = s.rsplit_once(':').ok_or_else(|| anyhow!("listen address needs a port: {}", s))?;
+        let host = match host.strip_prefix('[') {
+            Some(bracketed) => bracketed.strip_suffix(']').ok_or_else(|| anyhow!("unclosed bracket in {}", s))?,
+            None if host.contains(':') => bail!("IPv6 listen addresses need brackets, as in [::1]:8000: {}", s),
+            None => host,
+        };
+        if host.is_empty() {
+            bail!("listen address needs a host: {}", s);
+        }
+        let port = port.parse().map_err(|_| anyhow!("invalid port in listen address: {}", s))?;
+        Ok(ListenAddr::Tcp(host.to_owned(), port))
+    }
+}
+
+impl std::fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddr::Tcp(host, port) if host.contains(':') => write!(f, "[{}]:{}", host, port),
+            ListenAddr::Tcp(host, port) => write!(f, "{}:{}", host, port),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.to_string_lossy()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp(host: &str, port: u16) -> ListenAddr {
+        ListenAddr::Tcp(host.to_owned(), port)
+    }
+
+    #[test]
+    fn only_ignores_missing_ipv6_loopback() {
+        let [v4, v6] = LOOPBACKS;
+        let not_available = || Err::<(), _>(io::Error::from(io::ErrorKind::AddrNotAvailable));
+        let in_use = || Err::<(), _>(io::Error::from(io::ErrorKind::AddrInUse));
+        assert!(bind_result_available(v6, not_available()));
+        assert!(!bind_result_available(v4, not_available()));
+        assert!(!bind_result_available(v4, in_use()));
+        assert!(!bind_result_available(v6, in_use()));
+        assert!(bind_result_available(v4, Ok(())));
+    }
+
+    #[test]
+    fn parses_listen_addrs() {
+        assert_eq!("127.0.0.1:8000".parse::<ListenAddr>().unwrap(), tcp("127.0.0.1", 8000));
+        assert_eq!("localhost:80".parse::<ListenAddr>().unwrap(), tcp("localhost", 80));
+        assert_eq!("0.0.0.0:0".parse::<ListenAddr>().unwrap(), tcp("0.0.0.0", 0));
+        assert_eq!("[::1]:8000".parse::<ListenAddr>().unwrap(), tcp("::1", 8000));
+        assert_eq!("[::]:443".parse::<ListenAddr>().unwrap(), tcp("::", 443));
+        assert_eq!(
+            "unix:/run/nucleus/api.sock".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Unix("/run/nucleus/api.sock".into()),
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_listen_addrs() {
+        for s in ["", "localhost", "localhost:", "localhost:http", "localhost:65536", ":8000", "[]:8000", "::1:8000",
+            "[::1:8000", "unix:"] {
+            assert!(s.parse::<ListenAddr>().is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn displays_listen_addrs_as_parsed() {
+        for s in ["127.0.0.1:8000", "[::1]:8000", "example.com:80", "unix:/tmp/api.sock"] {
+            assert_eq!(s.parse::<ListenAddr>().unwrap().to_string(), s);
+        }
+    }
+}
@@ -0,0 +1,2168 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use serde_json::{json, Value};
+use std::env;
+
+lazy_static! {
+    /// Serve a Swagger UI page at `/docs` that renders the OpenAPI document. The page loads its assets from a CDN.
+    pub static ref SWAGGER_UI: bool = env::var_os("NUCLEUS_SWAGGER_UI")
+        .map(|s| s.to_str().unwrap().parse::<bool>().unwrap())
+        .unwrap_or(false);
+
+    /// The OpenAPI document describing the HTTP API. Schemas are kept by hand in step with the serde types in main.rs,
+    /// jobs.rs, events.rs, slo.rs, and error.rs; update them together.
+    pub static ref DOCUMENT: Value = document();
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn json_content(schema: Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+fn ok(description: &str, schema: Value) -> Value {
+    json!({ "description": description, "content": json_content(schema) })
+}
+
+fn error(description: &str) -> Value {
+    json!({ "description": description, "content": json_content(schema_ref("ApiError")) })
+}
+
+fn accepted() -> Value {
+    json!({
+        "description": "The job was started; poll the URL in the Location header",
+        "headers": { "Location": { "schema": { "type": "string" } } },
+        "content": json_content(json!({
+            "type": "object",
+            "required": ["jobId"],
+            "properties": { "jobId": { "type": "string", "format": "uuid" } },
+        })),
+    })
+}
+
+fn name_param() -> Value {
+    json!({ "name": "name", "in": "path", "required": true, "schema": { "type": "string" }, "description": "The ship's @p, without the sig" })
+}
+
+fn job_id_param() -> Value {
+    json!({ "name": "id", "in": "path", "required": true, "schema": { "type": "string", "format": "uuid" } })
+}
+
+fn agent_param() -> Value {
+    json!({ "name": "app", "in": "path", "required": true, "schema": { "type": "string" }, "description": "An agent" })
+}
+
+fn plan_param() -> Value {
+    json!({
+        "name": "plan", "in": "path", "required": true,
+        "schema": { "type": "string", "pattern": "^[a-z0-9][a-z0-9-]*$", "maxLength": 64 },
+    })
+}
+
+fn env_schema() -> Value {
+    json!({ "type": "object", "additionalProperties": { "type": "string" } })
+}
+
+fn secret_param() -> Value {
+    json!({
+        "name": "secret", "in": "path", "required": true,
+        "schema": { "type": "string", "pattern": "^[a-z][a-z0-9-]*$", "maxLength": 64 },
+    })
+}
+
+fn layout_param() -> Value {
+    json!({
+        "name": "layout", "in": "query", "required": false,
+        "schema": { "type": "string", "enum": ["native", "portable"], "default": "native" },
+        "description": "portable roots the pier at the ship's name and adds a README, for booting with stock urbit",
+    })
+}
+
+fn export_target_param() -> Value {
+    json!({
+        "name": "target", "in": "query", "required": false,
+        "schema": { "type": "string", "enum": ["download", "s3", "store"], "default": "download" },
+        "description": "s3 uploads the archive to the configured bucket from the server, and the job reports its key; \
+            store snapshots the pier into the deduplicated backup store, and the job reports what it added",
+    })
+}
+
+fn gzip_download(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/gzip": { "schema": { "type": "string", "format": "binary" } } },
+    })
+}
+
+fn paths() -> Value {
+    json!({
+        "/pier": {
+            "get": {
+                "summary": "List managed piers",
+                "parameters": [
+                    {
+                        "name": "sort", "in": "query", "required": false,
+                        "schema": {
+                            "type": "string",
+                            "enum": ["name", "createdAt", "firstBootedAt", "lastLaunchedAt", "lastBackupAt"],
+                            "default": "name",
+                        },
+                    },
+                    { "name": "desc", "in": "query", "required": false, "schema": { "type": "boolean", "default": false } },
+                    {
+                        "name": "tag", "in": "query", "required": false, "schema": { "type": "string" },
+                        "description": "Only piers with all of these comma-separated tags; busy piers never match",
+                    },
+                    {
+                        "name": "owner", "in": "query", "required": false, "schema": { "type": "string" },
+                        "description": "Only piers with this owner; busy piers never match",
+                    },
+                ],
+                "responses": {
+                    "200": ok("The piers", json!({ "type": "array", "items": schema_ref("PierSummary") })),
+                },
+            },
+            "post": {
+                "summary": "Create a pier from a keyfile, archive or stored backup, or mine a new comet, and boot it",
+                "description": "The job's result carries the new ship's @p as `name`. fromFake boots a fake ship \
+                    for development, which talks only to other fake ships on the host and is never issued an ames \
+                    port.",
+                "parameters": [
+                    {
+                        "name": "Idempotency-Key", "in": "header", "required": false,
+                        "description": "Retries with the key of a request that started a job get that request's \
+                                        response back, with an `Idempotent-Replayed: true` header, instead of creating \
+                                        another pier. Keys are remembered for NUCLEUS_IDEMPOTENCY_KEY_TTL.",
+                        "schema": { "type": "string", "minLength": 1, "maxLength": 255 },
+                    },
+                ],
+                "requestBody": {
+                    "required": true,
+                    "content": { "multipart/form-data": {
+                        "schema": {
+                            "type": "object",
+                            "required": ["form"],
+                            "properties": {
+                                "form": schema_ref("PostPierForm"),
+                                "file": {
+                                    "type": "string", "format": "binary",
+                                    "description": "The keyfile or pier archive; omitted for fromComet, fromFake and \
+                                        fromBackup",
+                                },
+                            },
+                        },
+                        "encoding": { "form": { "contentType": "application/json" } },
+                    } },
+                },
+                "responses": {
+                    "202": accepted(),
+                    "400": error("The multipart body or Idempotency-Key was malformed, or name is not a valid @p \
+                        (invalidName)"),
+                    "409": error("A request with the same Idempotency-Key is still in progress"),
+                    "413": error("The form part, keyfile or pier archive was too large"),
+                    "422": error("The keyfile is malformed (invalidKeyfile) or belongs to a ship other than `name` \
+                        (keyfileShipMismatch)"),
+                    "503": error("The host already manages NUCLEUS_MAX_MANAGED_PIERS piers (atCapacity)"),
+                },
+            },
+        },
+        "/pier/adopt": {
+            "post": {
+                "summary": "Take over a pier directory already on the host, then boot it",
+                "description": "For ships run by hand with `urbit` on the same machine, which needn't be archived and \
+                    uploaded. The ship must be stopped. With mode move the directory is renamed into the harbor, \
+                    which requires it to be on the same filesystem; with mode copy it is copied, reflinking where \
+                    the filesystem supports it, and the original is left alone. The job's result carries the \
+                    ship's @p as `name`.",
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(json!({
+                        "type": "object",
+                        "required": ["path"],
+                        "properties": {
+                            "path": { "type": "string", "example": "/home/user/sampel-palnet" },
+                            "mode": { "type": "string", "enum": ["move", "copy"], "default": "move" },
+                        },
+                    })),
+                },
+                "responses": {
+                    "202": accepted(),
+                    "400": error("The body was malformed"),
+                    "422": error("The path is not absolute, not a pier, overlaps the harbor or is in use by a \
+                        running runtime (notAdoptable)"),
+                    "503": error("The host already manages NUCLEUS_MAX_MANAGED_PIERS piers (atCapacity)"),
+                },
+            },
+        },
+        "/pier/{name}/clone": {
+            "post": {
+                "summary": "Copy a stopped pier into a new development ship",
+                "description": "The copy is made in the dry dock, reflinking where the filesystem supports it, and \
+                    left stopped. Clones always run with local networking only, so that the original's peers never \
+                    hear from a second copy of it. The original is busy until the copy is complete. The job's \
+                    result carries the clone's `id` and `name`.",
+                "parameters": [name_param(), {
+                    "name": "as",
+                    "in": "query",
+                    "description": "Name for the clone: lowercase letters, digits and hyphens, and not an @p. \
+                        Defaults to the original's name with a random suffix.",
+                    "schema": { "type": "string", "maxLength": 64 },
+                }],
+                "responses": {
+                    "202": accepted(),
+                    "400": error("The clone name is invalid"),
+                    "404": error("No such pier"),
+                    "409": error("The ship is running, the pier is busy, or a pier with the clone's name already \
+                        exists (pierExists)"),
+                    "503": error("The host already manages NUCLEUS_MAX_MANAGED_PIERS piers (atCapacity)"),
+                },
+            },
+        },
+        "/fake-cluster": {
+            "post": {
+                "summary": "Provision a fake galaxy and fake planets under it, for app development",
+                "description": "The planets are the first ones under the galaxy's first star. Every pier is tagged \
+                    `fake-cluster:{name}`, so the cluster can be listed with `GET /pier?tag=`. Fake ships talk only \
+                    to each other, over the loopback interface. The galaxy boots first and the planets after it; if \
+                    one fails, the piers provisioned before it are kept. The job's result carries the `galaxy` and \
+                    `planets` by @p.",
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(json!({
+                        "type": "object",
+                        "required": ["name"],
+                        "properties": {
+                            "name": { "type": "string", "example": "dev" },
+                            "galaxy": { "type": "string", "default": "zod" },
+                            "planets": { "type": "integer", "minimum": 0, "maximum": 32, "default": 0 },
+                        },
+                    })),
+                },
+                "responses": {
+                    "202": accepted(),
+                    "400": error("The body was malformed, galaxy is not a galaxy (invalidName if not an @p), or \
+                        there are too many planets"),
+                    "409": error("One of the cluster's ships is already a pier (pierExists)"),
+                    "503": error("The cluster would take the host past NUCLEUS_MAX_MANAGED_PIERS piers (atCapacity)"),
+                },
+            },
+        },
+        "/pier/{name}/start": {
+            "post": {
+                "summary": "Boot a stopped pier",
+                "description": "Boots wait in the boot queue for a free slot. With onDemand, the boot goes to the \
+                    front of the queue; if the pier is already queued, it is moved to the front and its job returned.",
+                "parameters": [name_param(), {
+                    "name": "onDemand",
+                    "in": "query",
+                    "description": "Boot ahead of everything else in the boot queue",
+                    "schema": { "type": "boolean", "default": false },
+                }],
+                "responses": {
+                    "202": accepted(),
+                    "404": error("No such pier"),
+                    "409": error("The ship is already running or the pier is busy"),
+                    "503": error("NUCLEUS_MAX_RUNNING_SHIPS ships are already running or booting (atCapacity)"),
+                },
+            },
+        },
+        "/pier/{name}/vats": {
+            "get": {
+                "summary": "The desks installed on a running ship, parsed from +vats",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("The ship's desks", json!({ "type": "array", "items": schema_ref("DeskInfo") })),
+                    "404": error("No such pier"),
+                    "409": error("The ship is not running"),
+                    "502": error("The ship failed to answer"),
+                },
+            },
+        },
+        "/pier/{name}/desks": {
+            "get": {
+                "summary": "The desks installed on a running ship and their status, parsed from +vats",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("The ship's desks", json!({ "type": "array", "items": schema_ref("DeskInfo") })),
+                    "404": error("No such pier"),
+                    "409": error("The ship is not running"),
+                    "502": error("The ship failed to answer"),
+                },
+            },
+            "post": {
+                "summary": "Install, suspend or revive one of a running ship's desks",
+                "description": "Runs `|install`, `|suspend` or `|revive` through the lens. Installs finish in the \
+                    background, as kiln syncs the desk from its source; the desk's entry in `GET \
+                    /pier/{name}/desks` shows how far it has got. The response carries what dojo printed.",
+                "parameters": [name_param()],
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(json!({
+                        "type": "object",
+                        "required": ["action", "desk"],
+                        "properties": {
+                            "action": { "type": "string", "enum": ["install", "suspend", "revive"] },
+                            "desk": { "type": "string", "example": "groups" },
+                            "source": {
+                                "type": "string",
+                                "description": "For install, the @p of the ship to install from",
+                                "example": "~sogryp-dister-dozzod-dozzod",
+                            },
+                            "local": {
+                                "type": "string",
+                                "description": "For install, the name to install the desk under; `desk` by default",
+                            },
+                        },
+                    })),
+                },
+                "responses": {
+                    "200": ok("What dojo printed", json!({
+                        "type": "object",
+                        "properties": { "desk": { "type": "string" }, "output": { "type": "string" } },
+                    })),
+                    "400": error("The body was malformed, a desk name is invalid, or source is not an @p \
+                        (invalidName)"),
+                    "404": error("No such pier"),
+                    "409": error("The ship is not running"),
+                    "502": error("The ship failed to run the command"),
+                },
+            },
+        },
+        "/pier/{name}/ota": {
+            "get": {
+                "summary": "Where a running ship gets kernel updates from, and its %base hash, parsed from +vats %base",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("The ship's OTA status", json!({
+                        "type": "object",
+                        "properties": {
+                            "sourceShip": { "type": "string", "nullable": true },
+                            "baseHash": {
+                                "type": "string",
+                                "nullable": true,
+                                "description": "The trailing characters of %base's base hash, as +vats abbreviates it",
+                            },
+                            "pendingUpdates": { "type": "string", "nullable": true },
+                            "updatesPaused": {
+                                "type": "boolean",
+                                "description": "%base isn't syncing from any ship, as after `|pause-updates`",
+                            },
+                        },
+                    })),
+                    "404": error("No such pier"),
+                    "409": error("The ship is not running"),
+                    "502": error("The ship failed to answer"),
+                },
+            },
+            "post": {
+                "summary": "Pause or resume a running ship's kernel updates, or change where it gets them from",
+                "description": "Runs `|pause-updates`, `|resume-updates` or `|ota ~source` through the lens, so that \
+                    OTAs can be staged across the fleet. The response carries what dojo printed.",
+                "parameters": [name_param()],
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(json!({
+                        "type": "object",
+                        "required": ["action"],
+                        "properties": {
+                            "action": { "type": "string", "enum": ["pause", "resume", "setSource"] },
+                            "source": {
+                                "type": "string",
+                                "description": "For setSource, the @p of the ship to get updates from",
+                                "example": "~zod",
+                            },
+                        },
+                    })),
+                },
+                "responses": {
+                    "200": ok("What dojo printed", json!({
+                        "type": "object",
+                        "properties": { "output": { "type": "string" } },
+                    })),
+                    "400": error("The body was malformed, or source is not an @p (invalidName)"),
+                    "404": error("No such pier"),
+                    "409": error("The ship is not running"),
+                    "502": error("The ship failed to run the command"),
+                },
+            },
+        },
+        "/pier/{name}/scry/{app}/{path}": {
+            "get": {
+                "summary": "Scry one of a running ship's agents through eyre",
+                "description": "Logs into the ship with its +code as needed. The path must have a json mark \
+                    conversion, and may contain slashes.",
+                "parameters": [name_param(), agent_param(), {
+                    "name": "path",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                }],
+                "responses": {
+                    "200": ok("What the agent answered", json!({})),
+                    "400": error("Invalid agent name"),
+                    "404": error("No such pier"),
+                    "409": error("The ship is not running"),
+                    "502": error("The ship failed to answer, e.g. because the agent has no such path"),
+                },
+            },
+        },
+        "/pier/{name}/poke": {
+            "post": {
+                "summary": "Poke one of a running ship's agents through eyre, and wait for its ack",
+                "parameters": [name_param()],
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(json!({
+                        "type": "object",
+                        "required": ["app", "mark", "json"],
+                        "properties": {
+                            "app": { "type": "string" },
+                            "mark": { "type": "string" },
+                            "json": { "description": "The poke, converted from json by the mark" },
+                        },
+                    })),
+                },
+                "responses": {
+                    "204": { "description": "The agent acked the poke" },
+                    "400": error("Invalid agent or mark name"),
+                    "404": error("No such pier"),
+                    "409": error("The ship is not running"),
+                    "502": error("The ship failed to answer, or the agent nacked the poke"),
+                },
+            },
+        },
+        "/pier/{name}/subscribe/{app}/{path}": {
+            "get": {
+                "summary": "Subscribe to a path of one of a running ship's agents through eyre",
+                "description": "Streams each fact the agent sends as a server-sent `fact` event, until the agent \
+                    kicks the subscription or the client disconnects.",
+                "parameters": [name_param(), agent_param(), {
+                    "name": "path",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                }],
+                "responses": {
+                    "200": {
+                        "description": "A stream of facts",
+                        "content": { "text/event-stream": { "schema": { "type": "string" } } },
+                    },
+                    "400": error("Invalid agent name"),
+                    "404": error("No such pier"),
+                    "409": error("The ship is not running"),
+                    "502": error("The ship failed to answer, or the agent refused the subscription"),
+                },
+            },
+        },
+        "/pier/{name}/eyre/{path}": {
+            "get": {
+                "summary": "Pass a request through to a running ship's own web interface, logged in as the ship",
+                "description": "Any method is passed through, with its body and the response's streamed. The \
+                    orchestrator logs into the ship with its +code and replaces any login cookie the client sends. \
+                    Websocket upgrades are tunnelled. Redirects to the ship's own pages are rewritten to stay under \
+                    /pier/{name}/eyre, but links within pages are not. Requests for a ship on its way up are held \
+                    for up to NUCLEUS_PROXY_BOOT_WAIT, as on the vhost listener.",
+                "parameters": [name_param(), {
+                    "name": "path",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                    "description": "The path on the ship, such as apps/landscape or ~/channel/1234",
+                }],
+                "responses": {
+                    "default": { "description": "Whatever the ship responded" },
+                    "404": error("No such pier"),
+                    "409": error("The ship is not running"),
+                    "410": error("The request is for an Eyre channel opened before the ship last restarted"),
+                    "502": error("The ship couldn't be logged into or failed to answer"),
+                    "503": error("The ship is stopping, and is letting requests already in flight finish"),
+                },
+            },
+        },
+        "/vhosts": {
+            "get": {
+                "summary": "The hostnames running ships' web interfaces are served at",
+                "description": "With NUCLEUS_VHOST_LISTEN set, a second listener passes requests through to running \
+                    ships' web interfaces by their Host header, mapped to ships with NUCLEUS_VHOST_TEMPLATE \
+                    ({name}.localhost by default). Unlike /pier/{name}/eyre, the client logs into the ship itself. \
+                    Fake ships and clones aren't served. With NUCLEUS_VHOST_TLS_LISTEN and NUCLEUS_ACME_DIRECTORY \
+                    set, they are also served over HTTPS, with a certificate from the ACME CA that is renewed and \
+                    extended to new ships' hostnames as needed. When a ship is stopped or restarted, the proxies \
+                    answer new requests for it with 503 shipStopping while those in flight are given up to \
+                    NUCLEUS_PROXY_DRAIN_TIMEOUT (10s by default) to finish. With NUCLEUS_PROXY_BOOT_WAIT set, e.g. \
+                    to 30s, requests for a ship that is queued to boot, booting or being restarted are held for up to \
+                    that long for it to come up, rather than failing straight away. A client that goes on using an \
+                    Eyre channel it opened before the ship last restarted is answered once with 410 channelExpired, \
+                    so that it opens a new one.",
+                "responses": {
+                    "200": ok("The ships being served", json!({ "type": "array", "items": schema_ref("VirtualHost") })),
+                    "404": error("NUCLEUS_VHOST_LISTEN is not set"),
+                },
+            },
+        },
+        "/pier/{name}/desks/{desk}/hash": {
+            "get": {
+                "summary": "The %cz hash of one of a running ship's desks",
+                "parameters": [name_param(), {
+                    "name": "desk",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                }],
+                "responses": {
+                    "200": ok("The desk's hash", json!({
+                        "type": "object",
+                        "properties": { "desk": { "type": "string" }, "hash": { "type": "string" } },
+                    })),
+                    "400": error("Invalid desk name"),
+                    "404": error("No such pier"),
+                    "409": error("The ship is not running"),
+                    "502": error("The ship failed to answer, e.g. because it has no such desk"),
+                },
+            },
+        },
+        "/pier/{name}/identity": {
+            "get": {
+                "summary": "A running ship's @p, rank, sponsor and %base hash, as it sees them",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("The ship's identity", schema_ref("Identity")),
+                    "404": error("No such pier"),
+                    "409": error("The ship is not running"),
+                    "502": error("The ship failed to answer"),
+                },
+            },
+        },
+        "/pier/{name}/logs": {
+            "get": {
+                "summary": "The ship's runtime output since its log was last rotated",
+                "parameters": [name_param(), {
+                    "name": "follow",
+                    "in": "query",
+                    "description": "Keep streaming output as the runtime writes it",
+                    "schema": { "type": "boolean", "default": false },
+                }],
+                "responses": {
+                    "200": {
+                        "description": "The log, as plain text",
+                        "content": { "text/plain": { "schema": { "type": "string" } } },
+                    },
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+        },
+        "/pier/{name}/restart-policy": {
+            "get": {
+                "summary": "What happens when the ship's runtime exits without being stopped",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("The restart policy", schema_ref("RestartPolicy")),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+            "put": {
+                "summary": "Set what happens when the ship's runtime exits without being stopped",
+                "description": "Relaunches back off exponentially. A ship that stays up for ten minutes starts the \
+                    backoff over. Starting or stopping the pier by hand cancels a pending relaunch.",
+                "parameters": [name_param()],
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(schema_ref("RestartPolicy")),
+                },
+                "responses": {
+                    "204": { "description": "The policy was set" },
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+        },
+        "/pier/{name}/config": {
+            "get": {
+                "summary": "The flags the pier's runtime is launched with, and the commands run after it boots",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("The runtime config", schema_ref("RuntimeConfig")),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+            "patch": {
+                "summary": "Change the flags the pier's runtime is launched with, or the commands run after it boots",
+                "description": "Fields left out are left as they are. `loomBits` and `snapshotIntervalSecs` are reset \
+                    to the runtime's defaults with null, and `extraArgs` and `startupCommands` replace the whole \
+                    list. A running ship keeps its current flags until it is next started.",
+                "parameters": [name_param()],
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(schema_ref("RuntimeConfig")),
+                },
+                "responses": {
+                    "200": ok("The resulting runtime config", schema_ref("RuntimeConfig")),
+                    "400": error("A flag is out of range, an extra argument is one the orchestrator sets itself, or a \
+                        startup command is empty or spans several lines"),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                    "422": error("loomBits or connSocket was set, but the pier's runtime version lacks --loom or \
+                        conn.sock"),
+                },
+            },
+        },
+        "/pier/{name}/boot-priority": {
+            "put": {
+                "summary": "Set where a stopped pier's boots go in the boot queue",
+                "parameters": [name_param()],
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(json!({
+                        "type": "object",
+                        "required": ["priority"],
+                        "properties": { "priority": schema_ref("BootPriority") },
+                    })),
+                },
+                "responses": {
+                    "204": { "description": "The priority was set" },
+                    "404": error("No such pier"),
+                    "409": error("The ship is running"),
+                },
+            },
+        },
+        "/pier/{name}/signed-links": {
+            "post": {
+                "summary": "Mint a link that lets whoever holds it take one action on this pier until it expires",
+                "description": "For handing customers narrowly scoped access, such as a restart button in an email, \
+                    without giving them the API. The link is authenticated by an HMAC over the action, the pier and \
+                    its expiry, under a key derived from the seal key.",
+                "parameters": [name_param()],
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(json!({
+                        "type": "object",
+                        "required": ["action"],
+                        "properties": {
+                            "action": { "type": "string", "enum": ["restart"] },
+                            "ttlSecs": {
+                                "type": "integer",
+                                "minimum": 1,
+                                "default": 86400,
+                                "description": "At most NUCLEUS_MAX_SIGNED_LINK_TTL, 7d by default",
+                            },
+                        },
+                    })),
+                },
+                "responses": {
+                    "201": ok("The link", json!({
+                        "type": "object",
+                        "properties": {
+                            "action": { "type": "string" },
+                            "url": {
+                                "type": "string",
+                                "description": "Relative to the orchestrator's root, wherever the provider exposes it",
+                                "example": "/signed/pier/sampel-palnet/restart?expires=1767225600&signature=9f86d0...",
+                            },
+                            "expiresAt": { "type": "string", "format": "date-time" },
+                        },
+                    })),
+                    "400": error("ttlSecs is out of range"),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+        },
+        "/signed/pier/{name}/restart": {
+            "post": {
+                "summary": "Restart the ship, or boot it if it is stopped, with a signed link",
+                "description": "Needs no other credentials, so it is the one route meant to be reachable by customers. \
+                    It is a POST so that scanners following links in emails can't set it off; the page the emailed \
+                    link leads to makes the request.",
+                "parameters": [
+                    name_param(),
+                    { "name": "expires", "in": "query", "required": true, "schema": { "type": "integer" } },
+                    { "name": "signature", "in": "query", "required": true, "schema": { "type": "string" } },
+                ],
+                "responses": {
+                    "202": accepted(),
+                    "403": error("The link has expired or its signature is invalid (invalidSignature)"),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                    "503": error("NUCLEUS_MAX_RUNNING_SHIPS ships are already running or booting (atCapacity)"),
+                },
+            },
+        },
+        "/pier/{name}/breach": {
+            "post": {
+                "summary": "Factory-reset a pier whose ship was breached, and boot it with its new keyfile",
+                "description": "Stops the ship and exports the pier for safekeeping, to S3 if a bucket is configured \
+                    and to the backup store otherwise. Then discards the pier's event log and state and boots it from \
+                    scratch with the new keyfile. The pier keeps its id, ports, config, metadata and secrets, and its \
+                    startup commands run again once it is up. The job's result says where the export went.",
+                "parameters": [name_param()],
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(json!({
+                        "type": "object",
+                        "required": ["keyfile"],
+                        "properties": {
+                            "keyfile": { "type": "string", "description": "The keyfile issued after the breach" },
+                        },
+                    })),
+                },
+                "responses": {
+                    "202": accepted(),
+                    "400": error("The keyfile is malformed (invalidKeyfile) or for another ship (keyfileShipMismatch), \
+                        or the pier is a fake ship or clone"),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy or under legal hold (underLegalHold)"),
+                },
+            },
+        },
+        "/pier/{name}/expiry": {
+            "get": {
+                "summary": "When the pier expires and how far its expiry has got",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("The pier's expiry, or null if it never expires", schema_ref("Expiry")),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+            "put": {
+                "summary": "Set when the pier expires, or with null that it never does",
+                "description": "A pierExpiring event is published NUCLEUS_EXPIRY_WARNING (3d by default) before the \
+                    pier expires. At expiry its ship is stopped and can't be booted, and after NUCLEUS_EXPIRY_GRACE \
+                    (7d by default) the pier is exported, to S3 if a bucket is configured and to the backup store \
+                    otherwise, and moved to the harbor's trash. Piers under legal hold aren't trashed until the hold \
+                    is released. Each step is recorded in the audit log. Setting the expiry starts it over, so \
+                    extending it lets a stopped pier boot again.",
+                "parameters": [name_param()],
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(json!({
+                        "type": "object",
+                        "required": ["expiresAt"],
+                        "properties": { "expiresAt": { "type": "string", "format": "date-time", "nullable": true } },
+                    })),
+                },
+                "responses": {
+                    "200": ok("The resulting expiry, or null", schema_ref("Expiry")),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+        },
+        "/audit": {
+            "get": {
+                "summary": "What the orchestrator has done to piers on its own, oldest first",
+                "parameters": [{
+                    "name": "pier",
+                    "in": "query",
+                    "schema": { "type": "string" },
+                    "description": "Only records concerning this pier",
+                }],
+                "responses": {
+                    "200": ok("The audit log", json!({ "type": "array", "items": schema_ref("AuditRecord") })),
+                },
+            },
+        },
+        "/pier/{name}/plan": {
+            "put": {
+                "summary": "Put the pier on a plan, or take it off its plan",
+                "description": "The plan's settings replace the pier's own. With null, the pier keeps the settings it \
+                    has. Flags take effect the next time the ship is launched.",
+                "parameters": [name_param()],
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(json!({
+                        "type": "object",
+                        "required": ["plan"],
+                        "properties": { "plan": { "type": "string", "nullable": true } },
+                    })),
+                },
+                "responses": {
+                    "204": { "description": "The plan was set" },
+                    "404": error("No such pier or plan"),
+                    "409": error("The pier is busy"),
+                },
+            },
+        },
+        "/plans": {
+            "get": {
+                "summary": "The plans piers can be put on, by name",
+                "responses": {
+                    "200": ok("The plans", json!({ "type": "object", "additionalProperties": schema_ref("Plan") })),
+                },
+            },
+        },
+        "/plans/{plan}": {
+            "put": {
+                "summary": "Define a plan, or redefine it and apply it to every pier already on it",
+                "parameters": [plan_param()],
+                "requestBody": { "required": true, "content": json_content(schema_ref("Plan")) },
+                "responses": {
+                    "200": ok("The piers the plan was applied to", schema_ref("PlanApplication")),
+                    "400": error("The plan's name or flags are invalid"),
+                },
+            },
+            "delete": {
+                "summary": "Delete a plan that no pier is on",
+                "parameters": [plan_param()],
+                "responses": {
+                    "204": { "description": "The plan was deleted" },
+                    "404": error("No such plan"),
+                    "409": error("Piers are still on the plan"),
+                },
+            },
+        },
+        "/plans/{plan}/apply": {
+            "post": {
+                "summary": "Apply a plan again to every pier on it",
+                "description": "Piers checked out by a job when a plan is changed keep their settings; this catches \
+                    them up.",
+                "parameters": [plan_param()],
+                "responses": {
+                    "200": ok("The piers the plan was applied to", schema_ref("PlanApplication")),
+                    "404": error("No such plan"),
+                },
+            },
+        },
+        "/boot-queue": {
+            "get": {
+                "summary": "Boots waiting for a slot, in the order they will start",
+                "responses": {
+                    "200": ok("The boot queue", schema_ref("BootQueue")),
+                },
+            },
+        },
+        "/piers/stop-all": {
+            "post": {
+                "summary": "Stop every running ship within a deadline, killing those that don't exit in time",
+                "parameters": [{
+                    "name": "grace",
+                    "in": "query",
+                    "description": "How long the stop may take before stragglers are killed, e.g. 30s",
+                    "schema": { "type": "string", "default": "30s" },
+                }],
+                "responses": {
+                    "200": ok("How each ship's shutdown went", json!({
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "name": { "type": "string" },
+                                "outcome": { "type": "string", "enum": ["exited", "signalled", "killed", "failed"] },
+                                "error": { "type": "string" },
+                            },
+                        },
+                    })),
+                    "400": error("grace is not a valid duration"),
+                },
+            },
+        },
+        "/piers/rolling-restart": {
+            "post": {
+                "summary": "Restart every running ship a batch at a time, waiting for each batch to be healthy",
+                "description": "A restarted ship is healthy once it answers on dojo. The job's result lists the \
+                                ships that were `restarted`, `failed` (with an `error`) and `skipped` because they \
+                                had stopped in the meantime. With abortOnFailure, the job fails after the first batch \
+                                with a failure.",
+                "requestBody": { "required": true, "content": json_content(schema_ref("RollingRestartForm")) },
+                "responses": {
+                    "202": accepted(),
+                    "400": error("The form was invalid"),
+                },
+            },
+        },
+        "/pier/{name}/stop": {
+            "post": {
+                "summary": "Stop a running ship",
+                "description": "Requests proxied to the ship are first given up to NUCLEUS_PROXY_DRAIN_TIMEOUT to \
+                    finish, while new ones are turned away.",
+                "parameters": [name_param()],
+                "responses": {
+                    "204": { "description": "The ship is stopped" },
+                    "404": error("No such pier"),
+                },
+            },
+        },
+        "/pier/{name}/pause": {
+            "post": {
+                "summary": "Freeze a running ship with SIGSTOP, keeping its memory but giving it no CPU",
+                "parameters": [name_param()],
+                "responses": {
+                    "204": { "description": "The ship is paused" },
+                    "409": error("The ship is not running"),
+                },
+            },
+        },
+        "/pier/{name}/resume": {
+            "post": {
+                "summary": "Thaw a paused ship with SIGCONT",
+                "parameters": [name_param()],
+                "responses": {
+                    "204": { "description": "The ship is running" },
+                    "409": error("The ship is not running"),
+                },
+            },
+        },
+        "/pier/{name}/export": {
+            "get": {
+                "summary": "Stop the ship and stream its pier as a gzipped tarball, or upload it to S3 in a job",
+                "parameters": [name_param(), layout_param(), export_target_param()],
+                "responses": {
+                    "200": gzip_download("The pier archive"),
+                    "202": accepted(),
+                    "400": error("target is s3 but no bucket is configured, or store with the portable layout"),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+            "post": {
+                "summary": "Stop the ship and export its pier to an artifact downloadable from the job, or to S3",
+                "parameters": [name_param(), layout_param(), export_target_param()],
+                "responses": {
+                    "202": accepted(),
+                    "400": error("target is s3 but no bucket is configured, or store with the portable layout"),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+        },
+        "/pier/{name}/backups": {
+            "get": {
+                "summary": "List the ship's snapshots in the backup store, newest first",
+                "description": "Ships that are no longer managed are listed too, so that they can be restored with \
+                    fromBackup.",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("The snapshots", json!({ "type": "array", "items": schema_ref("BackupSnapshot") })),
+                    "400": error("name is not a valid @p (invalidName)"),
+                },
+            },
+        },
+        "/backups/gc": {
+            "post": {
+                "summary": "Delete chunks in the backup store that no snapshot refers to",
+                "description": "Runs automatically whenever a new snapshot prunes old ones beyond \
+                    NUCLEUS_BACKUP_STORE_KEEP. The job reports chunksRemoved and bytesReclaimed.",
+                "responses": {
+                    "202": accepted(),
+                },
+            },
+        },
+        "/backups/verify": {
+            "post": {
+                "summary": "Prove a snapshot restorable by restoring it into a throwaway pier and logging into it",
+                "description": "The copy boots with its networking confined to the host, so it can't interfere with \
+                    the live ship. The result is recorded with the snapshot and published as a backupVerified event. \
+                    NUCLEUS_BACKUP_VERIFY_INTERVAL runs this periodically for a random ship.",
+                "parameters": [
+                    { "name": "name", "in": "query", "required": false, "schema": { "type": "string" },
+                      "description": "The ship whose snapshot to verify; a random ship if omitted" },
+                    { "name": "snapshot", "in": "query", "required": false, "schema": { "type": "string" },
+                      "description": "The snapshot to verify; the ship's newest if omitted" },
+                ],
+                "responses": {
+                    "202": accepted(),
+                    "400": error("name is not a valid @p (invalidName), or snapshot was given without name"),
+                    "404": error("There are no snapshots to verify (noBackups)"),
+                },
+            },
+        },
+        "/backups/replication": {
+            "get": {
+                "summary": "How far the replica bucket is behind",
+                "description": "With NUCLEUS_S3_REPLICA_* configured, every S3 export and backup store snapshot is \
+                    queued to be copied to the replica bucket in the background. Store snapshots are copied as their \
+                    chunks, under chunks/, then their manifest, under snapshots/<ship>/. Nothing is ever deleted from \
+                    the replica.",
+                "responses": {
+                    "200": ok("The replication status", schema_ref("ReplicationStatus")),
+                },
+            },
+        },
+        "/pier/{name}/pack": {
+            "post": {
+                "summary": "Defragment the ship's loom, live if it is running or offline if it is stopped",
+                "parameters": [name_param()],
+                "responses": {
+                    "202": accepted(),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+        },
+        "/pier/{name}/meld": {
+            "post": {
+                "summary": "Deduplicate the ship's nouns, live if it is running or offline if it is stopped",
+                "parameters": [name_param()],
+                "responses": {
+                    "202": accepted(),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+        },
+        "/pier/{name}/chop": {
+            "post": {
+                "summary": "Truncate a stopped pier's event log, reporting sizes before and after in the job result",
+                "parameters": [
+                    name_param(),
+                    {
+                        "name": "roll", "in": "query", "required": false,
+                        "schema": { "type": "boolean", "default": false },
+                        "description": "Start a new event log epoch first",
+                    },
+                ],
+                "responses": {
+                    "202": accepted(),
+                    "404": error("No such pier"),
+                    "409": error("The ship is running or the pier is busy"),
+                    "422": error("The pier's runtime version can't chop, or roll if asked to"),
+                },
+            },
+        },
+        "/pier/{name}/moons": {
+            "post": {
+                "summary": "Spawn a moon of a running ship with `|moon`, then create and boot a pier for it",
+                "parameters": [name_param()],
+                "requestBody": {
+                    "required": false,
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "properties": {
+                            "name": { "type": "string", "description": "The moon to spawn; random if omitted" },
+                        },
+                    } } },
+                },
+                "responses": {
+                    "202": accepted(),
+                    "400": error("The name is not the @p of a moon"),
+                    "404": error("No such pier"),
+                    "409": error("The parent ship is not running"),
+                    "503": error("The host already manages NUCLEUS_MAX_MANAGED_PIERS piers (atCapacity)"),
+                },
+            },
+        },
+        "/pier/{name}/dojo": {
+            "post": {
+                "summary": "Evaluate a dojo command on a running ship",
+                "parameters": [name_param()],
+                "requestBody": { "required": true, "content": json_content(schema_ref("DojoRequest")) },
+                "responses": {
+                    "200": ok("The command's output", schema_ref("DojoResponse")),
+                    "403": error("The command may modify ship state and allow_writes was not set"),
+                    "409": error("The ship is not running, or is paused"),
+                    "502": error("The ship failed to evaluate the command"),
+                },
+            },
+        },
+        "/pier/{name}/code": {
+            "get": {
+                "summary": "Get the ship's web login code, from the cache if the ship is stopped",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("The login code", schema_ref("CodeResponse")),
+                    "404": error("No such pier"),
+                    "409": error("The ship is stopped and its code isn't cached, or the pier is busy"),
+                    "502": error("The ship failed to produce its code"),
+                },
+            },
+        },
+        "/pier/{name}/code/reset": {
+            "post": {
+                "summary": "Reset the ship's web login code, logging out existing sessions",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("The new login code", schema_ref("CodeResponse")),
+                    "409": error("The ship is not running"),
+                    "502": error("The ship failed to reset its code"),
+                },
+            },
+        },
+        "/pier/{name}/login-link": {
+            "get": {
+                "summary": "Get the ship's public URL with the form fields that log a browser into it",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("The login link", schema_ref("LoginLink")),
+                    "404": error("No such pier"),
+                    "409": error("The ship is stopped and its code isn't cached, or the pier is busy"),
+                    "503": error("Public ship URLs are not configured"),
+                },
+            },
+        },
+        "/pier/{name}/ames-port": {
+            "put": {
+                "summary": "Pin the pier's ames port, or clear the pin with a null port",
+                "parameters": [name_param()],
+                "requestBody": { "required": true, "content": json_content(schema_ref("AmesPortForm")) },
+                "responses": {
+                    "204": { "description": "The port was updated" },
+                    "400": error("The port is reserved or already bound"),
+                    "404": error("No such pier"),
+                    "409": error("The ship is running"),
+                },
+            },
+        },
+        "/pier/{name}/env": {
+            "get": {
+                "summary": "Get the extra environment variables the ship's runtime is started with",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("The variables", env_schema()),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+            "put": {
+                "summary": "Replace the extra environment variables the ship's runtime is started with",
+                "description": "Runtimes otherwise get only PATH and a UTF-8 locale. Takes effect on the next boot.",
+                "parameters": [name_param()],
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(env_schema()),
+                },
+                "responses": {
+                    "204": { "description": "The variables were replaced" },
+                    "400": error("A variable name or value was invalid"),
+                    "404": error("No such pier"),
+                    "409": error("The ship is running"),
+                },
+            },
+        },
+        "/pier/{name}/metadata": {
+            "get": {
+                "summary": "Get the tags, owner and notes attached to the pier",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("The metadata", schema_ref("PierMetadata")),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+            "patch": {
+                "summary": "Change the tags, owner or notes attached to the pier, running or not",
+                "description": "Fields left out are unchanged. tags replaces all of the pier's tags; owner and notes \
+                    are cleared with null.",
+                "parameters": [name_param()],
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(json!({
+                        "type": "object",
+                        "properties": {
+                            "tags": { "type": "array", "items": { "type": "string" } },
+                            "owner": { "type": "string", "nullable": true },
+                            "notes": { "type": "string", "nullable": true },
+                        },
+                    })),
+                },
+                "responses": {
+                    "200": ok("The updated metadata", schema_ref("PierMetadata")),
+                    "400": error("A tag, the owner or the notes were invalid or too long"),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+        },
+        "/pier/{name}/legal-hold": {
+            "put": {
+                "summary": "Place the pier under legal hold, suspending automatic pruning of its backups",
+                "description": "Until the hold is released, no snapshot of the pier is pruned from the backup store \
+                    however many are taken. Placing a hold on a pier already under one only updates the reason. \
+                    Published as a legalHoldPlaced event.",
+                "parameters": [name_param()],
+                "requestBody": {
+                    "required": true,
+                    "content": json_content(json!({
+                        "type": "object",
+                        "properties": { "reason": { "type": "string", "nullable": true } },
+                    })),
+                },
+                "responses": {
+                    "200": ok("The hold", schema_ref("LegalHold")),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+            "delete": {
+                "summary": "Release the pier's legal hold",
+                "description": "Snapshots beyond NUCLEUS_BACKUP_STORE_KEEP are pruned the next time one is taken. \
+                    Published as a legalHoldReleased event.",
+                "parameters": [name_param()],
+                "responses": {
+                    "204": { "description": "The hold was released, or there was none" },
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+        },
+        "/pier/{name}/secrets": {
+            "get": {
+                "summary": "List the secrets handed to the ship after every boot, without their values",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("The secrets", json!({ "type": "array", "items": schema_ref("SecretSummary") })),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                },
+            },
+        },
+        "/pier/{name}/secrets/{secret}": {
+            "put": {
+                "summary": "Store a secret, and hand it to the ship now if it is running",
+                "parameters": [name_param(), secret_param()],
+                "requestBody": { "required": true, "content": json_content(schema_ref("Secret")) },
+                "responses": {
+                    "204": { "description": "The secret was stored" },
+                    "400": error("The secret's name or command was invalid"),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                    "502": error("The secret was stored, but the running ship failed to take it"),
+                },
+            },
+            "delete": {
+                "summary": "Forget a secret; the ship keeps whatever it was already given",
+                "parameters": [name_param(), secret_param()],
+                "responses": {
+                    "204": { "description": "The secret was removed" },
+                    "404": error("No such pier or secret"),
+                    "409": error("The pier is busy"),
+                },
+            },
+        },
+        "/pier/{name}/console": {
+            "get": {
+                "summary": "Attach to the ship's console over a websocket",
+                "parameters": [
+                    name_param(),
+                    {
+                        "name": "allowWrites", "in": "query", "required": false,
+                        "schema": { "type": "boolean", "default": false },
+                        "description": "Permit commands that may change the ship's state",
+                    },
+                ],
+                "responses": {
+                    "101": { "description": "Switching to the websocket protocol" },
+                    "400": error("The request was not a websocket handshake"),
+                    "404": error("No such pier"),
+                    "409": error("The ship is paused"),
+                },
+            },
+        },
+        "/pier/{name}/uptime": {
+            "get": {
+                "summary": "Uptime of the ship over each SLO window",
+                "parameters": [name_param()],
+                "responses": {
+                    "200": ok("Uptime per window", json!({ "type": "array", "items": schema_ref("WindowUptime") })),
+                    "404": error("No such pier"),
+                },
+            },
+        },
+        "/pier/{name}/sigil": {
+            "get": {
+                "summary": "The ship's sigil as an SVG",
+                "description": "Drawn from the glyphs in the file NUCLEUS_SIGIL_GLYPHS names, which come from urbit's \
+                    sigil-js: a JSON object mapping each @p syllable to SVG elements on a 128-unit square, with @FG \
+                    and @BG standing for the colours. Moons and comets have no sigil.",
+                "parameters": [
+                    name_param(),
+                    {
+                        "name": "size", "in": "query", "required": false,
+                        "description": "Width and height in pixels, from 16 to 1024",
+                        "schema": { "type": "integer", "default": 128 },
+                    },
+                    {
+                        "name": "foreground", "in": "query", "required": false,
+                        "description": "Colour of the glyphs, as #rgb or #rrggbb",
+                        "schema": { "type": "string", "default": "#ffffff" },
+                    },
+                    {
+                        "name": "background", "in": "query", "required": false,
+                        "description": "Colour behind the glyphs, as #rgb or #rrggbb",
+                        "schema": { "type": "string", "default": "#000000" },
+                    },
+                ],
+                "responses": {
+                    "200": {
+                        "description": "The sigil",
+                        "content": { "image/svg+xml": { "schema": { "type": "string" } } },
+                    },
+                    "400": error("The size or a colour was invalid"),
+                    "404": error("No such pier, or NUCLEUS_SIGIL_GLYPHS is not set"),
+                    "422": error("The ship is a moon or comet"),
+                },
+            },
+        },
+        "/pier/{name}/usage": {
+            "get": {
+                "summary": "Disk and memory usage of a pier",
+                "parameters": [
+                    name_param(),
+                    {
+                        "name": "mass", "in": "query", "required": false,
+                        "description": "Also include the running ship's `|mass` report, which is slow on large ships",
+                        "schema": { "type": "boolean", "default": false },
+                    },
+                ],
+                "responses": {
+                    "200": ok("The pier's usage", schema_ref("PierUsage")),
+                    "404": error("No such pier"),
+                    "409": error("The pier is busy"),
+                    "502": error("The ship failed to produce its `|mass` report"),
+                },
+            },
+        },
+        "/system/usage": {
+            "get": {
+                "summary": "Usage of every pier, with totals and the harbor filesystem's capacity",
+                "responses": {
+                    "200": ok("Usage across the harbor", schema_ref("SystemUsage")),
+                },
+            },
+        },
+        "/jobs": {
+            "get": {
+                "summary": "List jobs, oldest first",
+                "responses": {
+                    "200": ok("All known jobs", json!({ "type": "array", "items": schema_ref("JobStatus") })),
+                },
+            },
+        },
+        "/jobs/{id}": {
+            "get": {
+                "summary": "Get a job's status",
+                "parameters": [job_id_param()],
+                "responses": {
+                    "200": ok("The job", schema_ref("JobStatus")),
+                    "404": error("No such job"),
+                },
+            },
+        },
+        "/jobs/{id}/artifact": {
+            "get": {
+                "summary": "Download the archive produced by a successful export job",
+                "description": "Supports a single byte range per request, and advertises a strong ETag to use with \
+                    If-Range, so that large archives can be fetched as several ranges in parallel or resumed.",
+                "parameters": [
+                    job_id_param(),
+                    { "name": "Range", "in": "header", "required": false, "schema": { "type": "string" },
+                      "example": "bytes=0-1048575" },
+                    { "name": "If-Range", "in": "header", "required": false, "schema": { "type": "string" } },
+                    { "name": "If-None-Match", "in": "header", "required": false, "schema": { "type": "string" } },
+                ],
+                "responses": {
+                    "200": gzip_download("The pier archive"),
+                    "206": gzip_download("The requested range of the pier archive"),
+                    "304": { "description": "The client's copy, as identified by If-None-Match, is current" },
+                    "404": error("No such job"),
+                    "409": error("The job has no artifact"),
+                    "410": error("The artifact has been removed"),
+                    "416": error("The range lies beyond the end of the archive"),
+                },
+            },
+        },
+        "/state/export": {
+            "get": {
+                "summary": "Dump the orchestrator's records of its piers",
+                "description": "Each pier's settings, metadata, legal hold, port assignments and lifecycle history, \
+                    by name. Pier directories aren't included; back them up separately, e.g. to the backup store.",
+                "responses": {
+                    "200": ok("The dump", schema_ref("StateDump")),
+                },
+            },
+        },
+        "/state/import": {
+            "post": {
+                "summary": "Restore a state dump onto the stopped piers of the same names",
+                "description": "For bringing a rebuilt host back with its records once its piers have been \
+                    restored. Piers keep their own ids and run-as users. Running and busy piers are skipped. The dump \
+                    is limited to NUCLEUS_MAX_JSON_SIZE.",
+                "requestBody": { "required": true, "content": json_content(schema_ref("StateDump")) },
+                "responses": {
+                    "200": ok("What was restored", json!({
+                        "type": "object",
+                        "properties": {
+                            "restored": { "type": "array", "items": { "type": "string" } },
+                            "missing": {
+                                "type": "array", "items": { "type": "string" },
+                                "description": "Piers in the dump that aren't in the harbor",
+                            },
+                            "skipped": {
+                                "type": "array", "items": { "type": "string" },
+                                "description": "Piers that are running or busy",
+                            },
+                            "failed": { "type": "object", "additionalProperties": { "type": "string" } },
+                        },
+                    })),
+                    "400": error("The dump was malformed"),
+                    "413": error("The dump was too large"),
+                },
+            },
+        },
+        "/events": {
+            "get": {
+                "summary": "Stream lifecycle events as server-sent events, each carrying an EventEnvelope",
+                "responses": {
+                    "200": {
+                        "description": "An unending event stream",
+                        "content": { "text/event-stream": { "schema": schema_ref("EventEnvelope") } },
+                    },
+                },
+            },
+        },
+        "/summary": {
+            "get": {
+                "summary": "Fleet-wide overview",
+                "responses": {
+                    "200": ok("The summary", schema_ref("FleetSummary")),
+                },
+            },
+        },
+        "/metrics": {
+            "get": {
+                "summary": "Prometheus metrics",
+                "responses": {
+                    "200": {
+                        "description": "Metrics in the Prometheus text exposition format",
+                        "content": { "text/plain": { "schema": { "type": "string" } } },
+                    },
+                },
+            },
+        },
+        "/healthz": {
+            "get": {
+                "summary": "Liveness probe",
+                "responses": {
+                    "200": ok("The orchestrator is up", json!({ "type": "object" })),
+                },
+            },
+        },
+        "/readyz": {
+            "get": {
+                "summary": "Readiness probe. Until it succeeds, all other endpoints respond with 503 notReady.",
+                "responses": {
+                    "200": ok("The harbor has been scanned and the API is accepting requests", json!({
+                        "type": "object",
+                        "properties": { "status": { "type": "string" }, "resources": schema_ref("Resources") },
+                    })),
+                    "503": error("The orchestrator is still starting up"),
+                },
+            },
+        },
+        "/startup-report": {
+            "get": {
+                "summary": "What the orchestrator found in the harbor when it last started, and what it recovered",
+                "description": "clean is false if the previous run left stale locks, interrupted imports or \
+                    leftover dry dock entries, if a runtime the orchestrator didn't launch is still running a pier, \
+                    or if a pier couldn't be loaded. Unloadable piers are quarantined: left untouched and unmanaged \
+                    until the next startup.",
+                "responses": {
+                    "200": ok("The report", schema_ref("StartupReport")),
+                },
+            },
+        },
+        "/openapi.json": {
+            "get": {
+                "summary": "This document",
+                "responses": {
+                    "200": ok("The OpenAPI document", json!({ "type": "object" })),
+                },
+            },
+        },
+    })
+}
+
+fn schemas() -> Value {
+    json!({
+        "ApiError": {
+            "type": "object",
+            "required": ["code", "message", "detail"],
+            "properties": {
+                "code": { "type": "string", "description": "Stable identifier for the kind of error, e.g. pierNotFound" },
+                "message": {
+                    "type": "string",
+                    "description": "In the language of the request's Accept-Language header, if there is a catalog for \
+                        it, as given in Content-Language; otherwise English",
+                },
+                "detail": { "type": "string", "nullable": true },
+            },
+        },
+        "PostPierForm": {
+            "oneOf": [
+                {
+                    "type": "object",
+                    "required": ["method", "name"],
+                    "properties": {
+                        "method": { "type": "string", "enum": ["fromKeyfile"] },
+                        "name": { "type": "string" },
+                    },
+                },
+                {
+                    "type": "object",
+                    "required": ["method"],
+                    "properties": {
+                        "method": { "type": "string", "enum": ["fromPierArchive"] },
+                    },
+                },
+                {
+                    "type": "object",
+                    "required": ["method"],
+                    "properties": {
+                        "method": { "type": "string", "enum": ["fromComet"] },
+                    },
+                },
+                {
+                    "type": "object",
+                    "required": ["method", "name"],
+                    "properties": {
+                        "method": { "type": "string", "enum": ["fromFake"] },
+                        "name": { "type": "string", "example": "zod" },
+                    },
+                },
+                {
+                    "type": "object",
+                    "required": ["method", "name", "snapshot"],
+                    "properties": {
+                        "method": { "type": "string", "enum": ["fromBackup"] },
+                        "name": { "type": "string" },
+                        "snapshot": { "type": "string", "example": "20240101T000000Z" },
+                    },
+                },
+            ],
+            "discriminator": { "propertyName": "method" },
+        },
+        "BackupSnapshot": {
+            "type": "object",
+            "required": ["id", "takenAt", "size"],
+            "properties": {
+                "id": { "type": "string", "example": "20240101T000000Z" },
+                "takenAt": { "type": "string", "format": "date-time" },
+                "size": { "type": "integer", "description": "Total size of the pier's files, before deduplication" },
+                "verification": {
+                    "type": "object",
+                    "description": "The latest attempt to restore and boot the snapshot; absent if never verified",
+                    "required": ["verifiedAt", "ok"],
+                    "properties": {
+                        "verifiedAt": { "type": "string", "format": "date-time" },
+                        "ok": { "type": "boolean" },
+                        "error": { "type": "string" },
+                    },
+                },
+            },
+        },
+        "LegalHold": {
+            "type": "object",
+            "required": ["since"],
+            "properties": {
+                "since": { "type": "string", "format": "date-time" },
+                "reason": { "type": "string" },
+            },
+        },
+        "CloneOrigin": {
+            "type": "object",
+            "description": "The pier a development clone was copied from",
+            "required": ["name", "id", "clonedAt"],
+            "properties": {
+                "name": { "type": "string" },
+                "id": { "type": "string", "format": "uuid" },
+                "clonedAt": { "type": "string", "format": "date-time" },
+            },
+        },
+        "StateDump": {
+            "type": "object",
+            "required": ["exportedAt", "piers"],
+            "properties": {
+                "exportedAt": { "type": "string", "format": "date-time" },
+                "piers": {
+                    "type": "object",
+                    "description": "Each pier's config as stored in the harbor, by name",
+                    "additionalProperties": { "type": "object" },
+                },
+            },
+        },
+        "StartupReport": {
+            "type": "object",
+            "required": ["startedAt", "clean", "loaded", "locksRecovered", "strayRuntimes", "quarantined",
+                "resumedImports", "removedImports", "leftInDryDock"],
+            "properties": {
+                "startedAt": { "type": "string", "format": "date-time" },
+                "finishedAt": { "type": "string", "format": "date-time" },
+                "clean": { "type": "boolean" },
+                "loaded": { "type": "array", "items": { "type": "string" } },
+                "locksRecovered": {
+                    "type": "array",
+                    "description": "Piers whose lock was held by an orchestrator that died without releasing it",
+                    "items": { "type": "string" },
+                },
+                "strayRuntimes": {
+                    "type": "array",
+                    "description": "Piers still being run by a runtime this orchestrator didn't launch",
+                    "items": {
+                        "type": "object",
+                        "properties": { "name": { "type": "string" }, "pid": { "type": "integer" } },
+                    },
+                },
+                "quarantined": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": { "name": { "type": "string" }, "error": { "type": "string" } },
+                    },
+                },
+                "resumedImports": { "type": "array", "items": { "type": "string", "format": "uuid" } },
+                "removedImports": { "type": "array", "items": { "type": "string", "format": "uuid" } },
+                "leftInDryDock": { "type": "array", "items": { "type": "string", "format": "uuid" } },
+            },
+        },
+        "ReplicationStatus": {
+            "type": "object",
+            "required": ["configured", "pending", "lagSecs", "replicatedTotal", "failuresTotal"],
+            "properties": {
+                "configured": { "type": "boolean", "description": "Whether a replica bucket is configured" },
+                "pending": { "type": "integer", "description": "Backups not yet replicated" },
+                "oldestPendingAt": { "type": "string", "format": "date-time", "nullable": true },
+                "lagSecs": {
+                    "type": "integer",
+                    "description": "How long the oldest unreplicated backup has been waiting; 0 when caught up",
+                },
+                "lastReplicatedAt": { "type": "string", "format": "date-time", "nullable": true },
+                "lastError": {
+                    "type": "string",
+                    "nullable": true,
+                    "description": "The most recent failure, cleared by the next success",
+                },
+                "replicatedTotal": { "type": "integer" },
+                "failuresTotal": { "type": "integer" },
+            },
+        },
+        "PierSummary": {
+            "type": "object",
+            "required": ["name", "status"],
+            "properties": {
+                "name": { "type": "string" },
+                "id": { "type": "string", "format": "uuid", "nullable": true },
+                "status": { "type": "string", "enum": ["running", "paused", "stopped", "crashed", "booting", "busy"] },
+                "bootStage": {
+                    "type": "string",
+                    "enum": ["launching", "processStarted", "portsBound", "lensUp", "eyreUp"],
+                    "description": "For booting piers, how far the boot has got. A boot is complete once it passes \
+                        NUCLEUS_READINESS_PROBE: lensUp for `lens`, eyreUp for `eyre`, the default.",
+                },
+                "class": { "type": "string", "enum": ["galaxy", "star", "planet", "moon", "comet"], "nullable": true },
+                "parent": {
+                    "type": "string",
+                    "description": "The ship's parent by address, and its sponsor unless it has escaped; absent for \
+                        galaxies",
+                },
+                "parentManaged": {
+                    "type": "boolean",
+                    "description": "For moons, whether the parent planet is managed by this orchestrator too",
+                },
+                "clock": {
+                    "type": "object",
+                    "description": "The last check of a running ship's `now` against the host's clock",
+                    "properties": {
+                        "driftMs": { "type": "integer", "format": "int64" },
+                        "drifting": { "type": "boolean" },
+                        "checkedAt": { "type": "string", "format": "date-time" },
+                    },
+                },
+                "confinement": {
+                    "type": "string",
+                    "description": "AppArmor or SELinux label of a running ship's runtime",
+                },
+                "createdAt": { "type": "string", "format": "date-time", "nullable": true },
+                "firstBootedAt": { "type": "string", "format": "date-time", "nullable": true },
+                "lastLaunchedAt": { "type": "string", "format": "date-time", "nullable": true },
+                "lastBackupAt": { "type": "string", "format": "date-time", "nullable": true },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "owner": { "type": "string" },
+                "notes": { "type": "string" },
+                "legalHold": schema_ref("LegalHold"),
+                "cloneOf": schema_ref("CloneOrigin"),
+                "fake": { "type": "boolean", "description": "A fake ship, booted with -F for development" },
+                "plan": { "type": "string", "description": "The plan the pier is on" },
+                "expiry": schema_ref("Expiry"),
+            },
+        },
+        "PierMetadata": {
+            "type": "object",
+            "properties": {
+                "tags": {
+                    "type": "array",
+                    "items": { "type": "string", "minLength": 1, "maxLength": 64, "pattern": "^[^\\s,]+$" },
+                },
+                "owner": { "type": "string", "minLength": 1, "maxLength": 256, "description": "e.g. a customer id" },
+                "notes": { "type": "string", "maxLength": 65536 },
+            },
+        },
+        "DojoRequest": {
+            "type": "object",
+            "required": ["command"],
+            "properties": {
+                "command": { "type": "string" },
+                "timeout_ms": {
+                    "type": "integer",
+                    "format": "int64",
+                    "nullable": true,
+                    "description": "defaults to NUCLEUS_LENS_TIMEOUT",
+                },
+                "allow_writes": { "type": "boolean", "default": false },
+            },
+        },
+        "DojoResponse": {
+            "type": "object",
+            "required": ["command", "output", "elapsed_ms"],
+            "properties": {
+                "command": { "type": "string" },
+                "output": { "type": "string" },
+                "elapsed_ms": { "type": "integer", "format": "int64" },
+            },
+        },
+        "CodeResponse": {
+            "type": "object",
+            "required": ["code", "cached"],
+            "properties": {
+                "code": { "type": "string", "example": "lidlut-tabwed-pillex-ridrup" },
+                "cached": { "type": "boolean" },
+            },
+        },
+        "LoginLink": {
+            "type": "object",
+            "required": ["url", "loginUrl", "form", "code"],
+            "properties": {
+                "url": { "type": "string", "format": "uri" },
+                "loginUrl": { "type": "string", "format": "uri" },
+                "form": {
+                    "type": "object",
+                    "description": "Fields to POST to loginUrl as application/x-www-form-urlencoded",
+                    "properties": { "password": { "type": "string" }, "redirect": { "type": "string" } },
+                },
+                "code": { "type": "string" },
+            },
+        },
+        "RollingRestartForm": {
+            "type": "object",
+            "properties": {
+                "batchSize": { "type": "integer", "minimum": 1, "default": 1 },
+                "healthCheckTimeoutSecs": { "type": "integer", "minimum": 0, "default": 300 },
+                "abortOnFailure": { "type": "boolean", "default": true },
+            },
+        },
+        "AmesPortForm": {
+            "type": "object",
+            "properties": {
+                "port": { "type": "integer", "minimum": 1, "maximum": 65535, "nullable": true },
+            },
+        },
+        "Secret": {
+            "type": "object",
+            "required": ["command", "value"],
+            "properties": {
+                "command": {
+                    "type": "string",
+                    "description": "Dojo poke or hood generator run after every boot; `{value}` is replaced with the \
+                                    value quoted as a cord",
+                    "example": ":my-agent &my-agent-action [%set-api-key {value}]",
+                },
+                "value": { "type": "string", "writeOnly": true },
+            },
+        },
+        "SecretSummary": {
+            "type": "object",
+            "required": ["name", "command"],
+            "properties": {
+                "name": { "type": "string" },
+                "command": { "type": "string" },
+            },
+        },
+        "JobStatus": {
+            "type": "object",
+            "required": ["id", "kind", "state", "createdAt"],
+            "properties": {
+                "id": { "type": "string", "format": "uuid" },
+                "kind": { "type": "string", "example": "export" },
+                "pier": { "type": "string", "nullable": true },
+                "state": { "type": "string", "enum": ["pending", "running", "succeeded", "failed"] },
+                "progress": { "type": "string", "nullable": true },
+                "result": { "nullable": true },
+                "error": { "type": "string", "nullable": true },
+                "errorCode": {
+                    "type": "string",
+                    "nullable": true,
+                    "description": "Stable code for the failure, e.g. noPierInArchive, multiplePiersInArchive, \
+                        archiveTruncated or archivePathsDisallowed for an import of an unusable archive.",
+                },
+                "createdAt": { "type": "string", "format": "date-time" },
+                "finishedAt": { "type": "string", "format": "date-time", "nullable": true },
+            },
+        },
+        "WindowUptime": {
+            "type": "object",
+            "required": ["window", "targetPercent", "breached"],
+            "properties": {
+                "window": { "type": "string", "example": "24h" },
+                "uptimePercent": { "type": "number", "nullable": true },
+                "targetPercent": { "type": "number" },
+                "breached": { "type": "boolean" },
+            },
+        },
+        "DeskInfo": {
+            "type": "object",
+            "properties": {
+                "desk": { "type": "string" },
+                "kelvin": { "type": "string", "nullable": true, "example": "zuse 413" },
+                "baseHash": { "type": "string", "nullable": true, "description": "Trailing characters only" },
+                "czHash": { "type": "string", "nullable": true, "description": "Trailing characters only" },
+                "appStatus": { "type": "string", "nullable": true, "example": "running" },
+                "sourceShip": { "type": "string", "nullable": true },
+                "sourceDesk": { "type": "string", "nullable": true },
+                "sourceAeon": { "type": "integer", "nullable": true },
+                "pendingUpdates": { "type": "string", "nullable": true },
+                "other": {
+                    "type": "object",
+                    "description": "Lines not parsed above, by label",
+                    "additionalProperties": { "type": "string" },
+                },
+            },
+        },
+        "Identity": {
+            "type": "object",
+            "properties": {
+                "ship": { "type": "string" },
+                "rank": { "type": "string", "enum": ["czar", "king", "duke", "earl", "pawn"] },
+                "sponsor": { "type": "string" },
+                "baseHash": { "type": "string" },
+            },
+        },
+        "RestartPolicy": {
+            "type": "object",
+            "properties": {
+                "mode": { "type": "string", "enum": ["never", "onFailure", "always"], "default": "never" },
+                "maxRetries": {
+                    "type": "integer",
+                    "default": 5,
+                    "description": "Consecutive relaunches before giving up; 0 for no limit",
+                },
+                "initialBackoffSecs": { "type": "integer", "default": 5 },
+                "maxBackoffSecs": { "type": "integer", "default": 300 },
+            },
+        },
+        "RuntimeFlags": {
+            "type": "object",
+            "properties": {
+                "loomBits": {
+                    "type": "integer",
+                    "nullable": true,
+                    "minimum": 24,
+                    "maximum": 33,
+                    "description": "Log2 of the loom size in bytes, e.g. 33 for 8 GiB; the runtime's default if unset",
+                },
+                "snapshotIntervalSecs": { "type": "integer", "nullable": true, "minimum": 1 },
+                "verbosity": { "type": "string", "enum": ["quiet", "normal", "verbose"], "default": "normal" },
+                "connSocket": {
+                    "type": "boolean",
+                    "default": false,
+                    "description": "Make lens calls over the runtime's unix-socket control channel instead of \
+                        loopback TCP; only for runtimes with conn.sock",
+                },
+                "extraArgs": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Passed to the runtime as they are, before the pier path",
+                },
+            },
+        },
+        "RuntimeConfig": {
+            "allOf": [schema_ref("RuntimeFlags"), {
+                "type": "object",
+                "properties": {
+                    "startupCommands": {
+                        "type": "array",
+                        "maxItems": 32,
+                        "items": { "type": "string" },
+                        "description": "Dojo commands run in order after every boot, once the ship's secrets are \
+                            injected. How each went is in the boot job's result as `startupCommands`.",
+                        "example": ["|install ~zod %groups", "|knob %hole %skip"],
+                    },
+                },
+            }],
+        },
+        "Expiry": {
+            "type": "object",
+            "nullable": true,
+            "properties": {
+                "expiresAt": { "type": "string", "format": "date-time" },
+                "warnedAt": { "type": "string", "format": "date-time" },
+                "stoppedAt": {
+                    "type": "string",
+                    "format": "date-time",
+                    "description": "When the ship was stopped for having expired",
+                },
+            },
+        },
+        "AuditRecord": {
+            "type": "object",
+            "properties": {
+                "at": { "type": "string", "format": "date-time" },
+                "pier": { "type": "string" },
+                "action": {
+                    "type": "string",
+                    "enum": ["expiryWarned", "expiryStopped", "expiryExported", "expiryTrashed"],
+                },
+                "detail": { "type": "object", "description": "Depends on the action, e.g. where a pier went" },
+            },
+        },
+        "Plan": {
+            "type": "object",
+            "description": "A named bundle of settings, such as a hosting tier. Settings left out are left to each \
+                pier.",
+            "properties": {
+                "runtimeFlags": schema_ref("RuntimeFlags"),
+                "bootPriority": schema_ref("BootPriority"),
+                "restartPolicy": schema_ref("RestartPolicy"),
+            },
+        },
+        "PlanApplication": {
+            "type": "object",
+            "properties": {
+                "applied": { "type": "array", "items": { "type": "string" } },
+                "failed": {
+                    "type": "object",
+                    "additionalProperties": { "type": "string" },
+                    "description": "Piers on the plan that couldn't be updated, and why",
+                },
+            },
+        },
+        "BootPriority": { "type": "string", "enum": ["low", "normal", "high"], "default": "normal" },
+        "BootQueue": {
+            "type": "object",
+            "properties": {
+                "booting": { "type": "integer" },
+                "maxConcurrentBoots": { "type": "integer" },
+                "queued": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "name": { "type": "string" },
+                            "jobId": { "type": "string", "format": "uuid" },
+                            "position": { "type": "integer", "description": "1 for the next boot to start" },
+                            "priority": schema_ref("BootPriority"),
+                            "onDemand": { "type": "boolean" },
+                            "queuedAt": { "type": "string", "format": "date-time" },
+                        },
+                    },
+                },
+            },
+        },
+        "FleetSummary": {
+            "type": "object",
+            "properties": {
+                "running": { "type": "integer" },
+                "paused": { "type": "integer", "description": "Running ships that are paused" },
+                "stopped": { "type": "integer" },
+                "busy": { "type": "integer" },
+                "byClass": {
+                    "type": "object",
+                    "description": "Counts keyed by galaxy, star, planet, moon, or comet",
+                    "additionalProperties": { "type": "integer" },
+                },
+                "unclassified": { "type": "integer" },
+                "harborDiskBytes": { "type": "integer", "format": "int64", "nullable": true },
+                "crashesLast24h": { "type": "integer" },
+                "pendingJobs": { "type": "integer" },
+                "runningJobs": { "type": "integer" },
+                "maxRunningShips": {
+                    "type": "integer",
+                    "nullable": true,
+                    "description": "NUCLEUS_MAX_RUNNING_SHIPS, if set. Boots past it fail with atCapacity.",
+                },
+                "maxManagedPiers": {
+                    "type": "integer",
+                    "nullable": true,
+                    "description": "NUCLEUS_MAX_MANAGED_PIERS, if set. Creating piers past it fails with atCapacity.",
+                },
+                "hostClock": {
+                    "type": "object",
+                    "nullable": true,
+                    "description": "The host clock's NTP synchronization, as of the last clock check",
+                    "properties": {
+                        "synchronized": { "type": "boolean" },
+                        "maxErrorMs": { "type": "integer", "format": "int64" },
+                        "estimatedErrorMs": { "type": "integer", "format": "int64" },
+                    },
+                },
+                "resources": schema_ref("Resources"),
+            },
+        },
+        "FilesystemUsage": {
+            "type": "object",
+            "properties": {
+                "totalBytes": { "type": "integer", "format": "int64" },
+                "availableBytes": { "type": "integer", "format": "int64" },
+                "totalInodes": {
+                    "type": "integer",
+                    "format": "int64",
+                    "description": "0 for filesystems that allocate inodes as needed",
+                },
+                "availableInodes": { "type": "integer", "format": "int64" },
+            },
+        },
+        "VirtualHost": {
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "hostname": { "type": "string" },
+                "httpPort": { "type": "integer", "description": "The loopback port the ship's web interface is on" },
+            },
+        },
+        "Resources": {
+            "type": "object",
+            "description": "Resources that run out on dense hosts: the harbor filesystem's inodes and the \
+                orchestrator's file descriptors",
+            "properties": {
+                "harborFilesystem": { "allOf": [schema_ref("FilesystemUsage")], "nullable": true },
+                "fds": {
+                    "type": "object",
+                    "nullable": true,
+                    "properties": {
+                        "open": { "type": "integer", "format": "int64" },
+                        "limit": { "type": "integer", "format": "int64" },
+                    },
+                },
+                "warnings": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "What another launch would take past NUCLEUS_MIN_FREE_INODES_PERCENT (5 by \
+                        default) or NUCLEUS_MAX_FD_PERCENT (80 by default). Launches go ahead, with a warning logged.",
+                },
+            },
+        },
+        "DiskUsage": {
+            "type": "object",
+            "properties": {
+                "totalBytes": { "type": "integer", "format": "int64" },
+                "eventLogBytes": { "type": "integer", "format": "int64" },
+                "snapshotBytes": { "type": "integer", "format": "int64" },
+                "scratchBytes": { "type": "integer", "format": "int64" },
+            },
+        },
+        "DatasetUsage": {
+            "type": "object",
+            "description": "The pier's own ZFS dataset, when piers are stored as one dataset each",
+            "properties": {
+                "dataset": { "type": "string" },
+                "usedBytes": { "type": "integer", "format": "int64" },
+                "referencedBytes": { "type": "integer", "format": "int64" },
+                "snapshotBytes": { "type": "integer", "format": "int64" },
+                "compressRatio": { "type": "number" },
+            },
+        },
+        "PierUsage": {
+            "type": "object",
+            "required": ["name", "status"],
+            "properties": {
+                "name": { "type": "string" },
+                "status": { "type": "string", "enum": ["running", "paused", "stopped", "crashed", "booting", "busy"] },
+                "disk": { "allOf": [schema_ref("DiskUsage")], "nullable": true },
+                "dataset": schema_ref("DatasetUsage"),
+                "rssBytes": { "type": "integer", "format": "int64", "nullable": true },
+                "mass": { "type": "string" },
+            },
+        },
+        "SystemUsage": {
+            "type": "object",
+            "properties": {
+                "totalDiskBytes": { "type": "integer", "format": "int64" },
+                "totalRssBytes": { "type": "integer", "format": "int64" },
+                "harborFilesystem": { "allOf": [schema_ref("FilesystemUsage")], "nullable": true },
+                "piers": { "type": "array", "items": schema_ref("PierUsage") },
+            },
+        },
+        "EventEnvelope": {
+            "type": "object",
+            "required": ["seq", "at", "type"],
+            "description": "An event, tagged by `type`, plus its sequence number and timestamp. The remaining fields depend on the type.",
+            "properties": {
+                "seq": { "type": "integer", "format": "int64" },
+                "at": { "type": "string", "format": "date-time" },
+                "type": {
+                    "type": "string",
+                    "enum": [
+                        "pierCreated", "shipBooted", "shipStopped", "shipCrashed", "exportStarted", "exportCompleted",
+                        "sloBreached", "sloRecovered", "clockDrifted", "clockDriftResolved",
+                        "hostClockUnsynchronized", "hostClockSynchronized",
+                        "shipPaused", "shipResumed", "shipRestartScheduled", "shipRestartsExhausted",
+                        "backupVerified", "legalHoldPlaced", "legalHoldReleased", "shipOutput",
+                        "pierExpiring", "pierExpired", "pierTrashed", "pierBreached",
+                    ],
+                },
+                "id": { "type": "string", "format": "uuid" },
+                "name": { "type": "string", "nullable": true },
+                "httpPort": { "type": "integer" },
+                "amesPort": { "type": "integer" },
+                "status": { "type": "string" },
+                "window": { "type": "string" },
+                "uptimePercent": { "type": "number" },
+                "targetPercent": { "type": "number" },
+                "driftMs": { "type": "integer", "format": "int64" },
+                "maxErrorMs": { "type": "integer", "format": "int64" },
+                "event": schema_ref("ShipEvent"),
+                "expiresAt": { "type": "string", "format": "date-time", "description": "For pierExpiring" },
+                "rift": {
+                    "type": "integer",
+                    "format": "int64",
+                    "description": "For pierBreached, the ship's rift on Azimuth",
+                },
+            },
+        },
+        "ShipEvent": {
+            "type": "object",
+            "required": ["kind"],
+            "description": "Something a runtime printed, for shipOutput events. The other fields depend on the kind.",
+            "properties": {
+                "kind": { "type": "string", "enum": ["amesRebound", "otaApplied", "crud", "snapshotWritten"] },
+                "previousPort": { "type": "integer", "description": "For amesRebound" },
+                "port": { "type": "integer", "description": "For amesRebound" },
+                "desk": { "type": "string", "description": "For otaApplied" },
+                "tag": { "type": "string", "nullable": true, "description": "For crud, the failed event's tag" },
+                "line": { "type": "string", "description": "For crud, the line as printed" },
+            },
+        },
+    })
+}
+
+fn document() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "native-planet-orchestrator",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths(),
+        "components": { "schemas": schemas() },
+    })
+}
+
+/// A Swagger UI page pointed at `/openapi.json`.
+pub const SWAGGER_UI_HTML: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>native-planet-orchestrator API</title>
+<link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@4/swagger-ui.css">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script src="https://unpkg.com/swagger-ui-dist@4/swagger-ui-bundle.js"></script>
+<script>
+window.onload = () => { window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" }); };
+</script>
+</body>
+</html>
+"##;
@@ -0,0 +1,92 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use async_std::path::Path;
+use std::env;
+use std::os::unix::fs::PermissionsExt;
+
+lazy_static! {
+    /// Owner given to pier files and directories the orchestrator creates, for when the runtime runs as a different
+    /// user. Unset leaves them owned by the orchestrator's user.
+    pub static ref PIER_UID: Option<u32> = env::var_os("NUCLEUS_PIER_UID")
+        .map(|s| s.to_str().unwrap().parse().unwrap());
+
+    /// Group given to pier files and directories the orchestrator creates. Unset leaves the orchestrator's group.
+    pub static ref PIER_GID: Option<u32> = env::var_os("NUCLEUS_PIER_GID")
+        .map(|s| s.to_str().unwrap().parse().unwrap());
+
+    /// Octal permission bits cleared from pier files and directories the orchestrator creates, e.g. `027`. Unset leaves
+    /// modes as the process umask and archive made them.
+    pub static ref PIER_UMASK: Option<u32> = env::var_os("NUCLEUS_PIER_UMASK")
+        .map(|s| u32::from_str_radix(s.to_str().unwrap(), 8).unwrap());
+}
+
+fn configured() -> bool {
+    PIER_UID.is_some() || PIER_GID.is_some() || PIER_UMASK.is_some()
+}
+
+fn apply_sync(path: &std::path::Path, metadata: &std::fs::Metadata) -> std::io::Result<()> {
+    if PIER_UID.is_some() || PIER_GID.is_some() {
+        std::os::unix::fs::lchown(path, *PIER_UID, *PIER_GID)?;
+    }
+    // Symlinks have no permissions of their own, and chmod would follow them.
+    if let (Some(umask), false) = (*PIER_UMASK, metadata.file_type().is_symlink()) {
+        let mode = metadata.permissions().mode() & 0o7777;
+        if mode & umask != 0 {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & !umask))?;
+        }
+    }
+    Ok(())
+}
+
+/// Calls `f` on everything under `path`, not following symlinks. Directories come after their contents, so that a
+/// restrictive mode doesn't lock us out of them.
+fn walk<F>(path: &std::path::Path, f: &F) -> std::io::Result<()>
+    where F: Fn(&std::path::Path, &std::fs::Metadata) -> std::io::Result<()>
+{
+    let metadata = std::fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            walk(&entry?.path(), f)?;
+        }
+    }
+    f(path, &metadata)
+}
+
+/// Applies the configured pier ownership and umask to a single file or directory.
+pub async fn apply(path: &Path) -> Result<()> {
+    if !configured() {
+        return Ok(());
+    }
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let metadata = std::fs::symlink_metadata(&path)?;
+        apply_sync(path.as_ref(), &metadata)
+    }).await??;
+    Ok(())
+}
+
+/// Applies the configured pier ownership and umask to everything under `path`, not following symlinks.
+pub async fn apply_recursive(path: &Path) -> Result<()> {
+    if !configured() {
+        return Ok(());
+    }
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || walk(path.as_ref(), &apply_sync)).await??;
+    Ok(())
+}
+
+/// Changes the owner and/or group of a single file or directory, not following symlinks.
+pub async fn chown(path: &Path, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || std::os::unix::fs::lchown(&path, uid, gid)).await??;
+    Ok(())
+}
+
+/// Changes the owner and/or group of everything under `path`, not following symlinks.
+pub async fn chown_recursive(path: &Path, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || {
+        walk(path.as_ref(), &|path, _| std::os::unix::fs::lchown(path, uid, gid))
+    }).await??;
+    Ok(())
+}
@@ -0,0 +1,270 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::fmt::{self, Display};
+
+use crate::ship::ShipClass;
+
+/// A name that isn't a valid @p.
+#[derive(Debug)]
+pub struct InvalidPatpError {
+    pub name: String,
+    pub reason: &'static str,
+}
+
+impl Display for InvalidPatpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid @p {:?}: {}", self.name, self.reason)
+    }
+}
+
+impl StdError for InvalidPatpError {}
+
+/// Syllables for the high byte of each 16-bit word of an @p, indexed by byte value.
+const PREFIXES: &str = "\
+    dozmarbinwansamlitsighidfidlissogdirwacsabwissibrigsoldopmodfoglidhopdardorlorhodfolrintogsilmirholpaslacrovliv\
+    dalsatlibtabhanticpidtorbolfosdotlosdilforpilramtirwintadbicdifrocwidbisdasmidloprilnardapmolsanlocnovsitnidtip\
+    sicropwitnatpanminritpodmottamtolsavposnapnopsomfinfonbanmorworsipronnorbotwicsocwatdolmagpicdavbidbaltimtasmall\
+    igsivtagpadsaldivdactansidfabtarmonranniswolmispallasdismaprabtobrollatlonnodnavfignomnibpagsopralbilhaddocridmo\
+    cpacravripfaltodtiltinhapmicfanpattaclabmogsimsonpinlomrictapfirhasbosbatpochactidhavsaplindibhosdabbitbarracpar\
+    loddosbortochilmactomdigfilfasmithobharmighinradmashalraglagfadtopmophabnilnosmilfopfamdatnoldinhatnacrisfotribh\
+    ocnimlarfitwalrapsarnalmoslandondanladdovrivbacpollaptalpitnambonrostonfodponsovnocsorlavmatmipfip";
+
+/// Syllables for the low byte of each 16-bit word of an @p, and for a galaxy's only byte.
+const SUFFIXES: &str = "\
+    zodnecbudwessevpersutletfulpensytdurwepserwylsunrypsyxdyrnuphebpeglupdepdysputlughecryttyvsydnexlunmeplutseppesd\
+    elsulpedtemledtulmetwenbynhexfebpyldulhetmevruttylwydtepbesdexsefwycburderneppurrysrebdennutsubpetrulsynregtydsup\
+    semwynrecmegnetsecmulnymtevwebsummutnyxrextebfushepbenmuswyxsymselrucdecwexsyrwetdylmynmesdetbetbeltuxtugmyrpelsy\
+    ptermebsetdutdegtexsurfeltudnuxruxrenwytnubmedlytdusnebrumtynseglyxpunresredfunrevrefmectedrusbexlebduxrynnumpyx\
+    rygryxfeptyrtustyclegnemfermertenlusnussyltecmexpubrymtucfyllepdebbermughuttunbylsudpemdevlurdefbusbeprunmelpexd\
+    ytbyttyplevmylwedducfurfexnulluclennerlexrupnedlecrydlydfenwelnydhusrelrudneshesfetdesretdunlernyrsebhulrylludre\
+    mlysfynwerrycsugnysnyllyndyndemluxfedsedbecmunlyrtesmudnytbyrsenwegfyrmurtelreptegpecnelnevfes";
+
+fn syllable(syllables: &'static str, byte: u8) -> &'static str {
+    let start = byte as usize * 3;
+    &syllables[start..start + 3]
+}
+
+fn syllable_value(syllables: &'static str, syllable: &str) -> Option<u8> {
+    (0..=255).find(|byte| self::syllable(syllables, *byte) == syllable)
+}
+
+/// Keys for the rounds of the Feistel cipher that scrambles planet addresses, as in hoon's `+ob`.
+const RAKU: [u32; 4] = [0xb76d5eed, 0xee281300, 0x85bcae01, 0x4b387af7];
+
+/// MurmurHash3 (x86, 32-bit) of a 16-bit value's two little-endian bytes, hoon's `(muk syd 2 key)`.
+fn muk(seed: u32, key: u32) -> u32 {
+    let mut k = (key & 0xffff).wrapping_mul(0xcc9e2d51);
+    k = k.rotate_left(15).wrapping_mul(0x1b873593);
+    let mut h = seed ^ k;
+    h ^= 2;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^ (h >> 16)
+}
+
+fn round(j: u64, arr: u64) -> u64 {
+    muk(RAKU[j as usize], arr as u32) as u64
+}
+
+const FE_A: u64 = 0xffff;
+const FE_B: u64 = 0x1_0000;
+/// The size of `fe`'s domain, `FE_A * FE_B`, past which its output is walked back into range.
+const FE_K: u64 = 0xffff_0000;
+
+/// One pass of the four-round Feistel cipher over `[0, 0xffff * 0x10000)`.
+fn fe(m: u64) -> u64 {
+    let (mut ell, mut arr) = (m % FE_A, m / FE_A);
+    for j in 1..=4 {
+        let modulus = if j % 2 == 1 { FE_A } else { FE_B };
+        let tmp = (ell + round(j - 1, arr)) % modulus;
+        ell = arr;
+        arr = tmp;
+    }
+    if arr == FE_A { FE_A * arr + ell } else { FE_A * ell + arr }
+}
+
+/// Cycle-walks `fe` until its output is back in its domain.
+fn feis(m: u64) -> u64 {
+    let c = fe(m);
+    if c < FE_K { c } else { fe(c) }
+}
+
+/// Inverts one pass of `fe`.
+fn fen(m: u64) -> u64 {
+    let (ahh, ale) = (m % FE_A, m / FE_A);
+    let (mut ell, mut arr) = if ale == FE_A { (ahh, ale) } else { (ale, ahh) };
+    for j in (1..=4).rev() {
+        let modulus = if j % 2 == 1 { FE_A } else { FE_B };
+        let tmp = (arr + modulus - round(j - 1, ell) % modulus) % modulus;
+        arr = ell;
+        ell = tmp;
+    }
+    FE_A * arr + ell
+}
+
+/// Inverts `feis`.
+fn tail(m: u64) -> u64 {
+    let c = fen(m);
+    if c < FE_K { c } else { fen(c) }
+}
+
+/// Scrambles the low 32 bits of planets and moons so that neighbouring ships don't get similar names, as hoon's
+/// `fein:ob`. Galaxies, stars and comets are left as they are.
+fn fein(n: u128) -> u128 {
+    if (0x1_0000..=0xffff_ffff).contains(&n) {
+        0x1_0000 + feis(n as u64 - 0x1_0000) as u128
+    } else if (0x1_0000_0000..=0xffff_ffff_ffff_ffff).contains(&n) {
+        (n & 0xffff_ffff_0000_0000) | fein(n & 0xffff_ffff)
+    } else {
+        n
+    }
+}
+
+/// Inverts `fein`.
+fn fynd(n: u128) -> u128 {
+    if (0x1_0000..=0xffff_ffff).contains(&n) {
+        0x1_0000 + tail(n as u64 - 0x1_0000) as u128
+    } else if (0x1_0000_0000..=0xffff_ffff_ffff_ffff).contains(&n) {
+        (n & 0xffff_ffff_0000_0000) | fynd(n & 0xffff_ffff)
+    } else {
+        n
+    }
+}
+
+/// Renders a ship's address as its @p, without the leading sig, e.g. `sampel-palnet`.
+pub fn render(ship: u128) -> String {
+    let scrambled = fein(ship);
+    if scrambled < 0x100 {
+        return syllable(SUFFIXES, scrambled as u8).to_owned();
+    }
+
+    let words = (128 - scrambled.leading_zeros() as usize).div_ceil(16);
+    let mut name = String::new();
+    for idx in (0..words).rev() {
+        let word = (scrambled >> (idx * 16)) as u16;
+        name.push_str(syllable(PREFIXES, (word >> 8) as u8));
+        name.push_str(syllable(SUFFIXES, word as u8));
+        if idx > 0 {
+            name.push_str(if idx % 4 == 0 { "--" } else { "-" });
+        }
+    }
+    name
+}
+
+/// Parses an @p, with or without its leading sig, into the ship's address. Only the canonical form `render` produces
+/// is accepted, so e.g. `~dozzod-marzod` is rejected in favour of `~marzod`.
+pub fn parse(name: &str) -> Result<u128> {
+    let invalid = |reason| InvalidPatpError { name: name.to_owned(), reason };
+    let bare = name.strip_prefix('~').unwrap_or(name);
+    if bare.len() == 3 {
+        return syllable_value(SUFFIXES, bare).map(u128::from).ok_or_else(|| invalid("unknown syllable").into());
+    }
+
+    let letters: String = bare.chars().filter(|c| *c != '-').collect();
+    if letters.is_empty() || !letters.len().is_multiple_of(6) || !letters.is_ascii() {
+        bail!(invalid("expected one syllable, or words of two syllables separated by hyphens"));
+    }
+    if letters.len() > 6 * 8 {
+        bail!(invalid("too long"));
+    }
+    let mut scrambled: u128 = 0;
+    for word in letters.as_bytes().chunks(6) {
+        let word = std::str::from_utf8(word).unwrap();
+        let hi = syllable_value(PREFIXES, &word[..3]).ok_or_else(|| invalid("unknown syllable"))?;
+        let lo = syllable_value(SUFFIXES, &word[3..]).ok_or_else(|| invalid("unknown syllable"))?;
+        scrambled = (scrambled << 16) | (hi as u128) << 8 | lo as u128;
+    }
+
+    let ship = fynd(scrambled);
+    if render(ship) != bare {
+        bail!(invalid("not in canonical form"));
+    }
+    Ok(ship)
+}
+
+/// A ship's class follows from the size of its address.
+pub fn class(ship: u128) -> ShipClass {
+    match ship {
+        0..=0xff => ShipClass::Galaxy,
+        0x100..=0xffff => ShipClass::Star,
+        0x1_0000..=0xffff_ffff => ShipClass::Planet,
+        0x1_0000_0000..=0xffff_ffff_ffff_ffff => ShipClass::Moon,
+        _ => ShipClass::Comet,
+    }
+}
+
+/// The ship's parent by address: a moon's planet, a planet's or comet's star, a star's galaxy. Galaxies have none.
+/// This is also the ship's sponsor unless it has escaped to another, which only the network knows.
+pub fn parent(ship: u128) -> Option<u128> {
+    match class(ship) {
+        ShipClass::Galaxy => None,
+        ShipClass::Star => Some(ship & 0xff),
+        ShipClass::Planet | ShipClass::Comet => Some(ship & 0xffff),
+        ShipClass::Moon => Some(ship & 0xffff_ffff),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_known_ships() {
+        assert_eq!(parse("zod").unwrap(), 0);
+        assert_eq!(parse("~marzod").unwrap(), 256);
+        assert_eq!(parse("dapnep-ronmyl").unwrap(), 65536);
+        assert_eq!(parse("~sampel-palnet").unwrap(), 1624961343);
+    }
+
+    #[test]
+    fn renders_known_ships() {
+        assert_eq!(render(0), "zod");
+        assert_eq!(render(256), "marzod");
+        assert_eq!(render(65536), "dapnep-ronmyl");
+        assert_eq!(render(1624961343), "sampel-palnet");
+    }
+
+    #[test]
+    fn round_trips() {
+        let ships = [0, 1, 0xff, 0x100, 0xffff, 0x1_0000, 0x1_2345, 0xffff_fffe, 0xffff_ffff, 0x1_0000_0000,
+            0xdead_beef_cafe, 0xffff_ffff_ffff_ffff, 0x1_0000_0000_0000_0000, u128::MAX];
+        for ship in ships {
+            assert_eq!(parse(&render(ship)).unwrap(), ship, "{}", render(ship));
+        }
+        for ship in (0x1_0000u128..0xffff_ffff).step_by(0x10_0001) {
+            assert_eq!(parse(&render(ship)).unwrap(), ship, "{}", render(ship));
+        }
+    }
+
+    #[test]
+    fn rejects_non_canonical_names() {
+        assert!(parse("dozzod-marzod").is_err());
+        assert!(parse("dozzod-dozzod-dozzod-dozzod").is_err());
+        assert!(parse("marzo").is_err());
+        assert!(parse("zodmar").is_err());
+        assert!(parse("sampelpalnet-").is_err());
+        assert!(parse("sampel-palxyz").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn classifies_ships() {
+        assert_eq!(class(parse("zod").unwrap()), ShipClass::Galaxy);
+        assert_eq!(class(parse("marzod").unwrap()), ShipClass::Star);
+        assert_eq!(class(parse("sampel-palnet").unwrap()), ShipClass::Planet);
+        assert_eq!(class(0x1_0000_0000), ShipClass::Moon);
+        assert_eq!(class(0x1_0000_0000_0000_0000), ShipClass::Comet);
+    }
+
+    #[test]
+    fn finds_parents() {
+        assert_eq!(parent(0), None);
+        assert_eq!(parent(parse("marzod").unwrap()), Some(0));
+        let planet = parse("sampel-palnet").unwrap();
+        assert_eq!(parent(planet), Some(planet & 0xffff));
+        assert_eq!(parent((0xdead << 32) | planet), Some(planet));
+        assert_eq!(parent(0x1_0000_0000_0000_0000_0000_0000_0100), Some(0x100));
+    }
+}
@@ -0,0 +1,99 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::collections::BTreeMap;
+
+use crate::runtime::RuntimeFlags;
+use crate::ship::{BootPriority, HARBOR};
+use crate::supervisor::RestartPolicy;
+
+/// A named bundle of settings, such as a hosting tier, that piers can be put on. Settings a plan leaves out are left to
+/// each pier; the ones it has replace the pier's own whenever the plan is applied.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Plan {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime_flags: Option<RuntimeFlags>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot_priority: Option<BootPriority>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart_policy: Option<RestartPolicy>,
+}
+
+impl Plan {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(flags) = &self.runtime_flags {
+            flags.validate()?;
+        }
+        Ok(())
+    }
+}
+
+const MAX_PLAN_NAME_LEN: usize = 64;
+
+/// Plan names are short identifiers like `basic` or `pro`: lowercase letters, digits and hyphens.
+pub fn validate_plan_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_PLAN_NAME_LEN {
+        bail!("plan names must be 1 to {} characters long", MAX_PLAN_NAME_LEN);
+    }
+    if !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') || name.starts_with('-') {
+        bail!("plan names may only contain lowercase letters, digits and hyphens, and may not start with a hyphen");
+    }
+    Ok(())
+}
+
+/// The plans defined on this host, kept in the harbor.
+#[derive(Debug, Default)]
+pub struct Plans(BTreeMap<String, Plan>);
+
+impl Plans {
+    pub async fn load() -> Result<Self> {
+        let path = HARBOR.plans_path();
+        match async_std::fs::read(&path).await {
+            Ok(json) => Ok(Plans(serde_json::from_slice(&json)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Plans::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn save(&self) -> Result<()> {
+        let path = HARBOR.plans_path();
+        let tmp_path = path.with_extension("json.tmp");
+        async_std::fs::write(&tmp_path, serde_json::to_vec_pretty(&self.0)?).await?;
+        async_std::fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+
+    pub fn all(&self) -> &BTreeMap<String, Plan> {
+        &self.0
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Plan> {
+        self.0.get(name)
+    }
+
+    /// Defines a plan, or redefines it if it exists.
+    pub async fn insert(&mut self, name: &str, plan: Plan) -> Result<()> {
+        validate_plan_name(name)?;
+        plan.validate()?;
+        let previous = self.0.insert(name.to_owned(), plan);
+        if let Err(e) = self.save().await {
+            match previous {
+                Some(previous) => self.0.insert(name.to_owned(), previous),
+                None => self.0.remove(name),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub async fn remove(&mut self, name: &str) -> Result<Option<Plan>> {
+        let Some(plan) = self.0.remove(name) else {
+            return Ok(None);
+        };
+        if let Err(e) = self.save().await {
+            self.0.insert(name.to_owned(), plan);
+            return Err(e);
+        }
+        Ok(Some(plan))
+    }
+}
@@ -0,0 +1,60 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use async_std::path::Path;
+use async_std::sync::{Mutex, MutexGuard};
+use std::collections::HashSet;
+use std::env;
+use std::ops::Range;
+
+use crate::ownership;
+
+lazy_static! {
+    /// Range of uids, e.g. `20000..21000`, from which each ship is assigned its own unprivileged user to run as. The
+    /// ship's group has the same id. Unset runs every ship as the orchestrator's user. Requires running as root.
+    pub static ref SHIP_UID_RANGE: Option<Range<u32>> = env::var_os("NUCLEUS_SHIP_UID_RANGE")
+        .map(|s| s.to_str().unwrap().parse::<MyRange<u32>>().unwrap().inner);
+
+    static ref ALLOCATION: Mutex<()> = Mutex::new(());
+}
+
+/// Held while choosing a uid and recording it in the pier's config, so that two piers can't be assigned the same one.
+pub async fn allocation_lock() -> MutexGuard<'static, ()> {
+    ALLOCATION.lock().await
+}
+
+/// The lowest uid in `SHIP_UID_RANGE` not in `taken`.
+pub fn pick_uid(taken: &HashSet<u32>) -> Result<u32> {
+    let range = SHIP_UID_RANGE.clone().ok_or_else(|| anyhow!("no ship uid range is configured"))?;
+    range.clone()
+        .find(|uid| !taken.contains(uid))
+        .ok_or_else(|| anyhow!("all ship uids in {}..{} are assigned", range.start, range.end))
+}
+
+/// Gives the ship's user the pier to itself. The pier's metadata directory stays owned by the orchestrator, but its
+/// group becomes the ship's so that the runtime can create the pier directory on first boot; the sticky bit stops it
+/// from removing the orchestrator's files there.
+pub async fn prepare_pier(meta_path: &Path, pier_path: &Path, keyfile_path: &Path, uid: u32) -> Result<()> {
+    ownership::chown(meta_path, None, Some(uid)).await?;
+    async_std::fs::set_permissions(meta_path, std::os::unix::fs::PermissionsExt::from_mode(0o1770)).await?;
+    if keyfile_path.exists().await {
+        ownership::chown(keyfile_path, Some(uid), Some(uid)).await?;
+    }
+    if pier_path.exists().await {
+        ownership::chown_recursive(pier_path, Some(uid), Some(uid)).await?;
+    }
+    Ok(())
+}
+
+/// Switches the calling process to `uid`, with the group of the same id and no supplementary groups. Meant to be called
+/// between fork and exec, so it only makes async-signal-safe calls.
+pub fn drop_privileges(uid: u32) -> std::io::Result<()> {
+    unsafe {
+        if libc::setgroups(0, std::ptr::null()) == -1
+            || libc::setgid(uid) == -1
+            || libc::setuid(uid) == -1
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
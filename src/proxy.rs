@@ -0,0 +1,463 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpRequest, HttpResponse};
+use async_std::net::TcpStream;
+use futures::channel::mpsc;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Display};
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::async_util;
+use crate::util;
+
+lazy_static! {
+    /// How long stopping or restarting a ship waits for the requests the proxies are forwarding to it to finish, e.g.
+    /// `10s`, while new ones are turned away. Event streams and upgraded connections never finish, so aren't waited on.
+    pub static ref DRAIN_TIMEOUT: Duration = env::var_os("NUCLEUS_PROXY_DRAIN_TIMEOUT")
+        .map(|s| util::parse_duration(s.to_str().unwrap()).unwrap())
+        .unwrap_or(Duration::from_secs(10));
+
+    /// How long the proxies hold a request for a ship that is on its way up, booting or being restarted, for it to
+    /// be running, e.g. `30s`, so that brief restarts go unnoticed. Unset fails such requests straight away.
+    pub static ref BOOT_WAIT: Option<Duration> = env::var_os("NUCLEUS_PROXY_BOOT_WAIT")
+        .map(|s| util::parse_duration(s.to_str().unwrap()).unwrap());
+}
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often a held request checks whether its ship is up yet.
+pub const BOOT_WAIT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many Eyre channels are remembered per ship. Past this, the least recently used are forgotten.
+const MAX_TRACKED_CHANNELS: usize = 1024;
+
+/// Where ships' web interfaces are reached. The runtime serves HTTP on IPv4 only, so ships are proxied to over IPv4
+/// loopback whichever family the client connected over; it is the proxy's own listeners that are dual-stack.
+const UPSTREAM_HOST: Ipv4Addr = Ipv4Addr::LOCALHOST;
+
+/// The longest response head a ship may send to an upgrade request.
+const MAX_UPGRADE_RESPONSE_HEAD: usize = 64 * 1024;
+
+/// Headers that describe one hop of a connection rather than the request or response, so are never forwarded.
+const HOP_BY_HOP: &[&str] = &[
+    "connection", "keep-alive", "proxy-authenticate", "proxy-authorization", "te", "trailer", "transfer-encoding",
+    "upgrade", "host",
+];
+
+#[derive(Debug, Default)]
+struct ShipTraffic {
+    in_flight: usize,
+    draining: bool,
+}
+
+/// An Eyre channel a client has used through a proxy, and the runtime instance it was used with.
+#[derive(Debug)]
+struct Channel {
+    pid: u32,
+    last_used: Instant,
+}
+
+/// The requests the proxies are forwarding to each ship, so that stopping a ship can let them finish first, and the
+/// Eyre channels clients have used through them, so that ones opened before a restart can be recognized.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    ships: Mutex<HashMap<String, ShipTraffic>>,
+    channels: Mutex<HashMap<String, HashMap<String, Channel>>>,
+}
+
+/// A client tried to use an Eyre channel that it opened before the ship last restarted. It should open a new one
+/// rather than wait on events that are never coming.
+#[derive(Debug)]
+pub struct ChannelExpiredError {
+    pub id: String,
+}
+
+impl Display for ChannelExpiredError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the ship has restarted since channel {} was opened; open a new channel", self.id)
+    }
+}
+
+impl StdError for ChannelExpiredError {}
+
+/// The id of the Eyre channel a request to `path` is for, if any.
+fn channel_id(path: &str) -> Option<&str> {
+    let id = path.strip_prefix("/~/channel/").or_else(|| path.strip_prefix("/%7E/channel/"))
+        .or_else(|| path.strip_prefix("/%7e/channel/"))?;
+    (!id.is_empty() && !id.contains('/')).then_some(id)
+}
+
+impl Traffic {
+    /// Counts a request to the named ship until the returned guard is dropped, or returns None if the ship is being
+    /// drained for a stop and should be given no more.
+    pub fn begin(self: &Arc<Self>, name: &str) -> Option<InFlight> {
+        let mut ships = self.ships.lock().unwrap();
+        let ship = ships.entry(name.to_owned()).or_default();
+        if ship.draining {
+            return None;
+        }
+        ship.in_flight += 1;
+        Some(InFlight { traffic: self.clone(), name: name.to_owned() })
+    }
+
+    /// Whether the named ship is being drained for a stop.
+    pub fn draining(&self, name: &str) -> bool {
+        self.ships.lock().unwrap().get(name).is_some_and(|ship| ship.draining)
+    }
+
+    /// Turns away new requests for the named ship, and waits up to `timeout` for those in flight to finish. Requests
+    /// are let through again once the returned guard is dropped, by when the ship should have been stopped.
+    pub async fn drain(self: &Arc<Self>, name: &str, timeout: Duration) -> Draining {
+        self.ships.lock().unwrap().entry(name.to_owned()).or_default().draining = true;
+        let draining = Draining { traffic: self.clone(), name: name.to_owned() };
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let in_flight = self.ships.lock().unwrap().get(name).map_or(0, |ship| ship.in_flight);
+            if in_flight == 0 {
+                break;
+            }
+            if Instant::now() >= deadline {
+                log::warn!("stopping {} with {} proxied request(s) still in flight", name, in_flight);
+                break;
+            }
+            actix_web::rt::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+        draining
+    }
+
+    /// Notes a request to `path` on the named ship, whose runtime has process id `pid`. If it is for an Eyre channel
+    /// the client last used with an earlier runtime, it fails with a `ChannelExpiredError`, and the channel is
+    /// forgotten, so that the client is told once and can then reopen it under the same id if it likes. Closing a
+    /// channel always goes through.
+    pub fn check_channel(&self, name: &str, pid: u32, method: &Method, path: &str) -> Result<()> {
+        let Some(id) = channel_id(path) else { return Ok(()) };
+        let mut channels = self.channels.lock().unwrap();
+        let ship = channels.entry(name.to_owned()).or_default();
+        if *method == Method::DELETE {
+            ship.remove(id);
+            return Ok(());
+        }
+        match ship.get_mut(id) {
+            Some(channel) if channel.pid != pid => {
+                ship.remove(id);
+                bail!(ChannelExpiredError { id: id.to_owned() });
+            },
+            Some(channel) => channel.last_used = Instant::now(),
+            None => {
+                if ship.len() >= MAX_TRACKED_CHANNELS {
+                    let oldest = ship.iter().min_by_key(|(_, channel)| channel.last_used).map(|(id, _)| id.clone());
+                    if let Some(oldest) = oldest {
+                        ship.remove(&oldest);
+                    }
+                }
+                ship.insert(id.to_owned(), Channel { pid, last_used: Instant::now() });
+            },
+        }
+        Ok(())
+    }
+
+    fn update(&self, name: &str, f: impl FnOnce(&mut ShipTraffic)) {
+        let mut ships = self.ships.lock().unwrap();
+        if let Some(ship) = ships.get_mut(name) {
+            f(ship);
+            if ship.in_flight == 0 && !ship.draining {
+                ships.remove(name);
+            }
+        }
+    }
+}
+
+/// A request being forwarded to a ship, counted by `Traffic` until dropped.
+#[derive(Debug)]
+pub struct InFlight {
+    traffic: Arc<Traffic>,
+    name: String,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.traffic.update(&self.name, |ship| ship.in_flight -= 1);
+    }
+}
+
+/// A ship being drained of proxied requests, which are turned away until this is dropped.
+#[derive(Debug)]
+pub struct Draining {
+    traffic: Arc<Traffic>,
+    name: String,
+}
+
+impl Drop for Draining {
+    fn drop(&mut self) {
+        self.traffic.update(&self.name, |ship| ship.draining = false);
+    }
+}
+
+/// The client's cookies, minus any eyre login of its own, plus the orchestrator's `cookie`, so that the request is
+/// made as the ship's owner whoever the client is logged in as.
+fn with_login(headers: &HeaderMap, cookie: &str) -> String {
+    headers.get_all(header::COOKIE)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .map(str::trim)
+        .filter(|c| !c.is_empty() && !c.starts_with("urbauth-"))
+        .chain([cookie])
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Points a redirect to somewhere else on the ship back through the proxy at `prefix`.
+fn rewrite_location(value: &HeaderValue, prefix: &str) -> HeaderValue {
+    match value.to_str() {
+        Ok(location) if location.starts_with('/') && !location.starts_with("//") => {
+            HeaderValue::from_str(&format!("{}{}", prefix, location)).unwrap_or_else(|_| value.clone())
+        },
+        _ => value.clone(),
+    }
+}
+
+/// The response headers to pass back to the client.
+fn response_headers<'a>(
+    headers: impl Iterator<Item = (&'a HeaderName, &'a HeaderValue)>,
+    prefix: &str,
+) -> Vec<(HeaderName, HeaderValue)> {
+    headers
+        .filter(|(name, _)| !HOP_BY_HOP.contains(&name.as_str()))
+        .map(|(name, value)| match *name {
+            header::LOCATION => (name.clone(), rewrite_location(value, prefix)),
+            _ => (name.clone(), value.clone()),
+        })
+        .collect()
+}
+
+/// Forwards a request to the ship's web interface on `port`, at `path_and_query`. With a login `cookie`, the request is
+/// made with it in place of the client's own; without, the client's cookies are passed through as they are. Request
+/// and response bodies are streamed, and websocket and other upgrade requests are tunnelled. Redirects to the ship's
+/// own pages are rewritten to go through the proxy at `prefix`. The request counts as `in_flight` until its response
+/// has been sent, unless the response is an event stream or an upgrade, which last as long as the client likes.
+pub async fn forward(
+    req: &HttpRequest,
+    payload: web::Payload,
+    port: u16,
+    path_and_query: &str,
+    cookie: Option<&str>,
+    prefix: &str,
+    in_flight: InFlight,
+) -> Result<HttpResponse> {
+    if req.headers().contains_key(header::UPGRADE) {
+        return tunnel(req, payload, port, path_and_query, cookie, prefix, in_flight).await;
+    }
+
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        // Bodies are passed through as the ship encoded them.
+        .no_gzip()
+        .build()?;
+    let method = reqwest::Method::from_bytes(req.method().as_str().as_bytes())?;
+    let mut upstream = client.request(method, format!("http://{}:{}{}", UPSTREAM_HOST, port, path_and_query));
+    for (name, value) in req.headers() {
+        if !HOP_BY_HOP.contains(&name.as_str()) && (cookie.is_none() || *name != header::COOKIE) {
+            upstream = upstream.header(name.as_str(), value.as_bytes());
+        }
+    }
+    if let Some(cookie) = cookie {
+        upstream = upstream.header(header::COOKIE.as_str(), with_login(req.headers(), cookie));
+    }
+
+    // The payload can't leave this thread, but the request body must be Send, so it is pumped through a channel.
+    let (mut tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(4);
+    let mut payload = payload;
+    actix_web::rt::spawn(async move {
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk.map_err(|e| std::io::Error::other(e.to_string()));
+            if tx.send(chunk).await.is_err() {
+                break;
+            }
+        }
+    });
+    let res = upstream.body(reqwest::Body::wrap_stream(rx)).send().await?;
+
+    let mut proxied = HttpResponse::build(res.status());
+    for header in response_headers(res.headers().iter(), prefix) {
+        proxied.append_header(header);
+    }
+    let in_flight = (!is_event_stream(res.headers())).then_some(in_flight);
+    Ok(proxied.streaming(res.bytes_stream().map_err(Error::from).map(move |chunk| {
+        let _ = &in_flight;
+        chunk
+    })))
+}
+
+fn is_event_stream(headers: &reqwest::header::HeaderMap) -> bool {
+    headers.get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"))
+}
+
+/// Parses a response head, `HTTP/1.1 101 Switching Protocols` and its headers.
+fn parse_response_head(head: &[u8]) -> Result<(StatusCode, HeaderMap)> {
+    let head = std::str::from_utf8(head)?;
+    let mut lines = head.split("\r\n");
+    let status = lines.next()
+        .and_then(|line| line.split(' ').nth(1))
+        .ok_or_else(|| anyhow!("malformed response status line from ship"))?;
+    let status = StatusCode::from_bytes(status.as_bytes())?;
+    let mut headers = HeaderMap::new();
+    for line in lines.filter(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':').ok_or_else(|| anyhow!("malformed response header from ship"))?;
+        headers.append(HeaderName::from_bytes(name.trim().as_bytes())?, HeaderValue::from_str(value.trim())?);
+    }
+    Ok((status, headers))
+}
+
+/// Forwards an upgrade request over a connection of its own, and once the ship switches protocols, relays bytes both
+/// ways until either side closes. A ship that declines the upgrade has its response passed back as is.
+async fn tunnel(
+    req: &HttpRequest,
+    payload: web::Payload,
+    port: u16,
+    path_and_query: &str,
+    cookie: Option<&str>,
+    prefix: &str,
+    in_flight: InFlight,
+) -> Result<HttpResponse> {
+    let mut upstream = TcpStream::connect((UPSTREAM_HOST, port)).await?;
+
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}:{}\r\n", req.method(), path_and_query, UPSTREAM_HOST, port);
+    for (name, value) in req.headers() {
+        // The upgrade itself is hop-by-hop, but is what is being forwarded.
+        if matches!(name.as_str(), "host" | "transfer-encoding") || (cookie.is_some() && *name == header::COOKIE) {
+            continue;
+        }
+        head.push_str(&format!("{}: {}\r\n", name, value.to_str()?));
+    }
+    if let Some(cookie) = cookie {
+        head.push_str(&format!("Cookie: {}\r\n", with_login(req.headers(), cookie)));
+    }
+    head.push_str("\r\n");
+    upstream.write_all(head.as_bytes()).await?;
+
+    let mut buf = Vec::new();
+    let head_len = loop {
+        if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        if buf.len() > MAX_UPGRADE_RESPONSE_HEAD {
+            bail!("ship sent an overlong response head");
+        }
+        let mut chunk = [0u8; 4096];
+        let n = upstream.read(&mut chunk).await?;
+        if n == 0 {
+            bail!("ship closed the connection before responding");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let (status, headers) = parse_response_head(&buf[..head_len])?;
+    let rest = Bytes::copy_from_slice(&buf[head_len..]);
+
+    let mut res = HttpResponse::build(status);
+    for header in response_headers(headers.iter(), prefix) {
+        res.append_header(header);
+    }
+    if status != StatusCode::SWITCHING_PROTOCOLS {
+        // The connection may be kept alive, so the body ends where its length says rather than when it closes.
+        let length: u64 = headers.get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .unwrap_or(0);
+        let remaining = length.saturating_sub(rest.len() as u64);
+        let body = stream::once(future::ok(rest)).chain(async_util::read_stream(upstream.take(remaining)));
+        return Ok(res.streaming(body.map(move |chunk| {
+            let _ = &in_flight;
+            chunk
+        })));
+    }
+    drop(in_flight);
+
+    if let Some(protocol) = headers.get(header::UPGRADE).and_then(|value| value.to_str().ok()) {
+        res.upgrade(protocol);
+    }
+    let mut writer = upstream.clone();
+    let mut payload = payload;
+    actix_web::rt::spawn(async move {
+        while let Some(Ok(chunk)) = payload.next().await {
+            if writer.write_all(&chunk).await.is_err() {
+                break;
+            }
+        }
+        _ = writer.shutdown(std::net::Shutdown::Write);
+    });
+    let body = stream::once(future::ok(rest)).chain(async_util::read_stream(upstream));
+    Ok(res.streaming(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn drain_waits_for_requests_in_flight() {
+        let traffic = Arc::new(Traffic::default());
+        let in_flight = traffic.begin("zod").unwrap();
+        actix_web::rt::spawn(async move {
+            actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+            drop(in_flight);
+        });
+
+        let started = Instant::now();
+        let draining = traffic.drain("zod", Duration::from_secs(10)).await;
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(traffic.begin("zod").is_none());
+        assert!(traffic.begin("marzod").is_some());
+
+        drop(draining);
+        assert!(traffic.begin("zod").is_some());
+        assert!(traffic.ships.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn expires_channels_from_earlier_runtimes() {
+        let traffic = Traffic::default();
+        let expired = |result: Result<()>| result.unwrap_err().downcast::<ChannelExpiredError>().unwrap().id;
+
+        traffic.check_channel("zod", 100, &Method::PUT, "/~/channel/a").unwrap();
+        traffic.check_channel("zod", 100, &Method::GET, "/~/channel/a").unwrap();
+        traffic.check_channel("zod", 100, &Method::PUT, "/~/channel/b").unwrap();
+        traffic.check_channel("marzod", 100, &Method::PUT, "/~/channel/a").unwrap();
+
+        assert_eq!(expired(traffic.check_channel("zod", 200, &Method::GET, "/~/channel/a")), "a");
+        traffic.check_channel("zod", 200, &Method::PUT, "/~/channel/a").unwrap();
+        traffic.check_channel("zod", 200, &Method::GET, "/~/channel/a").unwrap();
+        traffic.check_channel("zod", 200, &Method::DELETE, "/~/channel/b").unwrap();
+        traffic.check_channel("zod", 200, &Method::PUT, "/~/channel/b").unwrap();
+        traffic.check_channel("marzod", 100, &Method::GET, "/~/channel/a").unwrap();
+        traffic.check_channel("zod", 300, &Method::GET, "/apps/landscape/").unwrap();
+        assert_eq!(expired(traffic.check_channel("zod", 300, &Method::GET, "/%7E/channel/a")), "a");
+    }
+
+    #[test]
+    fn finds_channel_ids() {
+        assert_eq!(channel_id("/~/channel/1700000000-abc123"), Some("1700000000-abc123"));
+        assert_eq!(channel_id("/%7E/channel/x"), Some("x"));
+        assert_eq!(channel_id("/~/channel/"), None);
+        assert_eq!(channel_id("/~/channel/x/y"), None);
+        assert_eq!(channel_id("/~/scry/x.json"), None);
+    }
+
+    #[actix_web::test]
+    async fn drain_gives_up_after_timeout() {
+        let traffic = Arc::new(Traffic::default());
+        let _in_flight = traffic.begin("zod").unwrap();
+        let started = Instant::now();
+        let _draining = traffic.drain("zod", Duration::from_millis(200)).await;
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(traffic.begin("zod").is_none());
+    }
+}
@@ -0,0 +1,98 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::collections::BTreeMap;
+
+/// One desk as reported by `+vats`.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeskInfo {
+    pub desk: String,
+    /// The kernel version the desk targets, e.g. `zuse 413`.
+    pub kelvin: Option<String>,
+    /// The trailing characters of the desk's base and `%cz` hashes, as `+vats` abbreviates them.
+    pub base_hash: Option<String>,
+    pub cz_hash: Option<String>,
+    /// `running` or `suspended`.
+    pub app_status: Option<String>,
+    pub source_ship: Option<String>,
+    pub source_desk: Option<String>,
+    pub source_aeon: Option<u64>,
+    pub pending_updates: Option<String>,
+    /// Lines this parser doesn't know about, by label, so that nothing newer runtimes report is lost.
+    pub other: BTreeMap<String, String>,
+}
+
+/// `~` as printed by hoon for an empty value.
+fn non_null(value: String) -> Option<String> {
+    (value != "~" && !value.is_empty()).then_some(value)
+}
+
+/// Parses the output of `+vats`: a `%desk` line per desk followed by indented `label: value` lines.
+pub fn parse_vats(output: &str) -> Result<Vec<DeskInfo>> {
+    let mut desks = Vec::new();
+    for line in output.lines() {
+        let trimmed = line.trim().trim_matches('"');
+        if trimmed.is_empty() || trimmed == "::" {
+            continue;
+        }
+        if let Some(desk) = trimmed.strip_prefix('%').filter(|_| !line.starts_with(char::is_whitespace)) {
+            desks.push(DeskInfo { desk: desk.to_owned(), ..DeskInfo::default() });
+            continue;
+        }
+        let Some(info) = desks.last_mut() else {
+            bail!("unexpected +vats output before the first desk: {:?}", line);
+        };
+        let Some((label, value)) = trimmed.split_once(':') else { continue };
+        let value = value.trim().to_owned();
+        match label.trim() {
+            "/sys/kelvin" => info.kelvin = non_null(value.trim_matches(|c| c == '[' || c == ']').replace('%', "")),
+            "base hash ends in" | "base hash" => info.base_hash = non_null(value),
+            "%cz hash ends in" | "%cz hash" => info.cz_hash = non_null(value),
+            "app status" => info.app_status = non_null(value),
+            "source ship" => info.source_ship = non_null(value),
+            "source desk" => info.source_desk = non_null(value.trim_start_matches('%').to_owned()),
+            "source aeon" => info.source_aeon = value.parse().ok(),
+            "pending updates" => info.pending_updates = non_null(value),
+            label => { info.other.insert(label.to_owned(), value); },
+        }
+    }
+    if desks.is_empty() {
+        bail!("no desks in +vats output: {:?}", output);
+    }
+    Ok(desks)
+}
+
+/// Who a ship is, from its own point of view.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Identity {
+    /// The ship's @p, without the leading sig.
+    pub ship: String,
+    /// `czar` (galaxy), `king` (star), `duke` (planet), `earl` (moon) or `pawn` (comet), as hoon's `clan:title`.
+    pub rank: String,
+    pub sponsor: String,
+    /// The `%cz` hash of the `%base` desk, which identifies the kernel and base apps the ship runs.
+    pub base_hash: String,
+}
+
+/// Strips the quoting and sigils dojo prints around an atom, e.g. `"~zod"` or `%czar`.
+pub fn parse_atom(output: &str) -> Result<String> {
+    let atom = output.trim().trim_matches('"').trim_start_matches(['~', '%']);
+    if atom.is_empty() || atom.contains(char::is_whitespace) {
+        bail!("unexpected dojo output for an atom: {:?}", output);
+    }
+    Ok(atom.to_owned())
+}
+
+/// Whether `desk` can name a desk: lowercase letters, digits and hyphens, like a hoon term. Checked before desk names
+/// are spliced into dojo expressions.
+pub fn is_valid_desk(desk: &str) -> bool {
+    !desk.is_empty()
+        && desk.starts_with(|c: char| c.is_ascii_lowercase())
+        && desk.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// The dojo expression for a desk's `%cz` hash, which changes whenever any file in the desk does.
+pub fn desk_hash_expr(desk: &str) -> String {
+    format!(".^(@uv %cz /(scot %p our)/{}/(scot %da now))", desk)
+}
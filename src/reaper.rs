@@ -0,0 +1,175 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::collections::HashSet;
+use std::io;
+use std::process::{ExitStatus, Output, Stdio};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::process;
+
+lazy_static! {
+    /// Pids of processes we spawned ourselves. Tokio reaps these when their `Child` is waited on or dropped, so the
+    /// reaper must leave them alone. Every child must be spawned through [`spawn`] so that it lands here.
+    static ref TRACKED: Mutex<HashSet<u32>> = Mutex::new(HashSet::new());
+}
+
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Spawns `cmd` and tracks the child until [`untrack`] is called on its pid.
+pub fn spawn(cmd: &mut process::Command) -> io::Result<process::Child> {
+    // Hold the lock across the fork, so that the reaper can't see the child exit before it's tracked.
+    let mut tracked = TRACKED.lock().unwrap();
+    let child = cmd.spawn()?;
+    if let Some(pid) = child.id() {
+        tracked.insert(pid);
+    }
+    Ok(child)
+}
+
+pub fn untrack(pid: u32) {
+    TRACKED.lock().unwrap().remove(&pid);
+}
+
+/// Untracks a child spawned through [`spawn`] when dropped. Keep it alongside the `Child` for as long as the child may
+/// still be waited on.
+pub struct Tracked(Option<u32>);
+
+impl Tracked {
+    pub fn of(child: &process::Child) -> Self {
+        Tracked(child.id())
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        if let Some(pid) = self.0 {
+            untrack(pid);
+        }
+    }
+}
+
+/// Like [`process::Command::output`], but tracked. Stdin is left as the caller set it.
+pub async fn output(cmd: &mut process::Command) -> io::Result<Output> {
+    let child = spawn(cmd.stdout(Stdio::piped()).stderr(Stdio::piped()))?;
+    let _tracked = Tracked::of(&child);
+    child.wait_with_output().await
+}
+
+/// Like [`process::Command::status`], but tracked.
+pub async fn status(cmd: &mut process::Command) -> io::Result<ExitStatus> {
+    let mut child = spawn(cmd)?;
+    let _tracked = Tracked::of(&child);
+    child.wait().await
+}
+
+/// Asks the kernel to reparent orphaned descendants (e.g. serf workers whose urbit parent died) to this process rather
+/// than to init, so that they can be found and reaped here instead of lingering with the pier's files open.
+pub fn become_subreaper() -> Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } == -1 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Sends `signal` to every process in the group led by `pgid`. A group that no longer exists is not an error.
+pub fn signal_process_group(pgid: u32, signal: libc::c_int) -> Result<()> {
+    if unsafe { libc::killpg(pgid as libc::pid_t, signal) } == -1 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ESRCH) {
+            return Err(err.into());
+        }
+    }
+    Ok(())
+}
+
+/// Pids of other processes with a file under `dir` open or mapped, found by scanning /proc. Processes this one may not
+/// inspect aren't found. `dir` must be canonical, as /proc gives canonical paths.
+pub fn processes_using(dir: &std::path::Path) -> io::Result<Vec<u32>> {
+    let own_pid = std::process::id();
+    let mut result = Vec::new();
+
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid: u32 = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(pid) if pid != own_pid => pid,
+            _ => continue,
+        };
+        // The process may exit, or be one we may not look into; either way it isn't found.
+        let open = std::fs::read_dir(entry.path().join("fd")).into_iter().flatten()
+            .filter_map(|fd| std::fs::read_link(fd.ok()?.path()).ok())
+            .any(|target| target.starts_with(dir));
+        let mapped = || std::fs::read_to_string(entry.path().join("maps")).is_ok_and(|maps| {
+            maps.lines()
+                .filter_map(|line| line.split_ascii_whitespace().nth(5))
+                .any(|path| std::path::Path::new(path).starts_with(dir))
+        });
+        if open || mapped() {
+            result.push(pid);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Finds zombie children of this process by scanning /proc.
+fn zombies() -> io::Result<Vec<u32>> {
+    let own_pid = std::process::id();
+    let mut result = Vec::new();
+
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid: u32 = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // The process may exit between listing and reading; that's fine.
+        let stat = match std::fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        // The command name field is parenthesized and may contain spaces, so parse from the last ')'.
+        let mut fields = match stat.rfind(')') {
+            Some(idx) => stat[idx + 1..].split_ascii_whitespace(),
+            None => continue,
+        };
+        let state = fields.next();
+        let ppid: Option<u32> = fields.next().and_then(|s| s.parse().ok());
+
+        if state == Some("Z") && ppid == Some(own_pid) {
+            result.push(pid);
+        }
+    }
+
+    Ok(result)
+}
+
+fn reap_once() {
+    let zombies = match zombies() {
+        Ok(zombies) => zombies,
+        Err(e) => {
+            log::warn!("failed to scan for zombie processes: {}", e);
+            return;
+        },
+    };
+
+    for pid in zombies {
+        // Check under the lock, so that a child spawned since the scan with a recycled pid is left to Tokio.
+        let tracked = TRACKED.lock().unwrap();
+        if tracked.contains(&pid) {
+            continue;
+        }
+        let mut status = 0;
+        if unsafe { libc::waitpid(pid as libc::pid_t, &mut status, libc::WNOHANG) } > 0 {
+            log::info!("reaped orphaned process {} (wait status {})", pid, status);
+        }
+    }
+}
+
+/// Periodically reaps orphaned descendants that were reparented to us.
+pub async fn run() {
+    let mut interval = actix_web::rt::time::interval(REAP_INTERVAL);
+    loop {
+        interval.tick().await;
+        tokio::task::spawn_blocking(reap_once).await.unwrap_or_else(|e| log::error!("reaper panicked: {}", e));
+    }
+}
@@ -0,0 +1,214 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use actix_web::web::Bytes;
+use async_std::sync::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+use crate::async_util;
+use crate::backup_store;
+use crate::bandwidth;
+use crate::retry::RetryPolicy;
+use crate::s3::{self, S3Config};
+use crate::ship::HARBOR;
+
+/// How long the worker waits before looking at an empty queue again.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long the worker waits after a failure before trying again, growing with consecutive failures.
+const FAILURE_BACKOFF: RetryPolicy = RetryPolicy {
+    attempts: u32::MAX,
+    initial_backoff: Duration::from_secs(5),
+    max_backoff: Duration::from_secs(5 * 60),
+};
+
+/// A backup to copy from where it was first written to the replica bucket.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Item {
+    /// An export uploaded to the primary bucket, by its name under the bucket's prefix.
+    #[serde(rename_all = "camelCase")]
+    Export { name: String },
+    /// A snapshot in the local backup store. Its chunks are copied first and its manifest last, so that a manifest in
+    /// the replica can always be restored from.
+    #[serde(rename_all = "camelCase")]
+    Snapshot { ship: String, id: String },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Pending {
+    item: Item,
+    #[serde(with = "time::serde::rfc3339")]
+    enqueued_at: OffsetDateTime,
+    attempts: u32,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    pending: VecDeque<Pending>,
+    last_replicated_at: Option<OffsetDateTime>,
+    last_error: Option<String>,
+    replicated: u64,
+    failures: u64,
+}
+
+/// How far the replica is behind.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplicationStatus {
+    /// Whether a replica bucket is configured. Nothing is queued when it isn't.
+    pub configured: bool,
+    pub pending: usize,
+    /// When the oldest backup not yet replicated was queued.
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub oldest_pending_at: Option<OffsetDateTime>,
+    /// How long the oldest backup not yet replicated has been waiting; zero when the replica is caught up.
+    pub lag_secs: u64,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_replicated_at: Option<OffsetDateTime>,
+    /// The most recent failure, cleared by the next success.
+    pub last_error: Option<String>,
+    /// Backups replicated and attempts failed since the orchestrator started.
+    pub replicated_total: u64,
+    pub failures_total: u64,
+}
+
+/// Copies backups to the replica bucket in the background, in the order they were taken. The queue is persisted in
+/// the harbor, so that backups taken shortly before a restart are still replicated after it.
+#[derive(Debug, Default)]
+pub struct Replicator {
+    queue: Mutex<Queue>,
+}
+
+impl Replicator {
+    /// Reloads whatever was still queued when the orchestrator last stopped.
+    pub async fn load(&self) -> Result<()> {
+        let path = HARBOR.replication_queue_path();
+        let pending: VecDeque<Pending> = match async_std::fs::read(&path).await {
+            Ok(json) => serde_json::from_slice(&json)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => return Err(e.into()),
+        };
+        if !pending.is_empty() {
+            log::info!("{} backups are still waiting to be replicated", pending.len());
+        }
+        self.queue.lock().await.pending = pending;
+        Ok(())
+    }
+
+    /// Queues a backup for replication. Does nothing if no replica is configured.
+    pub async fn enqueue(&self, item: Item) -> Result<()> {
+        if s3::S3_REPLICA.is_none() {
+            return Ok(());
+        }
+        let mut queue = self.queue.lock().await;
+        queue.pending.push_back(Pending { item, enqueued_at: OffsetDateTime::now_utc(), attempts: 0 });
+        save(&queue).await
+    }
+
+    pub async fn status(&self) -> ReplicationStatus {
+        let queue = self.queue.lock().await;
+        let oldest_pending_at = queue.pending.iter().map(|pending| pending.enqueued_at).min();
+        let lag_secs = oldest_pending_at
+            .map(|at| (OffsetDateTime::now_utc() - at).whole_seconds().max(0) as u64)
+            .unwrap_or(0);
+        ReplicationStatus {
+            configured: s3::S3_REPLICA.is_some(),
+            pending: queue.pending.len(),
+            oldest_pending_at,
+            lag_secs,
+            last_replicated_at: queue.last_replicated_at,
+            last_error: queue.last_error.clone(),
+            replicated_total: queue.replicated,
+            failures_total: queue.failures,
+        }
+    }
+
+    /// Replicates queued backups one at a time, forever. An item that fails goes to the back of the queue, so that one
+    /// bad backup can't hold up the rest.
+    pub async fn run(self: Arc<Self>, replica: &'static S3Config) {
+        let mut consecutive_failures = 0;
+        loop {
+            let Some(pending) = self.queue.lock().await.pending.front().cloned() else {
+                actix_web::rt::time::sleep(IDLE_POLL_INTERVAL).await;
+                continue;
+            };
+
+            let result = replicate(&pending.item, replica).await;
+            let mut queue = self.queue.lock().await;
+            queue.pending.pop_front();
+            match &result {
+                Ok(()) => {
+                    consecutive_failures = 0;
+                    queue.last_replicated_at = Some(OffsetDateTime::now_utc());
+                    queue.last_error = None;
+                    queue.replicated += 1;
+                },
+                Err(e) => {
+                    consecutive_failures += 1;
+                    log::warn!("failed to replicate {:?} (attempt {}): {:#}", pending.item, pending.attempts + 1, e);
+                    queue.last_error = Some(format!("{:#}", e));
+                    queue.failures += 1;
+                    queue.pending.push_back(Pending { attempts: pending.attempts + 1, ..pending });
+                },
+            }
+            if let Err(e) = save(&queue).await {
+                log::error!("failed to save the replication queue: {:#}", e);
+            }
+            drop(queue);
+
+            if result.is_err() {
+                actix_web::rt::time::sleep(FAILURE_BACKOFF.delay(consecutive_failures)).await;
+            }
+        }
+    }
+}
+
+async fn save(queue: &Queue) -> Result<()> {
+    let path = HARBOR.replication_queue_path();
+    let tmp_path = path.with_extension("json.tmp");
+    async_std::fs::write(&tmp_path, serde_json::to_vec(&queue.pending)?).await?;
+    async_std::fs::rename(&tmp_path, &path).await?;
+    Ok(())
+}
+
+async fn replicate(item: &Item, replica: &S3Config) -> Result<()> {
+    match item {
+        Item::Export { name } => {
+            let primary = s3::S3.as_ref().ok_or_else(|| anyhow!("no primary S3 bucket is configured"))?;
+            let body = async_util::throttle(
+                primary.download_stream(&primary.key(name)).await?,
+                || bandwidth::BACKUP_BANDWIDTH.current_limit(),
+            );
+            replica.upload_stream(&replica.key(name), body, |_| {}).await?;
+        },
+        Item::Snapshot { ship, id } => {
+            let Some((manifest, chunks)) = backup_store::manifest(ship, id).await? else {
+                log::info!("not replicating snapshot {} of ~{}, which has since been pruned", id, ship);
+                return Ok(());
+            };
+            let mut missing = Vec::new();
+            for hash in chunks {
+                if !replica.exists(&replica.key(&backup_store::chunk_object_name(&hash))).await? {
+                    missing.push(hash);
+                }
+            }
+            // One throttled stream across all of the chunks, so that the cap holds for the snapshot as a whole.
+            let bodies = async_util::throttle(
+                stream::iter(missing.clone())
+                    .then(|hash| async move { Ok(Bytes::from(backup_store::read_chunk(&hash).await?)) }),
+                || bandwidth::BACKUP_BANDWIDTH.current_limit(),
+            );
+            futures::pin_mut!(bodies);
+            for hash in missing {
+                let body = bodies.next().await.ok_or_else(|| anyhow!("chunk stream ended early"))??;
+                replica.put_object(&replica.key(&backup_store::chunk_object_name(&hash)), body).await?;
+            }
+            replica.put_object(&replica.key(&backup_store::snapshot_object_name(ship, id)), manifest.into()).await?;
+        },
+    }
+    Ok(())
+}
@@ -0,0 +1,59 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use rand::Rng;
+use std::env;
+use std::time::Duration;
+
+use crate::util::parse_duration;
+
+lazy_static! {
+    /// How calls to a ship's lens are retried. Ships routinely drop the first requests after booting.
+    pub static ref LENS_RETRY: RetryPolicy = RetryPolicy {
+        attempts: env::var_os("NUCLEUS_LENS_RETRY_ATTEMPTS")
+            .map(|s| s.to_str().unwrap().parse().unwrap())
+            .unwrap_or(3),
+        initial_backoff: env::var_os("NUCLEUS_LENS_RETRY_BACKOFF")
+            .map(|s| parse_duration(s.to_str().unwrap()).unwrap())
+            .unwrap_or(Duration::from_millis(250)),
+        max_backoff: Duration::from_secs(5),
+    };
+}
+
+/// How often and how patiently to retry an operation that fails transiently.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Tries in total, including the first; 1 disables retrying.
+    pub attempts: u32,
+    /// Delay before the first retry, doubled for each one after.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// The delay before the given 1-based retry, with jitter: somewhere between half and all of the exponential
+    /// backoff, so that callers that failed together don't all retry together.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.initial_backoff
+            .saturating_mul(1u32.checked_shl(retry.saturating_sub(1)).unwrap_or(u32::MAX))
+            .min(self.max_backoff);
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    /// Runs `op` until it succeeds, fails with an error `retryable` rejects, or runs out of attempts. Returns its last
+    /// result along with how many times it was retried.
+    pub async fn run<T, F, Fut>(&self, mut op: F, retryable: impl Fn(&Error) -> bool) -> (Result<T>, u32)
+        where F: FnMut() -> Fut,
+              Fut: Future<Output = Result<T>>,
+    {
+        let mut retries = 0;
+        loop {
+            match op().await {
+                Err(e) if retries + 1 < self.attempts && retryable(&e) => {
+                    retries += 1;
+                    actix_web::rt::time::sleep(self.delay(retries)).await;
+                },
+                result => return (result, retries),
+            }
+        }
+    }
+}
@@ -4,15 +4,21 @@ use async_std::io;
 use async_std::path::{Path, PathBuf};
 use serde::de::{self, Visitor};
 use sha2::Sha512;
+use std::collections::BTreeMap;
 use std::env;
 use std::fmt::Display;
+use std::process::Stdio;
 use tokio::process;
 
+use crate::confinement;
+use crate::privsep;
+use crate::reaper;
+
 #[cfg(target_arch = "x86_64")]
-const TARGET_ARCH: &'static str = "x86_64";
+const TARGET_ARCH: &str = "x86_64";
 
 #[cfg(target_arch = "aarch64")]
-const TARGET_ARCH: &'static str = "aarch64";
+const TARGET_ARCH: &str = "aarch64";
 
 lazy_static! {
     pub static ref URBIT_BIN_REPO: reqwest::Url = env::var_os("NUCLEUS_URBIT_REPO")
@@ -21,12 +27,18 @@ lazy_static! {
         .join(&format!("{TARGET_ARCH}/")).unwrap();
 
     pub static ref RUNTIME_HOME: PathBuf = env::var_os("NUCLEUS_RUNTIME_HOME")
-        .map(|s| PathBuf::from(s))
+        .map(PathBuf::from)
         .unwrap_or(PathBuf::from("/var/urbits"));
 }
 
+/// Search path given to runtimes.
+const RUNTIME_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// Locale given to runtimes, whatever the orchestrator's own is, so that they always read and write UTF-8.
+const RUNTIME_LOCALE: &str = "C.UTF-8";
+
 pub use Version::*;
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Ord, PartialOrd)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Ord, PartialOrd)]
 pub enum Version {
     UrbitV1_0,
     UrbitV1_1,
@@ -37,6 +49,7 @@ pub enum Version {
     UrbitV1_6,
     UrbitV1_7,
     UrbitV1_8,
+    #[default]
     UrbitV1_9,
 }
 
@@ -215,6 +228,18 @@ impl Version {
         }
     }
 
+    pub fn supports(self, feature: Feature) -> bool {
+        feature.since().is_some_and(|since| self >= since)
+    }
+
+    /// Fails with `UnsupportedFeatureError` unless this version has `feature`.
+    pub fn require(self, feature: Feature) -> Result<()> {
+        if !self.supports(feature) {
+            return Err(UnsupportedFeatureError { feature, version: self }.into());
+        }
+        Ok(())
+    }
+
     pub fn binary_name(self) -> String {
         // format!("urbit-{}", self)
         "urbit-{}".to_owned()
@@ -250,65 +275,227 @@ impl Version {
             reqwest::get(URBIT_BIN_REPO.join(&self.binary_name())?).await?
                 .bytes_stream()
                 .into_checksum_verify::<Sha512>(self.binary_checksum().into())
-                .map_err(|e| futures::io::Error::new(futures::io::ErrorKind::Other, e))
+                .map_err(futures::io::Error::other)
                 .into_async_read()
         )
     }
 
     fn translate_options(self, cmd: &mut process::Command, options: &Options<'_>) -> Result<()> {
-        match options.new_pier {
-            Some(path) => { cmd.arg("--pier").arg(path); },
-            _ => {},
+        if let Some(path) = options.new_pier {
+            cmd.arg("--pier").arg(path);
+        }
+        if let Some(path) = options.keyfile {
+            cmd.arg("--key-file").arg(path);
+        }
+        if let Some(name) = options.name {
+            cmd.arg("--name").arg(name);
+        }
+        if let Some(name) = options.fake {
+            cmd.arg("--fake").arg(name);
         }
-        match options.keyfile {
-            Some(path) => { cmd.arg("--key-file").arg(path); },
-            _ => {},
+        if let Some(port) = options.ames_port {
+            cmd.arg("--ames-port").arg(port.to_string());
         }
-        match options.name {
-            Some(name) => { cmd.arg("--name").arg(name); },
-            _ => {},
+        if let Some(port) = options.http_port {
+            cmd.arg("--http-port").arg(port.to_string());
         }
-        match options.ames_port {
-            Some(port) => { cmd.arg("--ames-port").arg(port.to_string()); },
-            _ => {},
+        if options.dock == Some(false) {
+            cmd.arg("--no-dock");
         }
-        match options.http_port {
-            Some(port) => { cmd.arg("--http-port").arg(port.to_string()); },
-            _ => {},
+        if options.tty == Some(false) {
+            cmd.arg("--no-tty");
         }
-        match options.dock {
-            Some(false) => { cmd.arg("--no-dock"); },
-            _ => {},
+        if options.local == Some(true) {
+            cmd.arg("--local");
         }
-        match options.tty {
-            Some(false) => { cmd.arg("--no-tty"); },
-            _ => {},
+        if let Some(flags) = options.flags {
+            if let Some(bits) = flags.loom_bits {
+                self.require(Feature::LoomSize)?;
+                cmd.arg("--loom").arg(bits.to_string());
+            }
+            if flags.conn_socket {
+                self.require(Feature::ConnSocket)?;
+            }
+            if let Some(secs) = flags.snapshot_interval_secs {
+                cmd.arg("--snap-time").arg(secs.to_string());
+            }
+            match flags.verbosity {
+                Verbosity::Quiet => { cmd.arg("--quiet"); },
+                Verbosity::Normal => {},
+                Verbosity::Verbose => { cmd.arg("--verbose"); },
+            }
+            cmd.args(&flags.extra_args);
         }
-        match options.existing_pier {
-            Some(path) => { cmd.arg(path); },
-            _ => {},
+        if let Some(path) = options.existing_pier {
+            cmd.arg(path);
         }
 
         Ok(())
     }
 
-    pub async fn exec(self, options: &Options<'_>) -> Result<process::Child> {
+    /// A command for this runtime, spawned in its own process group so that it and the serf workers it forks can be
+    /// signalled together, optionally as an unprivileged user, and under `SHIP_CONFINEMENT` if configured. The runtime
+    /// gets a fixed environment plus `env` rather than the orchestrator's, which may hold credentials. With a
+    /// `scratch_dir`, it runs there and keeps its temporary files there.
+    async fn command(
+        self,
+        run_as: Option<u32>,
+        env: &BTreeMap<String, String>,
+        scratch_dir: Option<&Path>,
+    ) -> Result<process::Command> {
         self.ensure_installed().await?;
 
+        let exec_attr = confinement::SHIP_CONFINEMENT.as_ref().map(|c| c.exec_attr());
         let mut cmd = process::Command::new(self.binary_path());
-        self.translate_options(&mut cmd, options)?;
         cmd.kill_on_drop(true);
+        cmd.env_clear()
+            .env("PATH", RUNTIME_PATH)
+            .env("LANG", RUNTIME_LOCALE)
+            .env("LC_ALL", RUNTIME_LOCALE)
+            .envs(env);
+        if let Some(dir) = scratch_dir {
+            cmd.current_dir(dir).env("TMPDIR", dir);
+        }
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::setpgid(0, 0) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                // Before dropping privileges, which can cost us write access to our own /proc entries.
+                if let Some(attr) = &exec_attr {
+                    confinement::set_exec_attr(attr)?;
+                }
+                if let Some(uid) = run_as {
+                    privsep::drop_privileges(uid)?;
+                }
+                Ok(())
+            });
+        }
+        Ok(cmd)
+    }
+
+    /// Spawns the runtime with its output piped, for the caller to log.
+    pub async fn exec(self, options: &Options<'_>) -> Result<process::Child> {
+        let mut cmd = self.command(options.run_as, options.env.unwrap_or(&BTreeMap::new()), options.scratch_dir).await?;
+        self.translate_options(&mut cmd, options)?;
+        cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+
+        Ok(reaper::spawn(&mut cmd)?)
+    }
+
+    /// Runs an offline maintenance subcommand against a stopped pier and waits for it to finish, returning its combined
+    /// output.
+    pub async fn run_subcommand(
+        self,
+        subcommand: Subcommand,
+        pier: &Path,
+        run_as: Option<u32>,
+        env: &BTreeMap<String, String>,
+        scratch_dir: &Path,
+    ) -> Result<String> {
+        if let Some(feature) = subcommand.feature() {
+            self.require(feature)?;
+        }
+        let mut cmd = self.command(run_as, env, Some(scratch_dir)).await?;
+        cmd.arg(subcommand.name()).arg(pier).stdin(Stdio::null());
+
+        let output = reaper::output(&mut cmd).await?;
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        if !output.status.success() {
+            bail!("urbit {} exited with {}: {}", subcommand.name(), output.status, text.trim());
+        }
+        Ok(text)
+    }
+}
+
+/// Maintenance operations the runtime can perform on a pier without booting it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subcommand {
+    /// Defragment the loom.
+    Pack,
+    /// Deduplicate nouns in the snapshot.
+    Meld,
+    /// Delete event log segments already covered by the snapshot.
+    Chop,
+    /// Start a new event log epoch, so that everything before it can be chopped.
+    Roll,
+}
 
-        Ok(cmd.spawn()?)
+impl Subcommand {
+    pub fn name(self) -> &'static str {
+        match self {
+            Subcommand::Pack => "pack",
+            Subcommand::Meld => "meld",
+            Subcommand::Chop => "chop",
+            Subcommand::Roll => "roll",
+        }
+    }
+
+    /// The feature the runtime needs to run this subcommand, if not every supported runtime has it.
+    fn feature(self) -> Option<Feature> {
+        match self {
+            Subcommand::Pack | Subcommand::Meld => None,
+            Subcommand::Chop => Some(Feature::Chop),
+            Subcommand::Roll => Some(Feature::Roll),
+        }
     }
 }
 
-impl Default for Version {
-    fn default() -> Self {
-        UrbitV1_9
+/// What only some runtime versions can do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    /// The `chop` subcommand.
+    Chop,
+    /// The `roll` subcommand, which needs event log epochs.
+    Roll,
+    /// `--loom`, for a loom of other than the fixed 2 GiB.
+    LoomSize,
+    /// The `.urb/conn.sock` control channel, which can stand in for the loopback lens.
+    ConnSocket,
+}
+
+impl Feature {
+    /// The first runtime version with the feature, or None if it came after every version supported here.
+    fn since(self) -> Option<Version> {
+        match self {
+            Feature::Chop => Some(UrbitV1_9),
+            Feature::Roll | Feature::LoomSize | Feature::ConnSocket => None,
+        }
     }
 }
 
+impl Display for Feature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Feature::Chop => f.write_str("urbit chop"),
+            Feature::Roll => f.write_str("urbit roll"),
+            Feature::LoomSize => f.write_str("--loom"),
+            Feature::ConnSocket => f.write_str("conn.sock"),
+        }
+    }
+}
+
+/// The pier's runtime version lacks a feature it was asked to use.
+#[derive(Debug)]
+pub struct UnsupportedFeatureError {
+    pub feature: Feature,
+    pub version: Version,
+}
+
+impl Display for UnsupportedFeatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.feature.since() {
+            Some(since) => {
+                write!(f, "{} needs urbit {} or later, but the pier runs {}", self.feature, since, self.version)
+            },
+            None => write!(f, "{} isn't supported by any runtime version available here", self.feature),
+        }
+    }
+}
+
+impl StdError for UnsupportedFeatureError {}
+
 impl TryFrom<f32> for Version {
     type Error = anyhow::Error;
 
@@ -355,9 +542,9 @@ impl TryFrom<&str> for Version {
     }
 }
 
-impl Into<String> for Version {
-    fn into(self) -> String {
-        match self {
+impl From<Version> for String {
+    fn from(version: Version) -> Self {
+        match version {
             UrbitV1_0 => "v1.1".to_owned(),
             UrbitV1_1 => "v1.1".to_owned(),
             UrbitV1_2 => "v1.2".to_owned(),
@@ -372,9 +559,9 @@ impl Into<String> for Version {
     }
 }
 
-impl Into<f32> for Version {
-    fn into(self) -> f32 {
-        match self {
+impl From<Version> for f32 {
+    fn from(version: Version) -> Self {
+        match version {
             UrbitV1_0 => 1.1,
             UrbitV1_1 => 1.1,
             UrbitV1_2 => 1.2,
@@ -389,9 +576,9 @@ impl Into<f32> for Version {
     }
 }
 
-impl Into<f64> for Version {
-    fn into(self) -> f64 {
-        let result: f32 = self.into();
+impl From<Version> for f64 {
+    fn from(version: Version) -> Self {
+        let result: f32 = version.into();
         result as f64
     }
 }
@@ -448,43 +635,155 @@ impl<'de> Deserialize<'de> for Version {
     }
 }
 
+/// Per-pier tuning of the runtime, passed on the command line whenever it is launched.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeFlags {
+    /// Log2 of the loom size in bytes, e.g. 32 for 4 GiB or 33 for 8 GiB, which big ships need. The runtime's default
+    /// is 31. Only runtimes with `Feature::LoomSize` accept it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loom_bits: Option<u8>,
+    /// Seconds between snapshots, for the runtime's default when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot_interval_secs: Option<u32>,
+    #[serde(default)]
+    pub verbosity: Verbosity,
+    /// Whether to make lens calls over the runtime's unix-socket control channel rather than loopback TCP, so that
+    /// other local users can't reach the ship's dojo. Only runtimes with `Feature::ConnSocket` accept it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub conn_socket: bool,
+    /// Further arguments passed as they are, before the pier path. Flags that the orchestrator sets itself are
+    /// rejected.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_args: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Verbosity {
+    Quiet,
+    #[default]
+    Normal,
+    Verbose,
+}
+
+/// The loom sizes runtimes accept, from 16 MiB to 8 GiB.
+const LOOM_BITS: std::ops::RangeInclusive<u8> = 24..=33;
+
+/// Flags that the orchestrator sets itself, or that would change how the runtime is supervised, which can't be
+/// passed through `RuntimeFlags::extra_args`.
+const MANAGED_FLAGS: &[&str] = &[
+    "-c", "--pier", "-k", "--key-file", "-w", "--name", "-F", "--fake", "-p", "--ames-port", "--http-port",
+    "--no-dock", "-t", "--no-tty", "-L", "--local", "--loom", "--snap-time", "-v", "--verbose", "-q", "--quiet",
+    "-d", "--daemon",
+];
+
+impl RuntimeFlags {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(bits) = self.loom_bits {
+            if !LOOM_BITS.contains(&bits) {
+                bail!("loom bits must be between {} and {}", LOOM_BITS.start(), LOOM_BITS.end());
+            }
+        }
+        if self.snapshot_interval_secs == Some(0) {
+            bail!("snapshot interval must be positive");
+        }
+        for arg in &self.extra_args {
+            let flag = arg.split_once('=').map_or(arg.as_str(), |(flag, _)| flag);
+            if MANAGED_FLAGS.contains(&flag) {
+                bail!("{} is set by the orchestrator and can't be passed through", flag);
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Default, Debug, Eq, Hash, PartialEq)]
 pub struct Options<'a> {
     new_pier: Option<&'a Path>,
     keyfile: Option<&'a Path>,
+    fake: Option<&'a str>,
     name: Option<&'a str>,
     ames_port: Option<u16>,
     http_port: Option<u16>,
     dock: Option<bool>,
     tty: Option<bool>,
+    local: Option<bool>,
     existing_pier: Option<&'a Path>,
+    run_as: Option<u32>,
+    env: Option<&'a BTreeMap<String, String>>,
+    scratch_dir: Option<&'a Path>,
+    flags: Option<&'a RuntimeFlags>,
 }
 
 impl<'a> Options<'a> {
     pub fn launch_existing_pier(pier: &'a Path) -> Self {
-        let mut result = Options::default();
-        result.existing_pier = Some(pier);
-        result.tty = Some(false);
-        result.dock = Some(false);
-        result
+        Options {
+            existing_pier: Some(pier),
+            tty: Some(false),
+            dock: Some(false),
+            ..Options::default()
+        }
     }
 
     pub fn launch_from_keyfile(keyfile: &'a Path, name: &'a str, pier: &'a Path) -> Self {
-        let mut result = Options::default();
-        result.new_pier = Some(pier);
-        result.keyfile = Some(keyfile);
-        result.name = Some(name);
-        result.tty = Some(false);
-        result.dock = Some(false);
-        result
+        Options {
+            new_pier: Some(pier),
+            keyfile: Some(keyfile),
+            name: Some(name),
+            tty: Some(false),
+            dock: Some(false),
+            ..Options::default()
+        }
     }
 
     pub fn launch_new_comet(pier: &'a Path) -> Self {
-        let mut result = Options::default();
-        result.new_pier = Some(pier);
-        result.tty = Some(false);
-        result.dock = Some(false);
-        result
+        Options {
+            new_pier: Some(pier),
+            tty: Some(false),
+            dock: Some(false),
+            ..Options::default()
+        }
+    }
+
+    /// Boots a new fake ship, which has no keys and only talks to other fake ships on the same host. `name` is the
+    /// ship's @p without the sig.
+    pub fn launch_fake(name: &'a str, pier: &'a Path) -> Self {
+        Options {
+            new_pier: Some(pier),
+            fake: Some(name),
+            tty: Some(false),
+            dock: Some(false),
+            ..Options::default()
+        }
+    }
+
+    /// Runs the runtime as the given uid and the gid of the same number, instead of as the orchestrator's user.
+    pub fn run_as(&mut self, uid: Option<u32>) -> &mut Self {
+        self.run_as = uid;
+        self
+    }
+
+    /// Extra environment variables for the runtime, on top of the fixed ones every runtime gets.
+    pub fn env(&mut self, env: &'a BTreeMap<String, String>) -> &mut Self {
+        self.env = Some(env);
+        self
+    }
+
+    /// Working directory and TMPDIR for the runtime.
+    pub fn scratch_dir(&mut self, dir: &'a Path) -> &mut Self {
+        self.scratch_dir = Some(dir);
+        self
+    }
+
+    /// Keeps ames traffic on the host, so that the ship neither reaches nor is reached by the network.
+    pub fn local_networking(&mut self, local: bool) -> &mut Self {
+        self.local = Some(local);
+        self
     }
 
     pub fn ames_port(&mut self, p: u16) -> &mut Self {
@@ -496,4 +795,9 @@ impl<'a> Options<'a> {
         self.http_port = Some(p);
         self
     }
+
+    pub fn flags(&mut self, flags: &'a RuntimeFlags) -> &mut Self {
+        self.flags = Some(flags);
+        self
+    }
 }
\ No newline at end of file
@@ -0,0 +1,285 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use actix_web::web::Bytes;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use sha2::{Digest, Sha256};
+use std::env;
+use time::OffsetDateTime;
+
+use crate::util::parse_size;
+
+lazy_static! {
+    /// Where server-side exports are uploaded, configured by `NUCLEUS_S3_*`. None unless a bucket is set.
+    pub static ref S3: Option<S3Config> = S3Config::from_env("NUCLEUS_S3");
+
+    /// A second bucket, typically in another region or with another provider, that exports and backup store snapshots
+    /// are replicated to for disaster recovery. Configured by `NUCLEUS_S3_REPLICA_*` just as the primary is configured
+    /// by `NUCLEUS_S3_*`. None unless a bucket is set.
+    pub static ref S3_REPLICA: Option<S3Config> = S3Config::from_env("NUCLEUS_S3_REPLICA");
+}
+
+/// S3 requires every part but the last to be at least this big.
+const MIN_PART_SIZE: usize = 5 << 20;
+
+/// Connection details for an S3-compatible bucket. Objects are addressed path-style (`endpoint/bucket/key`), which
+/// every S3-compatible store supports.
+pub struct S3Config {
+    pub endpoint: reqwest::Url,
+    pub region: String,
+    pub bucket: String,
+    /// Prepended to every object key, e.g. `backups/`.
+    pub key_prefix: String,
+    access_key_id: String,
+    secret_access_key: String,
+    /// Size of each uploaded part. Uploads are limited to 10,000 parts, so this bounds the largest export.
+    part_size: usize,
+}
+
+impl std::fmt::Debug for S3Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Config")
+            .field("endpoint", &self.endpoint.as_str())
+            .field("region", &self.region)
+            .field("bucket", &self.bucket)
+            .field("key_prefix", &self.key_prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(data)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters, and `/` too if `keep_slash`, as SigV4 requires.
+fn uri_encode(s: &str, keep_slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            b'/' if keep_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+impl S3Config {
+    /// Reads a bucket's configuration from `<prefix>_BUCKET`, `<prefix>_REGION` and so on. None unless the bucket is
+    /// set.
+    fn from_env(prefix: &str) -> Option<S3Config> {
+        let var = |name: &str| env::var_os(format!("{}_{}", prefix, name)).map(|s| s.to_str().unwrap().to_owned());
+        let bucket = var("BUCKET")?;
+        Some(S3Config {
+            endpoint: var("ENDPOINT")
+                .map(|s| s.parse::<reqwest::Url>().unwrap())
+                .unwrap_or_else(|| "https://s3.amazonaws.com".parse::<reqwest::Url>().unwrap()),
+            region: var("REGION").unwrap_or("us-east-1".to_owned()),
+            bucket,
+            key_prefix: var("KEY_PREFIX").unwrap_or_default(),
+            access_key_id: var("ACCESS_KEY_ID")
+                .unwrap_or_else(|| panic!("{}_ACCESS_KEY_ID must be set along with {}_BUCKET", prefix, prefix)),
+            secret_access_key: var("SECRET_ACCESS_KEY")
+                .unwrap_or_else(|| panic!("{}_SECRET_ACCESS_KEY must be set along with {}_BUCKET", prefix, prefix)),
+            part_size: var("PART_SIZE")
+                .map(|s| parse_size(&s).unwrap() as usize)
+                .unwrap_or(64 << 20),
+        })
+    }
+
+    /// The full key of the object named `name` under the configured prefix.
+    pub fn key(&self, name: &str) -> String {
+        format!("{}{}", self.key_prefix, name)
+    }
+
+    fn object_path(&self, key: &str) -> String {
+        let base = self.endpoint.path().trim_end_matches('/');
+        format!("{}/{}/{}", base, uri_encode(&self.bucket, false), uri_encode(key, true))
+    }
+
+    /// Builds a request signed with AWS Signature Version 4.
+    fn request(
+        &self,
+        client: &reqwest::Client,
+        method: reqwest::Method,
+        key: &str,
+        query: &[(&str, &str)],
+        body: Bytes,
+    ) -> Result<reqwest::RequestBuilder> {
+        let now = OffsetDateTime::now_utc();
+        let date = format!("{:04}{:02}{:02}", now.year(), now.month() as u8, now.day());
+        let amz_date = format!("{}T{:02}{:02}{:02}Z", date, now.hour(), now.minute(), now.second());
+        let payload_hash = hex(&Sha256::digest(&body));
+
+        let host = match self.endpoint.port() {
+            Some(port) => format!("{}:{}", self.endpoint.host_str().unwrap_or_default(), port),
+            None => self.endpoint.host_str().unwrap_or_default().to_owned(),
+        };
+        let path = self.object_path(key);
+        let mut query: Vec<(String, String)> = query.iter()
+            .map(|(k, v)| (uri_encode(k, false), uri_encode(v, false)))
+            .collect();
+        query.sort();
+        let query = query.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&");
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            method, path, query, host, payload_hash, amz_date, payload_hash,
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date, scope, hex(&Sha256::digest(canonical_request.as_bytes())),
+        );
+
+        let mut signing_key = hmac(format!("AWS4{}", self.secret_access_key).as_bytes(), date.as_bytes())?;
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            signing_key = hmac(&signing_key, part.as_bytes())?;
+        }
+        let signature = hex(&hmac(&signing_key, string_to_sign.as_bytes())?);
+
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        url.set_query(if query.is_empty() { None } else { Some(&query) });
+
+        Ok(client.request(method, url)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("authorization", format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+                self.access_key_id, scope, signature,
+            ))
+            .body(body))
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            bail!("S3 request failed with {}: {}", status, body.trim());
+        }
+        Ok(response)
+    }
+
+    /// Uploads a small object in a single request.
+    pub async fn put_object(&self, key: &str, body: Bytes) -> Result<()> {
+        let client = reqwest::Client::new();
+        self.send(self.request(&client, reqwest::Method::PUT, key, &[], body)?).await?;
+        Ok(())
+    }
+
+    /// Whether an object exists at `key`.
+    pub async fn exists(&self, key: &str) -> Result<bool> {
+        let client = reqwest::Client::new();
+        let response = self.request(&client, reqwest::Method::HEAD, key, &[], Bytes::new())?.send().await?;
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            reqwest::StatusCode::NOT_FOUND => Ok(false),
+            status => bail!("S3 request failed with {}", status),
+        }
+    }
+
+    /// Downloads the object at `key` as a stream of its bytes.
+    pub async fn download_stream(&self, key: &str) -> Result<impl Stream<Item = Result<Bytes>>> {
+        let client = reqwest::Client::new();
+        let response = self.send(self.request(&client, reqwest::Method::GET, key, &[], Bytes::new())?).await?;
+        Ok(response.bytes_stream().map_err(Error::from))
+    }
+
+    /// Uploads everything `body` yields to `key` with a multipart upload, holding one part in memory at a time.
+    /// Returns the number of bytes uploaded. On failure the upload is aborted, so no partial object is left behind.
+    pub async fn upload_stream<S>(&self, key: &str, body: S, on_progress: impl Fn(u64)) -> Result<u64>
+        where S: Stream<Item = Result<Bytes>>
+    {
+        let client = reqwest::Client::new();
+        let created = self.send(self.request(&client, reqwest::Method::POST, key, &[("uploads", "")], Bytes::new())?)
+            .await?
+            .text()
+            .await?;
+        let upload_id = xml_element(&created, "UploadId")
+            .ok_or_else(|| anyhow!("S3 did not return an upload id: {}", created))?
+            .to_owned();
+
+        let uploaded = self.upload_parts(&client, key, &upload_id, body, on_progress).await;
+        if uploaded.is_err() {
+            let abort = self.request(&client, reqwest::Method::DELETE, key, &[("uploadId", &upload_id)], Bytes::new());
+            if let Err(e) = async { self.send(abort?).await }.await {
+                log::warn!("failed to abort S3 upload of {}: {}", key, e);
+            }
+        }
+        uploaded
+    }
+
+    async fn upload_parts<S>(
+        &self,
+        client: &reqwest::Client,
+        key: &str,
+        upload_id: &str,
+        body: S,
+        on_progress: impl Fn(u64),
+    ) -> Result<u64>
+        where S: Stream<Item = Result<Bytes>>
+    {
+        futures::pin_mut!(body);
+        let part_size = self.part_size.max(MIN_PART_SIZE);
+        let mut etags = Vec::new();
+        let mut total = 0;
+        let mut buf = Vec::with_capacity(part_size);
+        let mut done = false;
+
+        while !done {
+            match body.next().await {
+                Some(chunk) => buf.extend_from_slice(&chunk?),
+                None => done = true,
+            }
+            // An empty body still needs one (empty) part to complete the upload.
+            if buf.len() < part_size && !(done && (!buf.is_empty() || etags.is_empty())) {
+                continue;
+            }
+
+            let part = Bytes::from(std::mem::replace(&mut buf, Vec::with_capacity(part_size)));
+            let part_number = (etags.len() + 1).to_string();
+            let len = part.len() as u64;
+            let query = [("partNumber", part_number.as_str()), ("uploadId", upload_id)];
+            let response = self.send(self.request(client, reqwest::Method::PUT, key, &query, part)?).await?;
+            let etag = response.headers().get("etag")
+                .and_then(|etag| etag.to_str().ok())
+                .ok_or_else(|| anyhow!("S3 did not return an ETag for part {}", part_number))?
+                .to_owned();
+            etags.push(etag);
+            total += len;
+            on_progress(total);
+        }
+
+        let mut complete = String::from("<CompleteMultipartUpload>");
+        for (idx, etag) in etags.iter().enumerate() {
+            complete.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", idx + 1, etag));
+        }
+        complete.push_str("</CompleteMultipartUpload>");
+        let response = self.send(self.request(
+            client, reqwest::Method::POST, key, &[("uploadId", upload_id)], Bytes::from(complete),
+        )?).await?.text().await?;
+        // CompleteMultipartUpload can fail after a 200 status, with the error in the body.
+        if response.contains("<Error>") {
+            bail!("S3 failed to complete the upload: {}", response);
+        }
+
+        Ok(total)
+    }
+}
+
+/// The text of the first `<name>` element in a small XML document.
+fn xml_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let len = xml[start..].find(&format!("</{}>", name))?;
+    Some(&xml[start..start + len])
+}
@@ -0,0 +1,100 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use async_std::fs;
+use async_std::os::unix::fs::OpenOptionsExt;
+use async_std::path::PathBuf;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rand::rand_bytes;
+use openssl::sign::Signer;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use std::env;
+
+use crate::ship::HARBOR;
+
+lazy_static! {
+    /// File holding the 256-bit key used to seal sensitive data at rest. It is generated with mode 0600 on first use if
+    /// it doesn't exist.
+    pub static ref SEAL_KEY_FILE: PathBuf = env::var_os("NUCLEUS_SEAL_KEY_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| HARBOR.as_path().join("seal.key"));
+
+    static ref KEY: async_std::sync::Mutex<Option<[u8; KEY_LEN]>> = async_std::sync::Mutex::new(None);
+}
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+async fn key() -> Result<[u8; KEY_LEN]> {
+    let mut cached = KEY.lock().await;
+    if let Some(key) = *cached {
+        return Ok(key);
+    }
+
+    let key = match fs::read(&*SEAL_KEY_FILE).await {
+        Ok(bytes) => bytes.try_into()
+            .map_err(|_| anyhow!("seal key file {} must hold exactly {} bytes", SEAL_KEY_FILE.to_string_lossy(), KEY_LEN))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let mut key = [0; KEY_LEN];
+            rand_bytes(&mut key)?;
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&*SEAL_KEY_FILE)
+                .await?;
+            file.write_all(&key).await?;
+            file.sync_all().await?;
+            log::info!("generated new seal key at {}", SEAL_KEY_FILE.to_string_lossy());
+            key
+        },
+        Err(e) => return Err(e.into()),
+    };
+
+    *cached = Some(key);
+    Ok(key)
+}
+
+/// Seals with a fixed key rather than one from `SEAL_KEY_FILE`, so that tests don't touch the disk.
+#[cfg(test)]
+pub async fn use_test_key() {
+    *KEY.lock().await = Some([7; KEY_LEN]);
+}
+
+/// Encrypts and authenticates `plaintext` with AES-256-GCM. The output is the nonce, then the tag, then the ciphertext.
+pub async fn seal(plaintext: &[u8]) -> Result<Vec<u8>> {
+    let key = key().await?;
+    let mut nonce = [0; NONCE_LEN];
+    rand_bytes(&mut nonce)?;
+    let mut tag = [0; TAG_LEN];
+    let ciphertext = encrypt_aead(Cipher::aes_256_gcm(), &key, Some(&nonce), &[], plaintext, &mut tag)?;
+
+    let mut result = Vec::with_capacity(NONCE_LEN + TAG_LEN + ciphertext.len());
+    result.extend_from_slice(&nonce);
+    result.extend_from_slice(&tag);
+    result.extend_from_slice(&ciphertext);
+    Ok(result)
+}
+
+/// Reverses `seal`, failing if the data was tampered with or sealed under a different key.
+pub async fn unseal(sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        bail!("sealed data is truncated");
+    }
+    let key = key().await?;
+    let (nonce, rest) = sealed.split_at(NONCE_LEN);
+    let (tag, ciphertext) = rest.split_at(TAG_LEN);
+
+    decrypt_aead(Cipher::aes_256_gcm(), &key, Some(nonce), &[], ciphertext, tag)
+        .map_err(|_| anyhow!("sealed data failed authentication"))
+}
+
+/// A key for `purpose` derived from the seal key, so that other uses of a secret key, such as signing links, needn't
+/// each keep a key of their own.
+pub async fn derive_key(purpose: &str) -> Result<Vec<u8>> {
+    let key = PKey::hmac(&key().await?)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(purpose.as_bytes())?;
+    Ok(signer.sign_to_vec()?)
+}
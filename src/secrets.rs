@@ -0,0 +1,63 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::collections::BTreeMap;
+
+/// Placeholder in a secret's command that is replaced with the secret's value, quoted as a hoon cord.
+pub const VALUE_PLACEHOLDER: &str = "{value}";
+
+/// A value handed to the ship after every boot by running a dojo command, e.g. an API key poked into an installed
+/// agent with `:agent &agent-action [%set-key {value}]`. Stored sealed in the pier's metadata directory.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Secret {
+    pub command: String,
+    pub value: String,
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Secret").field("command", &self.command).finish_non_exhaustive()
+    }
+}
+
+impl Secret {
+    pub fn validate(&self) -> Result<()> {
+        if !self.command.contains(VALUE_PLACEHOLDER) {
+            bail!("command must contain {}", VALUE_PLACEHOLDER);
+        }
+        if self.command.trim_start().starts_with(|c| c != ':' && c != '|') {
+            bail!("command must be a poke (:) or hood generator (|)");
+        }
+        Ok(())
+    }
+
+    /// The dojo command handing the value to the ship.
+    pub fn render(&self) -> String {
+        self.command.replace(VALUE_PLACEHOLDER, &hoon_cord(&self.value))
+    }
+}
+
+/// Secrets by name, in a stable order so that they are injected predictably.
+pub type Secrets = BTreeMap<String, Secret>;
+
+/// Whether `name` can name a secret: lowercase letters, digits and hyphens, like a hoon term.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 64
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Quotes `value` as a hoon cord literal.
+fn hoon_cord(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('\'');
+    for c in value.chars() {
+        match c {
+            '\'' | '\\' => { out.push('\\'); out.push(c); },
+            c if (c as u32) < 0x20 || c as u32 == 0x7f => out.push_str(&format!("\\{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+    out
+}
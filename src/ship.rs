@@ -1,35 +1,105 @@
 #[allow(unused_imports)] use crate::prelude::*;
 
+use actix_web::web::Bytes;
+use futures::channel::{mpsc, oneshot};
+use futures::future::{BoxFuture, Shared};
+use futures::stream::BoxStream;
 use async_std::fs;
 use async_std::io;
 use async_std::path::{Path, PathBuf};
+use async_std::sync::Mutex as AsyncMutex;
 use libarchive::archive::ExtractOption;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::env;
-use std::error::Error as StdError;
-use std::fmt::Display;
 use std::ops::Range;
+use std::os::unix::fs::FileTypeExt;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::OffsetDateTime;
 use tokio::process;
 
 use crate::archive;
+use crate::backup_store;
+use crate::clock;
+use crate::error::{PierLockedError, PierNotFoundError, ShipPausedError};
+use crate::expiry::Expiry;
+use crate::eyre;
 use crate::filelock::FileLock;
-use crate::net_util::TcpPortIssuer;
-use crate::runtime;
-
-pub use harbor_private::{HARBOR, Harbor, HarborBuf};
+use crate::import;
+use crate::keyfile;
+use crate::net_util::{self, PortIssuer};
+use crate::ownership;
+use crate::patp;
+use crate::plans::Plan;
+use crate::privsep;
+use crate::queries;
+use crate::reaper;
+use crate::storage::STORAGE;
+use crate::retry;
+use crate::runtime::{self, RuntimeFlags};
+use crate::seal;
+use crate::secrets;
+use crate::ship_events::ShipEvent;
+use crate::shiplog;
+use crate::supervisor::RestartPolicy;
+
+pub use harbor_private::{HARBOR, PORT_LAYOUT};
 
 mod harbor_private {
     #[allow(unused_imports)] use crate::prelude::*;
 
     use async_std::fs::DirEntry;
+    use sha2::{Digest, Sha256};
     use std::borrow::Borrow;
     use std::env;
     use std::io;
     use std::ops::Deref;
     use async_std::path::{Path, PathBuf};
 
+    use crate::filelock::FileLock;
+    use crate::storage::STORAGE;
+
     lazy_static! {
         pub static ref HARBOR: HarborBuf = HarborBuf::default();
+
+        /// How piers are laid out in the harbor's port. Piers laid out otherwise are moved on startup.
+        pub static ref PORT_LAYOUT: PortLayout = env::var_os("NUCLEUS_PORT_LAYOUT")
+            .map(|s| s.to_str().unwrap().parse().unwrap())
+            .unwrap_or(PortLayout::Flat);
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum PortLayout {
+        /// `port/sampel-palnet`
+        Flat,
+        /// `port/3f/sampel-palnet`, sharded by the first byte of the SHA-256 of the name, in hex. Keeps directories
+        /// small with thousands of piers, for filesystems that slow down with large directories.
+        Sharded,
+    }
+
+    impl std::str::FromStr for PortLayout {
+        type Err = Error;
+
+        fn from_str(s: &str) -> Result<Self> {
+            match s {
+                "flat" => Ok(PortLayout::Flat),
+                "sharded" => Ok(PortLayout::Sharded),
+                _ => bail!("unknown port layout {:?}; expected flat or sharded", s),
+            }
+        }
+    }
+
+    /// The shard directory a pier goes in under the sharded layout.
+    fn shard(name: &str) -> String {
+        format!("{:02x}", Sha256::digest(name.as_bytes())[0])
+    }
+
+    /// Whether an entry of the port is a shard directory rather than a pier. No @p is two characters long.
+    fn is_shard(entry: &str) -> bool {
+        entry.len() == 2 && entry.chars().all(|c| c.is_ascii_hexdigit())
     }
 
     #[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -53,6 +123,17 @@ mod harbor_private {
             Ok(result)
         }
 
+        /// Where the pier `name` is kept in port under `PORT_LAYOUT`, whether or not it is there. Computed from the
+        /// name alone, so that finding a pier never means searching the port.
+        pub async fn pier_path_in_port(&self, name: &str) -> Result<PathBuf> {
+            let mut result = self.port_path().await?;
+            if *PORT_LAYOUT == PortLayout::Sharded {
+                result.push(shard(name));
+            }
+            result.push(name);
+            Ok(result)
+        }
+
         pub async fn dry_dock_path(&self) -> Result<PathBuf> {
             let mut result = self.0.to_owned();
             result.push(Path::new("dry_dock"));
@@ -64,34 +145,157 @@ mod harbor_private {
             Ok(result)
         }
 
+        /// Where finished export artifacts are kept until they are downloaded. Created on demand.
+        pub async fn exports_path(&self) -> Result<PathBuf> {
+            let result = self.0.join("exports");
+            async_std::fs::create_dir_all(&result).await?;
+            Ok(result)
+        }
+
+        /// Where the deduplicated backup store keeps its chunks and snapshots. Created on demand.
+        pub async fn backups_path(&self) -> Result<PathBuf> {
+            let result = self.0.join("backups");
+            async_std::fs::create_dir_all(&result).await?;
+            Ok(result)
+        }
+
+        /// Where backups waiting to be copied to the replica bucket are queued, so that none are forgotten across
+        /// restarts.
+        pub fn replication_queue_path(&self) -> PathBuf {
+            self.0.join("replication.json")
+        }
+
+        /// Where piers that are no longer managed, such as expired trials, are moved rather than deleted outright.
+        /// Created on demand.
+        pub async fn trash_path(&self) -> Result<PathBuf> {
+            let result = self.0.join("trash");
+            async_std::fs::create_dir_all(&result).await?;
+            Ok(result)
+        }
+
+        /// Where what the orchestrator does to piers on its own is recorded.
+        pub fn audit_log_path(&self) -> PathBuf {
+            self.0.join("audit.jsonl")
+        }
+
+        /// Where the plans piers can be put on are defined.
+        pub fn plans_path(&self) -> PathBuf {
+            self.0.join("plans.json")
+        }
+
+        /// The harbor's own directory, e.g. to keep piers being adopted from overlapping it.
+        pub fn root(&self) -> &Path {
+            &self.0
+        }
+
+        /// Where in-progress uploads are spooled before being handed to a pier constructor. Created on demand.
+        pub async fn uploads_path(&self) -> Result<PathBuf> {
+            let result = self.0.join("uploads");
+            async_std::fs::create_dir_all(&result).await?;
+            Ok(result)
+        }
+
         pub fn as_path(&self) -> &Path {
             self.into()
         }
 
-        pub async fn piers_in_port(&self) -> Result<Vec<String>> {
-            let directory_listing = self.port_path().await?.read_dir().await?;
+        /// Ids of the entries in the dry dock, which hold piers that are still being created.
+        pub async fn piers_in_dry_dock(&self) -> Result<Vec<Uuid>> {
+            let directory_listing = self.dry_dock_path().await?.read_dir().await?;
 
-            let mut result: Vec<String> = Vec::new();
+            let mut result = Vec::new();
 
             for entry in directory_listing.collect::<Vec<io::Result<DirEntry>>>().await {
                 let entry = entry?;
                 if !entry.file_type().await?.is_dir() {
                     continue
                 }
-                let name = match entry.file_name().into_string() {
-                    Ok(s) => s,
-                    Err(_) => continue,
-                };
-                result.push(name);
+                if let Some(id) = entry.file_name().to_str().and_then(|s| Uuid::parse_str(s).ok()) {
+                    result.push(id);
+                }
+            }
+
+            Ok(result)
+        }
+
+        /// Names of the piers in port, laid out either way, along with where each one is.
+        async fn piers_in_port_with_paths(&self) -> Result<Vec<(String, PathBuf)>> {
+            let port_path = self.port_path().await?;
+            let mut dirs = vec![port_path.clone()];
+            let mut result = Vec::new();
+
+            while let Some(dir) = dirs.pop() {
+                let directory_listing = dir.read_dir().await?;
+                for entry in directory_listing.collect::<Vec<io::Result<DirEntry>>>().await {
+                    let entry = entry?;
+                    if !entry.file_type().await?.is_dir() {
+                        continue
+                    }
+                    let name = match entry.file_name().into_string() {
+                        Ok(s) => s,
+                        Err(_) => continue,
+                    };
+                    if dir == port_path && is_shard(&name) {
+                        dirs.push(entry.path());
+                    } else {
+                        result.push((name, entry.path()));
+                    }
+                }
+            }
+
+            Ok(result)
+        }
+
+        pub async fn piers_in_port(&self) -> Result<Vec<String>> {
+            Ok(self.piers_in_port_with_paths().await?.into_iter().map(|(name, _)| name).collect())
+        }
+
+        /// Moves piers laid out other than as `PORT_LAYOUT` says into place, as after the layout is changed, and
+        /// removes shard directories left empty. Piers locked by another process are left where they are, to be moved
+        /// on a later startup. Returns the names of the piers moved.
+        pub async fn migrate_port_layout(&self) -> Result<Vec<String>> {
+            let mut moved = Vec::new();
+            for (name, path) in self.piers_in_port_with_paths().await? {
+                let target = self.pier_path_in_port(&name).await?;
+                if path == target {
+                    continue;
+                }
+                // The lock is released before the move, as it is tied to its path. Nothing else in this process
+                // touches the port until startup has scanned it.
+                match FileLock::try_acquire(super::PierState::lockfile_path_given_meta(path.clone())).await? {
+                    Some(lockfile) => lockfile.release().await?,
+                    None => {
+                        log::warn!("not moving pier {} into the {:?} port layout: it is locked", name, *PORT_LAYOUT);
+                        continue;
+                    },
+                }
+                if target.exists().await {
+                    bail!("can't move pier {} to {}: something is already there", name, target.to_string_lossy());
+                }
+                if let Some(parent) = target.parent() {
+                    async_std::fs::create_dir_all(parent).await?;
+                }
+                async_std::fs::rename(&path, &target).await?;
+                STORAGE.relocate(&path.join("pier"), &target.join("pier")).await?;
+                moved.push(name);
             }
 
-            return Ok(result)
+            let port_path = self.port_path().await?;
+            let directory_listing = port_path.read_dir().await?;
+            for entry in directory_listing.collect::<Vec<io::Result<DirEntry>>>().await {
+                let entry = entry?;
+                if entry.file_name().to_str().is_some_and(is_shard) {
+                    // Fails, harmlessly, unless the shard is empty.
+                    _ = async_std::fs::remove_dir(entry.path()).await;
+                }
+            }
+            Ok(moved)
         }
     }
 
-    impl<'a> Into<&'a Path> for &'a Harbor {
-        fn into(self) -> &'a Path {
-            &self.0
+    impl<'a> From<&'a Harbor> for &'a Path {
+        fn from(harbor: &'a Harbor) -> Self {
+            &harbor.0
         }
     }
 
@@ -135,7 +339,7 @@ mod harbor_private {
             use std::path::{Path, PathBuf};
 
             let path = env::var_os("NUCLEUS_HARBOR_PATH")
-                .map(|p| PathBuf::from(p))
+                .map(PathBuf::from)
                 .unwrap_or(
                     Path::new("/var/harbor").to_owned()
                 );
@@ -157,21 +361,96 @@ lazy_static! {
     pub static ref AMES_PORT_RANGE: Range<u16> = env::var_os("NUCLEUS_AMES_PORT_RANGE")
         .map(|s| s.to_str().unwrap().parse::<MyRange<u16>>().unwrap().inner)
         .unwrap_or(4300..4400);
+
+    /// How long a ship gets to exit on its own after being asked to with `|exit`, before it is signalled.
+    pub static ref SHUTDOWN_EXIT_TIMEOUT: Duration = env::var_os("NUCLEUS_SHUTDOWN_EXIT_TIMEOUT")
+        .map(|s| crate::util::parse_duration(s.to_str().unwrap()).unwrap())
+        .unwrap_or(Duration::from_secs(30));
+
+    /// How long a ship gets to exit after each of SIGINT and SIGTERM, before the next, harsher signal.
+    pub static ref SHUTDOWN_SIGNAL_TIMEOUT: Duration = env::var_os("NUCLEUS_SHUTDOWN_SIGNAL_TIMEOUT")
+        .map(|s| crate::util::parse_duration(s.to_str().unwrap()).unwrap())
+        .unwrap_or(Duration::from_secs(10));
+
+    /// How long a launched runtime gets to write its ports file and answer on its lens port. First boots replay the
+    /// whole OTA from the sponsor, so this is generous.
+    pub static ref BOOT_TIMEOUT: Duration = env::var_os("NUCLEUS_BOOT_TIMEOUT")
+        .map(|s| crate::util::parse_duration(s.to_str().unwrap()).unwrap())
+        .unwrap_or(Duration::from_secs(10 * 60));
+
+    /// How long a lens call may take unless its caller says otherwise. `|meld` on a large ship is the slowest call
+    /// made, so this is generous.
+    pub static ref LENS_TIMEOUT: Duration = env::var_os("NUCLEUS_LENS_TIMEOUT")
+        .map(|s| crate::util::parse_duration(s.to_str().unwrap()).unwrap())
+        .unwrap_or(Duration::from_secs(30 * 60));
+
+    /// What a launched runtime must do before its boot is complete: `lens` to answer on its lens port, or `eyre` to
+    /// also serve its login page, which can come a good while later on a first boot.
+    pub static ref READINESS_PROBE: ReadinessProbe = env::var_os("NUCLEUS_READINESS_PROBE")
+        .map(|s| s.to_str().unwrap().parse().unwrap())
+        .unwrap_or(ReadinessProbe::Eyre);
 }
 
-#[derive(Debug)]
-pub struct InvalidPierArchiveError;
+/// How often a booting runtime is checked for readiness.
+const BOOT_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
-impl Display for InvalidPierArchiveError {
-    fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        todo!()
-    }
+/// How an exported pier is laid out inside its archive.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExportLayout {
+    /// The pier directory as the orchestrator stores it, rooted at `pier/`. Suitable for importing back into an
+    /// orchestrator.
+    #[default]
+    Native,
+    /// The pier rooted at a directory named after the ship, plus a README with boot instructions, so that the archive
+    /// can be unpacked and booted with a stock `urbit` binary.
+    Portable,
+}
+
+fn portable_readme(name: &str, version: runtime::Version) -> String {
+    format!(r#"# ~{name}
+
+This archive contains the pier (the complete state) of the Urbit ship ~{name}, exported
+from a hosting provider so that you can run it yourself.
+
+## Booting
+
+1. Install the Urbit runtime, version {version} or later, from https://urbit.org/getting-started.
+2. Unpack this archive:
+
+       tar -xzf {name}.tar.gz
+
+3. Boot the ship from the unpacked directory:
+
+       urbit {name}
+
+The ship keeps its identity, apps, and data. Once it is running, get your web login
+code by typing `+code` into the dojo.
+
+## Important
+
+Only ever run one copy of a ship at a time. Make sure your ship is no longer running
+at your hosting provider before booting it here; running two copies of the same ship,
+or booting an older copy after a newer one has run, corrupts its networking state and
+requires a factory reset (breach) to fix.
+"#)
 }
 
-impl StdError for InvalidPierArchiveError {}
+#[derive(Clone, Copy, Debug, Deserialize, Hash, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ShipClass {
+    Galaxy,
+    Star,
+    Planet,
+    Moon,
+    Comet,
+}
 
-fn find_extracted_pier(_unpack_path: &Path) -> Option<PathBuf> {
-    todo!();
+impl ShipClass {
+    /// Classifies a ship by its @p, or None if it isn't one.
+    pub fn of_name(name: &str) -> Option<Self> {
+        patp::parse(name).ok().map(patp::class)
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
@@ -181,6 +460,215 @@ pub struct PierConfig {
     id: Uuid,
     #[serde(rename = "@p")]
     name: Option<String>,
+    /// A well-known Ames port that this pier always binds, bypassing the Ames port issuer. Intended for stars and
+    /// galaxies, which are reached directly by other ships and can't rely on NAT traversal.
+    #[serde(default)]
+    fixed_ames_port: Option<u16>,
+    /// The unprivileged user this pier's runtime runs as, assigned on first launch when `SHIP_UID_RANGE` is configured.
+    #[serde(default)]
+    run_as_uid: Option<u32>,
+    /// Extra environment variables for the runtime, e.g. for debugging.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    boot_priority: BootPriority,
+    #[serde(default, skip_serializing_if = "RestartPolicy::is_default")]
+    restart_policy: RestartPolicy,
+    #[serde(default, skip_serializing_if = "PierMetadata::is_empty")]
+    metadata: PierMetadata,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    legal_hold: Option<LegalHold>,
+    /// The ports the pier was last launched with, which later launches are issued again if they are still free. Ames
+    /// works best when a ship keeps its UDP port, and proxies in front of the ship needn't be reconfigured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ports: Option<PierPorts>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clone_of: Option<CloneOrigin>,
+    /// A fake ship, booted with `-F` for development. Fake ships only talk to each other over the loopback interface,
+    /// so they are never issued an ames port and are kept out of anything facing the network.
+    #[serde(default)]
+    fake: bool,
+    #[serde(default, skip_serializing_if = "RuntimeFlags::is_default")]
+    runtime_flags: RuntimeFlags,
+    /// Dojo commands run through the lens, in order, after every boot, such as `|install` or `|knob` settings.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    startup_commands: Vec<String>,
+    /// The plan the pier is on, whose settings were last applied to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    plan: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expiry: Option<Expiry>,
+    /// Set between a breach resetting the pier and its first boot from the new keyfile, while it has no pier
+    /// directory.
+    #[serde(default)]
+    pending_breach: bool,
+    /// The ship's rift (breach count) on Azimuth when it was last booted, to tell when it has since been breached.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rift: Option<u64>,
+    #[serde(flatten)]
+    lifecycle: Lifecycle,
+}
+
+/// The pier a clone was copied from. Clones are disposable copies of a real ship for development, and always run with
+/// their networking confined to the host, so that the real ship's peers never hear from a second copy of it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CloneOrigin {
+    pub name: String,
+    pub id: Uuid,
+    #[serde(with = "time::serde::rfc3339")]
+    pub cloned_at: OffsetDateTime,
+}
+
+const MAX_CLONE_NAME_LEN: usize = 64;
+
+/// Clones are named like piers but can't be mistaken for ships: lowercase letters, digits and hyphens, never an @p.
+pub fn validate_clone_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_CLONE_NAME_LEN {
+        bail!("clone names must be 1 to {} characters long", MAX_CLONE_NAME_LEN);
+    }
+    if !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') || name.starts_with('-') {
+        bail!("clone names may only contain lowercase letters, digits and hyphens, and may not start with a hyphen");
+    }
+    if patp::parse(name).is_ok() {
+        bail!("clone names may not be an @p");
+    }
+    Ok(())
+}
+
+const MAX_STARTUP_COMMANDS: usize = 32;
+
+/// Startup commands are single lines of dojo input.
+pub fn validate_startup_commands(commands: &[String]) -> Result<()> {
+    if commands.len() > MAX_STARTUP_COMMANDS {
+        bail!("at most {} startup commands", MAX_STARTUP_COMMANDS);
+    }
+    for command in commands {
+        if command.trim().is_empty() || command.contains(['\n', '\r']) {
+            bail!("startup commands must be single, non-empty lines: {:?}", command);
+        }
+    }
+    Ok(())
+}
+
+/// How one of a pier's startup commands went, as recorded in the result of the job that booted it.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StartupCommandOutcome {
+    pub command: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PierPorts {
+    pub http: u16,
+    pub ames: u16,
+}
+
+/// A pier under legal hold keeps every backup taken of it: nothing is pruned automatically until the hold is released.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LegalHold {
+    #[serde(with = "time::serde::rfc3339")]
+    pub since: OffsetDateTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Details operators attach to a pier, such as the customer it belongs to. The orchestrator never acts on them.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PierMetadata {
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+const MAX_TAG_LEN: usize = 64;
+const MAX_OWNER_LEN: usize = 256;
+const MAX_NOTES_LEN: usize = 64 * 1024;
+
+impl PierMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Tags may not contain whitespace or commas, which separate them when filtering.
+    pub fn validate(&self) -> Result<()> {
+        for tag in &self.tags {
+            if tag.is_empty() || tag.len() > MAX_TAG_LEN {
+                bail!("tags must be 1 to {} bytes long: {:?}", MAX_TAG_LEN, tag);
+            }
+            if tag.contains(|c: char| c.is_whitespace() || c.is_control() || c == ',') {
+                bail!("tags may not contain whitespace or commas: {:?}", tag);
+            }
+        }
+        if self.owner.as_ref().is_some_and(|owner| owner.is_empty() || owner.len() > MAX_OWNER_LEN) {
+            bail!("owner must be 1 to {} bytes long", MAX_OWNER_LEN);
+        }
+        if self.notes.as_ref().is_some_and(|notes| notes.len() > MAX_NOTES_LEN) {
+            bail!("notes may be at most {} bytes long", MAX_NOTES_LEN);
+        }
+        Ok(())
+    }
+}
+
+/// Where a pier's boots go in the boot queue relative to others'.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BootPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// When notable things last happened to a pier. Piers created before these were tracked have them unset until the
+/// corresponding operation next happens.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Lifecycle {
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub created_at: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub first_booted_at: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub last_launched_at: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub last_backup_at: Option<OffsetDateTime>,
+}
+
+impl Lifecycle {
+    fn new() -> Self {
+        Lifecycle { created_at: Some(OffsetDateTime::now_utc()), ..Lifecycle::default() }
+    }
+
+    /// Combines what two records of the same pier know: the earliest creation and first boot, the latest launch and
+    /// backup.
+    fn merge(&mut self, other: &Lifecycle) {
+        fn pick(
+            a: Option<OffsetDateTime>,
+            b: Option<OffsetDateTime>,
+            f: fn(OffsetDateTime, OffsetDateTime) -> OffsetDateTime,
+        ) -> Option<OffsetDateTime> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(f(a, b)),
+                (a, b) => a.or(b),
+            }
+        }
+        self.created_at = pick(self.created_at, other.created_at, std::cmp::min);
+        self.first_booted_at = pick(self.first_booted_at, other.first_booted_at, std::cmp::min);
+        self.last_launched_at = pick(self.last_launched_at, other.last_launched_at, std::cmp::max);
+        self.last_backup_at = pick(self.last_backup_at, other.last_backup_at, std::cmp::max);
+    }
 }
 
 /// A PierState represents the data for an Urbit ship. Specifically it is a unique handle to the directory where all
@@ -203,24 +691,24 @@ pub struct PierState {
     /// false if initialized, used to indicate whether to perform the initial launch with a keyfile or as a comet
     comet: bool,
     filelock: FileLock,
+    /// The config as last read or written, to tell whether it has changes that were never saved.
+    saved_config: Vec<u8>,
+    /// Launch with networking confined to the host, as for a throwaway copy of a ship that is live elsewhere.
+    local_networking: bool,
 }
 
 impl PierState {
-    async fn load_from_port(path: &Path, name: &str) -> Result<Self> {
-        let mut meta_path = HARBOR.port_path().await?;
-        meta_path.push(name);
+    pub async fn load_from_port(name: &str) -> Result<Self> {
+        let meta_path = HARBOR.pier_path_in_port(name).await?;
 
         if !meta_path.is_dir().await {
-            bail!("Pier '{}' does not exist in harbor port", name);
+            return Err(PierNotFoundError(name.to_owned()).into());
         }
 
         let filelock = FileLock::try_acquire(
             Self::lockfile_path_given_meta(meta_path.clone())
         ).await?;
-        let filelock = filelock.ok_or_else(|| anyhow!(
-            "Attempted to acquire multiple handles for the same pier: {}",
-            meta_path.to_string_lossy(),
-        ))?;
+        let filelock = filelock.ok_or_else(|| PierLockedError(meta_path.to_string_lossy().into_owned()))?;
 
         let config = Self::load_config(&meta_path).await?;
 
@@ -237,39 +725,41 @@ impl PierState {
 
         let config = Self::load_config(&meta_path).await?;
 
-        let result = Self {
+        let mut result = Self {
             id: config.id,
             name: Some(name.to_owned()),
             meta_path,
             filelock,
+            saved_config: serde_json::to_vec(&config)?,
             config,
             dry_docked: false,
             comet: false,
             initialized: true,
+            local_networking: false,
         };
 
         if !result.pier_path().exists().await {
-            bail!("attempted to load uninitialized pier from port; only dry dock piers may be uninitialized")
+            if !result.config.pending_breach {
+                bail!("attempted to load uninitialized pier from port; only dry dock piers may be uninitialized")
+            }
+            result.initialized = false;
         }
 
         Ok(result)
     }
 
-    async fn load_from_dry_dock(path: &Path, id: Uuid) -> Result<Self> {
+    pub async fn load_from_dry_dock(id: Uuid) -> Result<Self> {
         let mut meta_path = HARBOR.dry_dock_path().await?;
         meta_path.push(format!("{}", id.hyphenated()));
 
         if !meta_path.is_dir().await {
-            bail!("Pier '{}' does not exist in harbor dry dock", id.hyphenated());
+            return Err(PierNotFoundError(id.hyphenated().to_string()).into());
         }
 
         let filelock = FileLock::try_acquire(
             Self::lockfile_path_given_meta(meta_path.clone())
         ).await?;
-        let filelock = filelock.ok_or_else(|| anyhow!(
-            "Attempted to acquire multiple handles for the same pier: {}",
-            meta_path.to_string_lossy(),
-        ))?;
+        let filelock = filelock.ok_or_else(|| PierLockedError(meta_path.to_string_lossy().into_owned()))?;
 
         let config = Self::load_config(&meta_path).await?;
 
@@ -278,14 +768,16 @@ impl PierState {
         }
 
         let mut result = Self {
-            id: id,
+            id,
             name: config.name.clone(),
             meta_path,
             filelock,
-            config: config,
+            saved_config: serde_json::to_vec(&config)?,
+            config,
             dry_docked: true,
             comet: false,
             initialized: false,
+            local_networking: false,
         };
 
         result.initialized = result.pier_path().exists().await;
@@ -293,6 +785,20 @@ impl PierState {
         Ok(result)
     }
 
+    /// Persists the config. Every change to it should be followed by a call to this; dropping a pier with unsaved
+    /// changes still writes them, but blocks the runtime while it does.
+    async fn save_config(&mut self) -> Result<()> {
+        let json = serde_json::to_vec(&self.config)?;
+        if json == self.saved_config {
+            return Ok(());
+        }
+        let path = Self::config_path_given_meta(self.meta_path.clone());
+        let contents = json.clone();
+        tokio::task::spawn_blocking(move || write_config_sync(path.as_ref(), &contents)).await??;
+        self.saved_config = json;
+        Ok(())
+    }
+
     async fn load_config(meta_path: &Path) -> Result<PierConfig> {
         let config_buf = fs::read(Self::config_path_given_meta(meta_path.to_owned())).await?;
         Ok(serde_json::from_slice(&config_buf)?)
@@ -302,12 +808,19 @@ impl PierState {
         key_infile: &mut In,
         name: String,
     ) -> Result<Self> {
+        // Stored without the sig, as the runtime names the pier.
+        let name = patp::render(patp::parse(&name)?);
+        let mut key = Vec::new();
+        key_infile.read_to_end(&mut key).await?;
+        keyfile::check(&String::from_utf8_lossy(&key), &name)?;
+
         let id = Uuid::new_v4();
 
         let mut meta_path = HARBOR.dry_dock_path().await?;
         meta_path.push(format!("{}", id.hyphenated()));
 
         fs::create_dir(&meta_path).await?;
+        ownership::apply(&meta_path).await?;
 
         let filelock = FileLock::try_acquire(
             Self::lockfile_path_given_meta(meta_path.clone())
@@ -315,12 +828,29 @@ impl PierState {
         let filelock = filelock.ok_or_else(|| anyhow!("failed to acquire lock on newly created pier"))?;
 
         let config = PierConfig {
-            id: id,
+            id,
             name: Some(name.clone()),
             runtime_version: runtime::Version::default(),
+            fixed_ames_port: None,
+            run_as_uid: None,
+            env: BTreeMap::new(),
+            boot_priority: BootPriority::default(),
+            restart_policy: RestartPolicy::default(),
+            metadata: PierMetadata::default(),
+            legal_hold: None,
+            ports: None,
+            clone_of: None,
+            fake: false,
+            runtime_flags: RuntimeFlags::default(),
+            startup_commands: Vec::new(),
+            plan: None,
+            expiry: None,
+            pending_breach: false,
+            rift: None,
+            lifecycle: Lifecycle::new(),
         };
 
-        let result = Self {
+        let mut result = Self {
             id,
             name: Some(name),
             filelock,
@@ -329,6 +859,8 @@ impl PierState {
             dry_docked: true,
             comet: false,
             initialized: false,
+            saved_config: Vec::new(),
+            local_networking: false,
         };
 
         let mut key_outfile = fs::OpenOptions::new()
@@ -338,22 +870,29 @@ impl PierState {
             .create_new(true)
             .open(result.keyfile_path())
             .await?;
-        io::copy(key_infile, &mut key_outfile).await?;
+        key_outfile.write_all(&key).await?;
+        ownership::apply(&result.keyfile_path()).await?;
+        result.save_config().await?;
 
         Ok(result)
     }
 
-    pub async fn new_from_pier_archive<In>(
-        archive_infile: &mut In,
-    ) -> Result<Self>
-        where In: io::Read + Unpin
-    {
+    /// Creates a fake ship in the dry dock, to be booted with `-F`. `name` may be any ship but a comet.
+    pub async fn new_fake(name: &str) -> Result<Self> {
+        let ship = patp::parse(name)?;
+        if patp::class(ship) == ShipClass::Comet {
+            bail!("comets can't be fake ships");
+        }
+        // Stored without the sig, as the runtime names the pier.
+        let name = patp::render(ship);
+
         let id = Uuid::new_v4();
 
         let mut meta_path = HARBOR.dry_dock_path().await?;
         meta_path.push(format!("{}", id.hyphenated()));
 
         fs::create_dir(&meta_path).await?;
+        ownership::apply(&meta_path).await?;
 
         let filelock = FileLock::try_acquire(
             Self::lockfile_path_given_meta(meta_path.clone())
@@ -361,88 +900,117 @@ impl PierState {
         let filelock = filelock.ok_or_else(|| anyhow!("failed to acquire lock on newly created pier"))?;
 
         let config = PierConfig {
-            id: id,
-            name: None,
+            id,
+            name: Some(name.clone()),
             runtime_version: runtime::Version::default(),
+            fixed_ames_port: None,
+            run_as_uid: None,
+            env: BTreeMap::new(),
+            boot_priority: BootPriority::default(),
+            restart_policy: RestartPolicy::default(),
+            metadata: PierMetadata::default(),
+            legal_hold: None,
+            ports: None,
+            clone_of: None,
+            fake: true,
+            runtime_flags: RuntimeFlags::default(),
+            startup_commands: Vec::new(),
+            plan: None,
+            expiry: None,
+            pending_breach: false,
+            rift: None,
+            lifecycle: Lifecycle::new(),
         };
 
-        let result = Self {
+        let mut result = Self {
             id,
-            name: None,
+            name: Some(name),
             filelock,
             config,
             meta_path,
             dry_docked: true,
             comet: false,
             initialized: false,
+            saved_config: Vec::new(),
+            local_networking: false,
         };
-
-        let archive_path = result.archive_path();
-        let unpack_path = result.unpack_path();
-        let mut result = Self::new_from_pier_archive_inner(archive_infile, result, &archive_path, &unpack_path).await?;
-
-        if archive_path.is_file().await {
-            _ = fs::remove_file(&archive_path).await;
-        }
-        if unpack_path.is_dir().await {
-            _ = fs::remove_dir_all(&unpack_path).await;
-        }
-
-        result.initialized = true;
+        result.save_config().await?;
 
         Ok(result)
     }
 
-    // All the business logic is here, split out to allow simpler cleanup in the face of no async Drop.
-    #[inline]
-    async fn new_from_pier_archive_inner<In>(
+    pub async fn new_from_pier_archive<In>(
         archive_infile: &mut In,
-        result: Self,
-        archive_path: &Path,
-        unpack_path: &Path,
-    ) -> Result<Self>
+    ) -> Result<(Self, import::ImportReport)>
         where In: io::Read + Unpin
     {
-        fs::create_dir(&result.meta_path).await?;
+        let id = Uuid::new_v4();
 
-        let mut archive_outfile = fs::OpenOptions::new()
-            .read(false)
-            .write(true)
-            .truncate(true)
-            .create_new(true)
-            .open(&archive_path)
-            .await?;
-        io::copy(archive_infile, &mut archive_outfile).await?;
+        let mut meta_path = HARBOR.dry_dock_path().await?;
+        meta_path.push(format!("{}", id.hyphenated()));
 
-        fs::create_dir(&unpack_path).await?;
+        fs::create_dir(&meta_path).await?;
+        ownership::apply(&meta_path).await?;
 
-        let mut extract_options = archive::safe_extract_options();
-        extract_options.add(ExtractOption::Time);
-        archive::extract_file(
-            archive_path.to_owned(),
-            unpack_path.to_owned(),
-            extract_options,
+        let filelock = FileLock::try_acquire(
+            Self::lockfile_path_given_meta(meta_path.clone())
         ).await?;
+        let filelock = filelock.ok_or_else(|| anyhow!("failed to acquire lock on newly created pier"))?;
 
-        fs::remove_file(&archive_path).await?;
+        let config = PierConfig {
+            id,
+            name: None,
+            runtime_version: runtime::Version::default(),
+            fixed_ames_port: None,
+            run_as_uid: None,
+            env: BTreeMap::new(),
+            boot_priority: BootPriority::default(),
+            restart_policy: RestartPolicy::default(),
+            metadata: PierMetadata::default(),
+            legal_hold: None,
+            ports: None,
+            clone_of: None,
+            fake: false,
+            runtime_flags: RuntimeFlags::default(),
+            startup_commands: Vec::new(),
+            plan: None,
+            expiry: None,
+            pending_breach: false,
+            rift: None,
+            lifecycle: Lifecycle::new(),
+        };
 
-        let extracted_pier_path = find_extracted_pier(&unpack_path).ok_or(InvalidPierArchiveError)?;
-        fs::rename(&extracted_pier_path, result.pier_path()).await?;
+        let mut result = Self {
+            id,
+            name: None,
+            filelock,
+            config,
+            meta_path,
+            dry_docked: true,
+            comet: false,
+            initialized: false,
+            saved_config: Vec::new(),
+            local_networking: false,
+        };
 
-        fs::remove_dir_all(&unpack_path).await?;
+        // Written now rather than on drop so that the entry can be reloaded if the import is interrupted by a restart.
+        result.save_config().await?;
 
-        Ok(result)
+        let meta_path = result.meta_path.clone();
+        let imported = Self::receive_pier_archive(archive_infile, result).await;
+        Self::finish_archive_import(imported, &meta_path).await
     }
 
-    pub async fn new_comet(
-        config: Option<PierConfig>,
-    ) -> Result<Self> {
+    /// Creates a pier in the dry dock from a snapshot in the backup store. Like an imported pier, it learns its name
+    /// when it is released.
+    pub async fn new_from_backup(ship: &str, snapshot: &str) -> Result<Self> {
         let id = Uuid::new_v4();
 
         let mut meta_path = HARBOR.dry_dock_path().await?;
         meta_path.push(format!("{}", id.hyphenated()));
 
         fs::create_dir(&meta_path).await?;
+        ownership::apply(&meta_path).await?;
 
         let filelock = FileLock::try_acquire(
             Self::lockfile_path_given_meta(meta_path.clone())
@@ -450,60 +1018,884 @@ impl PierState {
         let filelock = filelock.ok_or_else(|| anyhow!("failed to acquire lock on newly created pier"))?;
 
         let config = PierConfig {
-            id: id,
+            id,
             name: None,
             runtime_version: runtime::Version::default(),
+            fixed_ames_port: None,
+            run_as_uid: None,
+            env: BTreeMap::new(),
+            boot_priority: BootPriority::default(),
+            restart_policy: RestartPolicy::default(),
+            metadata: PierMetadata::default(),
+            legal_hold: None,
+            ports: None,
+            clone_of: None,
+            fake: false,
+            runtime_flags: RuntimeFlags::default(),
+            startup_commands: Vec::new(),
+            plan: None,
+            expiry: None,
+            pending_breach: false,
+            rift: None,
+            lifecycle: Lifecycle::new(),
         };
 
-        let result = Self {
+        let mut result = Self {
             id,
             name: None,
             filelock,
             config,
             meta_path,
             dry_docked: true,
-            comet: true,
+            comet: false,
             initialized: false,
+            saved_config: Vec::new(),
+            local_networking: false,
         };
+        result.save_config().await?;
+
+        let restored = async {
+            STORAGE.create(&result.pier_path()).await?;
+            backup_store::restore(ship, snapshot, result.pier_path().as_ref()).await?;
+            ownership::apply_recursive(&result.pier_path()).await
+        }.await;
+        if let Err(e) = restored {
+            drop(result);
+            Self::remove_from_dry_dock(id).await?;
+            return Err(e);
+        }
+        result.initialized = true;
 
         Ok(result)
     }
 
-    pub fn config(&self) -> &PierConfig {
-        &self.config
-    }
-
-    pub fn dry_docked(&self) -> bool {
-        self.dry_docked
-    }
-
-    pub fn name(&self) -> Option<&str> {
-        self.name.as_deref()
-    }
-
-    pub fn initialized(&self) -> bool {
-        self.initialized
-    }
+    /// Creates a pier in the dry dock from a pier directory already on the host, such as one run by hand with `urbit`,
+    /// moving or copying it into place. Like an imported pier, it learns its name when it is released.
+    pub async fn adopt(path: &Path, mode: import::AdoptMode) -> Result<(Self, import::AdoptReport)> {
+        let path = import::check_adoptable(path, HARBOR.root()).await?;
+        let id = Uuid::new_v4();
 
-    fn config_path_given_meta(mut meta_path: PathBuf) -> PathBuf {
-        meta_path.push("config.json");
-        meta_path
-    }
+        let mut meta_path = HARBOR.dry_dock_path().await?;
+        meta_path.push(format!("{}", id.hyphenated()));
 
-    fn lockfile_path_given_meta(mut meta_path: PathBuf) -> PathBuf {
-        meta_path.push("lockfile");
-        meta_path
-    }
+        fs::create_dir(&meta_path).await?;
+        ownership::apply(&meta_path).await?;
 
-    fn pier_path(&self) -> PathBuf {
-        self.meta_path.join("pier")
-    }
+        let filelock = FileLock::try_acquire(
+            Self::lockfile_path_given_meta(meta_path.clone())
+        ).await?;
+        let filelock = filelock.ok_or_else(|| anyhow!("failed to acquire lock on newly created pier"))?;
 
-    fn keyfile_path(&self) -> PathBuf {
-        self.meta_path.join("keyfile")
-    }
+        let config = PierConfig {
+            id,
+            name: None,
+            runtime_version: runtime::Version::default(),
+            fixed_ames_port: None,
+            run_as_uid: None,
+            env: BTreeMap::new(),
+            boot_priority: BootPriority::default(),
+            restart_policy: RestartPolicy::default(),
+            metadata: PierMetadata::default(),
+            legal_hold: None,
+            ports: None,
+            clone_of: None,
+            fake: false,
+            runtime_flags: RuntimeFlags::default(),
+            startup_commands: Vec::new(),
+            plan: None,
+            expiry: None,
+            pending_breach: false,
+            rift: None,
+            lifecycle: Lifecycle::new(),
+        };
 
-    fn archive_path(&self) -> PathBuf {
+        let mut result = Self {
+            id,
+            name: None,
+            filelock,
+            config,
+            meta_path,
+            dry_docked: true,
+            comet: false,
+            initialized: false,
+            saved_config: Vec::new(),
+            local_networking: false,
+        };
+        result.save_config().await?;
+
+        let taken = match mode {
+            import::AdoptMode::Move => STORAGE.ingest(&path, &result.pier_path()).await
+                .map(|()| None)
+                .map_err(|e| match e.downcast_ref::<std::io::Error>().and_then(std::io::Error::raw_os_error) {
+                    Some(libc::EXDEV) => anyhow!(import::NotAdoptableError {
+                        path: path.to_string_lossy().into_owned(),
+                        reason: "it is on another filesystem than the harbor, so it can only be copied".to_owned(),
+                    }),
+                    _ => e,
+                }),
+            import::AdoptMode::Copy => STORAGE.copy(&path, &result.pier_path()).await.map(Some),
+        };
+        let copied = match taken {
+            Ok(copied) => copied,
+            Err(e) => {
+                drop(result);
+                Self::remove_from_dry_dock(id).await?;
+                return Err(e);
+            },
+        };
+
+        // The pier is in the dry dock from here on, where it is left for an operator if anything else fails.
+        let stripped = import::strip_junk(&result.pier_path()).await?;
+        ownership::apply_recursive(&result.pier_path()).await?;
+        result.initialized = true;
+
+        Ok((result, import::AdoptReport { mode, stripped, copied }))
+    }
+
+    /// Copies this pier into a new one named `name`, marked as a clone of this one. The copy is made in the dry dock,
+    /// reflinking where the filesystem supports it, and moved into port once complete. The ship must not be running.
+    pub async fn new_clone(&self, name: &str) -> Result<(Self, crate::util::CopyReport)> {
+        validate_clone_name(name)?;
+        if !self.initialized {
+            bail!("cannot clone uninitialized pier");
+        }
+        let origin = CloneOrigin {
+            name: self.name.clone().ok_or_else(|| anyhow!("cannot clone a pier with no name"))?,
+            id: self.id,
+            cloned_at: OffsetDateTime::now_utc(),
+        };
+        let id = Uuid::new_v4();
+
+        let mut meta_path = HARBOR.dry_dock_path().await?;
+        meta_path.push(format!("{}", id.hyphenated()));
+
+        fs::create_dir(&meta_path).await?;
+        ownership::apply(&meta_path).await?;
+
+        let filelock = FileLock::try_acquire(
+            Self::lockfile_path_given_meta(meta_path.clone())
+        ).await?;
+        let filelock = filelock.ok_or_else(|| anyhow!("failed to acquire lock on newly created pier"))?;
+
+        let config = PierConfig {
+            id,
+            name: Some(name.to_owned()),
+            runtime_version: self.config.runtime_version,
+            fixed_ames_port: None,
+            run_as_uid: None,
+            env: self.config.env.clone(),
+            boot_priority: BootPriority::default(),
+            restart_policy: RestartPolicy::default(),
+            metadata: PierMetadata::default(),
+            legal_hold: None,
+            ports: None,
+            clone_of: Some(origin),
+            fake: self.config.fake,
+            runtime_flags: self.config.runtime_flags.clone(),
+            startup_commands: self.config.startup_commands.clone(),
+            plan: self.config.plan.clone(),
+            expiry: None,
+            pending_breach: false,
+            rift: None,
+            lifecycle: Lifecycle::new(),
+        };
+
+        let mut result = Self {
+            id,
+            name: Some(name.to_owned()),
+            filelock,
+            config,
+            meta_path,
+            dry_docked: true,
+            comet: false,
+            initialized: false,
+            saved_config: Vec::new(),
+            local_networking: false,
+        };
+        result.save_config().await?;
+
+        let copied = async {
+            let copied = STORAGE.copy(&self.pier_path(), &result.pier_path()).await?;
+            import::strip_junk(&result.pier_path()).await?;
+            ownership::apply_recursive(&result.pier_path()).await?;
+            Ok::<_, Error>(copied)
+        }.await;
+        let copied = match copied {
+            Ok(copied) => copied,
+            Err(e) => {
+                drop(result);
+                Self::remove_from_dry_dock(id).await?;
+                return Err(e);
+            },
+        };
+        result.initialized = true;
+        result.move_into_port().await?;
+
+        Ok((result, copied))
+    }
+
+    pub fn fake(&self) -> bool {
+        self.config.fake
+    }
+
+    pub fn clone_of(&self) -> Option<&CloneOrigin> {
+        self.config.clone_of.as_ref()
+    }
+
+    /// Deletes a dry dock entry and everything in it, such as a pier that failed to be created or a throwaway copy of
+    /// one. Fails if the entry is still loaded.
+    pub async fn remove_from_dry_dock(id: Uuid) -> Result<()> {
+        let pier = Self::load_from_dry_dock(id).await?;
+        let (meta_path, pier_path) = (pier.meta_path.clone(), pier.pier_path());
+        drop(pier);
+        if pier_path.exists().await {
+            STORAGE.remove(&pier_path).await?;
+        }
+        fs::remove_dir_all(&meta_path).await?;
+        Ok(())
+    }
+
+    /// Confines the ship's networking to the host from its next launch.
+    pub fn set_local_networking(&mut self, local: bool) {
+        self.local_networking = local;
+    }
+
+    /// Picks up an archive import that was interrupted by a restart, extracting the rest of the stored archive.
+    pub async fn resume_pier_archive_import(id: Uuid) -> Result<(Self, import::ImportReport)> {
+        let result = Self::load_from_dry_dock(id).await?;
+        let meta_path = result.meta_path.clone();
+
+        let imported = match import::ImportProgress::load(&meta_path).await? {
+            Some(import::ImportProgress::Extracting { entries_completed }) if result.archive_path().is_file().await => {
+                log::info!("resuming import of {} after {} archive entries", id, entries_completed);
+                result.extract_pier_archive(Some(entries_completed)).await
+            },
+            Some(import::ImportProgress::Extracting { .. }) => Err(anyhow!("the stored archive is missing")),
+            Some(import::ImportProgress::Receiving) => Err(anyhow!("the archive was not fully received")),
+            None => Err(anyhow!("no import is in progress")),
+        };
+        Self::finish_archive_import(imported, &meta_path).await
+            .map_err(|e| e.context(format!("failed to resume import of {}", id)))
+    }
+
+    /// Removes the import's working files whether or not it succeeded.
+    async fn finish_archive_import(
+        imported: Result<(Self, import::ImportReport)>,
+        meta_path: &Path,
+    ) -> Result<(Self, import::ImportReport)> {
+        let archive_path = meta_path.join("archive");
+        if archive_path.is_file().await {
+            _ = fs::remove_file(&archive_path).await;
+        }
+        let unpack_path = meta_path.join("unpack");
+        if unpack_path.is_dir().await {
+            _ = fs::remove_dir_all(&unpack_path).await;
+        }
+        _ = import::ImportProgress::clear(meta_path).await;
+
+        let (mut result, report) = imported?;
+        result.initialized = true;
+
+        Ok((result, report))
+    }
+
+    // All the business logic is here, split out to allow simpler cleanup in the face of no async Drop.
+    #[inline]
+    async fn receive_pier_archive<In>(
+        archive_infile: &mut In,
+        result: Self,
+    ) -> Result<(Self, import::ImportReport)>
+        where In: io::Read + Unpin
+    {
+        import::ImportProgress::Receiving.save(&result.meta_path).await?;
+
+        let mut archive_outfile = fs::OpenOptions::new()
+            .read(false)
+            .write(true)
+            .truncate(true)
+            .create_new(true)
+            .open(result.archive_path())
+            .await?;
+        io::copy(archive_infile, &mut archive_outfile).await?;
+        archive_outfile.sync_all().await?;
+
+        result.extract_pier_archive(None).await
+    }
+
+    /// Extracts the stored archive into the pier directory, skipping the entries a previous attempt got through.
+    async fn extract_pier_archive(self, resume_from: Option<usize>) -> Result<(Self, import::ImportReport)> {
+        let archive_path = self.archive_path();
+        let unpack_path = self.unpack_path();
+
+        import::ImportProgress::Extracting { entries_completed: resume_from.unwrap_or(0) }
+            .save(&self.meta_path)
+            .await?;
+        if !unpack_path.is_dir().await {
+            fs::create_dir(&unpack_path).await?;
+        }
+
+        let mut extract_options = archive::safe_extract_options();
+        extract_options.add(ExtractOption::Time);
+        let progress_meta_path: std::path::PathBuf = self.meta_path.clone().into();
+        archive::extract_file(
+            archive_path.to_owned(),
+            unpack_path.to_owned(),
+            extract_options,
+            resume_from,
+            move |entries_completed| {
+                let progress = import::ImportProgress::Extracting { entries_completed };
+                if let Err(e) = progress.save_sync(&progress_meta_path) {
+                    log::warn!("failed to record import progress: {}", e);
+                }
+            },
+        ).await.map_err(|e| match e.downcast::<archive::ExtractError>() {
+            Ok(archive::ExtractError::Unreadable(detail)) => {
+                import::InvalidPierArchiveError::Truncated { detail }.into()
+            },
+            Ok(archive::ExtractError::DisallowedPaths(paths)) => {
+                import::InvalidPierArchiveError::DisallowedPaths { paths }.into()
+            },
+            Err(e) => e,
+        })?;
+
+        fs::remove_file(&archive_path).await?;
+
+        let (extracted_pier_path, layout) = import::find_extracted_pier(&unpack_path).await?;
+        log::debug!("found pier in {:?} archive layout at {}", layout, extracted_pier_path.to_string_lossy());
+        if layout == import::ArchiveLayout::LooseUrb {
+            STORAGE.create(&self.pier_path()).await?;
+            STORAGE.ingest(&extracted_pier_path, &self.pier_path().join(".urb")).await?;
+        } else {
+            STORAGE.ingest(&extracted_pier_path, &self.pier_path()).await?;
+        }
+
+        // With a bare or loose layout the unpack directory itself may have been moved.
+        if unpack_path.is_dir().await {
+            fs::remove_dir_all(&unpack_path).await?;
+        }
+
+        let stripped = import::strip_junk(&self.pier_path()).await?;
+        ownership::apply_recursive(&self.pier_path()).await?;
+
+        Ok((self, import::ImportReport { layout, stripped }))
+    }
+
+    pub async fn new_comet() -> Result<Self> {
+        let id = Uuid::new_v4();
+
+        let mut meta_path = HARBOR.dry_dock_path().await?;
+        meta_path.push(format!("{}", id.hyphenated()));
+
+        fs::create_dir(&meta_path).await?;
+        ownership::apply(&meta_path).await?;
+
+        let filelock = FileLock::try_acquire(
+            Self::lockfile_path_given_meta(meta_path.clone())
+        ).await?;
+        let filelock = filelock.ok_or_else(|| anyhow!("failed to acquire lock on newly created pier"))?;
+
+        let config = PierConfig {
+            id,
+            name: None,
+            runtime_version: runtime::Version::default(),
+            fixed_ames_port: None,
+            run_as_uid: None,
+            env: BTreeMap::new(),
+            boot_priority: BootPriority::default(),
+            restart_policy: RestartPolicy::default(),
+            metadata: PierMetadata::default(),
+            legal_hold: None,
+            ports: None,
+            clone_of: None,
+            fake: false,
+            runtime_flags: RuntimeFlags::default(),
+            startup_commands: Vec::new(),
+            plan: None,
+            expiry: None,
+            pending_breach: false,
+            rift: None,
+            lifecycle: Lifecycle::new(),
+        };
+
+        let mut result = Self {
+            id,
+            name: None,
+            filelock,
+            config,
+            meta_path,
+            dry_docked: true,
+            comet: true,
+            initialized: false,
+            saved_config: Vec::new(),
+            local_networking: false,
+        };
+        result.save_config().await?;
+
+        Ok(result)
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn runtime_version(&self) -> runtime::Version {
+        self.config.runtime_version
+    }
+
+    pub fn config(&self) -> &PierConfig {
+        &self.config
+    }
+
+    pub fn lifecycle(&self) -> &Lifecycle {
+        &self.config.lifecycle
+    }
+
+    /// Notes that a full export of the pier was just taken.
+    pub async fn record_backup(&mut self) -> Result<()> {
+        self.config.lifecycle.last_backup_at = Some(OffsetDateTime::now_utc());
+        self.save_config().await
+    }
+
+    /// Pins the pier to a well-known Ames port, or returns it to using the issuer when `port` is None. The port must lie
+    /// outside the issuer's range, otherwise another ship could be handed it while this one is stopped.
+    pub async fn set_fixed_ames_port(&mut self, port: Option<u16>) -> Result<()> {
+        if port.is_some() && self.config.fake {
+            bail!("fake ships can't have an ames port");
+        }
+        if let Some(port) = port {
+            if AMES_PORT_RANGE.contains(&port) {
+                bail!(
+                    "fixed ames port {} lies within the issued ames port range {}..{}",
+                    port, AMES_PORT_RANGE.start, AMES_PORT_RANGE.end,
+                );
+            }
+            if !net_util::udp_port_available(port).await {
+                bail!("fixed ames port {} is already bound by another process", port);
+            }
+        }
+
+        self.config.fixed_ames_port = port;
+        self.save_config().await
+    }
+
+    pub fn env(&self) -> &BTreeMap<String, String> {
+        &self.config.env
+    }
+
+    pub fn boot_priority(&self) -> BootPriority {
+        self.config.boot_priority
+    }
+
+    pub fn restart_policy(&self) -> &RestartPolicy {
+        &self.config.restart_policy
+    }
+
+    pub async fn set_restart_policy(&mut self, policy: RestartPolicy) -> Result<()> {
+        self.config.restart_policy = policy;
+        self.save_config().await
+    }
+
+    pub fn runtime_flags(&self) -> &RuntimeFlags {
+        &self.config.runtime_flags
+    }
+
+    /// Takes effect the next time the pier is launched.
+    pub async fn set_runtime_flags(&mut self, flags: RuntimeFlags) -> Result<()> {
+        flags.validate()?;
+        self.config.runtime_flags = flags;
+        self.save_config().await
+    }
+
+    pub fn startup_commands(&self) -> &[String] {
+        &self.config.startup_commands
+    }
+
+    /// Takes effect the next time the pier boots.
+    pub async fn set_startup_commands(&mut self, commands: Vec<String>) -> Result<()> {
+        validate_startup_commands(&commands)?;
+        self.config.startup_commands = commands;
+        self.save_config().await
+    }
+
+    pub fn plan(&self) -> Option<&str> {
+        self.config.plan.as_deref()
+    }
+
+    /// Puts the pier on a plan, given by name, and takes over the settings it bundles; or takes the pier off its plan
+    /// with None, which leaves its settings as they are. Flags take effect the next time the pier is launched.
+    pub async fn set_plan(&mut self, plan: Option<(&str, &Plan)>) -> Result<()> {
+        if let Some((_, plan)) = plan {
+            plan.validate()?;
+            if let Some(flags) = &plan.runtime_flags {
+                self.config.runtime_flags = flags.clone();
+            }
+            if let Some(priority) = plan.boot_priority {
+                self.config.boot_priority = priority;
+            }
+            if let Some(policy) = &plan.restart_policy {
+                self.config.restart_policy = policy.clone();
+            }
+        }
+        self.config.plan = plan.map(|(name, _)| name.to_owned());
+        self.save_config().await
+    }
+
+    /// The keyfile the pier was or will be booted from, if it has one; comets and fake ships don't.
+    pub async fn keyfile(&self) -> Result<Option<String>> {
+        match fs::read_to_string(self.keyfile_path()).await {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether the pier's ship talks to the live network under its own keys, rather than being a fake ship or a
+    /// clone confined to the host.
+    pub fn networked(&self) -> bool {
+        !self.config.fake && self.config.clone_of.is_none() && !self.local_networking
+    }
+
+    pub fn rift(&self) -> Option<u64> {
+        self.config.rift
+    }
+
+    /// Records the ship's rift on Azimuth. Saved with the config on the next launch.
+    pub fn set_rift(&mut self, rift: u64) {
+        self.config.rift = Some(rift);
+    }
+
+    pub fn expiry(&self) -> Option<&Expiry> {
+        self.config.expiry.as_ref()
+    }
+
+    /// Sets when the pier expires, or with None that it never does. Either way its expiry starts over, so that a pier
+    /// stopped for having expired can be booted again.
+    pub async fn set_expiry(&mut self, expires_at: Option<OffsetDateTime>) -> Result<()> {
+        self.config.expiry = expires_at.map(Expiry::new);
+        self.save_config().await
+    }
+
+    /// Records how far the pier's expiry has got.
+    pub async fn update_expiry(&mut self, expiry: Expiry) -> Result<()> {
+        self.config.expiry = Some(expiry);
+        self.save_config().await
+    }
+
+    pub fn metadata(&self) -> &PierMetadata {
+        &self.config.metadata
+    }
+
+    /// Replaces the pier's metadata. Unlike most settings, it can be changed while the ship runs.
+    pub async fn set_metadata(&mut self, metadata: PierMetadata) -> Result<()> {
+        metadata.validate()?;
+        self.config.metadata = metadata;
+        self.save_config().await
+    }
+
+    /// Takes over the settings and history in another copy of this pier's config, such as one dumped before the host
+    /// was rebuilt. The pier keeps its own id, name and run-as user, which belong to this host. The ship must not be
+    /// running.
+    pub async fn restore_config(&mut self, other: &PierConfig) -> Result<()> {
+        other.metadata.validate()?;
+        self.config.runtime_version = other.runtime_version;
+        self.config.fixed_ames_port = other.fixed_ames_port;
+        self.config.env = other.env.clone();
+        self.config.boot_priority = other.boot_priority;
+        self.config.restart_policy = other.restart_policy.clone();
+        self.config.metadata = other.metadata.clone();
+        self.config.legal_hold = other.legal_hold.clone();
+        self.config.ports = other.ports;
+        self.config.clone_of = other.clone_of.clone();
+        self.config.fake = other.fake;
+        self.config.runtime_flags = other.runtime_flags.clone();
+        self.config.startup_commands = other.startup_commands.clone();
+        self.config.plan = other.plan.clone();
+        self.config.expiry = other.expiry.clone();
+        self.config.lifecycle.merge(&other.lifecycle);
+        self.save_config().await
+    }
+
+    /// The ports the pier keeps between launches, if it has been launched.
+    pub fn ports(&self) -> Option<PierPorts> {
+        self.config.ports
+    }
+
+    pub fn legal_hold(&self) -> Option<&LegalHold> {
+        self.config.legal_hold.as_ref()
+    }
+
+    /// Places the pier under legal hold, or releases it with None. Like metadata, it can change while the ship runs.
+    pub async fn set_legal_hold(&mut self, hold: Option<LegalHold>) -> Result<()> {
+        self.config.legal_hold = hold;
+        self.save_config().await
+    }
+
+    pub async fn set_boot_priority(&mut self, priority: BootPriority) -> Result<()> {
+        self.config.boot_priority = priority;
+        self.save_config().await
+    }
+
+    /// Replaces the extra environment variables the runtime is started with. They take effect on the next launch.
+    pub async fn set_env(&mut self, env: BTreeMap<String, String>) -> Result<()> {
+        for (key, value) in &env {
+            let valid_key = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid_key {
+                bail!("invalid environment variable name: {:?}", key);
+            }
+            if value.contains('\0') {
+                bail!("value of environment variable {} contains a nul byte", key);
+            }
+        }
+
+        self.config.env = env;
+        self.save_config().await
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn initialized(&self) -> bool {
+        self.initialized
+    }
+
+    /// Streams a tar.gz of the pier directory in the given layout. The caller is responsible for making sure the ship
+    /// isn't running, since archiving a live pier can capture an inconsistent snapshot.
+    pub async fn export_stream(&self, layout: ExportLayout) -> Result<impl Stream<Item = Result<Bytes>>> {
+        if !self.initialized {
+            bail!("cannot export uninitialized pier");
+        }
+
+        match layout {
+            ExportLayout::Native => archive::create_stream(self.pier_path().as_ref(), None, &[]),
+            ExportLayout::Portable => {
+                let name = self.name.as_deref()
+                    .ok_or_else(|| anyhow!("cannot make a portable export of a pier with no name"))?;
+                let readme_path = self.meta_path.join("README.md");
+                fs::write(&readme_path, portable_readme(name, self.config.runtime_version)).await?;
+                archive::create_stream(self.pier_path().as_ref(), Some(name), &[readme_path.as_ref()])
+            },
+        }
+    }
+
+    /// Writes a tar.gz of the pier directory to `dst`, returning the number of bytes written. As with `export_stream`,
+    /// the ship must not be running.
+    pub async fn export_to_file(&self, dst: &Path, layout: ExportLayout) -> Result<u64> {
+        let body = self.export_stream(layout).await?;
+        futures::pin_mut!(body);
+        let mut outfile = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dst)
+            .await?;
+
+        let mut written = 0;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            outfile.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        outfile.sync_all().await?;
+
+        Ok(written)
+    }
+
+    /// Takes a snapshot of the pier into the deduplicated backup store. As with `export_stream`, the ship must not be
+    /// running.
+    pub async fn snapshot_to_store(&self) -> Result<backup_store::SnapshotReport> {
+        if !self.initialized {
+            bail!("cannot back up uninitialized pier");
+        }
+        let name = self.name.as_deref().ok_or_else(|| anyhow!("cannot back up a pier with no name"))?;
+        backup_store::snapshot(name, self.pier_path().as_ref(), self.config.legal_hold.is_none()).await
+    }
+
+    /// Runs an offline maintenance subcommand, such as `urbit pack`, against the pier. The ship must not be running.
+    pub async fn run_subcommand(&self, subcommand: runtime::Subcommand) -> Result<String> {
+        if !self.initialized {
+            bail!("cannot run {} on uninitialized pier", subcommand.name());
+        }
+        // A pier that has never been launched with privilege separation has no user yet, and is left as it is.
+        if let Some(uid) = self.config.run_as_uid {
+            privsep::prepare_pier(&self.meta_path, &self.pier_path(), &self.keyfile_path(), uid).await?;
+        }
+        let run_as = self.config.run_as_uid;
+        let scratch_path = self.prepare_scratch_dir(run_as).await?;
+        self.config.runtime_version
+            .run_subcommand(subcommand, &self.pier_path(), run_as, &self.config.env, &scratch_path).await
+    }
+
+    /// The uid this pier's runtime should run as, assigning it one from `SHIP_UID_RANGE` if it doesn't have one yet.
+    /// None if privilege separation is disabled.
+    async fn ensure_run_as_uid(&mut self) -> Result<Option<u32>> {
+        if privsep::SHIP_UID_RANGE.is_none() {
+            return Ok(None);
+        }
+        if let Some(uid) = self.config.run_as_uid {
+            return Ok(Some(uid));
+        }
+
+        let _lock = privsep::allocation_lock().await;
+        let mut taken = HashSet::new();
+        for name in HARBOR.piers_in_port().await? {
+            if let Ok(config) = Self::load_config(&HARBOR.pier_path_in_port(&name).await?).await {
+                taken.extend(config.run_as_uid);
+            }
+        }
+        let dry_dock_path = HARBOR.dry_dock_path().await?;
+        for id in HARBOR.piers_in_dry_dock().await? {
+            if let Ok(config) = Self::load_config(&dry_dock_path.join(id.hyphenated().to_string())).await {
+                taken.extend(config.run_as_uid);
+            }
+        }
+
+        let uid = privsep::pick_uid(&taken)?;
+        self.config.run_as_uid = Some(uid);
+        // Saved while still holding the lock, so that the next allocation sees it.
+        self.save_config().await?;
+        log::info!("assigned uid {} to pier {}", uid, self.name().unwrap_or_default());
+        Ok(Some(uid))
+    }
+
+    fn config_path_given_meta(mut meta_path: PathBuf) -> PathBuf {
+        meta_path.push("config.json");
+        meta_path
+    }
+
+    fn lockfile_path_given_meta(mut meta_path: PathBuf) -> PathBuf {
+        meta_path.push("lockfile");
+        meta_path
+    }
+
+    /// The directory the runtime boots from.
+    pub fn pier_path(&self) -> PathBuf {
+        self.meta_path.join("pier")
+    }
+
+    /// Working directory and TMPDIR of the pier's runtime, so that whatever it leaves around counts towards the pier's
+    /// disk usage and goes away with the pier.
+    pub fn scratch_path(&self) -> PathBuf {
+        self.meta_path.join("tmp")
+    }
+
+    /// Where the runtime's output is logged; see `shiplog`.
+    pub fn logs_path(&self) -> PathBuf {
+        self.meta_path.join("logs")
+    }
+
+    /// Empties the scratch directory, which nothing is expected to survive a restart, and gives it to the runtime's
+    /// user.
+    async fn prepare_scratch_dir(&self, run_as: Option<u32>) -> Result<PathBuf> {
+        let path = self.scratch_path();
+        if path.exists().await {
+            fs::remove_dir_all(&path).await?;
+        }
+        fs::create_dir(&path).await?;
+        fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o700)).await?;
+        match run_as {
+            Some(uid) => ownership::chown(&path, Some(uid), Some(uid)).await?,
+            None => ownership::apply(&path).await?,
+        }
+        Ok(path)
+    }
+
+    fn portsfile_path(&self) -> PathBuf {
+        self.pier_path().join(".http.ports")
+    }
+
+    /// The runtime's lens port, from the `.http.ports` file it writes once its HTTP servers are up. None until then.
+    async fn lens_port(&self) -> Result<Option<u16>> {
+        let portsfile_path = self.portsfile_path();
+        let portsdesc = match fs::read_to_string(&portsfile_path).await {
+            Ok(portsdesc) => portsdesc,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        // The runtime may not have finished writing the file yet, so an undecodable one counts as not written.
+        Ok(portsdesc.lines()
+            .filter(|line| line.ends_with("loopback"))
+            .map(|line| line.split_ascii_whitespace().nth(0))
+            .nth(0)
+            .flatten()
+            .and_then(|port_str| port_str.parse().ok()))
+    }
+
+    /// Whether the orchestrator's lock on this pier was left behind by a process that died holding it.
+    pub fn recovered_stale_lock(&self) -> bool {
+        self.filelock.recovered()
+    }
+
+    /// The pid in the runtime's `.vere.lock`, if that process is still alive. A stopped pier with one is being run by
+    /// something other than this orchestrator, such as a runtime that outlived the orchestrator that launched it.
+    pub async fn live_runtime_pid(&self) -> Option<u32> {
+        let contents = fs::read_to_string(self.pier_path().join(".vere.lock")).await.ok()?;
+        let pid = contents.trim().parse::<libc::pid_t>().ok()?;
+        (unsafe { libc::kill(pid, 0) } == 0).then_some(pid as u32)
+    }
+
+    /// Removes what a runtime that didn't shut down cleanly leaves in the pier: its `.vere.lock`, its `.http.ports` file,
+    /// whose ports died with it, and unix sockets nothing is bound to. Fails, removing nothing, if any process still has
+    /// the pier's files open, since then the pier is still being run, whatever its lock says.
+    async fn clear_unclean_shutdown(&self) -> Result<()> {
+        let pier_path = fs::canonicalize(self.pier_path()).await?;
+        let scanned = pier_path.clone();
+        let holders = tokio::task::spawn_blocking(move || reaper::processes_using(scanned.as_ref())).await??;
+        if let Some(pid) = holders.first() {
+            bail!("process {} still has files in {} open", pid, pier_path.to_string_lossy());
+        }
+
+        let mut leftovers = vec![pier_path.join(".vere.lock"), self.portsfile_path()];
+        for dir in [pier_path.clone(), pier_path.join(".urb")] {
+            let Ok(mut entries) = fs::read_dir(&dir).await else { continue };
+            while let Some(entry) = entries.next().await {
+                let entry = entry?;
+                if entry.file_type().await?.is_socket() {
+                    let path = entry.path();
+                    if net_util::unix_socket_bound(path.as_ref())? {
+                        bail!("a process is still listening on {}", path.to_string_lossy());
+                    }
+                    leftovers.push(path);
+                }
+            }
+        }
+        for path in leftovers {
+            match fs::remove_file(&path).await {
+                Ok(()) => {
+                    log::warn!("removed {}, left by a runtime that didn't shut down cleanly", path.to_string_lossy());
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the runtime's `.vere.lock` if the process it names is gone, which happens when the runtime is killed.
+    /// Fails if that process is still alive.
+    async fn clear_stale_vere_lock(&self) -> Result<()> {
+        let lock_path = self.pier_path().join(".vere.lock");
+        let contents = match fs::read_to_string(&lock_path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if let Ok(pid) = contents.trim().parse::<libc::pid_t>() {
+            if unsafe { libc::kill(pid, 0) } == 0 {
+                bail!("runtime {} still holds {}", pid, lock_path.to_string_lossy());
+            }
+        }
+        log::warn!("removing stale {}", lock_path.to_string_lossy());
+        fs::remove_file(&lock_path).await?;
+        Ok(())
+    }
+
+    fn keyfile_path(&self) -> PathBuf {
+        self.meta_path.join("keyfile")
+    }
+
+    fn archive_path(&self) -> PathBuf {
         self.meta_path.join("archive")
     }
 
@@ -511,127 +1903,862 @@ impl PierState {
         self.meta_path.join("unpack")
     }
 
+    /// The login code last retrieved from the ship, if any, so it can be served without booting the ship.
+    pub fn code_cache(&self) -> CodeCache {
+        CodeCache(self.meta_path.join("code.sealed"))
+    }
+
+    /// The secrets handed to the ship after every boot.
+    pub fn secrets_file(&self) -> SecretsFile {
+        SecretsFile(self.meta_path.join("secrets.sealed"))
+    }
+
     pub async fn release_from_dry_dock(
         mut self,
-        http_port_issuer: &mut TcpPortIssuer,
-        ames_port_issuer: &mut TcpPortIssuer,
+        http_port_issuer: &AsyncMutex<PortIssuer>,
+        ames_port_issuer: &AsyncMutex<PortIssuer>,
     ) -> Result<Self> {
         let mut ship = self.launch(http_port_issuer, ames_port_issuer).await?;
-        ship.pier.name = Some(ship.dojo("our").await?.trim().to_owned());
+        let name = ship.dojo("our").await?.trim().to_owned();
+        ship.pier.name = Some(name.clone());
+        ship.pier.config.name = Some(name);
+        // Saved before the pier moves into port, where a config without a name wouldn't load.
+        ship.pier.save_config().await?;
         self = ship.shutdown().await?;
+        self.move_into_port().await?;
+
+        Ok(self)
+    }
 
-        let mut new_meta_path = HARBOR.port_path().await?;
-        new_meta_path.push(self.name.as_ref().unwrap());
+    /// Moves a named pier out of the dry dock into port, where it is managed under its name.
+    async fn move_into_port(&mut self) -> Result<()> {
+        let new_meta_path = HARBOR.pier_path_in_port(self.name.as_ref().unwrap()).await?;
+        if let Some(shard) = new_meta_path.parent() {
+            fs::create_dir_all(shard).await?;
+        }
 
         let old_meta_path = self.meta_path.clone();
         self.meta_path = new_meta_path;
 
         fs::rename(&old_meta_path, &self.meta_path).await?;
         self.dry_docked = false;
+        STORAGE.relocate(&old_meta_path.join("pier"), &self.pier_path()).await?;
+        Ok(())
+    }
 
-        Ok(self)
+    /// Discards the pier's event log and state for a factory reset after its ship was breached, keeping its meta
+    /// directory, and with it its id, config, ports and secrets. Its next launch boots it from scratch with `key`, the
+    /// keyfile for its new life.
+    pub async fn reset_for_breach(&mut self, key: &str) -> Result<()> {
+        let Some(name) = self.name.clone().filter(|_| !self.dry_docked) else {
+            bail!("only piers in port can be reset");
+        };
+        if self.config.fake || self.config.clone_of.is_some() {
+            bail!("fake ships and clones can't be breached");
+        }
+        keyfile::check(key, &name)?;
+
+        let tmp_path = self.keyfile_path().with_extension("tmp");
+        fs::write(&tmp_path, key).await?;
+        ownership::apply(&tmp_path).await?;
+        fs::rename(&tmp_path, self.keyfile_path()).await?;
+
+        // Saved first, so that the pier can still be loaded if the orchestrator dies once it is gone.
+        self.config.pending_breach = true;
+        self.config.rift = None;
+        self.save_config().await?;
+        let pier_path = self.pier_path();
+        if pier_path.exists().await {
+            STORAGE.remove(&pier_path).await?;
+        }
+        self.code_cache().invalidate().await?;
+        self.initialized = false;
+        Ok(())
+    }
+
+    /// Moves the pier out of port into the harbor's trash, after which it is no longer managed. Trashed piers are kept,
+    /// named after the ship and when they were trashed, until an operator deletes them. Returns where it went.
+    pub async fn trash(mut self) -> Result<PathBuf> {
+        let Some(name) = self.name.clone().filter(|_| !self.dry_docked) else {
+            bail!("only piers in port can be trashed");
+        };
+        let mut trash_path = HARBOR.trash_path().await?;
+        trash_path.push(format!("{}-{}", name, OffsetDateTime::now_utc().unix_timestamp()));
+
+        let old_meta_path = self.meta_path.clone();
+        fs::rename(&old_meta_path, &trash_path).await?;
+        self.meta_path = trash_path.clone();
+        STORAGE.relocate(&old_meta_path.join("pier"), &self.pier_path()).await?;
+        Ok(trash_path)
     }
 
+    /// Starts the runtime on fresh ports. The returned ship is still booting; see `Ship::ready`.
     pub async fn launch(
         mut self,
-        http_port_issuer: &mut TcpPortIssuer,
-        ames_port_issuer: &mut TcpPortIssuer,
+        http_port_issuer: &AsyncMutex<PortIssuer>,
+        ames_port_issuer: &AsyncMutex<PortIssuer>,
     ) -> Result<Ship> {
 
-        let ames_port = ames_port_issuer.get_port().await?;
-        let http_port = http_port_issuer.get_port().await?;
+        let previous_ports = self.config.ports;
+        // The issuers are only locked while ports are issued and reserved, so that slow launches don't hold up others.
+        let ames_port = match self.config.fixed_ames_port {
+            // Fake ships find each other on loopback ports of the runtime's choosing.
+            _ if self.config.fake => 0,
+            Some(port) => {
+                if !net_util::udp_port_available(port).await {
+                    bail!("fixed ames port {} is already bound by another process", port);
+                }
+                port
+            },
+            None => {
+                let preferred = previous_ports.map(|ports| ports.ames);
+                ames_port_issuer.lock().await.get_port_preferring(preferred).await?
+            },
+        };
+        let preferred = previous_ports.map(|ports| ports.http);
+        let http_port = http_port_issuer.lock().await.get_port_preferring(preferred).await?;
+
+        if !self.initialized {
+            // Booting from a keyfile may rekey the ship, which changes its code.
+            self.code_cache().invalidate().await?;
+        }
+
+        let run_as = self.ensure_run_as_uid().await?;
+        if let Some(uid) = run_as {
+            privsep::prepare_pier(&self.meta_path, &self.pier_path(), &self.keyfile_path(), uid).await?;
+        }
+        let scratch_path = self.prepare_scratch_dir(run_as).await?;
+
+        // A ports file left by the previous run would name a lens port that may now belong to another ship, and a lock
+        // left by one that died would stop the runtime from starting at all.
+        if self.initialized {
+            self.clear_unclean_shutdown().await?;
+        }
 
-        let proc = if self.initialized {
-            self.config.runtime_version.exec(
-                runtime::Options::launch_existing_pier(&self.pier_path())
-                    .http_port(http_port)
-                    .ames_port(ames_port)
-            ).await?
+        let local_networking = self.local_networking || self.config.clone_of.is_some() || self.config.fake;
+        let (pier_path, keyfile_path) = (self.pier_path(), self.keyfile_path());
+        let mut options = if self.initialized {
+            runtime::Options::launch_existing_pier(&pier_path)
+        } else if self.config.fake {
+            runtime::Options::launch_fake(self.name.as_ref().unwrap(), &pier_path)
+        } else if self.comet {
+            runtime::Options::launch_new_comet(&pier_path)
         } else {
-            if self.comet {
-                self.config.runtime_version.exec(
-                    runtime::Options::launch_new_comet(&self.pier_path())
-                        .http_port(http_port)
-                        .ames_port(ames_port)
-                ).await?
-            } else {
-                let name = self.name.as_ref().unwrap();
-                self.config.runtime_version.exec(
-                    runtime::Options::launch_from_keyfile(&self.keyfile_path(), name, &self.pier_path())
-                        .http_port(http_port)
-                        .ames_port(ames_port)
-                ).await?
-            }
+            runtime::Options::launch_from_keyfile(&keyfile_path, self.name.as_ref().unwrap(), &pier_path)
         };
+        options
+            .run_as(run_as)
+            .env(&self.config.env)
+            .scratch_dir(&scratch_path)
+            .http_port(http_port)
+            .local_networking(local_networking)
+            .flags(&self.config.runtime_flags);
+        if !self.config.fake {
+            options.ames_port(ames_port);
+        }
+        let proc = self.config.runtime_version.exec(&options).await?;
+
+        let ports = PierPorts { http: http_port, ames: ames_port };
+        // A throwaway copy of a ship that is live elsewhere has no use for ports of its own.
+        if !self.local_networking && previous_ports != Some(ports) {
+            let mut http_port_issuer = http_port_issuer.lock().await;
+            let mut ames_port_issuer = ames_port_issuer.lock().await;
+            if let Some(previous) = previous_ports {
+                http_port_issuer.unreserve(previous.http);
+                ames_port_issuer.unreserve(previous.ames);
+            }
+            http_port_issuer.reserve(ports.http);
+            ames_port_issuer.reserve(ports.ames);
+            self.config.ports = Some(ports);
+        }
 
         self.initialized = true;
+        self.config.pending_breach = false;
+        let now = OffsetDateTime::now_utc();
+        self.config.lifecycle.first_booted_at.get_or_insert(now);
+        self.config.lifecycle.last_launched_at = Some(now);
+        // The runtime is already up, so this is no reason to fail the launch; dropping the pier retries the write.
+        if let Err(err) = self.save_config().await {
+            log::warn!("failed to save config of pier {}: {:#}", self.id.hyphenated(), err);
+        }
 
-        Ok(Ship::new(self, proc, ames_port, http_port).await?)
+        Ship::watch(self, proc, http_port, ames_port)
     }
 }
 
+/// Writes a pier config to a temporary file and renames it over the old one, so that dying mid-write leaves the
+/// previous config rather than a truncated one.
+fn write_config_sync(path: &std::path::Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    let mut file = std::fs::File::create(&tmp_path)?;
+    std::io::Write::write_all(&mut file, contents)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)
+}
+
 impl Drop for PierState {
+    /// A last resort for config changes that were never saved with `save_config`.
     fn drop(&mut self) {
-        let config_file = std::fs::OpenOptions::new()
-            .create(true)
-            .read(false)
-            .write(true)
-            .truncate(true)
-            .open(PierState::config_path_given_meta(self.meta_path.clone()));
-        let config_file = match config_file {
-            Ok(f) => f,
+        let json = match serde_json::to_vec(&self.config) {
+            Ok(json) => json,
             Err(err) => {
                 log::error!("encountered error during PierState cleanup: {}", err);
                 return
             },
         };
+        if json == self.saved_config {
+            return;
+        }
 
-        _ = serde_json::to_writer(config_file, &self.config).unwrap_or_else(|err| {
+        log::warn!("pier {} was dropped with unsaved config changes", self.id.hyphenated());
+        let path = PierState::config_path_given_meta(self.meta_path.clone());
+        if let Err(err) = write_config_sync(path.as_ref(), &json) {
             log::error!("encountered error during PierState cleanup: {}", err);
-        });
+        }
+    }
+}
+
+/// How far a shutdown had to escalate before the runtime exited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ShutdownOutcome {
+    /// The runtime exited on `|exit`.
+    Exited,
+    /// The runtime exited after SIGINT or SIGTERM.
+    Signalled,
+    /// The runtime had to be killed.
+    Killed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadinessProbe {
+    Lens,
+    Eyre,
+}
+
+impl std::str::FromStr for ReadinessProbe {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lens" => Ok(ReadinessProbe::Lens),
+            "eyre" => Ok(ReadinessProbe::Eyre),
+            _ => bail!("unknown readiness probe: {}", s),
+        }
+    }
+}
+
+/// How far a booting ship has got, in the order it gets there.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BootStage {
+    /// Waiting for the runtime to be launched.
+    #[default]
+    Launching,
+    ProcessStarted,
+    /// The runtime has written its `.http.ports` file.
+    PortsBound,
+    LensUp,
+    /// Eyre serves the login page.
+    EyreUp,
+}
+
+/// The stage a boot is at, shared between `Ship::ready` and whatever reports on the boot.
+#[derive(Clone, Debug, Default)]
+pub struct BootProgress(Arc<Mutex<BootStage>>);
+
+impl BootProgress {
+    pub fn stage(&self) -> BootStage {
+        *self.0.lock().unwrap()
+    }
+
+    fn advance(&self, stage: BootStage) {
+        let mut current = self.0.lock().unwrap();
+        *current = stage.max(*current);
     }
 }
 
+/// Resolves with the runtime's exit status once it exits, however it exits. None if it couldn't be waited on.
+pub type ExitWatch = Shared<BoxFuture<'static, Option<ExitStatus>>>;
+
 pub struct Ship {
     pier: PierState,
-    proc: process::Child,
+    /// Pid of the runtime, which is also the id of the process group holding its serfs.
+    pid: u32,
+    exited: ExitWatch,
     http_port: u16,
     ames_port: u16,
     lens_port: u16,
+    /// Whether the runtime's process group has been frozen with SIGSTOP. Shared with its `Lens` handles.
+    paused: Arc<AtomicBool>,
+    output_events: Option<mpsc::UnboundedReceiver<ShipEvent>>,
+    eyre: Arc<eyre::Session>,
 }
 
 impl Ship {
-    async fn new(pier: PierState, proc: process::Child, http_port: u16, ames_port: u16) -> Result<Self> {
-        let portsfile_path = pier.pier_path().join(&Path::new(".http.ports"));
-        let portsdesc = fs::read_to_string(&portsfile_path).await?;
+    /// Hands the runtime to a task that waits for it to exit, so that its exit is noticed even if nothing is waiting.
+    fn watch(pier: PierState, mut proc: process::Child, http_port: u16, ames_port: u16) -> Result<Self> {
+        let pid = proc.id().ok_or_else(|| anyhow!("runtime exited immediately"))?;
+        let (events_tx, events_rx) = mpsc::unbounded();
+        if let (Some(stdout), Some(stderr)) = (proc.stdout.take(), proc.stderr.take()) {
+            actix_web::rt::spawn(shiplog::capture(pier.logs_path(), stdout, stderr, events_tx));
+        }
+        let (tx, rx) = oneshot::channel();
+        actix_web::rt::spawn(async move {
+            let status = proc.wait().await
+                .map_err(|e| log::error!("failed to wait for runtime {}: {}", pid, e))
+                .ok();
+            _ = tx.send(status);
+        });
+        let exited = rx.map(|status| status.ok().flatten()).boxed().shared();
+        let eyre = Arc::new(eyre::Session::new(http_port, pier.name().unwrap_or_default()));
+        Ok(Ship {
+            pier,
+            pid,
+            exited,
+            http_port,
+            ames_port,
+            lens_port: 0,
+            paused: Arc::new(AtomicBool::new(false)),
+            output_events: Some(events_rx),
+            eyre,
+        })
+    }
 
-        let lens_port: u16 = portsdesc.lines()
-            .filter(|line| line.ends_with("loopback"))
-            .map(|line| line.split_ascii_whitespace().nth(0))
-            .nth(0)
-            .flatten()
-            .and_then(|port_str| port_str.parse().ok())
-            .ok_or(anyhow!("could not decode .http.ports file: {}", portsfile_path.to_string_lossy()))?;
+    /// Waits for a freshly launched runtime to become ready: it must write its `.http.ports` file, answer on its lens
+    /// port and, with the eyre `READINESS_PROBE`, serve its login page. `progress` follows it through those stages.
+    /// Fails if the runtime exits first or isn't ready within `BOOT_TIMEOUT`, in which case it is killed.
+    pub async fn ready(self, progress: &BootProgress) -> Result<Self> {
+        let mut ship = self;
+        progress.advance(BootStage::ProcessStarted);
+        let deadline = std::time::Instant::now() + *BOOT_TIMEOUT;
+        let mut last_err = anyhow!("no .http.ports file");
+
+        loop {
+            if let Some(status) = ship.exit_status() {
+                bail!("runtime exited before it was ready: {}", describe_exit(status));
+            }
+            if ship.lens_port == 0 {
+                match ship.pier.lens_port().await {
+                    Ok(Some(port)) => {
+                        ship.lens_port = port;
+                        progress.advance(BootStage::PortsBound);
+                    },
+                    Ok(None) => {},
+                    Err(e) => last_err = e,
+                }
+            }
+            if ship.lens_port != 0 && progress.stage() < BootStage::LensUp {
+                match ship.lens_request("our", Some(Duration::from_secs(5))).await {
+                    Ok(_) => progress.advance(BootStage::LensUp),
+                    Err(e) => last_err = e,
+                }
+            }
+            if progress.stage() >= BootStage::LensUp {
+                match *READINESS_PROBE {
+                    ReadinessProbe::Lens => return Ok(ship),
+                    ReadinessProbe::Eyre => match ship.check_login_page().await {
+                        Ok(()) => {
+                            progress.advance(BootStage::EyreUp);
+                            return Ok(ship);
+                        },
+                        Err(e) => last_err = e,
+                    },
+                }
+            }
+            if std::time::Instant::now() >= deadline {
+                break;
+            }
+            actix_web::rt::time::sleep(BOOT_POLL_INTERVAL).await;
+        }
 
-        Ok(Ship {
-            pier, proc, http_port, ames_port,
-            lens_port,
+        log::error!("{} was not ready after {:?}; killing it", ship.pier.name().unwrap_or_default(), *BOOT_TIMEOUT);
+        reaper::signal_process_group(ship.pid, libc::SIGKILL)?;
+        ship.exited.clone().await;
+        reaper::untrack(ship.pid);
+        ship.pier.clear_stale_vere_lock().await?;
+        bail!("runtime was not ready after {:?}: {:#}", *BOOT_TIMEOUT, last_err)
+    }
+
+    pub fn pier(&self) -> &PierState {
+        &self.pier
+    }
+
+    /// For updating the pier's settings while the ship runs. Most only take effect on the next launch.
+    pub fn pier_mut(&mut self) -> &mut PierState {
+        &mut self.pier
+    }
+
+    pub fn http_port(&self) -> u16 {
+        self.http_port
+    }
+
+    /// 0 for fake ships, which aren't issued one.
+    pub fn ames_port(&self) -> u16 {
+        self.ames_port
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// What the runtime's output says is happening to it, until it exits. Can only be taken once.
+    pub fn take_output_events(&mut self) -> Option<mpsc::UnboundedReceiver<ShipEvent>> {
+        self.output_events.take()
+    }
+
+    /// Freezes the runtime and its serfs with SIGSTOP. They keep their memory but get no CPU, and the ship answers
+    /// nothing, until resumed.
+    pub fn pause(&mut self) -> Result<()> {
+        let pid = self.pid().ok_or_else(|| anyhow!("runtime has exited"))?;
+        // Set first, so that no new lens call gets stuck on the frozen runtime.
+        self.paused.store(true, Ordering::SeqCst);
+        if let Err(e) = reaper::signal_process_group(pid, libc::SIGSTOP) {
+            self.paused.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Thaws a paused runtime and its serfs with SIGCONT.
+    pub fn resume(&mut self) -> Result<()> {
+        let pid = self.pid().ok_or_else(|| anyhow!("runtime has exited"))?;
+        reaper::signal_process_group(pid, libc::SIGCONT)?;
+        self.paused.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Pid of the runtime, which is also the id of the process group holding its serfs. None once it has exited.
+    pub fn pid(&self) -> Option<u32> {
+        self.exit_status().is_none().then_some(self.pid)
+    }
+
+    /// The runtime's exit status if it has exited, with None inside if it couldn't be determined.
+    pub fn exit_status(&self) -> Option<Option<ExitStatus>> {
+        self.exited.clone().now_or_never()
+    }
+
+    /// Resolves once the runtime exits.
+    pub fn exited(&self) -> ExitWatch {
+        self.exited.clone()
+    }
+
+    /// Waits up to `timeout` for the runtime to exit, returning whether it did.
+    async fn wait_for_exit(&mut self, timeout: Duration) -> Result<bool> {
+        Ok(actix_web::rt::time::timeout(timeout, self.exited.clone()).await.is_ok())
+    }
+
+    /// Stops the runtime as gently as it will allow: `|exit`, then SIGINT, SIGTERM and finally SIGKILL to its whole
+    /// process group, each after the previous step's timeout. Abrupt kills can lose events and corrupt snapshots, so
+    /// they are logged. Serfs left behind by the runtime are killed, and a stale `.vere.lock` is removed, so the pier
+    /// is ready to boot again.
+    pub async fn shutdown(self) -> Result<PierState> {
+        let (pier, _) = self.shutdown_with(*SHUTDOWN_EXIT_TIMEOUT, *SHUTDOWN_SIGNAL_TIMEOUT).await?;
+        Ok(pier)
+    }
+
+    /// Like `shutdown`, but escalates quickly enough to be done within about `grace`: half of it is given to `|exit`,
+    /// and a quarter to each of SIGINT and SIGTERM.
+    pub async fn shutdown_within(self, grace: Duration) -> Result<(PierState, ShutdownOutcome)> {
+        self.shutdown_with(grace / 2, grace / 4).await
+    }
+
+    async fn shutdown_with(
+        mut self,
+        exit_timeout: Duration,
+        signal_timeout: Duration,
+    ) -> Result<(PierState, ShutdownOutcome)> {
+        let name = self.pier.name().unwrap_or_default().to_owned();
+        let pid = self.pid;
+        let mut outcome = ShutdownOutcome::Exited;
+        // Already exited on its own, e.g. it crashed, but its serfs and lock may still need cleaning up.
+        let mut exited = self.exit_status().is_some();
+
+        // A frozen runtime can't exit gracefully, or even handle SIGINT.
+        if self.paused() && !exited {
+            self.resume()?;
+        }
+
+        if !exited {
+            // The lens connection usually drops as the ship exits, so an error here means nothing.
+            _ = self.lens_request("|exit", Some(exit_timeout.min(Duration::from_secs(5)))).await;
+            exited = self.wait_for_exit(exit_timeout).await?;
+        }
+        for (signal, signal_name) in [(libc::SIGINT, "SIGINT"), (libc::SIGTERM, "SIGTERM")] {
+            if exited {
+                break;
+            }
+            log::warn!("{} did not exit in time; sending {}", name, signal_name);
+            reaper::signal_process_group(pid, signal)?;
+            outcome = ShutdownOutcome::Signalled;
+            exited = self.wait_for_exit(signal_timeout).await?;
+        }
+        if !exited {
+            log::error!("{} did not exit in time; killing it, which may lose events", name);
+            reaper::signal_process_group(pid, libc::SIGKILL)?;
+            outcome = ShutdownOutcome::Killed;
+            self.exited.clone().await;
+        }
+        reaper::untrack(pid);
+
+        // The runtime is gone, so anything left in its group is an orphaned serf still holding the loom.
+        reaper::signal_process_group(pid, libc::SIGKILL)?;
+        self.pier.clear_stale_vere_lock().await?;
+        Ok((self.pier, outcome))
+    }
+
+    async fn code(&self) -> Result<String> {
+        self.lens().code().await
+    }
+
+    /// Checks that eyre serves the login page, without logging in.
+    async fn check_login_page(&self) -> Result<()> {
+        let res = reqwest::Client::new()
+            .get(format!("http://127.0.0.1:{}/~/login", self.http_port))
+            .timeout(Duration::from_secs(5))
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            bail!("eyre answered GET /~/login with {}", res.status());
+        }
+        Ok(())
+    }
+
+    /// Logs into the ship's web interface with its `+code`, to check that it gets as far as serving a user.
+    pub async fn check_login(&self) -> Result<()> {
+        self.eyre.login(&self.code().await?).await
+    }
+
+    pub async fn dojo(&self, eval_str: &str) -> Result<String> {
+        self.lens().dojo(eval_str).await
+    }
+
+    async fn lens_request(&self, eval_str: &str, timeout: Option<Duration>) -> Result<String> {
+        self.lens().request(eval_str, timeout).await
+    }
+
+    /// A handle for lens calls to the ship that outlives any lock on the app state, so that slow calls don't hold it.
+    pub fn lens(&self) -> Lens {
+        Lens {
+            name: self.pier.name().unwrap_or_default().to_owned(),
+            port: self.lens_port,
+            paused: self.paused.clone(),
+            code_cache: self.pier.code_cache(),
+            eyre: self.eyre.clone(),
+        }
+    }
+}
+
+/// Where a pier caches its ship's login code, sealed. Read and written through this handle, the cache can be used
+/// without holding on to the pier.
+#[derive(Clone, Debug)]
+pub struct CodeCache(PathBuf);
+
+impl CodeCache {
+    pub async fn load(&self) -> Result<Option<String>> {
+        let sealed = match fs::read(&self.0).await {
+            Ok(sealed) => sealed,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(String::from_utf8(seal::unseal(&sealed).await?)?))
+    }
+
+    async fn store(&self, code: &str) -> Result<()> {
+        let sealed = seal::seal(code.as_bytes()).await?;
+        let tmp_path = self.0.with_extension("sealed.tmp");
+        fs::write(&tmp_path, sealed).await?;
+        fs::rename(&tmp_path, &self.0).await?;
+        Ok(())
+    }
+
+    async fn invalidate(&self) -> Result<()> {
+        match fs::remove_file(&self.0).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Where a pier's secrets are kept, sealed. Read and written through this handle, the file can be used without holding
+/// on to the pier.
+#[derive(Clone, Debug)]
+pub struct SecretsFile(PathBuf);
+
+impl SecretsFile {
+    pub async fn load(&self) -> Result<secrets::Secrets> {
+        let sealed = match fs::read(&self.0).await {
+            Ok(sealed) => sealed,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(secrets::Secrets::new()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(serde_json::from_slice(&seal::unseal(&sealed).await?)?)
+    }
+
+    pub async fn save(&self, secrets: &secrets::Secrets) -> Result<()> {
+        let sealed = seal::seal(&serde_json::to_vec(secrets)?).await?;
+        let tmp_path = self.0.with_extension("sealed.tmp");
+        fs::write(&tmp_path, sealed).await?;
+        fs::rename(&tmp_path, &self.0).await?;
+        Ok(())
+    }
+}
+
+/// Where to make lens calls to a running ship. See `Ship::lens`.
+#[derive(Clone, Debug)]
+pub struct Lens {
+    name: String,
+    port: u16,
+    paused: Arc<AtomicBool>,
+    code_cache: CodeCache,
+    eyre: Arc<eyre::Session>,
+}
+
+impl Lens {
+    /// The ship's web login code (`+code`), without the leading sig. The code is also cached with the pier so that it
+    /// can be served while the ship is stopped.
+    pub async fn code(&self) -> Result<String> {
+        let output = self.dojo("+code").await?;
+        let code = output.trim().trim_matches('"').trim_start_matches('~');
+        if code.is_empty() || !code.split('-').all(|word| word.len() == 6 && word.chars().all(|c| c.is_ascii_lowercase())) {
+            bail!("unexpected +code output from urbit: {:?}", output);
+        }
+
+        if let Err(e) = self.code_cache.store(code).await {
+            log::warn!("failed to cache +code for {}: {}", self.name, e);
+        }
+        Ok(code.to_owned())
+    }
+
+    /// Changes the ship's web login code, logging out existing sessions, and returns the new one.
+    pub async fn reset_code(&self) -> Result<String> {
+        self.code_cache.invalidate().await?;
+        self.dojo("|code %reset").await?;
+        self.code().await
+    }
+
+    /// The desks installed on the ship and where they get updates from, as reported by `+vats`.
+    pub async fn vats(&self) -> Result<Vec<queries::DeskInfo>> {
+        queries::parse_vats(&self.dojo("+vats").await?)
+    }
+
+    /// The `%cz` hash of one of the ship's desks.
+    pub async fn desk_hash(&self, desk: &str) -> Result<String> {
+        if !queries::is_valid_desk(desk) {
+            bail!("invalid desk name: {:?}", desk);
+        }
+        queries::parse_atom(&self.dojo(&queries::desk_hash_expr(desk)).await?)
+    }
+
+    pub async fn identity(&self) -> Result<queries::Identity> {
+        Ok(queries::Identity {
+            ship: queries::parse_atom(&self.dojo("our").await?)?,
+            rank: queries::parse_atom(&self.dojo("(clan:title our)").await?)?,
+            sponsor: queries::parse_atom(&self.dojo("(sein:title our now our)").await?)?,
+            base_hash: self.desk_hash("base").await?,
         })
     }
 
-    pub async fn shutdown(mut self) -> Result<PierState> {
-        self.proc.kill().await?;
-        Ok(self.pier)
+    /// The ship's idea of the current time, as dojo's `now`.
+    pub async fn now(&self) -> Result<time::OffsetDateTime> {
+        clock::parse_da(&self.dojo_with_timeout("now", Some(Duration::from_secs(10))).await?)
+    }
+
+    /// Runs the pier's startup commands, as given by `PierState::startup_commands`, in order. A failed command doesn't
+    /// stop the ones after it, which may not depend on it.
+    pub async fn run_startup_commands(&self, commands: &[String]) -> Vec<StartupCommandOutcome> {
+        let name = &self.name;
+        let mut outcomes = Vec::new();
+        for command in commands {
+            let result = self.dojo(command).await;
+            if let Err(e) = &result {
+                log::warn!("startup command for {} failed: {}: {:#}", name, command, e);
+            }
+            outcomes.push(StartupCommandOutcome {
+                command: command.clone(),
+                ok: result.is_ok(),
+                error: result.as_ref().err().map(|e| format!("{:#}", e)),
+                output: result.ok(),
+            });
+        }
+        outcomes
+    }
+
+    /// The runtime's memory report (`|mass`).
+    pub async fn mass(&self) -> Result<String> {
+        self.dojo("|mass").await
+    }
+
+    /// Spawns a moon of this ship with `|moon`, either the given one or a random one, and returns its @p (without the
+    /// leading sig) and keyfile contents.
+    pub async fn moon(&self, name: Option<&str>) -> Result<(String, String)> {
+        let command = match name {
+            Some(name) => format!("|moon ~{}", name.trim_start_matches('~')),
+            None => "|moon".to_owned(),
+        };
+        let output = self.dojo(&command).await?;
+
+        // |moon prints `moon: ~name` followed by the keyfile, an @uw beginning with 0w.
+        let moon = output.split(|c: char| c.is_whitespace() || c == '"')
+            .skip_while(|token| *token != "moon:")
+            .nth(1)
+            .map(|name| name.trim_start_matches('~'))
+            .filter(|name| ShipClass::of_name(name) == Some(ShipClass::Moon));
+        let key = output.split(|c: char| c.is_whitespace() || c == '"')
+            .find(|token| token.starts_with("0w"));
+
+        match (moon, key) {
+            (Some(moon), Some(key)) => Ok((moon.to_owned(), key.to_owned())),
+            _ => bail!("unexpected |moon output from urbit: {:?}", output),
+        }
+    }
+
+    /// Installs `desk` from `source`, an @p, with `|install`, under the name `local` if given. Kiln syncs the desk in
+    /// the background, so this returns before it is installed; `+vats` shows its progress.
+    pub async fn install_desk(&self, source: &str, desk: &str, local: Option<&str>) -> Result<String> {
+        let source = patp::render(patp::parse(source)?);
+        for desk in std::iter::once(desk).chain(local) {
+            if !queries::is_valid_desk(desk) {
+                bail!("invalid desk name: {:?}", desk);
+            }
+        }
+        let command = match local {
+            Some(local) => format!("|install ~{} %{}, =local %{}", source, desk, local),
+            None => format!("|install ~{} %{}", source, desk),
+        };
+        self.dojo(&command).await
+    }
+
+    /// Stops the agents on one of the ship's desks with `|suspend`, keeping their state.
+    pub async fn suspend_desk(&self, desk: &str) -> Result<String> {
+        if !queries::is_valid_desk(desk) {
+            bail!("invalid desk name: {:?}", desk);
+        }
+        self.dojo(&format!("|suspend %{}", desk)).await
+    }
+
+    /// Restarts the agents on a suspended desk with `|revive`.
+    pub async fn revive_desk(&self, desk: &str) -> Result<String> {
+        if !queries::is_valid_desk(desk) {
+            bail!("invalid desk name: {:?}", desk);
+        }
+        self.dojo(&format!("|revive %{}", desk)).await
+    }
+
+    /// Where the ship gets kernel updates from and what its `%base` desk is at, from `+vats %base`.
+    pub async fn ota_status(&self) -> Result<queries::DeskInfo> {
+        queries::parse_vats(&self.dojo("+vats %base").await?)?
+            .into_iter()
+            .find(|info| info.desk == "base")
+            .ok_or_else(|| anyhow!("+vats %base didn't report %base"))
+    }
+
+    /// Stops `%base` from taking updates from its source with `|pause-updates`, until `resume_updates`.
+    pub async fn pause_updates(&self) -> Result<String> {
+        self.dojo("|pause-updates").await
+    }
+
+    pub async fn resume_updates(&self) -> Result<String> {
+        self.dojo("|resume-updates").await
+    }
+
+    /// Switches the ship to getting kernel updates from `source`, an @p, with `|ota`.
+    pub async fn set_ota_source(&self, source: &str) -> Result<String> {
+        self.dojo(&format!("|ota ~{}", patp::render(patp::parse(source)?))).await
+    }
+
+    /// Scries an agent through eyre, for richer answers than the lens gives. See `eyre::Session::scry`.
+    pub async fn scry(&self, app: &str, path: &str) -> Result<serde_json::Value> {
+        self.eyre.scry(app, path, || self.code()).await
+    }
+
+    /// Pokes an agent through eyre and waits for its ack. See `eyre::Session::poke`.
+    pub async fn poke(&self, app: &str, mark: &str, json: serde_json::Value) -> Result<()> {
+        self.eyre.poke(app, mark, json, || self.code()).await
+    }
+
+    /// Subscribes to an agent through eyre. See `eyre::Session::subscribe`.
+    pub async fn subscribe(&self, app: &str, path: &str) -> Result<BoxStream<'static, Result<serde_json::Value>>> {
+        self.eyre.subscribe(app, path, || self.code()).await
+    }
+
+    /// A cookie logged into the ship's web interface, for passing requests through to it.
+    pub async fn eyre_cookie(&self) -> Result<String> {
+        self.eyre.cookie(|| self.code()).await
+    }
+
+    /// Logs in again next time, after the ship has turned `eyre_cookie` away.
+    pub fn forget_eyre_cookie(&self) {
+        self.eyre.forget_cookie()
+    }
+
+    /// Defragments the running ship's loom.
+    pub async fn pack(&self) -> Result<String> {
+        self.dojo("|pack").await
+    }
+
+    /// Deduplicates the running ship's nouns. This can take a long time and much memory on large ships.
+    pub async fn meld(&self) -> Result<String> {
+        self.dojo("|meld").await
+    }
+
+    /// Hands the given secrets to the ship. All of them are attempted; the error names the ones that failed.
+    pub async fn inject_secrets(&self, secrets: secrets::Secrets) -> Result<Vec<String>> {
+        let mut injected = Vec::new();
+        let mut failed = Vec::new();
+        for (name, secret) in secrets {
+            match self.dojo(&secret.render()).await {
+                Ok(_) => injected.push(name),
+                Err(e) => {
+                    log::warn!("failed to inject secret {} into {}: {}", name, self.name, e);
+                    failed.push(name);
+                },
+            }
+        }
+        if !failed.is_empty() {
+            bail!("failed to inject secrets: {}", failed.join(", "));
+        }
+        Ok(injected)
     }
 
     pub async fn dojo(&self, eval_str: &str) -> Result<String> {
+        self.dojo_with_timeout(eval_str, None).await
+    }
+
+    /// Evaluates a dojo expression through the lens, retrying per `LENS_RETRY` if the ship drops the request. Commands
+    /// that may change the ship's state are only retried if the connection was refused, so they never run twice.
+    /// Each attempt gives up after `timeout`, or `LENS_TIMEOUT` if None.
+    pub async fn dojo_with_timeout(&self, eval_str: &str, timeout: Option<Duration>) -> Result<String> {
+        let read_only = dojo_is_read_only(eval_str);
+        let (result, retries) = retry::LENS_RETRY
+            .run(|| self.request(eval_str, timeout), |e| read_only || is_connect_error(e))
+            .await;
+        if retries > 0 {
+            LENS_RETRIES.fetch_add(retries as u64, Ordering::Relaxed);
+            // The command isn't logged, as it may carry a secret.
+            let name = &self.name;
+            match &result {
+                Ok(_) => log::info!("lens call to {} succeeded after {} retries", name, retries),
+                Err(e) => log::warn!("lens call to {} failed after {} retries: {}", name, retries, e),
+            }
+        }
+        result
+    }
+
+    /// A single lens request, without retries. Fails straight away with `ShipPausedError` if the ship is paused, as a
+    /// frozen runtime would leave the call hanging until it timed out.
+    async fn request(&self, eval_str: &str, timeout: Option<Duration>) -> Result<String> {
+        if self.paused.load(Ordering::SeqCst) {
+            return Err(ShipPausedError(self.name.clone()).into());
+        }
         let res_json = reqwest::Client::new()
-            .post(format!("http://127.0.0.1:{}", self.lens_port))
+            .post(format!("http://127.0.0.1:{}", self.port))
             .header("Content-type", "application/json")
             .json(&serde_json::json!({
                 "source": { "dojo": eval_str },
                 "sink": { "stdout": null },
             }))
+            .timeout(timeout.unwrap_or(*LENS_TIMEOUT))
             .send()
             .await?
             .bytes()
@@ -644,4 +2771,94 @@ impl Ship {
             _ => bail!("invalid response from urbit"),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Lens calls retried since startup, across all ships.
+static LENS_RETRIES: AtomicU64 = AtomicU64::new(0);
+
+pub fn lens_retries() -> u64 {
+    LENS_RETRIES.load(Ordering::Relaxed)
+}
+
+/// Whether a lens call failed before the request reached the ship, which makes it safe to retry any command.
+fn is_connect_error(e: &Error) -> bool {
+    e.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_connect())
+}
+
+/// A runtime's exit status for humans, e.g. `exit status: 1` or `signal: 9 (SIGKILL)`.
+pub fn describe_exit(status: Option<ExitStatus>) -> String {
+    status.map_or_else(|| "unknown exit status".to_owned(), |status| status.to_string())
+}
+
+/// Whether a dojo expression is safe to evaluate without changing the ship's state. This is a syntactic check on the
+/// leading rune of the command: hood generators (`|`), pokes (`:`), clay writes (`*`), unix exports (`.`), dojo
+/// variable bindings (`=`) and threads (`-`), which may do anything, are all rejected; plain hoon expressions and `+`
+/// generators are allowed.
+pub fn dojo_is_read_only(eval_str: &str) -> bool {
+    match eval_str.trim_start().chars().next() {
+        Some('|' | ':' | '*' | '.' | '=' | '-') => false,
+        Some(_) => true,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_read_only_dojo_commands() {
+        for command in ["now", "(add 2 2)", "+vats", "  +trouble", "our", "~zod", "!>(1)", "%ames", "`@ux`42"] {
+            assert!(dojo_is_read_only(command), "{:?}", command);
+        }
+    }
+
+    #[test]
+    fn rejects_dojo_commands_with_effects() {
+        for command in [
+            "|hi ~zod", ":hood +hood/mass", "*%/foo/txt 'bar'", ".foo/txt 'bar'", "=x 5", "-build-file %/gen/vats/hoon",
+            " |commit %base", "\t:dojo|wipe", "", "   ",
+        ] {
+            assert!(!dojo_is_read_only(command), "{:?}", command);
+        }
+    }
+
+    /// Keeps every log message, for tests to check what was logged.
+    struct CapturingLogger(std::sync::Mutex<Vec<String>>);
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _: &log::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CapturingLogger = CapturingLogger(std::sync::Mutex::new(Vec::new()));
+
+    #[actix_web::test]
+    async fn never_logs_retried_secrets() {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        // Nothing listens on the port, so every attempt is refused and retried.
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let lens = Lens {
+            name: "sampel-palnet".to_owned(),
+            port,
+            paused: Arc::default(),
+            code_cache: CodeCache(PathBuf::from("/nonexistent/code.sealed")),
+            eyre: Arc::new(eyre::Session::new(port, "sampel-palnet")),
+        };
+        let secret = secrets::Secret { command: ":agent &set-key {value}".to_owned(), value: "hunter2".to_owned() };
+        assert!(lens.dojo(&secret.render()).await.is_err());
+
+        let logged = LOGGER.0.lock().unwrap();
+        assert!(logged.iter().any(|message| message.starts_with("lens call to sampel-palnet failed after")));
+        assert!(logged.iter().all(|message| !message.contains("hunter2")), "{:?}", logged);
+    }
+}
@@ -0,0 +1,72 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+/// Something noteworthy a runtime printed, recognized in its output by `OutputParser`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ShipEvent {
+    /// Ames went live on a different UDP port than it was on before, without the runtime restarting.
+    #[serde(rename_all = "camelCase")]
+    AmesRebound { previous_port: u16, port: u16 },
+    /// Kiln merged an update into one of the ship's desks.
+    #[serde(rename_all = "camelCase")]
+    OtaApplied { desk: String },
+    /// Arvo failed to process an event and reported it with `%crud`.
+    #[serde(rename_all = "camelCase")]
+    Crud { tag: Option<String>, line: String },
+    #[serde(rename_all = "camelCase")]
+    SnapshotWritten {},
+}
+
+/// Classifies the lines of one runtime's output by the patterns vere and arvo print. Lines that aren't recognized are
+/// ignored, so a runtime that words things differently only costs events.
+#[derive(Debug, Default)]
+pub struct OutputParser {
+    ames_port: Option<u16>,
+}
+
+/// The first `%tag` in `text`, without the `%`.
+fn first_tag(text: &str) -> Option<&str> {
+    let start = text.find('%')? + 1;
+    let tag = &text[start..];
+    let end = tag.find(|c: char| !(c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')).unwrap_or(tag.len());
+    (end > 0).then(|| &tag[..end])
+}
+
+impl OutputParser {
+    pub fn parse(&mut self, line: &str) -> Option<ShipEvent> {
+        let line = line.trim();
+
+        // `ames: live on 31337`, printed whenever ames binds its socket.
+        if let Some(rest) = line.strip_prefix("ames: live on ") {
+            let port: u16 = rest.split_ascii_whitespace().next()?.parse().ok()?;
+            let previous_port = self.ames_port.replace(port);
+            return match previous_port {
+                Some(previous_port) if previous_port != port => Some(ShipEvent::AmesRebound { previous_port, port }),
+                _ => None,
+            };
+        }
+
+        // `kiln: merge into %base succeeded`, or `kiln: merged into %base` from newer kilns.
+        if let Some(rest) = line.strip_prefix("kiln: ") {
+            let merged = rest.starts_with("merged into %")
+                || (rest.starts_with("merge into %") && rest.ends_with("succeeded"));
+            if !merged {
+                return None;
+            }
+            return Some(ShipEvent::OtaApplied { desk: first_tag(rest)?.to_owned() });
+        }
+
+        // `crud: %ames event failed`, or the raw `[%crud %tag ...]` card.
+        if line.starts_with("crud:") || line.starts_with("[%crud") {
+            let rest = line.trim_start_matches("[%crud").trim_start_matches("crud:");
+            return Some(ShipEvent::Crud { tag: first_tag(rest).map(str::to_owned), line: line.to_owned() });
+        }
+
+        let lowercase = line.to_ascii_lowercase();
+        if lowercase.contains("snapshot") && ["saved", "written", "wrote"].iter().any(|word| lowercase.contains(word)) {
+            return Some(ShipEvent::SnapshotWritten {});
+        }
+
+        None
+    }
+}
@@ -0,0 +1,174 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use actix_web::web::Bytes;
+use async_std::fs;
+use async_std::path::{Path, PathBuf};
+use futures::channel::mpsc;
+use std::env;
+use std::os::unix::fs::MetadataExt;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+use crate::ship_events::{OutputParser, ShipEvent};
+use crate::util::parse_size;
+
+lazy_static! {
+    /// Size at which a ship's runtime log is rotated.
+    pub static ref SHIP_LOG_MAX_SIZE: u64 = env::var_os("NUCLEUS_SHIP_LOG_MAX_SIZE")
+        .map(|s| parse_size(s.to_str().unwrap()).unwrap())
+        .unwrap_or(10 << 20);
+
+    /// How many rotated runtime logs are kept besides the current one.
+    pub static ref SHIP_LOG_FILES: usize = env::var_os("NUCLEUS_SHIP_LOG_FILES")
+        .map(|s| s.to_str().unwrap().parse().unwrap())
+        .unwrap_or(5);
+}
+
+/// How often a followed log is checked for new output.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The current runtime log in a pier's log directory. Rotated logs are `runtime.log.1` (newest) and up.
+pub fn current_log_path(dir: &Path) -> PathBuf {
+    dir.join("runtime.log")
+}
+
+fn rotated_log_path(dir: &Path, n: usize) -> PathBuf {
+    dir.join(format!("runtime.log.{}", n))
+}
+
+/// Appends lines to the current runtime log, rotating it once it reaches `SHIP_LOG_MAX_SIZE`.
+struct RotatingLog {
+    dir: PathBuf,
+    file: fs::File,
+    size: u64,
+}
+
+impl RotatingLog {
+    async fn open(dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir).await?;
+        let file = fs::OpenOptions::new().create(true).append(true).open(current_log_path(&dir)).await?;
+        let size = file.metadata().await?.len();
+        Ok(RotatingLog { dir, file, size })
+    }
+
+    async fn rotate(&mut self) -> Result<()> {
+        self.file.flush().await?;
+        for n in (1..*SHIP_LOG_FILES).rev() {
+            let from = rotated_log_path(&self.dir, n);
+            if from.exists().await {
+                fs::rename(&from, rotated_log_path(&self.dir, n + 1)).await?;
+            }
+        }
+        if *SHIP_LOG_FILES == 0 {
+            fs::remove_file(current_log_path(&self.dir)).await?;
+        } else {
+            fs::rename(current_log_path(&self.dir), rotated_log_path(&self.dir, 1)).await?;
+        }
+        self.file = fs::OpenOptions::new().create(true).append(true).open(current_log_path(&self.dir)).await?;
+        self.size = 0;
+        Ok(())
+    }
+
+    async fn write_line(&mut self, line: &[u8]) -> Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 + 1 > *SHIP_LOG_MAX_SIZE {
+            self.rotate().await?;
+        }
+        self.file.write_all(line).await?;
+        self.file.write_all(b"\n").await?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+}
+
+fn lines<R: AsyncRead + Unpin>(reader: R) -> impl Stream<Item = std::io::Result<Vec<u8>>> {
+    stream::unfold(BufReader::new(reader), |mut reader| async move {
+        let mut line = Vec::new();
+        match reader.read_until(b'\n', &mut line).await {
+            Ok(0) => None,
+            Ok(_) => {
+                if line.last() == Some(&b'\n') {
+                    line.pop();
+                }
+                Some((Ok(line), reader))
+            },
+            Err(e) => Some((Err(e), reader)),
+        }
+    })
+}
+
+/// Copies a runtime's stdout and stderr, line by line, into the log directory `dir` until both are closed, sending
+/// whatever `OutputParser` recognizes to `events`. Output is still read if the log can't be written, so that the
+/// runtime doesn't die of SIGPIPE.
+pub async fn capture<O, E>(dir: PathBuf, stdout: O, stderr: E, events: mpsc::UnboundedSender<ShipEvent>)
+    where O: AsyncRead + Unpin, E: AsyncRead + Unpin
+{
+    let mut log = RotatingLog::open(dir.clone()).await
+        .map_err(|e| log::error!("failed to open runtime log in {}: {}", dir.to_string_lossy(), e))
+        .ok();
+
+    let mut parser = OutputParser::default();
+    let output = stream::select(lines(stdout), lines(stderr));
+    futures::pin_mut!(output);
+    while let Some(line) = output.next().await {
+        if let Some(event) = line.as_ref().ok().and_then(|line| parser.parse(&String::from_utf8_lossy(line))) {
+            // Nobody may be listening, e.g. for a throwaway boot.
+            _ = events.unbounded_send(event);
+        }
+        let Some(log) = log.as_mut() else { continue };
+        let result = match line {
+            Ok(line) => log.write_line(&line).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            log::warn!("failed to write runtime log in {}: {}", dir.to_string_lossy(), e);
+        }
+    }
+    if let Some(log) = log.as_mut() {
+        _ = log.file.flush().await;
+    }
+}
+
+/// The current runtime log in `dir`, then whatever is appended to it, following it across rotations, like `tail -F`.
+/// Ends only if the log can't be read.
+pub fn follow(dir: PathBuf) -> impl Stream<Item = Result<Bytes>> {
+    struct Following {
+        path: PathBuf,
+        file: Option<(fs::File, u64)>,
+    }
+
+    let path = current_log_path(&dir);
+    stream::try_unfold(Following { path, file: None }, |mut following| async move {
+        loop {
+            let current_ino = match fs::metadata(&following.path).await {
+                Ok(metadata) => Some(metadata.ino()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+            // Reopen when the log has been rotated, or first appears.
+            if following.file.as_ref().map(|(_, ino)| Some(*ino)) != Some(current_ino) {
+                if let Some((file, _)) = following.file.as_mut() {
+                    // Drain what was written to the old file before it was rotated.
+                    let mut buf = Vec::new();
+                    file.read_to_end(&mut buf).await?;
+                    if !buf.is_empty() {
+                        return Ok(Some((Bytes::from(buf), following)));
+                    }
+                }
+                following.file = match current_ino {
+                    Some(ino) => Some((fs::File::open(&following.path).await?, ino)),
+                    None => None,
+                };
+            }
+
+            if let Some((file, _)) = following.file.as_mut() {
+                let mut buf = vec![0; 64 * 1024];
+                let n = file.read(&mut buf).await?;
+                if n > 0 {
+                    buf.truncate(n);
+                    return Ok(Some((Bytes::from(buf), following)));
+                }
+            }
+            actix_web::rt::time::sleep(FOLLOW_POLL_INTERVAL).await;
+        }
+    })
+}
@@ -0,0 +1,155 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use async_std::fs;
+use async_std::path::PathBuf;
+use async_std::sync::Mutex;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Display};
+use std::sync::Arc;
+
+use crate::patp;
+use crate::ship::ShipClass;
+
+lazy_static! {
+    /// A JSON file holding the glyphs sigils are drawn from, as published in urbit's sigil-js: an object mapping each
+    /// of the 512 @p syllables to its glyph, as SVG elements drawn on a 128-unit square with `@FG` and `@BG` standing
+    /// for the foreground and background colours. Unset, sigils aren't served.
+    pub static ref SIGIL_GLYPHS: Option<PathBuf> = env::var_os("NUCLEUS_SIGIL_GLYPHS").map(PathBuf::from);
+
+    static ref GLYPHS: Mutex<Option<Arc<Glyphs>>> = Mutex::new(None);
+}
+
+/// The side of the square each glyph is drawn on.
+const TILE: u32 = 128;
+
+pub const MIN_SIZE: u32 = 16;
+pub const MAX_SIZE: u32 = 1024;
+
+/// Each syllable's glyph.
+pub type Glyphs = HashMap<String, String>;
+
+/// Loads the glyphs from `SIGIL_GLYPHS` the first time they are needed. None if it isn't set.
+pub async fn glyphs() -> Result<Option<Arc<Glyphs>>> {
+    let Some(path) = &*SIGIL_GLYPHS else { return Ok(None) };
+    let mut cached = GLYPHS.lock().await;
+    if let Some(glyphs) = &*cached {
+        return Ok(Some(glyphs.clone()));
+    }
+
+    let glyphs: Glyphs = serde_json::from_slice(&fs::read(path).await?)
+        .map_err(|e| anyhow!("invalid sigil glyphs in {}: {}", path.to_string_lossy(), e))?;
+    let glyphs = Arc::new(glyphs);
+    *cached = Some(glyphs.clone());
+    Ok(Some(glyphs))
+}
+
+/// Only galaxies, stars and planets have sigils.
+#[derive(Debug)]
+pub struct NoSigilError(pub String);
+
+impl Display for NoSigilError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "only galaxies, stars and planets have sigils, and ~{} is not one", self.0)
+    }
+}
+
+impl StdError for NoSigilError {}
+
+/// Parses a `#rgb` or `#rrggbb` colour.
+pub fn parse_color(s: &str) -> Result<String> {
+    match s.strip_prefix('#') {
+        Some(hex) if matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()) => Ok(s.to_owned()),
+        _ => bail!("expected a colour like #1a2b3c: {}", s),
+    }
+}
+
+/// Draws the ship's sigil as an SVG `size` pixels square: a galaxy's one glyph fills it, a star's two sit side by side
+/// across its middle, and a planet's four fill it two by two.
+pub fn render(glyphs: &Glyphs, ship: u128, size: u32, foreground: &str, background: &str) -> Result<String> {
+    let name = patp::render(ship);
+    if matches!(patp::class(ship), ShipClass::Moon | ShipClass::Comet) {
+        bail!(NoSigilError(name));
+    }
+    let letters: String = name.chars().filter(|c| *c != '-').collect();
+    let syllables: Vec<&str> = (0..letters.len()).step_by(3).map(|i| &letters[i..i + 3]).collect();
+
+    let columns = syllables.len().min(2) as u32;
+    let rows = (syllables.len() as u32).div_ceil(columns);
+    let side = columns * TILE;
+    let top = (side - rows * TILE) / 2;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{size}\" height=\"{size}\" viewBox=\"0 0 {side} {side}\">\
+            <rect width=\"{side}\" height=\"{side}\" fill=\"{background}\"/>",
+    );
+    for (idx, syllable) in syllables.iter().enumerate() {
+        let glyph = glyphs.get(*syllable).ok_or_else(|| anyhow!("the sigil glyphs have none for {}", syllable))?;
+        let (x, y) = (idx as u32 % columns * TILE, top + idx as u32 / columns * TILE);
+        svg.push_str(&format!(
+            "<g transform=\"translate({x} {y})\">{}</g>",
+            glyph.replace("@FG", foreground).replace("@BG", background),
+        ));
+    }
+    svg.push_str("</svg>");
+    Ok(svg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glyphs() -> Glyphs {
+        ["zod", "mar", "sam", "pel", "pal", "net"].iter()
+            .map(|syllable| (syllable.to_string(), format!("<path id=\"{}\" stroke=\"@FG\" fill=\"@BG\"/>", syllable)))
+            .collect()
+    }
+
+    fn tiles(svg: &str) -> Vec<&str> {
+        svg.split("<g transform=\"translate(").skip(1).map(|tile| tile.split_once(')').unwrap().0).collect()
+    }
+
+    #[test]
+    fn lays_out_glyphs_by_class() {
+        let galaxy = render(&glyphs(), 0, 64, "#fff", "#000").unwrap();
+        assert!(galaxy.contains("width=\"64\" height=\"64\" viewBox=\"0 0 128 128\""));
+        assert_eq!(tiles(&galaxy), ["0 0"]);
+
+        let star = render(&glyphs(), patp::parse("marzod").unwrap(), 128, "#fff", "#000").unwrap();
+        assert!(star.contains("viewBox=\"0 0 256 256\""));
+        assert_eq!(tiles(&star), ["0 64", "128 64"]);
+        assert!(star.find("id=\"mar\"").unwrap() < star.find("id=\"zod\"").unwrap());
+
+        let planet = render(&glyphs(), patp::parse("sampel-palnet").unwrap(), 128, "#fff", "#000").unwrap();
+        assert_eq!(tiles(&planet), ["0 0", "128 0", "0 128", "128 128"]);
+        let order: Vec<usize> = ["sam", "pel", "pal", "net"].iter()
+            .map(|syllable| planet.find(&format!("id=\"{}\"", syllable)).unwrap())
+            .collect();
+        assert!(order.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn fills_in_colours() {
+        let svg = render(&glyphs(), 0, 128, "#123456", "#abc").unwrap();
+        assert!(svg.contains("<rect width=\"128\" height=\"128\" fill=\"#abc\"/>"));
+        assert!(svg.contains("<path id=\"zod\" stroke=\"#123456\" fill=\"#abc\"/>"));
+        assert!(!svg.contains('@'));
+    }
+
+    #[test]
+    fn refuses_moons_and_missing_glyphs() {
+        let moon = render(&glyphs(), 0x1_0000_0000, 128, "#fff", "#000").unwrap_err();
+        assert!(moon.downcast_ref::<NoSigilError>().is_some());
+        assert!(render(&glyphs(), patp::parse("dapnep-ronmyl").unwrap(), 128, "#fff", "#000").is_err());
+    }
+
+    #[test]
+    fn parses_colours() {
+        assert_eq!(parse_color("#1a2B3c").unwrap(), "#1a2B3c");
+        assert_eq!(parse_color("#fff").unwrap(), "#fff");
+        assert!(parse_color("fff").is_err());
+        assert!(parse_color("#ffff").is_err());
+        assert!(parse_color("#ggg").is_err());
+        assert!(parse_color("#fff\"/><script>").is_err());
+    }
+}
@@ -0,0 +1,134 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use std::env;
+use std::fmt::{self, Display};
+use std::time::Duration;
+use time::OffsetDateTime;
+
+use crate::seal;
+
+lazy_static! {
+    /// The longest a signed link may stay valid for.
+    pub static ref MAX_SIGNED_LINK_TTL: Duration = env::var_os("NUCLEUS_MAX_SIGNED_LINK_TTL")
+        .map(|s| crate::util::parse_duration(s.to_str().unwrap()).unwrap())
+        .unwrap_or(Duration::from_secs(7 * 24 * 60 * 60));
+}
+
+/// How long a signed link stays valid for if no lifetime is asked for.
+pub const DEFAULT_SIGNED_LINK_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What a signed link lets whoever holds it do, to the one pier it was signed for.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SignedAction {
+    Restart,
+}
+
+impl SignedAction {
+    pub fn name(self) -> &'static str {
+        match self {
+            SignedAction::Restart => "restart",
+        }
+    }
+}
+
+/// A signed link's signature is wrong for its action, pier and expiry, or the link has expired.
+#[derive(Debug)]
+pub struct InvalidSignatureError(&'static str);
+
+impl Display for InvalidSignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl StdError for InvalidSignatureError {}
+
+/// HMAC-SHA256 of the action, the pier's name and the link's expiry in Unix seconds, one per line, in hex.
+async fn signature(action: SignedAction, name: &str, expires: i64) -> Result<String> {
+    let key = PKey::hmac(&seal::derive_key("signed-links").await?)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(format!("{}\n{}\n{}", action.name(), name, expires).as_bytes())?;
+    Ok(signer.sign_to_vec()?.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// A link to `/signed/pier/{name}/{action}`, relative to the orchestrator's root, that lets whoever holds it take
+/// `action` on the pier `name` until `expires_at`, and nothing else.
+pub async fn sign(action: SignedAction, name: &str, expires_at: OffsetDateTime) -> Result<String> {
+    let expires = expires_at.unix_timestamp();
+    let signature = signature(action, name, expires).await?;
+    Ok(format!("/signed/pier/{}/{}?expires={}&signature={}", name, action.name(), expires, signature))
+}
+
+/// Checks a signed link's query against the action and pier it is being used for.
+pub async fn verify(action: SignedAction, name: &str, expires: i64, signature_hex: &str) -> Result<()> {
+    if OffsetDateTime::now_utc().unix_timestamp() >= expires {
+        bail!(InvalidSignatureError("the link has expired"));
+    }
+    let expected = signature(action, name, expires).await?;
+    if expected.len() != signature_hex.len() || !memcmp::eq(expected.as_bytes(), signature_hex.as_bytes()) {
+        bail!(InvalidSignatureError("the link's signature is invalid"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The expiry and signature from a signed link's query.
+    fn query(link: &str) -> (i64, String) {
+        let (_, query) = link.split_once('?').unwrap();
+        let params: std::collections::HashMap<&str, &str> = query.split('&')
+            .map(|param| param.split_once('=').unwrap())
+            .collect();
+        (params["expires"].parse().unwrap(), params["signature"].to_owned())
+    }
+
+    fn rejected(result: Result<()>) -> &'static str {
+        result.unwrap_err().downcast::<InvalidSignatureError>().unwrap().0
+    }
+
+    #[actix_web::test]
+    async fn verifies_signed_links() {
+        seal::use_test_key().await;
+        let expires_at = OffsetDateTime::now_utc() + Duration::from_secs(3600);
+        let link = sign(SignedAction::Restart, "sampel-palnet", expires_at).await.unwrap();
+        assert!(link.starts_with("/signed/pier/sampel-palnet/restart?expires="));
+
+        let (expires, signature) = query(&link);
+        assert_eq!(expires, expires_at.unix_timestamp());
+        assert_eq!(signature.len(), 64);
+        verify(SignedAction::Restart, "sampel-palnet", expires, &signature).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn rejects_tampered_links() {
+        seal::use_test_key().await;
+        let expires_at = OffsetDateTime::now_utc() + Duration::from_secs(3600);
+        let (expires, signature) = query(&sign(SignedAction::Restart, "sampel-palnet", expires_at).await.unwrap());
+        let invalid = "the link's signature is invalid";
+
+        assert_eq!(rejected(verify(SignedAction::Restart, "marzod", expires, &signature).await), invalid);
+        assert_eq!(rejected(verify(SignedAction::Restart, "sampel-palnet", expires + 1, &signature).await), invalid);
+        let mut flipped = signature.clone().into_bytes();
+        flipped[0] = if flipped[0] == b'0' { b'1' } else { b'0' };
+        let flipped = String::from_utf8(flipped).unwrap();
+        assert_eq!(rejected(verify(SignedAction::Restart, "sampel-palnet", expires, &flipped).await), invalid);
+        assert_eq!(rejected(verify(SignedAction::Restart, "sampel-palnet", expires, &signature[1..]).await), invalid);
+        assert_eq!(rejected(verify(SignedAction::Restart, "sampel-palnet", expires, "").await), invalid);
+    }
+
+    #[actix_web::test]
+    async fn rejects_expired_links() {
+        seal::use_test_key().await;
+        let expires_at = OffsetDateTime::now_utc() - Duration::from_secs(1);
+        let (expires, signature) = query(&sign(SignedAction::Restart, "sampel-palnet", expires_at).await.unwrap());
+        let result = verify(SignedAction::Restart, "sampel-palnet", expires, &signature).await;
+        assert_eq!(rejected(result), "the link has expired");
+    }
+}
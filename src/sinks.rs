@@ -0,0 +1,195 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use async_std::fs;
+use async_std::path::PathBuf;
+use reqwest::Url;
+use std::env;
+use std::sync::Arc;
+use time::format_description::well_known::Rfc3339;
+use tokio::net::{UdpSocket, UnixDatagram};
+
+use crate::events::{Envelope, EventBus};
+use crate::nats::NatsClient;
+
+lazy_static! {
+    /// Comma-separated list of sink URIs that every published event is copied to, e.g.
+    /// `file:///var/log/nucleus/events.jsonl,syslog://10.0.0.5:514,nats://10.0.0.6:4222/nucleus.events`.
+    pub static ref EVENT_SINKS: Vec<Url> = env::var_os("NUCLEUS_EVENT_SINKS")
+        .map(|s| s.to_str().unwrap()
+            .split(',')
+            .filter(|uri| !uri.trim().is_empty())
+            .map(|uri| uri.trim().parse::<Url>().unwrap())
+            .collect())
+        .unwrap_or_default();
+}
+
+/// A destination outside the orchestrator that events are forwarded to.
+#[async_trait(?Send)]
+pub trait EventSink {
+    fn describe(&self) -> String;
+
+    async fn write(&mut self, envelope: &Envelope) -> Result<()>;
+}
+
+/// Appends one JSON object per line to a file.
+#[derive(Debug)]
+pub struct JsonlFileSink {
+    path: PathBuf,
+    file: Option<fs::File>,
+}
+
+impl JsonlFileSink {
+    pub fn new(path: PathBuf) -> Self {
+        JsonlFileSink { path, file: None }
+    }
+}
+
+#[async_trait(?Send)]
+impl EventSink for JsonlFileSink {
+    fn describe(&self) -> String {
+        format!("jsonl file {}", self.path.to_string_lossy())
+    }
+
+    async fn write(&mut self, envelope: &Envelope) -> Result<()> {
+        if self.file.is_none() {
+            self.file = Some(fs::OpenOptions::new().append(true).create(true).open(&self.path).await?);
+        }
+
+        let mut line = serde_json::to_vec(envelope)?;
+        line.push(b'\n');
+
+        let file = self.file.as_mut().unwrap();
+        if let Err(e) = file.write_all(&line).await {
+            // Reopen on the next event, in case the file was rotated out from under us.
+            self.file = None;
+            return Err(e.into());
+        }
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum SyslogTarget {
+    Udp(String),
+    Unix(PathBuf),
+}
+
+/// Sends RFC 5424 messages to a syslog daemon over UDP or a local unix datagram socket.
+#[derive(Debug)]
+pub struct SyslogSink {
+    target: SyslogTarget,
+}
+
+/// facility local0, severity informational
+const SYSLOG_PRIORITY: u8 = 16 * 8 + 6;
+
+impl SyslogSink {
+    fn format(envelope: &Envelope) -> Result<Vec<u8>> {
+        let hostname = env::var("HOSTNAME").unwrap_or_else(|_| "-".to_owned());
+        Ok(format!(
+            "<{}>1 {} {} native-planet-orchestrator - {} - {}",
+            SYSLOG_PRIORITY,
+            envelope.at.format(&Rfc3339)?,
+            hostname,
+            envelope.event.kind(),
+            serde_json::to_string(envelope)?,
+        ).into_bytes())
+    }
+}
+
+#[async_trait(?Send)]
+impl EventSink for SyslogSink {
+    fn describe(&self) -> String {
+        match self.target {
+            SyslogTarget::Udp(ref addr) => format!("syslog udp {}", addr),
+            SyslogTarget::Unix(ref path) => format!("syslog socket {}", path.to_string_lossy()),
+        }
+    }
+
+    async fn write(&mut self, envelope: &Envelope) -> Result<()> {
+        let msg = Self::format(envelope)?;
+        match self.target {
+            SyslogTarget::Udp(ref addr) => {
+                let socket = UdpSocket::bind(("0.0.0.0", 0)).await?;
+                socket.send_to(&msg, addr.as_str()).await?;
+            },
+            SyslogTarget::Unix(ref path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.send_to(&msg, path).await?;
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Publishes each event as JSON on a NATS subject, reconnecting lazily after failures.
+#[derive(Debug)]
+pub struct NatsSink {
+    addr: String,
+    subject: String,
+    client: Option<NatsClient>,
+}
+
+#[async_trait(?Send)]
+impl EventSink for NatsSink {
+    fn describe(&self) -> String {
+        format!("nats {} subject {}", self.addr, self.subject)
+    }
+
+    async fn write(&mut self, envelope: &Envelope) -> Result<()> {
+        if self.client.is_none() {
+            self.client = Some(NatsClient::connect(&self.addr).await?);
+        }
+
+        let payload = serde_json::to_vec(envelope)?;
+        let result = self.client.as_ref().unwrap().publish(&self.subject, &payload).await;
+        if result.is_err() {
+            self.client = None;
+        }
+        result
+    }
+}
+
+/// Builds a sink from its URI. Supported schemes are `file`, `syslog` (with a `host:port` for UDP or an empty host and
+/// a socket path), and `nats` (with the subject as the path).
+pub fn from_url(url: &Url) -> Result<Box<dyn EventSink>> {
+    match url.scheme() {
+        "file" => Ok(Box::new(JsonlFileSink::new(PathBuf::from(url.path())))),
+        "syslog" => {
+            let target = match url.host_str() {
+                Some(host) if !host.is_empty() => SyslogTarget::Udp(format!("{}:{}", host, url.port().unwrap_or(514))),
+                _ => SyslogTarget::Unix(PathBuf::from(url.path())),
+            };
+            Ok(Box::new(SyslogSink { target }))
+        },
+        "nats" => {
+            let host = url.host_str().ok_or_else(|| anyhow!("nats sink needs a host: {}", url))?;
+            let subject = url.path().trim_start_matches('/').replace('/', ".");
+            if subject.is_empty() {
+                bail!("nats sink needs a subject as its path: {}", url);
+            }
+            Ok(Box::new(NatsSink {
+                addr: format!("{}:{}", host, url.port().unwrap_or(4222)),
+                subject,
+                client: None,
+            }))
+        },
+        "kafka" => bail!("kafka event sinks are not supported; bridge from a nats or file sink instead"),
+        scheme => bail!("unknown event sink scheme: {}", scheme),
+    }
+}
+
+/// Forwards every event published on `bus` to `sink` until the bus goes away. Failed writes are logged and the event is
+/// dropped; sinks are expected to recover on the next write.
+pub async fn run(bus: Arc<EventBus>, mut sink: Box<dyn EventSink>) {
+    let mut rx = bus.subscribe();
+    let description = sink.describe();
+    log::info!("forwarding events to {}", description);
+
+    while let Some(envelope) = rx.next().await {
+        if let Err(e) = sink.write(&envelope).await {
+            log::warn!("failed to write event {} to {}: {}", envelope.seq, description, e);
+        }
+    }
+}
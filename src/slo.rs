@@ -0,0 +1,212 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::collections::HashSet;
+use std::env;
+use std::sync::{Arc, Mutex};
+use time::OffsetDateTime;
+
+use crate::events::{Envelope, Event, EventBus};
+use crate::util::parse_duration;
+
+lazy_static! {
+    /// Uptime percentage each pier is expected to meet over every tracked window.
+    pub static ref SLO_TARGET_PERCENT: f64 = env::var_os("NUCLEUS_SLO_TARGET")
+        .map(|s| s.to_str().unwrap().parse::<f64>().unwrap())
+        .unwrap_or(99.0);
+
+    /// Rolling windows over which uptime is computed, as a comma-separated list like `1h,24h,7d,30d`.
+    pub static ref SLO_WINDOWS: Vec<Window> = env::var_os("NUCLEUS_SLO_WINDOWS")
+        .map(|s| s.to_str().unwrap().split(',').map(|w| w.parse::<Window>().unwrap()).collect())
+        .unwrap_or_else(|| ["1h", "24h", "7d", "30d"].iter().map(|w| w.parse::<Window>().unwrap()).collect());
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Window {
+    pub label: String,
+    pub duration: time::Duration,
+}
+
+impl std::str::FromStr for Window {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let duration = parse_duration(s)?;
+        Ok(Window {
+            label: s.trim().to_owned(),
+            duration: time::Duration::try_from(duration)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowUptime {
+    pub window: String,
+    /// None if no lifecycle events for the pier fall before the end of the window.
+    pub uptime_percent: Option<f64>,
+    pub target_percent: f64,
+    pub breached: bool,
+}
+
+/// Fraction of `window` (ending at `now`) during which the named ship was running, according to the boot/stop/crash
+/// events in `history`. If the history doesn't reach back to the start of the window, only the observed portion counts.
+/// Returns None when there is nothing to go on.
+pub fn uptime_ratio(
+    history: &[Arc<Envelope>],
+    name: &str,
+    window: time::Duration,
+    now: OffsetDateTime,
+) -> Option<f64> {
+    let start = now - window;
+
+    let mut observed_from: Option<OffsetDateTime> = None;
+    let mut up_since: Option<OffsetDateTime> = None;
+    let mut up_total = time::Duration::ZERO;
+
+    for envelope in history.iter().filter(|e| e.event.pier_name() == Some(name)) {
+        let up = match envelope.event {
+            Event::ShipBooted { .. } => true,
+            Event::ShipStopped { .. } | Event::ShipCrashed { .. } => false,
+            _ => continue,
+        };
+        if envelope.at > now {
+            break
+        }
+
+        let at = envelope.at.max(start);
+        observed_from.get_or_insert(at);
+
+        match (up_since, up) {
+            (None, true) => up_since = Some(at),
+            (Some(since), false) => {
+                up_total += at - since;
+                up_since = None;
+            },
+            _ => {},
+        }
+    }
+
+    if let Some(since) = up_since {
+        up_total += now - since;
+    }
+
+    let observed = now - observed_from?;
+    if observed <= time::Duration::ZERO {
+        return None
+    }
+    Some(up_total / observed)
+}
+
+pub fn pier_uptime(bus: &EventBus, name: &str) -> Vec<WindowUptime> {
+    let history = bus.history();
+    let now = OffsetDateTime::now_utc();
+
+    SLO_WINDOWS.iter().map(|window| {
+        let uptime_percent = uptime_ratio(&history, name, window.duration, now).map(|r| r * 100.0);
+        WindowUptime {
+            window: window.label.clone(),
+            uptime_percent,
+            target_percent: *SLO_TARGET_PERCENT,
+            breached: uptime_percent.is_some_and(|p| p < *SLO_TARGET_PERCENT),
+        }
+    }).collect()
+}
+
+/// Remembers which (pier, window) pairs are currently in breach so that notifications fire on transitions only.
+#[derive(Debug, Default)]
+pub struct SloTracker {
+    breached: Mutex<HashSet<(String, String)>>,
+}
+
+impl SloTracker {
+    pub fn evaluate(&self, bus: &EventBus, names: &[String]) {
+        for name in names {
+            for uptime in pier_uptime(bus, name) {
+                let uptime_percent = match uptime.uptime_percent {
+                    Some(p) => p,
+                    None => continue,
+                };
+                let key = (name.clone(), uptime.window.clone());
+
+                let mut breached = self.breached.lock().unwrap();
+                if uptime.breached && breached.insert(key.clone()) {
+                    drop(breached);
+                    bus.publish(Event::SloBreached {
+                        name: name.clone(),
+                        window: uptime.window,
+                        uptime_percent,
+                        target_percent: uptime.target_percent,
+                    });
+                } else if !uptime.breached && breached.remove(&key) {
+                    drop(breached);
+                    bus.publish(Event::SloRecovered {
+                        name: name.clone(),
+                        window: uptime.window,
+                        uptime_percent,
+                        target_percent: uptime.target_percent,
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Duration;
+
+    fn history(now: OffsetDateTime, events: &[(i64, &str, bool)]) -> Vec<Arc<Envelope>> {
+        events.iter().enumerate()
+            .map(|(seq, (hours_ago, name, up))| {
+                let name = name.to_string();
+                let event = match up {
+                    true => Event::ShipBooted { name, http_port: 8080, ames_port: 34343 },
+                    false => Event::ShipStopped { name },
+                };
+                Arc::new(Envelope { seq: seq as u64, at: now - Duration::hours(*hours_ago), event })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counts_time_up_within_window() {
+        let now = OffsetDateTime::now_utc();
+        let events = history(now, &[(100, "zod", true), (10, "zod", false), (4, "zod", true), (3, "marzod", false)]);
+        // Up from before the window until 10 hours ago, and for the last 4 hours.
+        let ratio = uptime_ratio(&events, "zod", Duration::hours(20), now).unwrap();
+        assert!((ratio - 14.0 / 20.0).abs() < 1e-9, "{}", ratio);
+    }
+
+    #[test]
+    fn only_counts_observed_portion() {
+        let now = OffsetDateTime::now_utc();
+        let events = history(now, &[(10, "zod", true), (5, "zod", false)]);
+        let ratio = uptime_ratio(&events, "zod", Duration::days(30), now).unwrap();
+        assert!((ratio - 0.5).abs() < 1e-9, "{}", ratio);
+    }
+
+    #[test]
+    fn treats_crashes_as_down_and_ignores_repeats() {
+        let now = OffsetDateTime::now_utc();
+        let mut events = history(now, &[(10, "zod", true), (8, "zod", true)]);
+        events.push(Arc::new(Envelope {
+            seq: 2,
+            at: now - Duration::hours(5),
+            event: Event::ShipCrashed { name: "zod".to_owned(), status: "signal 9".to_owned() },
+        }));
+        let ratio = uptime_ratio(&events, "zod", Duration::hours(10), now).unwrap();
+        assert!((ratio - 0.5).abs() < 1e-9, "{}", ratio);
+    }
+
+    #[test]
+    fn ignores_future_events_and_needs_history() {
+        let now = OffsetDateTime::now_utc();
+        assert_eq!(uptime_ratio(&[], "zod", Duration::hours(1), now), None);
+        let events = history(now, &[(5, "marzod", true)]);
+        assert_eq!(uptime_ratio(&events, "zod", Duration::hours(10), now), None);
+
+        let events = history(now, &[(4, "zod", true), (-1, "zod", false)]);
+        assert_eq!(uptime_ratio(&events, "zod", Duration::hours(10), now), Some(1.0));
+    }
+}
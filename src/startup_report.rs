@@ -0,0 +1,87 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use time::OffsetDateTime;
+
+/// What the orchestrator found in the harbor when it last started, and what it did about it, so that operators can
+/// check that a restart was clean.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StartupReport {
+    #[serde(with = "time::serde::rfc3339")]
+    pub started_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub finished_at: Option<OffsetDateTime>,
+    /// Whether the previous run left nothing to recover, resume or look into.
+    pub clean: bool,
+    /// Piers in port that were loaded and are managed.
+    pub loaded: Vec<String>,
+    /// Loaded piers whose lock was held by an orchestrator that died without releasing it.
+    pub locks_recovered: Vec<String>,
+    /// Loaded piers that a runtime not launched by this orchestrator is still running. They can't be booted until it
+    /// exits.
+    pub stray_runtimes: Vec<StrayRuntime>,
+    /// Piers in port that couldn't be loaded. They are left as they are, unmanaged, until the next startup.
+    pub quarantined: Vec<QuarantinedPier>,
+    /// Dry dock entries whose interrupted archive imports were resumed.
+    pub resumed_imports: Vec<Uuid>,
+    /// Dry dock entries removed because their archive uploads were interrupted.
+    pub removed_imports: Vec<Uuid>,
+    /// Dry dock entries left behind by interrupted jobs, which need an operator to decide what to do with them.
+    pub left_in_dry_dock: Vec<Uuid>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StrayRuntime {
+    pub name: String,
+    pub pid: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantinedPier {
+    pub name: String,
+    pub error: String,
+}
+
+impl StartupReport {
+    pub fn new() -> Self {
+        StartupReport {
+            started_at: OffsetDateTime::now_utc(),
+            finished_at: None,
+            clean: false,
+            loaded: Vec::new(),
+            locks_recovered: Vec::new(),
+            stray_runtimes: Vec::new(),
+            quarantined: Vec::new(),
+            resumed_imports: Vec::new(),
+            removed_imports: Vec::new(),
+            left_in_dry_dock: Vec::new(),
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.finished_at = Some(OffsetDateTime::now_utc());
+        self.clean = self.locks_recovered.is_empty()
+            && self.stray_runtimes.is_empty()
+            && self.quarantined.is_empty()
+            && self.resumed_imports.is_empty()
+            && self.removed_imports.is_empty()
+            && self.left_in_dry_dock.is_empty();
+    }
+
+    /// One line for the log.
+    pub fn summary(&self) -> String {
+        format!(
+            "loaded {} piers; {} stale locks recovered, {} stray runtimes, {} piers quarantined, {} imports resumed, \
+                {} removed, {} dry dock entries left behind",
+            self.loaded.len(),
+            self.locks_recovered.len(),
+            self.stray_runtimes.len(),
+            self.quarantined.len(),
+            self.resumed_imports.len(),
+            self.removed_imports.len(),
+            self.left_in_dry_dock.len(),
+        )
+    }
+}
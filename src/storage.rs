@@ -0,0 +1,289 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use async_std::fs;
+use async_std::path::Path;
+use std::env;
+use std::process::Stdio;
+use tokio::process;
+
+use crate::reaper;
+use crate::util::{self, CopyReport};
+
+lazy_static! {
+    /// How pier directories are stored: `directory` for plain directories in the harbor, or `zfs:POOL/DATASET` for a
+    /// ZFS dataset per pier under the given parent dataset.
+    pub static ref STORAGE: Box<dyn PierStorage> = from_name(
+        env::var_os("NUCLEUS_STORAGE").as_ref().map_or("directory", |s| s.to_str().unwrap())
+    ).unwrap();
+}
+
+/// Makes and disposes of the directories runtimes boot from, `PierState::pier_path`. Everything else in a pier's
+/// harbor entry, such as its config and logs, is plain files whatever the backend.
+#[async_trait(?Send)]
+pub trait PierStorage: Send + Sync {
+    fn describe(&self) -> String;
+
+    /// Makes an empty pier directory at `path`.
+    async fn create(&self, path: &Path) -> Result<()>;
+
+    /// Moves the directory `src`, which is outside any pier, to `dst`, which is a pier directory or inside one and
+    /// doesn't exist yet. Fails with the underlying io error if `src` is on another filesystem and can't be moved;
+    /// backends that give each pier a filesystem of its own copy it and delete the original instead.
+    async fn ingest(&self, src: &Path, dst: &Path) -> Result<()>;
+
+    /// Copies the directory `src`, which may or may not be a pier directory, to the new pier directory `dst`, sharing
+    /// storage with `src` where the backend can.
+    async fn copy(&self, src: &Path, dst: &Path) -> Result<CopyReport>;
+
+    /// Deletes the pier directory at `path` and everything in it.
+    async fn remove(&self, path: &Path) -> Result<()>;
+
+    /// Bytes the pier directory at `path` takes up.
+    async fn size(&self, path: &Path) -> Result<u64>;
+
+    /// Follows a pier directory whose harbor entry was renamed, such as when it moved out of the dry dock, from `from`
+    /// to where it is now, `to`.
+    async fn relocate(&self, _from: &Path, _to: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    /// Space accounting of the dataset the pier directory at `path` lives in, for backends with one per pier.
+    async fn dataset_usage(&self, _path: &Path) -> Result<Option<DatasetUsage>> {
+        Ok(None)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatasetUsage {
+    pub dataset: String,
+    /// Space the dataset and its snapshots take up in the pool.
+    pub used_bytes: u64,
+    /// Space the dataset's current contents refer to, some of which may be shared with snapshots.
+    pub referenced_bytes: u64,
+    pub snapshot_bytes: u64,
+    pub compress_ratio: f64,
+}
+
+/// Piers as plain directories in the harbor.
+#[derive(Debug)]
+pub struct DirectoryStorage;
+
+#[async_trait(?Send)]
+impl PierStorage for DirectoryStorage {
+    fn describe(&self) -> String {
+        "directories".to_owned()
+    }
+
+    async fn create(&self, path: &Path) -> Result<()> {
+        Ok(fs::create_dir(path).await?)
+    }
+
+    async fn ingest(&self, src: &Path, dst: &Path) -> Result<()> {
+        Ok(fs::rename(src, dst).await?)
+    }
+
+    async fn copy(&self, src: &Path, dst: &Path) -> Result<CopyReport> {
+        util::copy_tree(src, dst).await
+    }
+
+    async fn remove(&self, path: &Path) -> Result<()> {
+        Ok(fs::remove_dir_all(path).await?)
+    }
+
+    async fn size(&self, path: &Path) -> Result<u64> {
+        util::dir_size(path).await
+    }
+}
+
+/// A ZFS dataset per pier, mounted at the pier directory, under a parent dataset set aside for the orchestrator.
+/// Datasets are found by where they are mounted, so their names are arbitrary. Copies are made with `zfs send` and
+/// `zfs recv`, which copy blocks rather than files and leave the copy independent of the original.
+///
+/// The runtime makes the pier directories of ships it creates itself, on first boot from a keyfile or as a comet or
+/// fake ship. Those stay plain directories on whatever filesystem the harbor is on, and are treated as such.
+#[derive(Debug)]
+pub struct ZfsStorage {
+    parent: String,
+}
+
+/// Runs `zfs` with the given arguments and returns its output.
+async fn zfs(args: &[&str]) -> Result<String> {
+    let output = reaper::output(process::Command::new("zfs").args(args).stdin(Stdio::null())).await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("zfs {} exited with {}: {}", args.join(" "), output.status, stderr.trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+impl ZfsStorage {
+    /// The dataset mounted at `path`, if it is one of ours.
+    async fn dataset_at(&self, path: &Path) -> Result<Option<String>> {
+        if !path.exists().await {
+            return Ok(None);
+        }
+        let path_str = path.to_str().ok_or_else(|| anyhow!("non-UTF-8 path: {}", path.to_string_lossy()))?;
+        // Fails for paths that aren't on ZFS at all.
+        let Ok(listed) = zfs(&["list", "-H", "-o", "name,mountpoint", path_str]).await else {
+            return Ok(None);
+        };
+        let mut fields = listed.trim_end().split('\t');
+        let (Some(name), Some(mountpoint)) = (fields.next(), fields.next()) else {
+            return Ok(None);
+        };
+        let ours = name.strip_prefix(&self.parent).is_some_and(|rest| rest.starts_with('/'));
+        Ok((ours && Path::new(mountpoint) == path).then(|| name.to_owned()))
+    }
+
+    fn new_dataset_name(&self) -> String {
+        format!("{}/{}", self.parent, Uuid::new_v4().simple())
+    }
+
+    /// Copies everything in the directory `src` into the directory `dst`.
+    async fn fill(src: &Path, dst: &Path) -> Result<CopyReport> {
+        let mut report = CopyReport::default();
+        let mut entries = fs::read_dir(src).await?;
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let copied = util::copy_tree(entry.path(), dst.join(entry.file_name())).await?;
+            report.files += copied.files;
+            report.bytes += copied.bytes;
+            report.reflinked += copied.reflinked;
+        }
+        fs::set_permissions(dst, fs::metadata(src).await?.permissions()).await?;
+        Ok(report)
+    }
+}
+
+#[async_trait(?Send)]
+impl PierStorage for ZfsStorage {
+    fn describe(&self) -> String {
+        format!("zfs datasets under {}", self.parent)
+    }
+
+    async fn create(&self, path: &Path) -> Result<()> {
+        let mountpoint = format!("mountpoint={}", path.to_string_lossy());
+        zfs(&["create", "-o", &mountpoint, &self.new_dataset_name()]).await?;
+        Ok(())
+    }
+
+    async fn ingest(&self, src: &Path, dst: &Path) -> Result<()> {
+        // A pier directory gets a dataset of its own; see `PierState::pier_path` for the name. Anything inside one is
+        // renamed if it happens to be on the same filesystem, and copied otherwise.
+        let is_pier_root = dst.file_name().is_some_and(|name| name == "pier");
+        if !is_pier_root {
+            match fs::rename(src, dst).await {
+                Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {},
+                result => return Ok(result?),
+            }
+            util::copy_tree(src, dst).await?;
+        } else {
+            self.create(dst).await?;
+            Self::fill(src, dst).await?;
+        }
+        fs::remove_dir_all(src).await?;
+        Ok(())
+    }
+
+    async fn copy(&self, src: &Path, dst: &Path) -> Result<CopyReport> {
+        let Some(dataset) = self.dataset_at(src).await? else {
+            self.create(dst).await?;
+            return Self::fill(src, dst).await;
+        };
+
+        let copy = self.new_dataset_name();
+        let snapshot = format!("{}@copy-{}", dataset, Uuid::new_v4().simple());
+        zfs(&["snapshot", &snapshot]).await?;
+        let sent = async {
+            let mut send = reaper::spawn(process::Command::new("zfs")
+                .args(["send", &snapshot])
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped()))?;
+            let _tracked = reaper::Tracked::of(&send);
+            let stream: Stdio = send.stdout.take().unwrap().try_into()?;
+            let mountpoint = format!("mountpoint={}", dst.to_string_lossy());
+            let recv = reaper::output(process::Command::new("zfs")
+                .args(["recv", "-o", &mountpoint, &copy])
+                .stdin(stream))
+                .await?;
+            let send_status = send.wait().await?;
+            if !send_status.success() {
+                bail!("zfs send {} exited with {}", snapshot, send_status);
+            }
+            if !recv.status.success() {
+                let stderr = String::from_utf8_lossy(&recv.stderr);
+                bail!("zfs recv {} exited with {}: {}", copy, recv.status, stderr.trim());
+            }
+            let copied_snapshot = format!("{}@{}", copy, snapshot.rsplit_once('@').unwrap().1);
+            zfs(&["destroy", &copied_snapshot]).await?;
+            Ok(())
+        }.await;
+        if let Err(e) = zfs(&["destroy", &snapshot]).await {
+            log::warn!("failed to destroy snapshot {}: {:#}", snapshot, e);
+        }
+        sent?;
+
+        Ok(CopyReport { bytes: self.size(dst).await?, ..CopyReport::default() })
+    }
+
+    async fn remove(&self, path: &Path) -> Result<()> {
+        match self.dataset_at(path).await? {
+            Some(dataset) => {
+                zfs(&["destroy", "-r", &dataset]).await?;
+                // The mountpoint directory may be left behind.
+                if path.exists().await {
+                    fs::remove_dir(path).await?;
+                }
+                Ok(())
+            },
+            None => Ok(fs::remove_dir_all(path).await?),
+        }
+    }
+
+    async fn size(&self, path: &Path) -> Result<u64> {
+        match self.dataset_at(path).await? {
+            Some(dataset) => Ok(zfs(&["get", "-Hp", "-o", "value", "used", &dataset]).await?.trim().parse()?),
+            None => util::dir_size(path).await,
+        }
+    }
+
+    async fn relocate(&self, from: &Path, to: &Path) -> Result<()> {
+        // The kernel moves the mount along with the directory above it; the dataset's mountpoint has to follow.
+        let Some(dataset) = self.dataset_at(to).await?.or(self.dataset_at(from).await?) else {
+            return Ok(());
+        };
+        zfs(&["set", &format!("mountpoint={}", to.to_string_lossy()), &dataset]).await?;
+        Ok(())
+    }
+
+    async fn dataset_usage(&self, path: &Path) -> Result<Option<DatasetUsage>> {
+        let Some(dataset) = self.dataset_at(path).await? else {
+            return Ok(None);
+        };
+        let values = zfs(&["get", "-Hp", "-o", "value", "used,referenced,usedbysnapshots,compressratio", &dataset])
+            .await?;
+        let values: Vec<&str> = values.lines().collect();
+        let [used, referenced, snapshots, ratio] = values[..] else {
+            bail!("unexpected output from zfs get: {:?}", values);
+        };
+        Ok(Some(DatasetUsage {
+            dataset,
+            used_bytes: used.parse()?,
+            referenced_bytes: referenced.parse()?,
+            snapshot_bytes: snapshots.parse()?,
+            compress_ratio: ratio.trim_end_matches('x').parse()?,
+        }))
+    }
+}
+
+/// Builds a storage backend from its name in `NUCLEUS_STORAGE`.
+pub fn from_name(name: &str) -> Result<Box<dyn PierStorage>> {
+    match name.split_once(':') {
+        None if name == "directory" => Ok(Box::new(DirectoryStorage)),
+        Some(("zfs", parent)) if !parent.is_empty() => {
+            Ok(Box::new(ZfsStorage { parent: parent.trim_end_matches('/').to_owned() }))
+        },
+        _ => bail!("unknown storage backend: {}", name),
+    }
+}
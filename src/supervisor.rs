@@ -0,0 +1,86 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use std::time::Duration;
+
+/// A ship that stays up this long after being relaunched is considered recovered, so its next exit starts the
+/// backoff over.
+pub const RESTART_RESET_AFTER: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RestartMode {
+    /// Leave the ship stopped.
+    #[default]
+    Never,
+    /// Relaunch the ship if its runtime exits with an error or is killed.
+    OnFailure,
+    /// Relaunch the ship whenever its runtime exits without being stopped, even cleanly with `|exit`.
+    Always,
+}
+
+/// What to do when a ship's runtime exits without the orchestrator having stopped it.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestartPolicy {
+    #[serde(default)]
+    pub mode: RestartMode,
+    /// Relaunches attempted before giving up until the ship is next started by hand. 0 for no limit.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Delay before the first relaunch, doubled for each consecutive one.
+    #[serde(default = "default_initial_backoff_secs")]
+    pub initial_backoff_secs: u64,
+    #[serde(default = "default_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+fn default_initial_backoff_secs() -> u64 {
+    5
+}
+
+fn default_max_backoff_secs() -> u64 {
+    5 * 60
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            mode: RestartMode::default(),
+            max_retries: default_max_retries(),
+            initial_backoff_secs: default_initial_backoff_secs(),
+            max_backoff_secs: default_max_backoff_secs(),
+        }
+    }
+}
+
+impl RestartPolicy {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether a runtime exiting on its own, cleanly or not, should be relaunched.
+    pub fn applies_to(&self, clean_exit: bool) -> bool {
+        match self.mode {
+            RestartMode::Never => false,
+            RestartMode::OnFailure => !clean_exit,
+            RestartMode::Always => true,
+        }
+    }
+
+    /// Whether the given 1-based relaunch attempt is allowed.
+    pub fn allows_attempt(&self, attempt: u32) -> bool {
+        self.max_retries == 0 || attempt <= self.max_retries
+    }
+
+    /// How long to wait before the given 1-based relaunch attempt.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let secs = self.initial_backoff_secs
+            .saturating_mul(1u64.checked_shl(attempt.saturating_sub(1)).unwrap_or(u64::MAX))
+            .min(self.max_backoff_secs);
+        Duration::from_secs(secs)
+    }
+}
@@ -0,0 +1,149 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use async_std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::storage::STORAGE;
+use crate::util;
+
+lazy_static! {
+    /// How long measured directory sizes are reused before walking the pier again.
+    pub static ref USAGE_CACHE_TTL: Duration = env::var_os("NUCLEUS_USAGE_CACHE_TTL")
+        .map(|s| util::parse_duration(s.to_str().unwrap()).unwrap())
+        .unwrap_or(Duration::from_secs(5 * 60));
+}
+
+/// On-disk footprint of a pier, in bytes.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiskUsage {
+    pub total_bytes: u64,
+    pub event_log_bytes: u64,
+    pub snapshot_bytes: u64,
+    /// The runtime's working directory and temporary files, included in `total_bytes`.
+    pub scratch_bytes: u64,
+}
+
+/// Size of `path`, or 0 if it doesn't exist (e.g. a runtime version that doesn't create that directory).
+async fn size_or_zero(path: PathBuf) -> Result<u64> {
+    if !path.exists().await {
+        return Ok(0);
+    }
+    util::dir_size(path).await
+}
+
+/// Measures a pier directory and its runtime's scratch directory without caching.
+pub async fn measure(pier_path: &Path, scratch_path: &Path) -> Result<DiskUsage> {
+    let urb = pier_path.join(".urb");
+    let scratch_bytes = size_or_zero(scratch_path.to_owned()).await?;
+    Ok(DiskUsage {
+        total_bytes: STORAGE.size(pier_path).await? + scratch_bytes,
+        event_log_bytes: size_or_zero(urb.join("log")).await?,
+        snapshot_bytes: size_or_zero(urb.join("chk")).await?,
+        scratch_bytes,
+    })
+}
+
+/// Sum of the resident set sizes of every process in a process group, in bytes. Runtimes are spawned as group leaders,
+/// so this covers the king and all of its serfs.
+pub fn group_rss(pgid: u32) -> Result<u64> {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+    let mut total = 0;
+
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        if entry.file_name().to_str().and_then(|s| s.parse::<u32>().ok()).is_none() {
+            continue;
+        }
+        let stat = match std::fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        // Fields after the parenthesized command name: state, ppid, pgrp, ...; rss (in pages) is the 22nd of these.
+        let fields: Vec<&str> = match stat.rfind(')') {
+            Some(idx) => stat[idx + 1..].split_ascii_whitespace().collect(),
+            None => continue,
+        };
+        if fields.get(2).and_then(|s| s.parse::<u32>().ok()) != Some(pgid) {
+            continue;
+        }
+        if let Some(rss) = fields.get(21).and_then(|s| s.parse::<u64>().ok()) {
+            total += rss * page_size;
+        }
+    }
+
+    Ok(total)
+}
+
+/// Capacity of the filesystem holding `path`, in bytes and inodes. Filesystems that allocate inodes dynamically report
+/// 0 total inodes.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FilesystemUsage {
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+    pub total_inodes: u64,
+    pub available_inodes: u64,
+}
+
+pub fn filesystem_usage(path: &Path) -> Result<FilesystemUsage> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(FilesystemUsage {
+        total_bytes: stat.f_blocks as u64 * stat.f_frsize as u64,
+        available_bytes: stat.f_bavail as u64 * stat.f_frsize as u64,
+        total_inodes: stat.f_files as u64,
+        available_inodes: stat.f_favail as u64,
+    })
+}
+
+/// File descriptors the orchestrator process has open, and how many it may.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FdUsage {
+    pub open: u64,
+    pub limit: u64,
+}
+
+pub fn fd_usage() -> Result<FdUsage> {
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // Includes the descriptor reading the directory, which is closed again by the time anything else opens one.
+    let open = (std::fs::read_dir("/proc/self/fd")?.count() as u64).saturating_sub(1);
+    Ok(FdUsage { open, limit: limit.rlim_cur as u64 })
+}
+
+/// Caches pier disk measurements, which require walking the whole pier, for `USAGE_CACHE_TTL`.
+#[derive(Debug, Default)]
+pub struct UsageCollector {
+    cache: Mutex<HashMap<PathBuf, (Instant, DiskUsage)>>,
+}
+
+impl UsageCollector {
+    pub async fn disk_usage(&self, pier_path: &Path, scratch_path: &Path) -> Result<DiskUsage> {
+        if let Some((at, usage)) = self.cache.lock().unwrap().get(pier_path) {
+            if at.elapsed() < *USAGE_CACHE_TTL {
+                return Ok(*usage);
+            }
+        }
+
+        let usage = measure(pier_path, scratch_path).await?;
+        self.cache.lock().unwrap().insert(pier_path.to_owned(), (Instant::now(), usage));
+        Ok(usage)
+    }
+
+    /// Forgets the cached measurement for a pier, e.g. after an operation that changes its size substantially.
+    pub fn invalidate(&self, pier_path: &Path) {
+        self.cache.lock().unwrap().remove(pier_path);
+    }
+}
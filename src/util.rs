@@ -27,4 +27,217 @@ impl<A> Deref for MyRange<A> {
     fn deref(&self) -> &Self::Target {
         &self.inner
     }
-}
\ No newline at end of file
+}
+
+/// Parses a human-friendly duration such as `30s`, `5m`, `1h`, or `7d`. A bare number is taken as seconds.
+pub fn parse_duration(s: &str) -> Result<std::time::Duration> {
+    let s = s.trim();
+    let split_idx = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (count, unit) = s.split_at(split_idx);
+    let count: u64 = count.parse().map_err(|_| anyhow!("invalid duration: {:?}", s))?;
+
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "ms" => return Ok(std::time::Duration::from_millis(count)),
+        _ => bail!("invalid duration unit in {:?}", s),
+    };
+
+    let secs = count.checked_mul(multiplier).ok_or_else(|| anyhow!("duration too long: {:?}", s))?;
+    Ok(std::time::Duration::from_secs(secs))
+}
+
+/// Parses a human-friendly byte count such as `512K`, `64M`, or `20G`, in powers of 1024. A bare number is taken as
+/// bytes.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split_idx = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (count, unit) = s.split_at(split_idx);
+    let count: u64 = count.parse().map_err(|_| anyhow!("invalid size: {:?}", s))?;
+
+    let shift = match unit {
+        "" | "B" => 0,
+        "K" | "KiB" => 10,
+        "M" | "MiB" => 20,
+        "G" | "GiB" => 30,
+        "T" | "TiB" => 40,
+        _ => bail!("invalid size unit in {:?}", s),
+    };
+
+    count.checked_mul(1 << shift).ok_or_else(|| anyhow!("size too large: {:?}", s))
+}
+
+/// Parses a `Range` request header against a resource of `size` bytes. Only a single range of bytes is handled, which
+/// is all that parallel download clients send; anything else yields None, and the whole resource should be sent.
+/// Fails if the range lies beyond the end of the resource.
+pub fn parse_byte_range(header: &str, size: u64) -> Result<Option<Range<u64>>> {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else { return Ok(None) };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else { return Ok(None) };
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", "") => return Ok(None),
+        // The last `end` bytes.
+        ("", suffix) => {
+            let Ok(suffix) = suffix.parse::<u64>() else { return Ok(None) };
+            (size.saturating_sub(suffix), size)
+        },
+        (start, "") => {
+            let Ok(start) = start.parse::<u64>() else { return Ok(None) };
+            (start, size)
+        },
+        (start, end) => {
+            let (Ok(start), Ok(end)) = (start.parse::<u64>(), end.parse::<u64>()) else { return Ok(None) };
+            if end < start {
+                return Ok(None);
+            }
+            (start, end.saturating_add(1).min(size))
+        },
+    };
+    if start >= size || start == end {
+        bail!("range not satisfiable: {:?} of {} bytes", header, size);
+    }
+    Ok(Some(start..end))
+}
+
+/// Total apparent size in bytes of all regular files under `path`, not following symlinks. This walks the whole tree on
+/// a blocking thread, so callers should avoid doing it on hot paths.
+pub async fn dir_size<P: AsRef<std::path::Path>>(path: P) -> Result<u64> {
+    fn walk(path: &std::path::Path) -> std::io::Result<u64> {
+        let metadata = std::fs::symlink_metadata(path)?;
+        if !metadata.is_dir() {
+            return Ok(if metadata.is_file() { metadata.len() } else { 0 });
+        }
+
+        let mut total = 0;
+        for entry in std::fs::read_dir(path)? {
+            total += walk(&entry?.path())?;
+        }
+        Ok(total)
+    }
+
+    let path = path.as_ref().to_owned();
+    Ok(tokio::task::spawn_blocking(move || walk(&path)).await??)
+}
+
+/// What `copy_tree` copied.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CopyReport {
+    pub files: u64,
+    pub bytes: u64,
+    /// Files cloned copy-on-write, which share their blocks with the original until either one is written.
+    pub reflinked: u64,
+}
+
+/// Copies the tree at `src` to `dst`, which must not exist yet, keeping permissions and symlinks. Sockets, fifos and
+/// devices are skipped. Files are reflinked where the filesystem supports it, e.g. on btrfs or XFS, which is nearly
+/// instant and takes no space until either copy changes; elsewhere they are copied with `copy_file_range`.
+pub async fn copy_tree<P: AsRef<std::path::Path>, Q: AsRef<std::path::Path>>(src: P, dst: Q) -> Result<CopyReport> {
+    use std::fs;
+    use std::os::unix::io::AsRawFd;
+
+    /// `FICLONE` from linux/fs.h.
+    const FICLONE: libc::c_ulong = 0x4004_9409;
+
+    fn walk(src: &std::path::Path, dst: &std::path::Path, report: &mut CopyReport) -> std::io::Result<()> {
+        let metadata = fs::symlink_metadata(src)?;
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            fs::create_dir(dst)?;
+            for entry in fs::read_dir(src)? {
+                let entry = entry?;
+                walk(&entry.path(), &dst.join(entry.file_name()), report)?;
+            }
+            // Applied last, so that a read-only directory can still be filled.
+            fs::set_permissions(dst, metadata.permissions())?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(src)?, dst)?;
+        } else if file_type.is_file() {
+            let mut from = fs::File::open(src)?;
+            let mut to = fs::OpenOptions::new().write(true).create_new(true).open(dst)?;
+            if unsafe { libc::ioctl(to.as_raw_fd(), FICLONE as _, from.as_raw_fd()) } == 0 {
+                report.reflinked += 1;
+            } else {
+                std::io::copy(&mut from, &mut to)?;
+            }
+            to.set_permissions(metadata.permissions())?;
+            to.sync_all()?;
+            report.files += 1;
+            report.bytes += metadata.len();
+        }
+        Ok(())
+    }
+
+    let (src, dst) = (src.as_ref().to_owned(), dst.as_ref().to_owned());
+    Ok(tokio::task::spawn_blocking(move || {
+        let mut report = CopyReport::default();
+        walk(&src, &dst, &mut report).map(|()| report)
+    }).await??)
+}
+
+/// For `Option<Option<T>>` fields marked `#[serde(default)]`, so that a field given as null (`Some(None)`) can be told
+/// apart from one left out (`None`).
+pub fn deserialize_some<'de, T, D>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+    where T: Deserialize<'de>,
+          D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// A fresh directory under the system's temporary directory, removed along with its contents when dropped.
+#[cfg(test)]
+pub struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("nucleus-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+#[cfg(test)]
+impl Deref for TempDir {
+    type Target = std::path::Path;
+
+    fn deref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration(" 5m\n").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(7 * 24 * 3600));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("0s").unwrap(), Duration::ZERO);
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for s in ["", "s", "-5s", "1.5h", "5 m", "5min", "5M", "1h30m", "99999999999999999999d"] {
+            assert!(parse_duration(s).is_err(), "{:?}", s);
+        }
+        assert!(parse_duration(&format!("{}d", u64::MAX / 1000)).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+#[allow(unused_imports)] use crate::prelude::*;
+
+use socket2::{Domain, Socket, Type};
+use std::env;
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr, ToSocketAddrs};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::acme;
+use crate::net_util::ListenAddr;
+use crate::patp;
+
+lazy_static! {
+    /// Where to serve running ships' web interfaces by hostname, e.g. `0.0.0.0:80`. Unset disables the proxy. Either
+    /// unspecified address, `0.0.0.0` or `[::]`, listens on both IPv4 and IPv6.
+    pub static ref VHOST_LISTEN: Option<ListenAddr> = env::var_os("NUCLEUS_VHOST_LISTEN")
+        .map(|s| s.to_str().unwrap().parse::<ListenAddr>().unwrap());
+
+    /// Where to serve running ships' web interfaces over HTTPS, e.g. `0.0.0.0:443`, with certificates from
+    /// `NUCLEUS_ACME_DIRECTORY`. Needs `NUCLEUS_VHOST_LISTEN` too. Unspecified addresses listen on both families.
+    pub static ref VHOST_TLS_LISTEN: Option<SocketAddr> = env::var_os("NUCLEUS_VHOST_TLS_LISTEN")
+        .map(|s| s.to_str().unwrap().parse::<SocketAddr>().unwrap());
+
+    /// Each ship's hostname, with `{name}` standing for its @p without the sig, e.g. `{name}.ships.example.com`.
+    pub static ref VHOST_TEMPLATE: HostTemplate = env::var_os("NUCLEUS_VHOST_TEMPLATE")
+        .map(|s| s.to_str().unwrap().parse::<HostTemplate>().unwrap())
+        .unwrap_or_else(|| "{name}.localhost".parse().unwrap());
+}
+
+/// A hostname with a `{name}` placeholder, which maps ships' names to hostnames and back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostTemplate {
+    prefix: String,
+    suffix: String,
+}
+
+impl std::str::FromStr for HostTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (prefix, suffix) = s.split_once("{name}").ok_or_else(|| anyhow!("host template needs {{name}}: {}", s))?;
+        if suffix.contains("{name}") {
+            bail!("host template may only contain {{name}} once: {}", s);
+        }
+        Ok(HostTemplate { prefix: prefix.to_ascii_lowercase(), suffix: suffix.to_ascii_lowercase() })
+    }
+}
+
+impl HostTemplate {
+    pub fn hostname(&self, name: &str) -> String {
+        format!("{}{}{}", self.prefix, name, self.suffix)
+    }
+
+    /// A wildcard covering every ship's hostname, if ships' names make up whole labels of them, as in
+    /// `{name}.ships.example.com`.
+    pub fn wildcard(&self) -> Option<String> {
+        (self.prefix.is_empty() && self.suffix.starts_with('.')).then(|| format!("*{}", self.suffix))
+    }
+
+    /// The ship a request's `Host` header is for, if it fits the template and names a ship. Any port is ignored.
+    pub fn ship_for_host(&self, host: &str) -> Option<String> {
+        let host = match host.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() => host,
+            _ => host,
+        };
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let name = host.strip_prefix(&self.prefix)?.strip_suffix(&self.suffix)?;
+        if name.starts_with('~') {
+            return None;
+        }
+        patp::parse(name).ok()?;
+        Some(name.to_owned())
+    }
+}
+
+/// A running ship the proxy serves.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VirtualHost {
+    pub name: String,
+    pub hostname: String,
+    pub http_port: u16,
+}
+
+/// The base URL of a ship's web interface through the proxy, if it is configured: HTTPS if it serves that, with the
+/// port left out if it is the scheme's default. A proxy listening on a unix socket is assumed to sit behind another
+/// on the default HTTP port.
+pub fn ship_url(name: &str) -> Option<String> {
+    let hostname = VHOST_TEMPLATE.hostname(name);
+    let (scheme, port) = match (&*VHOST_LISTEN, *VHOST_TLS_LISTEN, &*acme::ACME_DIRECTORY) {
+        (None, _, _) => return None,
+        (Some(_), Some(tls_addr), Some(_)) => ("https", Some(tls_addr.port()).filter(|&port| port != 443)),
+        (Some(ListenAddr::Tcp(_, port)), _, _) => ("http", Some(*port).filter(|&port| port != 80)),
+        (Some(ListenAddr::Unix(_)), _, _) => ("http", None),
+    };
+    Some(match port {
+        Some(port) => format!("{}://{}:{}", scheme, hostname, port),
+        None => format!("{}://{}", scheme, hostname),
+    })
+}
+
+/// Accepts HTTPS connections on `addr` and relays them, decrypted, to the vhost listener at `upstream`. The certificate
+/// is looked up per connection, so renewals take effect without a restart; until there is one, connections are
+/// dropped.
+pub async fn serve_tls(addr: SocketAddr, upstream: SocketAddr) -> Result<()> {
+    let listener = TcpListener::from_std(bind(addr)?)?;
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        actix_web::rt::spawn(async move {
+            if let Err(e) = relay_tls(stream, upstream).await {
+                log::debug!("HTTPS connection from {} failed: {:#}", peer, e);
+            }
+        });
+    }
+}
+
+/// Binds a listener for the proxy to `host` and `port`, resolving `host` if it is a name.
+pub fn bind_host(host: &str, port: u16) -> io::Result<std::net::TcpListener> {
+    let addr = (host, port).to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} has no addresses", host)))?;
+    bind(addr)
+}
+
+/// Binds a listener for the proxy. An unspecified address listens on both families, so that clients can reach ships
+/// over whichever of a hostname's A and AAAA records they resolve, unless the host has no IPv6.
+pub fn bind(addr: SocketAddr) -> io::Result<std::net::TcpListener> {
+    let socket = match addr.ip().is_unspecified().then(|| Socket::new(Domain::IPV6, Type::STREAM, None)) {
+        Some(Ok(socket)) => {
+            socket.set_only_v6(false)?;
+            socket.set_reuse_address(true)?;
+            socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, addr.port())).into())?;
+            socket
+        },
+        _ => {
+            let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+            socket.set_reuse_address(true)?;
+            socket.bind(&addr.into())?;
+            socket
+        },
+    };
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+async fn relay_tls(stream: TcpStream, upstream: SocketAddr) -> Result<()> {
+    let acceptor = acme::acceptor().ok_or_else(|| anyhow!("no certificate yet"))?;
+    let mut stream = acceptor.accept(stream).await?;
+    let mut upstream = TcpStream::connect(upstream).await?;
+    tokio::io::copy_bidirectional(&mut stream, &mut upstream).await?;
+    Ok(())
+}